@@ -15,6 +15,10 @@ pub struct AppConfig {
     pub encrypt_secrets: EncryptSecretsConfig,
     pub monitoring: MonitoringConfig,
     pub redis: RedisConfig,
+    #[serde(default)]
+    pub chain: ChainConfig,
+    #[serde(default)]
+    pub tokens: Vec<TokenConfig>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -61,6 +65,55 @@ pub struct SecurityConfig {
     pub cors: CorsConfig,
     pub rate_limiting: RateLimitingConfig,
     pub password: PasswordConfig,
+    // User IDs allowed to run admin operations such as master key rotation
+    #[serde(default)]
+    pub admin_user_ids: Vec<String>,
+    #[serde(default)]
+    pub keystore_export: KeystoreExportConfig,
+    #[serde(default)]
+    pub idempotency: IdempotencyConfig,
+}
+
+/// Export of wallet private keys as Keystore V3 files
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeystoreExportConfig {
+    pub enabled: bool,
+    // scrypt cost of exported files is N = 2^scrypt_log_n
+    pub scrypt_log_n: u8,
+}
+
+impl Default for KeystoreExportConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            scrypt_log_n: 18,
+        }
+    }
+}
+
+/// Idempotency keys that make retried transfers safe
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IdempotencyConfig {
+    // How long the result of a request is kept for replays of its key
+    pub retention_secs: u64,
+    // How long a key stays claimed by a request that has not stored its result yet
+    #[serde(default = "IdempotencyConfig::default_lease_secs")]
+    pub lease_secs: u64,
+}
+
+impl IdempotencyConfig {
+    fn default_lease_secs() -> u64 {
+        120
+    }
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        Self {
+            retention_secs: 86400,
+            lease_secs: Self::default_lease_secs(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -82,6 +135,8 @@ pub struct RateLimitingConfig {
     pub api: RateLimitSettings,
     pub login: RateLimitSettings,
     pub paths: std::collections::HashMap<String, usize>,
+    #[serde(default)]
+    pub pin: PinLockoutConfig,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -91,6 +146,40 @@ pub struct RateLimitSettings {
     pub block_duration: Option<u64>,
 }
 
+/// Failed wallet PIN attempts: each failure doubles the wait before the next attempt,
+/// and `max_attempts` failures within `window_duration` seconds lock the wallet
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PinLockoutConfig {
+    pub max_attempts: usize,
+    pub window_duration: u64,
+    pub base_delay: u64,
+    pub max_delay: u64,
+}
+
+impl Default for PinLockoutConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            window_duration: 86400,
+            base_delay: 2,
+            max_delay: 300,
+        }
+    }
+}
+
+impl PinLockoutConfig {
+    /// Seconds a caller must wait after `failures` consecutive failed attempts
+    pub fn retry_delay(&self, failures: usize) -> u64 {
+        if failures == 0 {
+            return 0;
+        }
+        let exponent = (failures - 1).min(32) as u32;
+        self.base_delay
+            .saturating_mul(1u64 << exponent)
+            .min(self.max_delay)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PasswordConfig {
     pub min_length: usize,
@@ -138,11 +227,263 @@ pub struct RedisConfig {
     pub prefix: Option<String>,
 }
 
+/// Blockchain node the wallet service reads balances from and sends transactions to.
+/// An `rpc_url` of `memory` runs an in-process chain, only in the development environment.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChainConfig {
+    pub chain_id: u64,
+    pub rpc_url: String,
+    pub timeout_ms: u64,
+    // Symbol and decimals of the chain's native coin
+    pub native_symbol: String,
+    pub native_decimals: u8,
+    #[serde(default)]
+    pub confirmations: ConfirmationConfig,
+    #[serde(default)]
+    pub nonces: NonceConfig,
+    #[serde(default)]
+    pub quotes: QuoteConfig,
+}
+
+impl Default for ChainConfig {
+    fn default() -> Self {
+        Self {
+            chain_id: 1961,
+            rpc_url: "https://rpc.selendra.org".to_string(),
+            timeout_ms: 10000,
+            native_symbol: "SEL".to_string(),
+            native_decimals: 18,
+            confirmations: ConfirmationConfig::default(),
+            nonces: NonceConfig::default(),
+            quotes: QuoteConfig::default(),
+        }
+    }
+}
+
+/// How submitted transactions are followed until they are final: a transaction is
+/// confirmed once its block is `required` blocks deep, and dropped if the node has not
+/// known it for `drop_after_secs` seconds
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ConfirmationConfig {
+    pub required: u64,
+    pub poll_interval_ms: u64,
+    pub drop_after_secs: u64,
+    // Most transactions checked in one poll
+    pub batch_size: usize,
+}
+
+impl Default for ConfirmationConfig {
+    fn default() -> Self {
+        Self {
+            required: 12,
+            poll_interval_ms: 5000,
+            drop_after_secs: 1800,
+            batch_size: 100,
+        }
+    }
+}
+
+/// Nonces handed out to outgoing transactions, counted in Redis: a nonce that stays ahead
+/// of the node for `gap_timeout_secs` is handed out again, and the count of an address is
+/// forgotten after `ttl_secs` without transactions
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NonceConfig {
+    pub gap_timeout_secs: u64,
+    pub ttl_secs: u64,
+}
+
+impl Default for NonceConfig {
+    fn default() -> Self {
+        Self {
+            gap_timeout_secs: 60,
+            ttl_secs: 86400,
+        }
+    }
+}
+
+/// Transfer quotes: a transfer can be sent with the fees it was quoted at for `ttl_secs`
+/// after the quote
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QuoteConfig {
+    pub ttl_secs: u64,
+}
+
+impl Default for QuoteConfig {
+    fn default() -> Self {
+        Self { ttl_secs: 120 }
+    }
+}
+
+/// An ERC-20 token wallets can hold and transfer. Only tokens on the configured chain
+/// are offered; entries for other chains are kept so one file can serve several networks.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TokenConfig {
+    pub symbol: String,
+    // Contract address, `0x`-prefixed hex
+    pub address: String,
+    pub decimals: u8,
+    pub chain_id: u64,
+}
+
 // New struct for HCP Secrets configuration
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct EncryptSecretsConfig {
     pub master_key_name: String,
+    // Raw key material, only used when `provider` is `inline`
+    #[serde(default)]
     pub master_key: String,
+    #[serde(default)]
+    pub provider: KeyProviderConfig,
+    // Previous master keys, kept only to decrypt records until they are re-wrapped
+    #[serde(default)]
+    pub retired_master_keys: Vec<RetiredMasterKeyConfig>,
+    #[serde(default)]
+    pub rotation: KeyRotationConfig,
+    #[serde(default)]
+    pub dek_cache: DekCacheConfig,
+    #[serde(default)]
+    pub pin_kdf: PinKdfConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RetiredMasterKeyConfig {
+    pub name: String,
+    #[serde(default)]
+    pub key: String,
+    #[serde(default)]
+    pub provider: KeyProviderConfig,
+}
+
+/// Where a master key lives. Only `inline` keeps the key in this file; `transit`
+/// keeps it in a remote KMS so it never enters the process.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum KeyProviderConfig {
+    #[default]
+    Inline,
+    File {
+        path: String,
+    },
+    Env {
+        var: String,
+    },
+    Transit {
+        url: String,
+        key_name: String,
+        // Environment variable holding the KMS access token
+        token_env: String,
+        #[serde(default = "KeyProviderConfig::default_transit_timeout")]
+        timeout_ms: u64,
+    },
+}
+
+impl KeyProviderConfig {
+    fn default_transit_timeout() -> u64 {
+        5000
+    }
+
+    /// Check the provider settings of the master key called `name`
+    fn validate(&self, name: &str, inline_key: &str, errors: &mut Vec<String>) {
+        match self {
+            Self::Inline => {
+                if inline_key.trim().is_empty() {
+                    errors.push(format!("Master key '{}' cannot be empty", name));
+                }
+            }
+            Self::File { path } => {
+                if path.trim().is_empty() {
+                    errors.push(format!("Master key '{}' file path cannot be empty", name));
+                }
+            }
+            Self::Env { var } => {
+                if var.trim().is_empty() {
+                    errors.push(format!(
+                        "Master key '{}' environment variable cannot be empty",
+                        name
+                    ));
+                }
+            }
+            Self::Transit {
+                url,
+                key_name,
+                token_env,
+                ..
+            } => {
+                if url.trim().is_empty() || key_name.trim().is_empty() {
+                    errors.push(format!(
+                        "Master key '{}' transit URL and key name cannot be empty",
+                        name
+                    ));
+                }
+                if token_env.trim().is_empty() {
+                    errors.push(format!(
+                        "Master key '{}' transit token variable cannot be empty",
+                        name
+                    ));
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeyRotationConfig {
+    pub batch_size: usize,
+    pub concurrency: usize,
+    // How long a job stays with the instance running it without that instance finishing a
+    // batch; must be longer than a batch takes
+    #[serde(default = "KeyRotationConfig::default_lease_secs")]
+    pub lease_secs: u64,
+}
+
+impl KeyRotationConfig {
+    fn default_lease_secs() -> u64 {
+        300
+    }
+}
+
+impl Default for KeyRotationConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 100,
+            concurrency: 4,
+            lease_secs: Self::default_lease_secs(),
+        }
+    }
+}
+
+/// Limits of the in-memory cache of plaintext data encryption keys
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DekCacheConfig {
+    pub capacity: usize,
+    pub ttl_secs: u64,
+}
+
+impl Default for DekCacheConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            ttl_secs: 300,
+        }
+    }
+}
+
+/// Argon2id cost parameters for deriving the PIN-layer key of new wallet keys
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PinKdfConfig {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for PinKdfConfig {
+    fn default() -> Self {
+        Self {
+            memory_kib: 19456,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
 }
 
 impl AppConfig {
@@ -214,6 +555,28 @@ impl AppConfig {
             errors.push("JWT secret is not secure for production use".to_string());
         }
 
+        // Exported files must stay readable by the import limits (N <= 2^20)
+        let keystore_export = &self.security.keystore_export;
+        if keystore_export.enabled && !(10..=20).contains(&keystore_export.scrypt_log_n) {
+            errors.push("Keystore export scrypt_log_n must be between 10 and 20".to_string());
+        }
+
+        let idempotency = &self.security.idempotency;
+        if idempotency.retention_secs == 0 || idempotency.lease_secs == 0 {
+            errors.push("Idempotency key retention and lease must be greater than 0".to_string());
+        }
+        if idempotency.lease_secs > idempotency.retention_secs {
+            errors.push("Idempotency key lease cannot exceed its retention".to_string());
+        }
+
+        let pin_lockout = &self.security.rate_limiting.pin;
+        if pin_lockout.max_attempts == 0 || pin_lockout.window_duration == 0 {
+            errors.push("PIN lockout attempts and window must be greater than 0".to_string());
+        }
+        if pin_lockout.base_delay > pin_lockout.max_delay {
+            errors.push("PIN lockout base delay cannot exceed its max delay".to_string());
+        }
+
         // Validate monitoring configuration
         if is_production && self.monitoring.sentry.dsn.trim().is_empty() {
             errors.push("Sentry DSN should be configured in production".to_string());
@@ -231,10 +594,121 @@ impl AppConfig {
             errors.push("Redis pool size must be greater than 0".to_string());
         }
 
+        let chain = &self.chain;
+        if chain.chain_id == 0 {
+            errors.push("Chain ID must be greater than 0".to_string());
+        }
+        if chain.rpc_url.trim().is_empty() {
+            errors.push("Chain RPC URL cannot be empty".to_string());
+        } else if is_production && !chain.rpc_url.starts_with("https://") {
+            errors.push("Production should use a secure 'https://' chain RPC URL".to_string());
+        }
+        if chain.timeout_ms == 0 {
+            errors.push("Chain RPC timeout must be greater than 0".to_string());
+        }
+        let confirmations = &chain.confirmations;
+        if confirmations.required == 0 {
+            errors.push("Required confirmations must be greater than 0".to_string());
+        }
+        if confirmations.poll_interval_ms == 0 {
+            errors.push("Confirmation poll interval must be greater than 0".to_string());
+        }
+        if confirmations.drop_after_secs == 0 {
+            errors.push("Transaction drop timeout must be greater than 0".to_string());
+        }
+        if confirmations.batch_size == 0 {
+            errors.push("Confirmation batch size must be greater than 0".to_string());
+        }
+        if chain.nonces.gap_timeout_secs == 0 {
+            errors.push("Nonce gap timeout must be greater than 0".to_string());
+        }
+        if chain.nonces.ttl_secs <= chain.nonces.gap_timeout_secs {
+            errors.push("Nonce TTL must be longer than the nonce gap timeout".to_string());
+        }
+        if chain.quotes.ttl_secs == 0 {
+            errors.push("Transfer quote TTL must be greater than 0".to_string());
+        }
+
+        let mut seen_tokens = std::collections::HashSet::new();
+        for token in &self.tokens {
+            let symbol = token.symbol.trim();
+            if symbol.is_empty() {
+                errors.push(format!("Token '{}' must have a symbol", token.address));
+            }
+            let address = token.address.strip_prefix("0x").unwrap_or_default();
+            if address.len() != 40 || !address.chars().all(|c| c.is_ascii_hexdigit()) {
+                errors.push(format!(
+                    "Token '{}' address must be 0x followed by 40 hex digits",
+                    symbol
+                ));
+            } else if address.chars().all(|c| c == '0') {
+                errors.push(format!(
+                    "Token '{}' address cannot be the zero address",
+                    symbol
+                ));
+            }
+            if token.chain_id == 0 {
+                errors.push(format!(
+                    "Token '{}' chain ID must be greater than 0",
+                    symbol
+                ));
+            }
+            if symbol.eq_ignore_ascii_case(&chain.native_symbol) && token.chain_id == chain.chain_id
+            {
+                errors.push(format!(
+                    "Token '{}' uses the symbol of the native coin",
+                    symbol
+                ));
+            }
+            if !seen_tokens.insert((token.chain_id, symbol.to_ascii_uppercase())) {
+                errors.push(format!(
+                    "Token '{}' is listed more than once for chain {}",
+                    symbol, token.chain_id
+                ));
+            }
+        }
+
         // Validate HCP Secrets configuration if present
 
-        if self.encrypt_secrets.master_key.trim().is_empty() {
-            errors.push("encrypt secrets cannot be empty".to_string());
+        self.encrypt_secrets.provider.validate(
+            &self.encrypt_secrets.master_key_name,
+            &self.encrypt_secrets.master_key,
+            &mut errors,
+        );
+
+        let mut master_key_names = vec![self.encrypt_secrets.master_key_name.as_str()];
+        for retired in &self.encrypt_secrets.retired_master_keys {
+            retired
+                .provider
+                .validate(&retired.name, &retired.key, &mut errors);
+            if master_key_names.contains(&retired.name.as_str()) {
+                errors.push(format!("Duplicate master key name '{}'", retired.name));
+            }
+            master_key_names.push(&retired.name);
+        }
+
+        if self.encrypt_secrets.rotation.batch_size == 0 {
+            errors.push("Key rotation batch size must be greater than 0".to_string());
+        }
+
+        if self.encrypt_secrets.rotation.concurrency == 0 {
+            errors.push("Key rotation concurrency must be greater than 0".to_string());
+        }
+
+        if self.encrypt_secrets.dek_cache.capacity == 0 {
+            errors.push("DEK cache capacity must be greater than 0".to_string());
+        }
+
+        if self.encrypt_secrets.dek_cache.ttl_secs == 0 {
+            errors.push("DEK cache TTL must be greater than 0".to_string());
+        }
+
+        let pin_kdf = &self.encrypt_secrets.pin_kdf;
+        if pin_kdf.iterations == 0 || pin_kdf.parallelism == 0 {
+            errors.push("PIN KDF iterations and parallelism must be greater than 0".to_string());
+        }
+        if pin_kdf.memory_kib < 8 * pin_kdf.parallelism {
+            errors.push("PIN KDF memory must be at least 8 KiB per lane".to_string());
         }
 
         if !errors.is_empty() {
@@ -362,6 +836,7 @@ impl Default for AppConfig {
                         block_duration: Some(900),
                     },
                     paths: std::collections::HashMap::new(),
+                    pin: PinLockoutConfig::default(),
                 },
                 password: PasswordConfig {
                     min_length: 8,
@@ -376,6 +851,9 @@ impl Default for AppConfig {
                         parallelism: 4,
                     },
                 },
+                admin_user_ids: Vec::new(),
+                keystore_export: KeystoreExportConfig::default(),
+                idempotency: IdempotencyConfig::default(),
             },
             monitoring: MonitoringConfig {
                 sentry: SentryConfig {
@@ -396,9 +874,16 @@ impl Default for AppConfig {
                 connection_timeout: 5000,
                 prefix: Some("app".to_string()),
             },
+            chain: ChainConfig::default(),
+            tokens: Vec::new(),
             encrypt_secrets: EncryptSecretsConfig {
                 master_key_name: "encryption_service".to_string(),
                 master_key: "encryption_service".to_string(),
+                provider: KeyProviderConfig::Inline,
+                retired_master_keys: Vec::new(),
+                rotation: KeyRotationConfig::default(),
+                dek_cache: DekCacheConfig::default(),
+                pin_kdf: PinKdfConfig::default(),
             },
         }
     }
@@ -17,6 +17,11 @@ impl ConnectionPool {
         }
     }
 
+    /// Whether the pool opens in-memory databases
+    fn is_memory(&self) -> bool {
+        self.connection_url.starts_with("memory")
+    }
+
     /// Get a connection from the pool or create a new one if needed
     ///
     /// This optimized implementation:
@@ -33,7 +38,13 @@ impl ConnectionPool {
                     e
                 ))
             })?;
-            connections.pop()
+            // Every new in-memory connection is a separate, empty database, so an
+            // in-memory pool shares its one connection instead of handing it out
+            if self.is_memory() {
+                connections.first().cloned()
+            } else {
+                connections.pop()
+            }
         };
 
         // If we got a connection, verify it's still alive
@@ -76,7 +87,8 @@ impl ConnectionPool {
 
     pub fn return_connection(&self, conn: surrealdb::Surreal<Any>) {
         if let Ok(mut connections) = self.connections.lock() {
-            if connections.len() < self.max_size {
+            let max_size = if self.is_memory() { 1 } else { self.max_size };
+            if connections.len() < max_size {
                 connections.push(conn);
                 return;
             }
@@ -344,6 +356,12 @@ impl QueryResponse {
             .context("Failed to extract query results")
             .db_err()
     }
+
+    /// Fail if any statement of the query failed
+    pub fn check(self) -> AppResult<()> {
+        self.0.check().context("Query statement failed").db_err()?;
+        Ok(())
+    }
 }
 
 // The DbService
@@ -534,6 +552,16 @@ where
         }).await
     }
 
+    // Run a schema definition, such as a DEFINE INDEX statement, which returns no records
+    pub async fn run_definition(&self, sql: &str) -> AppResult<()> {
+        tracing::debug!("Executing definition on {}: {}", self.table_name, sql);
+
+        self.execute_db_operation("define", async {
+            self.db.query(sql).r#await().await?.check()
+        })
+        .await
+    }
+
     // New method: Execute a query with count for pagination
     pub async fn query_with_count(
         &self,
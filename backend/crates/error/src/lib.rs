@@ -1,6 +1,6 @@
 pub mod middleware_handling;
 
-use async_graphql::{Error as GraphQLError, ErrorExtensions, FieldError};
+use async_graphql::{Error as GraphQLError, ErrorExtensions, FieldError, Name, Value};
 use axum::{
     Json,
     http::StatusCode,
@@ -22,8 +22,31 @@ pub enum AppError {
     RateLimitError(String),
     InputError(String),
     CryptoError(String),
+    IntegrityError(String),
     NetworkError(String),
     ResourceExistsError(String),
+    WalletLockedError(String),
+    ChainError(String),
+    RevertError(RevertDetails),
+}
+
+// Why a transaction would revert on chain, decoded from the contract's revert data
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RevertDetails {
+    /// What would revert, such as "USDT transfer"
+    pub action: String,
+    /// Name of the contract's error, such as "EnforcedPause"
+    pub error_name: String,
+    /// Arguments of the error by parameter name, formatted for display
+    pub arguments: Vec<(String, String)>,
+    /// The decoded reason as a sentence
+    pub reason: String,
+}
+
+impl RevertDetails {
+    fn message(&self) -> String {
+        format!("The {} would fail: {}", self.action, self.reason)
+    }
 }
 
 // Mapping between error types and HTTP status codes/messages
@@ -92,6 +115,13 @@ const ERROR_MAPPINGS: &[(&str, StatusCode, &str, &str, Option<&str>)] = &[
         "Encryption error",
         None,
     ),
+    (
+        "IntegrityError",
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "INTEGRITY_ERROR",
+        "Stored data failed its integrity check",
+        None,
+    ),
     (
         "NetworkError",
         StatusCode::SERVICE_UNAVAILABLE,
@@ -106,6 +136,27 @@ const ERROR_MAPPINGS: &[(&str, StatusCode, &str, &str, Option<&str>)] = &[
         "",
         Some("The resource already exists."),
     ),
+    (
+        "WalletLockedError",
+        StatusCode::LOCKED,
+        "WALLET_LOCKED",
+        "",
+        Some("Sign in again or contact support to unlock your wallet."),
+    ),
+    (
+        "ChainError",
+        StatusCode::BAD_GATEWAY,
+        "CHAIN_ERROR",
+        "",
+        Some("The blockchain node rejected the request."),
+    ),
+    (
+        "RevertError",
+        StatusCode::UNPROCESSABLE_ENTITY,
+        "EXECUTION_REVERTED",
+        "",
+        Some("The transaction would fail on chain, so it was not sent."),
+    ),
     // Default case for ServerError and others
     (
         "",
@@ -131,8 +182,12 @@ impl AppError {
             Self::RateLimitError(_) => "RateLimitError",
             Self::InputError(_) => "InputError",
             Self::CryptoError(_) => "CryptoError",
+            Self::IntegrityError(_) => "IntegrityError",
             Self::NetworkError(_) => "NetworkError",
             Self::ResourceExistsError(_) => "ResourceExistsError",
+            Self::WalletLockedError(_) => "WalletLockedError",
+            Self::ChainError(_) => "ChainError",
+            Self::RevertError(_) => "RevertError",
         }
     }
 
@@ -151,6 +206,19 @@ impl AppError {
         ))
     }
 
+    pub fn pin_retry_delay(seconds: u64) -> Self {
+        Self::RateLimitError(format!(
+            "Incorrect PIN entered too many times. Please try again in {} seconds.",
+            seconds
+        ))
+    }
+
+    pub fn wallet_locked() -> Self {
+        Self::WalletLockedError(
+            "Your wallet has been locked after too many incorrect PIN attempts.".to_string(),
+        )
+    }
+
     pub fn token_expired() -> Self {
         Self::AuthenticationError(
             "Your session has expired. Please log in again to continue.".to_string(),
@@ -206,7 +274,10 @@ impl AppError {
                     | Self::InputError(msg)
                     | Self::CryptoError(msg)
                     | Self::NetworkError(msg)
-                    | Self::ResourceExistsError(msg) => msg.clone(),
+                    | Self::ResourceExistsError(msg)
+                    | Self::WalletLockedError(msg)
+                    | Self::ChainError(msg) => msg.clone(),
+                    Self::RevertError(revert) => revert.message(),
                     _ => default_msg.to_string(),
                 };
 
@@ -255,8 +326,18 @@ impl fmt::Display for AppError {
             Self::RateLimitError(msg) => write!(f, "Rate limit error: {}", msg),
             Self::InputError(msg) => write!(f, "Input error: {}", msg),
             Self::CryptoError(msg) => write!(f, "Crypto error: {}", msg),
+            Self::IntegrityError(msg) => write!(f, "Integrity error: {}", msg),
             Self::NetworkError(msg) => write!(f, "Network error: {}", msg),
             Self::ResourceExistsError(msg) => write!(f, "Resource exists error: {}", msg),
+            Self::WalletLockedError(msg) => write!(f, "Wallet locked: {}", msg),
+            Self::ChainError(msg) => write!(f, "Chain error: {}", msg),
+            Self::RevertError(revert) => {
+                write!(
+                    f,
+                    "Execution reverted in {}: {}",
+                    revert.action, revert.reason
+                )
+            }
         }
     }
 }
@@ -301,6 +382,77 @@ impl IntoResponse for AppError {
     }
 }
 
+// An error reduced to its type and message, so it can be stored, such as the outcome of
+// a request replayed with an idempotency key, and raised again later
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrorRecord {
+    pub error_type: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revert: Option<RevertDetails>,
+}
+
+impl From<&AppError> for ErrorRecord {
+    fn from(error: &AppError) -> Self {
+        let message = match error {
+            AppError::ConfigError(e) | AppError::DatabaseError(e) | AppError::ServerError(e) => {
+                e.to_string()
+            }
+            AppError::GraphQLError(e) => e.message.clone(),
+            AppError::ValidationError(msg)
+            | AppError::NotFoundError(msg)
+            | AppError::AuthenticationError(msg)
+            | AppError::AuthorizationError(msg)
+            | AppError::RateLimitError(msg)
+            | AppError::InputError(msg)
+            | AppError::CryptoError(msg)
+            | AppError::IntegrityError(msg)
+            | AppError::NetworkError(msg)
+            | AppError::ResourceExistsError(msg)
+            | AppError::WalletLockedError(msg)
+            | AppError::ChainError(msg) => msg.clone(),
+            AppError::RevertError(revert) => revert.message(),
+        };
+        let revert = match error {
+            AppError::RevertError(revert) => Some(revert.clone()),
+            _ => None,
+        };
+        Self {
+            error_type: error.error_type_name().to_string(),
+            message,
+            revert,
+        }
+    }
+}
+
+impl From<ErrorRecord> for AppError {
+    fn from(record: ErrorRecord) -> Self {
+        if let Some(revert) = record.revert {
+            return Self::RevertError(revert);
+        }
+        let message = record.message;
+        match record.error_type.as_str() {
+            "ConfigError" => Self::ConfigError(anyhow::anyhow!(message)),
+            "DatabaseError" => Self::DatabaseError(anyhow::anyhow!(message)),
+            "GraphQLError" => Self::GraphQLError(GraphQLError::new(message)),
+            "ValidationError" => Self::ValidationError(message),
+            "NotFoundError" => Self::NotFoundError(message),
+            "AuthenticationError" => Self::AuthenticationError(message),
+            "AuthorizationError" => Self::AuthorizationError(message),
+            "RateLimitError" => Self::RateLimitError(message),
+            "InputError" => Self::InputError(message),
+            "CryptoError" => Self::CryptoError(message),
+            "IntegrityError" => Self::IntegrityError(message),
+            "NetworkError" => Self::NetworkError(message),
+            "ResourceExistsError" => Self::ResourceExistsError(message),
+            "WalletLockedError" => Self::WalletLockedError(message),
+            "ChainError" => Self::ChainError(message),
+            // Includes ServerError, and types stored by a version that had more of them
+            _ => Self::ServerError(anyhow::anyhow!(message)),
+        }
+    }
+}
+
 // Utility for anyhow results to AppError conversions
 pub type AppResult<T> = Result<T, AppError>;
 
@@ -369,9 +521,35 @@ impl AppError {
                 | Self::InputError(msg)
                 | Self::CryptoError(msg)
                 | Self::NetworkError(msg)
-                | Self::ResourceExistsError(msg) => {
+                | Self::ResourceExistsError(msg)
+                | Self::WalletLockedError(msg)
+                | Self::ChainError(msg) => {
                     e.set("details", msg);
                 }
+                Self::RevertError(revert) => {
+                    e.set("details", revert.message());
+                    let arguments = revert
+                        .arguments
+                        .iter()
+                        .map(|(name, value)| (Name::new(name), Value::from(value.as_str())))
+                        .collect();
+                    e.set(
+                        "revert",
+                        Value::Object(
+                            [
+                                (Name::new("error"), Value::from(revert.error_name.as_str())),
+                                (Name::new("arguments"), Value::Object(arguments)),
+                            ]
+                            .into_iter()
+                            .collect(),
+                        ),
+                    );
+                }
+                Self::IntegrityError(msg) => {
+                    if cfg!(debug_assertions) {
+                        e.set("details", msg);
+                    }
+                }
                 Self::GraphQLError(err) => {
                     e.set("details", format!("{:?}", err));
                 }
@@ -380,7 +558,10 @@ impl AppError {
 
         // Log the error with appropriate level based on error type
         match self {
-            Self::ServerError(_) | Self::DatabaseError(_) | Self::ConfigError(_) => {
+            Self::ServerError(_)
+            | Self::DatabaseError(_)
+            | Self::ConfigError(_)
+            | Self::IntegrityError(_) => {
                 tracing::error!(error = %self, "GraphQL resolver error");
             }
             Self::AuthenticationError(_) | Self::AuthorizationError(_) => {
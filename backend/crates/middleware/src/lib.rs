@@ -8,6 +8,7 @@ pub mod validation;
 pub use security::jwt::{Claims, JwtService};
 
 pub use limits::rate_limiter::{
-    RedisApiRateLimiter, RedisLoginRateLimiter, RedisRateLimiter, create_redis_api_rate_limiter,
-    create_redis_login_rate_limiter,
+    AttemptReservation, RedisApiRateLimiter, RedisLoginRateLimiter, RedisPinRateLimiter,
+    RedisRateLimiter, create_redis_api_rate_limiter, create_redis_login_rate_limiter,
+    create_redis_pin_rate_limiter,
 };
@@ -1,6 +1,6 @@
 use app_error::{AppError, AppResult};
 use chrono::Utc;
-use redis::{AsyncCommands, Client, Pipeline, aio::ConnectionManager};
+use redis::{AsyncCommands, Client, Pipeline, Script, aio::ConnectionManager};
 use std::{
     collections::HashMap,
     fmt::Debug,
@@ -20,6 +20,18 @@ pub struct RateLimitStatus {
     pub window_reset: i64,        // Seconds until window resets
     pub block_reset: Option<i64>, // Seconds until block ends, if blocked
     pub is_blocked: bool,
+    pub last_attempt: Option<i64>, // Unix timestamp of the latest attempt in the window
+}
+
+/// Outcome of reserving an attempt with `reserve_attempt`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttemptReservation {
+    /// The attempt was counted; holds the attempts in the window including this one
+    Reserved(usize),
+    /// The attempt limit had already been reached
+    Exhausted(usize),
+    /// The caller must wait this many more seconds after the previous attempt
+    RetryAfter(u64),
 }
 
 /// Generic rate limiter configuration
@@ -49,6 +61,38 @@ const RATE_FIRST_SUFFIX: &str = "first";
 const RATE_LAST_SUFFIX: &str = "last";
 const RATE_BLOCK_SUFFIX: &str = "blocked_until";
 
+// Counts an attempt unless the limit (ARGV[3]) is reached or the delay after the attempts
+// so far (ARGV[4 + count]) has not passed since the last one. Returns {1, new count},
+// {0, count} when exhausted or {-1, seconds to wait}.
+const RESERVE_SCRIPT: &str = r"
+local count = tonumber(redis.call('GET', KEYS[1]) or '0')
+local now = tonumber(ARGV[1])
+if count >= tonumber(ARGV[3]) then
+    return {0, count}
+end
+local last = tonumber(redis.call('GET', KEYS[3]))
+local delay = tonumber(ARGV[4 + count] or ARGV[#ARGV])
+if count > 0 and last ~= nil and last + delay > now then
+    return {-1, last + delay - now}
+end
+if count == 0 then
+    redis.call('SET', KEYS[1], 1, 'EX', ARGV[2])
+    redis.call('SET', KEYS[2], now, 'EX', ARGV[2])
+else
+    redis.call('INCR', KEYS[1])
+end
+redis.call('SET', KEYS[3], now, 'EX', ARGV[2])
+return {1, count + 1}
+";
+
+// Takes back one counted attempt
+const RELEASE_SCRIPT: &str = r"
+if tonumber(redis.call('GET', KEYS[1]) or '0') > 0 then
+    redis.call('DECR', KEYS[1])
+end
+return 0
+";
+
 /// Distributed rate limiter using Redis for shared state
 #[derive(Clone)]
 pub struct RedisRateLimiter<T: Eq + Hash + Clone + Send + Sync + Debug + 'static> {
@@ -326,6 +370,68 @@ impl<T: Eq + Hash + Clone + Send + Sync + Debug + 'static> RedisRateLimiter<T> {
         Ok(())
     }
 
+    /// Atomically count an attempt before it is made, so concurrent callers can never
+    /// exceed the limit between checking and counting. `retry_delays[n]` is the number of
+    /// seconds that must pass after the last attempt once `n` have been counted (the last
+    /// entry applies to any higher count). Reset the counter with
+    /// `record_successful_attempt` if the attempt succeeds, or take it back with
+    /// `release_attempt` if it could not be made.
+    pub async fn reserve_attempt(
+        &self,
+        identifier: &T,
+        retry_delays: &[u64],
+    ) -> AppResult<AttemptReservation> {
+        let now = Utc::now().timestamp(); // i64
+        let key_base = self.get_rate_limit_key(identifier);
+        let window_secs = self.config.window_duration.as_secs();
+
+        let script = Script::new(RESERVE_SCRIPT);
+        let mut invocation = script.prepare_invoke();
+        invocation
+            .key(format!("{}:{}", key_base, RATE_COUNT_SUFFIX))
+            .key(format!("{}:{}", key_base, RATE_FIRST_SUFFIX))
+            .key(format!("{}:{}", key_base, RATE_LAST_SUFFIX))
+            .arg(now)
+            .arg(window_secs)
+            .arg(self.config.max_attempts)
+            .arg(retry_delays);
+
+        // Get a Redis connection
+        let mut conn = self.redis_manager.clone();
+
+        let (outcome, value): (i64, i64) =
+            invocation.invoke_async(&mut conn).await.map_err(|e| {
+                error!("Redis script error when reserving attempt: {}", e);
+                AppError::ServerError(anyhow::anyhow!("Rate limit tracking error"))
+            })?;
+
+        Ok(match outcome {
+            1 => AttemptReservation::Reserved(value as usize),
+            0 => AttemptReservation::Exhausted(value as usize),
+            _ => AttemptReservation::RetryAfter(value as u64),
+        })
+    }
+
+    /// Take back an attempt counted by `reserve_attempt` that turned out not to count
+    pub async fn release_attempt(&self, identifier: &T) -> AppResult<()> {
+        let key_base = self.get_rate_limit_key(identifier);
+        let count_key = format!("{}:{}", key_base, RATE_COUNT_SUFFIX);
+
+        // Get a Redis connection
+        let mut conn = self.redis_manager.clone();
+
+        let _: i64 = Script::new(RELEASE_SCRIPT)
+            .key(count_key)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| {
+                error!("Redis script error when releasing attempt: {}", e);
+                AppError::ServerError(anyhow::anyhow!("Rate limit tracking error"))
+            })?;
+
+        Ok(())
+    }
+
     /// Record a successful attempt, optionally resetting the counter
     pub async fn record_successful_attempt(&self, identifier: &T, reset: bool) -> AppResult<()> {
         if !reset {
@@ -408,6 +514,7 @@ impl<T: Eq + Hash + Clone + Send + Sync + Debug + 'static> RedisRateLimiter<T> {
         let key_base = self.get_rate_limit_key(identifier);
         let count_key = format!("{}:{}", key_base, RATE_COUNT_SUFFIX);
         let first_key = format!("{}:{}", key_base, RATE_FIRST_SUFFIX);
+        let last_key = format!("{}:{}", key_base, RATE_LAST_SUFFIX);
         let block_key = format!("{}:{}", key_base, RATE_BLOCK_SUFFIX);
 
         // Get a Redis connection
@@ -418,6 +525,7 @@ impl<T: Eq + Hash + Clone + Send + Sync + Debug + 'static> RedisRateLimiter<T> {
             .get(&count_key)
             .get(&first_key)
             .get(&block_key)
+            .get(&last_key)
             .query_async(&mut conn)
             .await
         {
@@ -431,6 +539,7 @@ impl<T: Eq + Hash + Clone + Send + Sync + Debug + 'static> RedisRateLimiter<T> {
         let count: Option<usize> = results[0].as_ref().and_then(|v| v.parse().ok());
         let first_attempt: Option<i64> = results[1].as_ref().and_then(|v| v.parse().ok());
         let blocked_until: Option<i64> = results[2].as_ref().and_then(|v| v.parse().ok());
+        let last_attempt: Option<i64> = results[3].as_ref().and_then(|v| v.parse().ok());
 
         if let Some(count) = count {
             if let Some(first) = first_attempt {
@@ -466,6 +575,7 @@ impl<T: Eq + Hash + Clone + Send + Sync + Debug + 'static> RedisRateLimiter<T> {
                         window_reset,
                         block_reset,
                         is_blocked,
+                        last_attempt,
                     });
                 }
             }
@@ -479,6 +589,7 @@ impl<T: Eq + Hash + Clone + Send + Sync + Debug + 'static> RedisRateLimiter<T> {
             window_reset: 0,
             block_reset: None,
             is_blocked: false,
+            last_attempt: None,
         })
     }
 
@@ -503,6 +614,9 @@ pub type RedisApiRateLimiter = RedisRateLimiter<String>;
 /// Login rate limiter using string identifiers (e.g., username, email)
 pub type RedisLoginRateLimiter = RedisRateLimiter<String>;
 
+/// Wallet PIN attempt limiter keyed by wallet ID
+pub type RedisPinRateLimiter = RedisRateLimiter<String>;
+
 /// Factory function for API rate limiter
 pub async fn create_redis_api_rate_limiter(
     redis_url: &str,
@@ -543,6 +657,26 @@ pub async fn create_redis_login_rate_limiter(redis_url: &str) -> AppResult<Redis
         .with_cleanup_interval(Duration::from_secs(300)))
 }
 
+/// Factory function for wallet PIN attempt limiter
+pub async fn create_redis_pin_rate_limiter(
+    redis_url: &str,
+    max_attempts: usize,
+    window_duration: Duration,
+) -> AppResult<RedisPinRateLimiter> {
+    // Failed attempts are counted over a long window; the wallet service applies
+    // its own escalating delays and persists the lock once the limit is reached
+    let config = RateLimitConfig {
+        max_attempts,
+        window_duration,
+        block_duration: None,
+        message_template: "Too many incorrect PIN attempts.".into(),
+    };
+
+    Ok(RedisPinRateLimiter::new(redis_url, config)
+        .await?
+        .with_cleanup_interval(Duration::from_secs(300)))
+}
+
 #[cfg(test)]
 mod integration_tests {
     use super::*;
@@ -0,0 +1,215 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+use app_error::{AppError, AppResult};
+use async_graphql::{InputValueError, InputValueResult, Scalar, ScalarType, Value};
+use primitive_types::U256;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// An exact amount of a coin or token: a count of base units and the number of decimal
+// places they are shifted by, so 12.5 USDT is 12500000 with 6 decimals.
+//
+// Amounts are exchanged as decimal strings ("12.5") in GraphQL, JSON and the database, so
+// no precision is lost to floating point. Arithmetic is checked, and converting to fewer
+// decimals fails rather than rounding.
+#[derive(Debug, Clone, Copy)]
+pub struct Amount {
+    value: U256,
+    decimals: u8,
+}
+
+// 10^exp, or None when it does not fit 256 bits
+fn pow10(exp: u8) -> Option<U256> {
+    U256::from(10).checked_pow(U256::from(exp))
+}
+
+fn too_large() -> AppError {
+    AppError::ValidationError("Amount is too large".to_string())
+}
+
+impl Amount {
+    // An amount of `value` base units of an asset with `decimals` decimal places
+    pub fn new(value: U256, decimals: u8) -> Self {
+        Self { value, decimals }
+    }
+
+    pub fn zero(decimals: u8) -> Self {
+        Self::new(U256::zero(), decimals)
+    }
+
+    pub fn base_units(&self) -> U256 {
+        self.value
+    }
+
+    pub fn decimals(&self) -> u8 {
+        self.decimals
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.value.is_zero()
+    }
+
+    // The same amount with `decimals` decimal places. Fails if the amount has more
+    // significant decimal places than that, or does not fit 256 bits once shifted.
+    pub fn to_decimals(&self, decimals: u8) -> AppResult<Self> {
+        let value = match decimals.cmp(&self.decimals) {
+            Ordering::Equal => self.value,
+            Ordering::Greater => pow10(decimals - self.decimals)
+                .and_then(|factor| self.value.checked_mul(factor))
+                .ok_or_else(too_large)?,
+            Ordering::Less => {
+                let exact = match pow10(self.decimals - decimals) {
+                    Some(factor) => (self.value % factor).is_zero().then(|| self.value / factor),
+                    // Every non-zero value is below the factor
+                    None => self.value.is_zero().then(U256::zero),
+                };
+                exact.ok_or_else(|| {
+                    AppError::ValidationError(format!(
+                        "Amount has more than {} decimal places",
+                        decimals
+                    ))
+                })?
+            }
+        };
+        Ok(Self::new(value, decimals))
+    }
+
+    // Sum of two amounts, with the larger number of decimal places of the two
+    pub fn checked_add(&self, other: &Self) -> AppResult<Self> {
+        let decimals = self.decimals.max(other.decimals);
+        let (a, b) = (self.to_decimals(decimals)?, other.to_decimals(decimals)?);
+        let value = a.value.checked_add(b.value).ok_or_else(too_large)?;
+        Ok(Self::new(value, decimals))
+    }
+
+    // Difference of two amounts, which must not be negative
+    pub fn checked_sub(&self, other: &Self) -> AppResult<Self> {
+        let decimals = self.decimals.max(other.decimals);
+        let (a, b) = (self.to_decimals(decimals)?, other.to_decimals(decimals)?);
+        let value = a
+            .value
+            .checked_sub(b.value)
+            .ok_or_else(|| AppError::ValidationError("Amount would be negative".to_string()))?;
+        Ok(Self::new(value, decimals))
+    }
+
+    // Without trailing zero decimal places, so equal amounts have equal parts
+    fn normalized(&self) -> (U256, u8) {
+        let ten = U256::from(10);
+        let (mut value, mut decimals) = (self.value, self.decimals);
+        while decimals > 0 && (value % ten).is_zero() {
+            value /= ten;
+            decimals -= 1;
+        }
+        (value, decimals)
+    }
+}
+
+impl PartialEq for Amount {
+    fn eq(&self, other: &Self) -> bool {
+        self.normalized() == other.normalized()
+    }
+}
+
+impl Eq for Amount {}
+
+impl PartialOrd for Amount {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Amount {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let (a, a_decimals) = self.normalized();
+        let (b, b_decimals) = other.normalized();
+        // Shift the one with fewer decimals; if that overflows it is the larger amount
+        if a_decimals >= b_decimals {
+            pow10(a_decimals - b_decimals)
+                .and_then(|factor| b.checked_mul(factor))
+                .map_or(Ordering::Less, |b| a.cmp(&b))
+        } else {
+            pow10(b_decimals - a_decimals)
+                .and_then(|factor| a.checked_mul(factor))
+                .map_or(Ordering::Greater, |a| a.cmp(&b))
+        }
+    }
+}
+
+// Every decimal place is written out, so "2.50" with 2 decimals and "2.5" with 1 stay apart
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.decimals == 0 {
+            return write!(f, "{}", self.value);
+        }
+        let decimals = usize::from(self.decimals);
+        let digits = format!("{:0>width$}", self.value.to_string(), width = decimals + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - decimals);
+        write!(f, "{}.{}", whole, fraction)
+    }
+}
+
+// Parses plain decimal notation such as "10", "0.5" or "1250.000001", keeping as many
+// decimal places as are written
+impl FromStr for Amount {
+    type Err = AppError;
+
+    fn from_str(s: &str) -> AppResult<Self> {
+        let s = s.trim();
+        let (whole, fraction) = match s.split_once('.') {
+            Some((whole, fraction)) if !fraction.is_empty() => (whole, fraction),
+            Some(_) => ("", ""),
+            None => (s, ""),
+        };
+        let is_digits = |part: &str| part.bytes().all(|byte| byte.is_ascii_digit());
+        if whole.is_empty() || !is_digits(whole) || !is_digits(fraction) {
+            return Err(AppError::ValidationError(format!("Invalid amount '{}'", s)));
+        }
+
+        let decimals = u8::try_from(fraction.len()).map_err(|_| {
+            AppError::ValidationError("Amount has too many decimal places".to_string())
+        })?;
+        // Only digits are left, so parsing fails only when the value overflows
+        let value =
+            U256::from_dec_str(&format!("{}{}", whole, fraction)).map_err(|_| too_large())?;
+        Ok(Self::new(value, decimals))
+    }
+}
+
+impl Serialize for Amount {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Amount {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+// A decimal string such as "12.5"; whole numbers may also be given as integers
+#[Scalar(name = "Amount")]
+impl ScalarType for Amount {
+    fn parse(value: Value) -> InputValueResult<Self> {
+        match &value {
+            Value::String(s) => s
+                .parse()
+                .map_err(|e: AppError| InputValueError::custom(e.to_string())),
+            Value::Number(number) => number
+                .as_u64()
+                .map(|value| Self::new(U256::from(value), 0))
+                .ok_or_else(|| {
+                    InputValueError::custom("Amounts with decimal places must be strings")
+                }),
+            _ => Err(InputValueError::expected_type(value)),
+        }
+    }
+
+    fn to_value(&self) -> Value {
+        Value::String(self.to_string())
+    }
+}
@@ -0,0 +1,13 @@
+use async_graphql::SimpleObject;
+use serde::{Deserialize, Serialize};
+
+// For API responses: counters of the wallet service's in-memory DEK cache
+#[derive(Debug, SimpleObject, Serialize, Deserialize, Clone)]
+pub struct DekCacheStatsInfo {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,   // Dropped to make room for newer DEKs
+    pub expirations: u64, // Dropped after outliving the TTL
+    pub size: usize,
+    pub capacity: usize,
+}
@@ -0,0 +1,121 @@
+use async_graphql::{Enum, SimpleObject};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::Thing;
+use uuid::Uuid;
+
+#[derive(Debug, Enum, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyRotationStatus {
+    Running,
+    Paused,
+    Cancelled,
+    Completed,
+}
+
+impl KeyRotationStatus {
+    // Whether the job can still make progress (possibly after being resumed)
+    pub fn is_active(&self) -> bool {
+        matches!(self, Self::Running | Self::Paused)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct KeyRotationJob {
+    #[serde(default = "KeyRotationJob::generate_id")]
+    pub id: Thing,
+    pub target_master_key_id: String, // Primary master key the job re-wraps keys onto
+    pub status: KeyRotationStatus,
+    #[serde(default)]
+    pub cursor: Option<String>, // ID of the last wallet key processed, None before the first batch
+    pub batch_size: usize,
+    pub concurrency: usize,
+    #[serde(default)]
+    pub scanned: u64, // Wallet keys looked at, including ones already on the target key
+    #[serde(default)]
+    pub rotated: u64,
+    #[serde(default)]
+    pub failed: u64,
+    #[serde(default)]
+    pub failed_wallets: Vec<String>, // The first failures only, see MAX_FAILED_WALLETS
+    #[serde(default)]
+    pub owner: Option<String>, // Run of the job holding it, None while no run does
+    #[serde(default)]
+    pub lease_expires_at: Option<DateTime<Utc>>, // When another run may take the job over
+    #[serde(default = "Utc::now")]
+    pub created_at: DateTime<Utc>,
+    #[serde(default = "Utc::now")]
+    pub updated_at: DateTime<Utc>,
+    #[serde(default)]
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl KeyRotationJob {
+    // Failed wallet IDs kept on a job; `failed` still counts every failure
+    pub const MAX_FAILED_WALLETS: usize = 100;
+
+    // Helper to generate a new ID
+    pub fn generate_id() -> Thing {
+        Thing::from(("key_rotation_jobs".to_string(), Uuid::new_v4().to_string()))
+    }
+
+    // Create a new running job
+    pub fn new(target_master_key_id: String, batch_size: usize, concurrency: usize) -> Self {
+        let now = Utc::now();
+        Self {
+            id: Self::generate_id(),
+            target_master_key_id,
+            status: KeyRotationStatus::Running,
+            cursor: None,
+            batch_size,
+            concurrency,
+            scanned: 0,
+            rotated: 0,
+            failed: 0,
+            failed_wallets: Vec::new(),
+            owner: None,
+            lease_expires_at: None,
+            created_at: now,
+            updated_at: now,
+            finished_at: None,
+        }
+    }
+}
+
+// For API responses
+#[derive(Debug, SimpleObject, Serialize, Deserialize, Clone)]
+pub struct KeyRotationJobInfo {
+    pub id: String,
+    pub target_master_key_id: String,
+    pub status: KeyRotationStatus,
+    pub cursor: Option<String>,
+    pub batch_size: usize,
+    pub concurrency: usize,
+    pub scanned: u64,
+    pub rotated: u64,
+    pub failed: u64,
+    pub failed_wallets: Vec<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl From<KeyRotationJob> for KeyRotationJobInfo {
+    fn from(job: KeyRotationJob) -> Self {
+        Self {
+            id: job.id.id.to_raw(),
+            target_master_key_id: job.target_master_key_id,
+            status: job.status,
+            cursor: job.cursor,
+            batch_size: job.batch_size,
+            concurrency: job.concurrency,
+            scanned: job.scanned,
+            rotated: job.rotated,
+            failed: job.failed,
+            failed_wallets: job.failed_wallets,
+            created_at: job.created_at,
+            updated_at: job.updated_at,
+            finished_at: job.finished_at,
+        }
+    }
+}
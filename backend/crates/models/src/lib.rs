@@ -1,5 +1,20 @@
+pub mod amount;
+pub mod dek_cache;
+pub mod key_rotation;
+pub mod quote;
+pub mod transaction;
 pub mod user;
 pub mod wallet;
 
+pub use amount::Amount;
+pub use dek_cache::DekCacheStatsInfo;
+pub use key_rotation::{KeyRotationJob, KeyRotationJobInfo, KeyRotationStatus};
+pub use quote::{FeeSpeed, FeeTier, TransferQuote, TransferQuoteInfo};
+pub use transaction::{
+    TransactionDirection, TransactionStatus, WalletTransaction, WalletTransactionInfo,
+    WalletTransactionPage,
+};
 pub use user::{AuthResponse, LoginInput, RegisterInput, User, UserProfile};
-pub use wallet::{Wallet, WalletInfo, WalletKey};
+pub use wallet::{
+    CreatedWalletInfo, Wallet, WalletAccount, WalletAccountInfo, WalletInfo, WalletKey,
+};
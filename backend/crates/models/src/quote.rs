@@ -0,0 +1,114 @@
+use async_graphql::{Enum, SimpleObject};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::Thing;
+use uuid::Uuid;
+
+use crate::amount::Amount;
+
+// How quickly a transfer should be mined, which sets how much it tips the block producer
+#[derive(Debug, Enum, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FeeSpeed {
+    Slow, // Tips as little as the cheaper recent transactions did
+    #[default]
+    Normal, // Tips what recent transactions typically did
+    Fast, // Tips as much as the more generous recent transactions did
+}
+
+// The fees of a quoted transfer at one speed. Fees per gas are in gwei, whole fees and
+// costs in the native coin.
+#[derive(Debug, SimpleObject, Serialize, Deserialize, Clone)]
+pub struct FeeTier {
+    pub speed: FeeSpeed,
+    pub max_priority_fee_per_gas: Amount, // Tip per gas
+    pub max_fee_per_gas: Amount,          // Most paid per gas, base fee and tip together
+    pub estimated_fee: Amount,            // Fee at the next block's base fee and the tip
+    pub max_fee: Amount,                  // Fee at the cap, the most gas can cost
+    // Most the transfer takes from the native balance: the fee at the cap, plus the
+    // amount when sending the native coin
+    pub total_cost: Amount,
+    pub can_cover_gas: bool, // Whether the native balance covers the total cost
+}
+
+// Fees offered for a transfer, kept until the quote expires so the transfer can be sent
+// with the fees the user accepted
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TransferQuote {
+    #[serde(default = "TransferQuote::generate_id")]
+    pub id: Thing,
+    pub wallet_id: String,
+    pub account_index: u32,
+    pub from: String,
+    pub to: String,
+    pub token: String, // Symbol of the token or native coin to send
+    #[serde(default)]
+    pub token_address: Option<String>, // Token contract; None for the native coin
+    pub amount: Amount,
+    pub gas_limit: u64,
+    pub base_fee_per_gas: Amount, // Base fee of the next block when quoted, in gwei
+    pub native_balance: Amount,   // Native balance of the sending account when quoted
+    pub tiers: Vec<FeeTier>,
+    pub chain_id: u64,
+    #[serde(default = "Utc::now")]
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl TransferQuote {
+    // Helper to generate a new ID
+    pub fn generate_id() -> Thing {
+        Thing::from(("transfer_quotes".to_string(), Uuid::now_v7().to_string()))
+    }
+
+    // The fees quoted at a speed
+    pub fn tier(&self, speed: FeeSpeed) -> Option<&FeeTier> {
+        self.tiers.iter().find(|tier| tier.speed == speed)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at <= Utc::now()
+    }
+}
+
+// For API responses
+#[derive(Debug, SimpleObject, Serialize, Deserialize, Clone)]
+pub struct TransferQuoteInfo {
+    pub id: String, // Pass as `quoteId` to transfer with these fees
+    pub wallet_id: String,
+    pub account_index: u32,
+    pub from: String,
+    pub to: String,
+    pub token: String,
+    pub token_address: Option<String>,
+    pub amount: Amount,
+    pub gas_limit: u64,
+    pub base_fee_per_gas: Amount,
+    pub native_balance: Amount,
+    pub tiers: Vec<FeeTier>,
+    pub chain_id: u64,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl From<TransferQuote> for TransferQuoteInfo {
+    fn from(quote: TransferQuote) -> Self {
+        Self {
+            id: quote.id.id.to_raw(),
+            wallet_id: quote.wallet_id,
+            account_index: quote.account_index,
+            from: quote.from,
+            to: quote.to,
+            token: quote.token,
+            token_address: quote.token_address,
+            amount: quote.amount,
+            gas_limit: quote.gas_limit,
+            base_fee_per_gas: quote.base_fee_per_gas,
+            native_balance: quote.native_balance,
+            tiers: quote.tiers,
+            chain_id: quote.chain_id,
+            created_at: quote.created_at,
+            expires_at: quote.expires_at,
+        }
+    }
+}
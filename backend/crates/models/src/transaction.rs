@@ -0,0 +1,157 @@
+use async_graphql::{Enum, SimpleObject};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use surrealdb::sql::Thing;
+use uuid::Uuid;
+
+use crate::amount::Amount;
+
+#[derive(Debug, Enum, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionStatus {
+    Pending,   // Signed and recorded, not yet accepted by the node
+    Submitted, // Accepted by the node, waiting to be mined
+    Confirmed, // Mined and buried under enough blocks
+    Failed,    // Rejected by the node, or mined but reverted
+    Dropped,   // Left the node's pool without being mined, or replaced
+}
+
+impl TransactionStatus {
+    // Whether the status can still change
+    pub fn is_final(&self) -> bool {
+        matches!(self, Self::Confirmed | Self::Failed | Self::Dropped)
+    }
+}
+
+#[derive(Debug, Enum, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionDirection {
+    Outgoing,
+    Incoming,
+}
+
+// A transfer to or from one of a wallet's accounts. A transfer between two wallets of the
+// service is recorded twice, once for each side, sharing the hash.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WalletTransaction {
+    #[serde(default = "WalletTransaction::generate_id")]
+    pub id: Thing,
+    pub wallet_id: String,
+    pub account_index: u32,
+    pub direction: TransactionDirection,
+    pub address: String,      // The wallet's account that sent or received the transfer
+    pub counterparty: String, // Recipient of an outgoing transfer, sender of an incoming one
+    pub token: String,        // Symbol of the token or native coin moved
+    #[serde(default)]
+    pub token_address: Option<String>, // Token contract; None for the native coin
+    pub amount: Amount,
+    #[serde(default)]
+    pub fee: Option<Amount>, // Most gas the sender can pay, then what it paid; None if incoming
+    pub nonce: u64,
+    pub hash: String,
+    #[serde(default)]
+    pub raw_transaction: Option<String>, // Signed transaction as sent; None if incoming
+    #[serde(default)]
+    pub replaces: Option<String>, // Hash of the stuck transaction this one speeds up or cancels
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>, // Sender's idempotency key, unique among records
+    pub chain_id: u64,
+    pub status: TransactionStatus,
+    #[serde(default)]
+    pub error: Option<String>, // Why the transfer failed
+    #[serde(default)]
+    pub block_number: Option<u64>, // Block the transaction was last seen mined in
+    #[serde(default)]
+    pub block_hash: Option<String>,
+    #[serde(default)]
+    pub confirmed_at: Option<DateTime<Utc>>,
+    #[serde(default = "Utc::now")]
+    pub created_at: DateTime<Utc>,
+    #[serde(default = "Utc::now")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl WalletTransaction {
+    // Helper to generate a new ID. Version 7 UUIDs start with a timestamp, so ordering by ID
+    // lists transactions in the order they were recorded.
+    pub fn generate_id() -> Thing {
+        Thing::from(("transactions".to_string(), Uuid::now_v7().to_string()))
+    }
+
+    // The incoming side of an outgoing transfer, for the wallet account that receives it
+    pub fn incoming(&self, wallet_id: String, account_index: u32) -> Self {
+        Self {
+            id: Self::generate_id(),
+            wallet_id,
+            account_index,
+            direction: TransactionDirection::Incoming,
+            address: self.counterparty.clone(),
+            counterparty: self.address.clone(),
+            fee: None,
+            raw_transaction: None,
+            idempotency_key: None,
+            ..self.clone()
+        }
+    }
+}
+
+// For API responses
+#[derive(Debug, SimpleObject, Serialize, Deserialize, Clone)]
+pub struct WalletTransactionInfo {
+    pub id: String,
+    pub wallet_id: String,
+    pub account_index: u32,
+    pub direction: TransactionDirection,
+    pub address: String,
+    pub counterparty: String,
+    pub token: String,
+    pub token_address: Option<String>,
+    pub amount: Amount,
+    pub fee: Option<Amount>,
+    pub nonce: u64,
+    pub hash: String,
+    pub replaces: Option<String>,
+    pub chain_id: u64,
+    pub status: TransactionStatus,
+    pub error: Option<String>,
+    pub block_number: Option<u64>,
+    pub block_hash: Option<String>,
+    pub confirmed_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<WalletTransaction> for WalletTransactionInfo {
+    fn from(transaction: WalletTransaction) -> Self {
+        Self {
+            id: transaction.id.id.to_raw(),
+            wallet_id: transaction.wallet_id,
+            account_index: transaction.account_index,
+            direction: transaction.direction,
+            address: transaction.address,
+            counterparty: transaction.counterparty,
+            token: transaction.token,
+            token_address: transaction.token_address,
+            amount: transaction.amount,
+            fee: transaction.fee,
+            nonce: transaction.nonce,
+            hash: transaction.hash,
+            replaces: transaction.replaces,
+            chain_id: transaction.chain_id,
+            status: transaction.status,
+            error: transaction.error,
+            block_number: transaction.block_number,
+            block_hash: transaction.block_hash,
+            confirmed_at: transaction.confirmed_at,
+            created_at: transaction.created_at,
+            updated_at: transaction.updated_at,
+        }
+    }
+}
+
+// A page of a wallet's transactions, newest first
+#[derive(Debug, SimpleObject, Serialize, Deserialize, Clone)]
+pub struct WalletTransactionPage {
+    pub transactions: Vec<WalletTransactionInfo>,
+    pub next_cursor: Option<String>, // Pass as `after` for the next page; None on the last page
+}
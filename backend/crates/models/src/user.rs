@@ -17,7 +17,7 @@ pub struct User {
     pub created_at: DateTime<Utc>,
     #[serde(default = "Utc::now")]
     pub updated_at: DateTime<Utc>,
-    // Add optional wallet reference
+    // The user's default wallet; wallets point to their owner through `Wallet.user_id`
     #[serde(skip_serializing_if = "Option::is_none")]
     pub wallet_id: Option<String>,
 }
@@ -64,7 +64,7 @@ pub struct UserProfile {
 impl From<User> for UserProfile {
     fn from(user: User) -> Self {
         Self {
-            id: user.id.id.to_string(),
+            id: user.id.id.to_raw(),
             name: user.name,
             username: user.username,
             email: user.email,
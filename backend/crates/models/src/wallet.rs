@@ -8,11 +8,22 @@ use uuid::Uuid;
 pub struct Wallet {
     #[serde(default = "Wallet::generate_id")]
     pub id: Thing,
-    pub user_email: String,
+    #[serde(default)]
+    pub user_id: String, // Record ID of the owner; empty on old rows until they are migrated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user_email: Option<String>, // Owner of rows written before wallets were keyed by user ID
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub is_default: bool, // The wallet used when a request does not name one
     pub address: String,
     // We'll replace the private_key field with a reference to the WalletKey
     #[serde(skip_serializing_if = "Option::is_none")]
     pub key_id: Option<String>, // Reference to the WalletKey record
+    #[serde(default)]
+    pub pin_locked_at: Option<DateTime<Utc>>, // Set when too many incorrect PINs lock the wallet
+    #[serde(default)]
+    pub hd: bool, // Whether the key record holds an HD seed rather than a single private key
     #[serde(default = "Utc::now")]
     pub created_at: DateTime<Utc>,
     #[serde(default = "Utc::now")]
@@ -26,13 +37,18 @@ impl Wallet {
     }
 
     // Create a new wallet with all required fields
-    pub fn new(user_email: String, address: String) -> Self {
+    pub fn new(user_id: String, address: String) -> Self {
         let now = Utc::now();
         Self {
             id: Self::generate_id(),
-            user_email,
+            user_id,
+            user_email: None,
+            label: None,
+            is_default: false,
             address,
             key_id: None, // Will be set after key is created
+            pin_locked_at: None,
+            hd: false,
             created_at: now,
             updated_at: now,
         }
@@ -43,28 +59,144 @@ impl Wallet {
         self.key_id = Some(key_id);
         self
     }
+
+    // Set a label chosen by the user
+    pub fn with_label(mut self, label: Option<String>) -> Self {
+        self.label = label;
+        self
+    }
+
+    // Mark the wallet as its owner's default
+    pub fn with_default(mut self, is_default: bool) -> Self {
+        self.is_default = is_default;
+        self
+    }
+
+    // Mark the wallet as an HD wallet
+    pub fn with_hd(mut self, hd: bool) -> Self {
+        self.hd = hd;
+        self
+    }
+
+    // Whether PIN operations are locked
+    pub fn is_pin_locked(&self) -> bool {
+        self.pin_locked_at.is_some()
+    }
 }
 
 // For API responses (without sensitive data)
 #[derive(Debug, SimpleObject, Serialize, Deserialize, Clone)]
 pub struct WalletInfo {
     pub id: String,
-    pub user_email: String,
-    pub address: String,
+    pub user_id: String,
+    pub label: Option<String>,
+    pub is_default: bool,
+    pub address: String, // Address of the first account
+    pub pin_locked: bool,
+    pub hd: bool,
+    pub accounts: Vec<WalletAccountInfo>,
     pub created_at: DateTime<Utc>,
 }
 
+impl WalletInfo {
+    // Set the accounts, which are stored apart from the wallet
+    pub fn with_accounts(mut self, accounts: Vec<WalletAccountInfo>) -> Self {
+        self.accounts = accounts;
+        self
+    }
+}
+
 impl From<Wallet> for WalletInfo {
     fn from(wallet: Wallet) -> Self {
         Self {
-            id: wallet.id.id.to_string(),
-            user_email: wallet.user_email,
+            id: wallet.id.id.to_raw(),
+            pin_locked: wallet.is_pin_locked(),
+            hd: wallet.hd,
+            accounts: Vec::new(),
+            user_id: wallet.user_id,
+            label: wallet.label,
+            is_default: wallet.is_default,
             address: wallet.address,
             created_at: wallet.created_at,
         }
     }
 }
 
+// An address of a wallet; HD wallets derive any number of them from their seed
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WalletAccount {
+    #[serde(default = "WalletAccount::generate_id")]
+    pub id: Thing,
+    pub wallet_id: String,
+    pub account_index: u32,
+    pub address: String,
+    pub derivation_path: Option<String>, // None for wallets holding a single key
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default = "Utc::now")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl WalletAccount {
+    // Helper to generate a new ID
+    pub fn generate_id() -> Thing {
+        Thing::from(("wallet_accounts".to_string(), Uuid::new_v4().to_string()))
+    }
+
+    // Create a new account entry
+    pub fn new(
+        wallet_id: String,
+        account_index: u32,
+        address: String,
+        derivation_path: Option<String>,
+    ) -> Self {
+        Self {
+            id: Self::generate_id(),
+            wallet_id,
+            account_index,
+            address,
+            derivation_path,
+            label: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    // Set a label chosen by the user
+    pub fn with_label(mut self, label: Option<String>) -> Self {
+        self.label = label;
+        self
+    }
+}
+
+// For API responses
+#[derive(Debug, SimpleObject, Serialize, Deserialize, Clone)]
+pub struct WalletAccountInfo {
+    pub index: u32,
+    pub address: String,
+    pub derivation_path: Option<String>,
+    pub label: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<WalletAccount> for WalletAccountInfo {
+    fn from(account: WalletAccount) -> Self {
+        Self {
+            index: account.account_index,
+            address: account.address,
+            derivation_path: account.derivation_path,
+            label: account.label,
+            created_at: account.created_at,
+        }
+    }
+}
+
+// Returned once when a wallet is created; the recovery phrase is never stored
+#[derive(SimpleObject, Clone)]
+pub struct CreatedWalletInfo {
+    pub wallet: WalletInfo,
+    pub recovery_phrase: String,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct WalletKey {
     #[serde(default = "WalletKey::generate_id")]
@@ -79,6 +211,10 @@ pub struct WalletKey {
     pub pin_iv: String,        // Hex-encoded IV for PIN encryption
     pub dek_iv: String,        // Hex-encoded IV for DEK encryption
     pub master_iv: String,     // Hex-encoded IV for master key encryption
+    #[serde(default)]
+    pub integrity_mac: Option<String>, // Hex-encoded HMAC over the fields above (None for old records)
+    #[serde(default)]
+    pub pin_kdf: Option<String>, // PIN KDF and its parameters (None for old PBKDF2 records)
     #[serde(default = "Utc::now")]
     pub created_at: DateTime<Utc>,
     #[serde(default = "Utc::now")]
@@ -117,8 +253,22 @@ impl WalletKey {
             pin_iv,
             dek_iv,
             master_iv,
+            integrity_mac: None,
+            pin_kdf: None,
             created_at: now,
             updated_at: now,
         }
     }
+
+    // Set the integrity MAC
+    pub fn with_integrity_mac(mut self, integrity_mac: String) -> Self {
+        self.integrity_mac = Some(integrity_mac);
+        self
+    }
+
+    // Set the PIN KDF parameters
+    pub fn with_pin_kdf(mut self, pin_kdf: String) -> Self {
+        self.pin_kdf = Some(pin_kdf);
+        self
+    }
 }
@@ -0,0 +1,191 @@
+//! Calls and events of ERC-20 tokens.
+
+use app_error::{AppError, AppResult};
+
+use super::{ParamType, Token, address_word, decode, encode, encode_call, event_topic};
+use crate::chain::Log;
+use crate::transaction::{H160, U256};
+
+pub const BALANCE_OF: &str = "balanceOf(address)";
+pub const TRANSFER: &str = "transfer(address,uint256)";
+pub const APPROVE: &str = "approve(address,uint256)";
+pub const ALLOWANCE: &str = "allowance(address,address)";
+pub const DECIMALS: &str = "decimals()";
+pub const TRANSFER_EVENT: &str = "Transfer(address,address,uint256)";
+
+fn unexpected(expected: &str) -> AppError {
+    AppError::ValidationError(format!("Invalid ABI data: expected {}", expected))
+}
+
+/// Calldata for `balanceOf(owner)`
+pub fn balance_of(owner: H160) -> Vec<u8> {
+    encode_call(BALANCE_OF, &[Token::Address(owner)])
+}
+
+/// Calldata for `transfer(to, amount)`
+pub fn transfer(to: H160, amount: U256) -> Vec<u8> {
+    encode_call(TRANSFER, &[Token::Address(to), Token::Uint(amount)])
+}
+
+/// Calldata for `approve(spender, amount)`
+pub fn approve(spender: H160, amount: U256) -> Vec<u8> {
+    encode_call(APPROVE, &[Token::Address(spender), Token::Uint(amount)])
+}
+
+/// Calldata for `allowance(owner, spender)`
+pub fn allowance(owner: H160, spender: H160) -> Vec<u8> {
+    encode_call(ALLOWANCE, &[Token::Address(owner), Token::Address(spender)])
+}
+
+/// Calldata for `decimals()`
+pub fn decimals() -> Vec<u8> {
+    encode_call(DECIMALS, &[])
+}
+
+/// Decode the `uint256` returned by `balanceOf` or `allowance`
+pub fn decode_amount(output: &[u8]) -> AppResult<U256> {
+    decode(&[ParamType::Uint], output)?[0]
+        .as_uint()
+        .ok_or_else(|| unexpected("uint256"))
+}
+
+/// Decode the value returned by `decimals`, which must fit a `uint8`
+pub fn decode_decimals(output: &[u8]) -> AppResult<u8> {
+    let decimals = decode_amount(output)?;
+    if decimals > U256::from(u8::MAX) {
+        return Err(AppError::ValidationError(
+            "Invalid ABI data: decimals do not fit a uint8".to_string(),
+        ));
+    }
+    Ok(decimals.low_u32() as u8)
+}
+
+/// Whether a `transfer` or `approve` succeeded. Some tokens predate the standard and
+/// return nothing, in which case not reverting means success.
+pub fn decode_success(output: &[u8]) -> AppResult<bool> {
+    if output.is_empty() {
+        return Ok(true);
+    }
+    decode(&[ParamType::Bool], output)?[0]
+        .as_bool()
+        .ok_or_else(|| unexpected("bool"))
+}
+
+/// A token transfer, read from a `Transfer` event
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferEvent {
+    /// Contract that emitted the event
+    pub token: H160,
+    pub from: H160,
+    pub to: H160,
+    pub value: U256,
+}
+
+impl TransferEvent {
+    /// The log a token emits for this transfer
+    pub fn to_log(&self) -> Log {
+        Log {
+            address: self.token,
+            topics: vec![
+                event_topic(TRANSFER_EVENT),
+                address_word(self.from),
+                address_word(self.to),
+            ],
+            data: encode(&[Token::Uint(self.value)]),
+        }
+    }
+
+    /// Read a `Transfer` event from a log; `None` if the log is another event.
+    /// ERC-721 transfers share the signature but index the token ID, so they have four
+    /// topics and are not matched.
+    pub fn from_log(log: &Log) -> AppResult<Option<Self>> {
+        if log.topics.len() != 3 || log.topics[0] != event_topic(TRANSFER_EVENT) {
+            return Ok(None);
+        }
+
+        // Indexed addresses are stored in topics, padded to a word like ABI values
+        let indexed: Vec<u8> = log.topics[1..].iter().flat_map(|topic| topic.0).collect();
+        let addresses = decode(&[ParamType::Address, ParamType::Address], &indexed)?;
+        Ok(Some(Self {
+            token: log.address,
+            from: addresses[0]
+                .as_address()
+                .ok_or_else(|| unexpected("address"))?,
+            to: addresses[1]
+                .as_address()
+                .ok_or_else(|| unexpected("address"))?,
+            value: decode_amount(&log.data)?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::parse_address;
+
+    fn address(byte: u8) -> H160 {
+        H160::repeat_byte(byte)
+    }
+
+    #[test]
+    fn test_calldata() {
+        assert_eq!(
+            hex::encode(balance_of(address(0x11))),
+            "70a082310000000000000000000000001111111111111111111111111111111111111111"
+        );
+        assert_eq!(hex::encode(decimals()), "313ce567");
+        assert_eq!(
+            hex::encode(&approve(address(0x22), U256::MAX)[..4]),
+            "095ea7b3"
+        );
+        assert_eq!(
+            hex::encode(&allowance(address(0x11), address(0x22))[..4]),
+            "dd62ed3e"
+        );
+        assert_eq!(transfer(address(0x22), U256::from(5)).len(), 4 + 64);
+    }
+
+    #[test]
+    fn test_decode_outputs() {
+        let amount = encode(&[Token::Uint(U256::from(1_500_000))]);
+        assert_eq!(decode_amount(&amount).unwrap(), U256::from(1_500_000));
+        assert_eq!(
+            decode_decimals(&encode(&[Token::Uint(U256::from(6))])).unwrap(),
+            6
+        );
+        assert!(decode_decimals(&encode(&[Token::Uint(U256::from(256))])).is_err());
+
+        assert!(decode_success(&encode(&[Token::Bool(true)])).unwrap());
+        assert!(!decode_success(&encode(&[Token::Bool(false)])).unwrap());
+        assert!(decode_success(&[]).unwrap());
+        assert!(decode_amount(&[]).is_err());
+    }
+
+    #[test]
+    fn test_transfer_event_roundtrip() {
+        let event = TransferEvent {
+            token: parse_address("0xdac17f958d2ee523a2206206994597c13d831ec7").unwrap(),
+            from: address(0x11),
+            to: address(0x22),
+            value: U256::from(42),
+        };
+        let log = event.to_log();
+        assert_eq!(
+            format!("{:#x}", log.topics[0]),
+            "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"
+        );
+        assert_eq!(TransferEvent::from_log(&log).unwrap(), Some(event));
+
+        // Same signature with an indexed token ID, as ERC-721 emits it
+        let mut nft_log = log.clone();
+        nft_log.topics.push(address_word(address(0x33)));
+        assert_eq!(TransferEvent::from_log(&nft_log).unwrap(), None);
+
+        let other = Log {
+            topics: vec![event_topic("Approval(address,address,uint256)")],
+            ..log
+        };
+        assert_eq!(TransferEvent::from_log(&other).unwrap(), None);
+    }
+}
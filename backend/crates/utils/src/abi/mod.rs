@@ -0,0 +1,339 @@
+//! Solidity ABI encoding of contract calls, return values and events.
+//!
+//! Only the types the wallet service exchanges with contracts are supported: addresses,
+//! `uint256`, `bool`, fixed-size byte arrays, `bytes` and `string`. Decoding is strict, so
+//! values with dirty padding or offsets pointing outside the data are rejected.
+
+pub mod erc20;
+pub mod revert;
+
+use app_error::{AppError, AppResult};
+
+use crate::transaction::{H160, H256, U256, keccak256};
+
+/// Size of an ABI word
+const WORD: usize = 32;
+
+/// Type of a parameter, used to decode a value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamType {
+    Address,
+    Uint,
+    Bool,
+    /// `bytes1` to `bytes32`
+    FixedBytes(usize),
+    Bytes,
+    String,
+}
+
+/// A value passed to or returned from a contract
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Address(H160),
+    Uint(U256),
+    Bool(bool),
+    FixedBytes(Vec<u8>),
+    Bytes(Vec<u8>),
+    String(String),
+}
+
+fn invalid(message: &str) -> AppError {
+    AppError::ValidationError(format!("Invalid ABI data: {}", message))
+}
+
+impl Token {
+    fn is_dynamic(&self) -> bool {
+        matches!(self, Token::Bytes(_) | Token::String(_))
+    }
+
+    pub fn as_address(&self) -> Option<H160> {
+        match self {
+            Token::Address(address) => Some(*address),
+            _ => None,
+        }
+    }
+
+    pub fn as_uint(&self) -> Option<U256> {
+        match self {
+            Token::Uint(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Token::Bool(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Token::FixedBytes(bytes) | Token::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Token::String(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+/// First four bytes of the Keccak-256 of a signature such as `transfer(address,uint256)`
+pub fn selector(signature: &str) -> [u8; 4] {
+    let hash = keccak256(signature.as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Topic identifying an event, the Keccak-256 of its signature
+pub fn event_topic(signature: &str) -> H256 {
+    keccak256(signature.as_bytes())
+}
+
+/// Calldata for a call of the function with `signature`
+pub fn encode_call(signature: &str, tokens: &[Token]) -> Vec<u8> {
+    let mut data = selector(signature).to_vec();
+    data.extend(encode(tokens));
+    data
+}
+
+fn uint_word(value: U256) -> [u8; WORD] {
+    value.to_big_endian()
+}
+
+fn usize_word(value: usize) -> [u8; WORD] {
+    uint_word(U256::from(value))
+}
+
+// Bytes padded with zeros to a whole number of words
+fn padded(bytes: &[u8]) -> Vec<u8> {
+    let mut out = bytes.to_vec();
+    out.resize(bytes.len().div_ceil(WORD) * WORD, 0);
+    out
+}
+
+/// An address as it appears in a word: left-padded with zeros, as in event topics
+pub fn address_word(address: H160) -> H256 {
+    let mut word = [0u8; WORD];
+    word[12..].copy_from_slice(address.as_bytes());
+    H256(word)
+}
+
+fn encode_head(token: &Token) -> [u8; WORD] {
+    match token {
+        Token::Address(address) => address_word(*address).0,
+        Token::Uint(value) => uint_word(*value),
+        Token::Bool(value) => usize_word(usize::from(*value)),
+        Token::FixedBytes(bytes) => {
+            let mut word = [0u8; WORD];
+            word[..bytes.len()].copy_from_slice(bytes);
+            word
+        }
+        Token::Bytes(_) | Token::String(_) => unreachable!("dynamic values have no static head"),
+    }
+}
+
+fn encode_tail(token: &Token) -> Vec<u8> {
+    let bytes = match token {
+        Token::Bytes(bytes) => bytes.as_slice(),
+        Token::String(value) => value.as_bytes(),
+        _ => return Vec::new(),
+    };
+    let mut out = usize_word(bytes.len()).to_vec();
+    out.extend(padded(bytes));
+    out
+}
+
+/// Encode values as a tuple, the way function arguments and return values are encoded
+pub fn encode(tokens: &[Token]) -> Vec<u8> {
+    let head_size = tokens.len() * WORD;
+    let mut head = Vec::with_capacity(head_size);
+    let mut tail = Vec::new();
+
+    for token in tokens {
+        if token.is_dynamic() {
+            // Dynamic values are stored after the heads, which point to them
+            head.extend(usize_word(head_size + tail.len()));
+            tail.extend(encode_tail(token));
+        } else {
+            head.extend(encode_head(token));
+        }
+    }
+
+    head.extend(tail);
+    head
+}
+
+fn word_at(data: &[u8], offset: usize) -> AppResult<&[u8]> {
+    offset
+        .checked_add(WORD)
+        .and_then(|end| data.get(offset..end))
+        .ok_or_else(|| invalid("data is too short"))
+}
+
+// A word that must hold a value small enough to use as a length or offset
+fn read_usize(data: &[u8], offset: usize) -> AppResult<usize> {
+    let value = U256::from_big_endian(word_at(data, offset)?);
+    if value > U256::from(u32::MAX) {
+        return Err(invalid("length or offset is too large"));
+    }
+    Ok(value.as_usize())
+}
+
+fn decode_value(param: ParamType, data: &[u8], offset: usize) -> AppResult<Token> {
+    let word = word_at(data, offset)?;
+    match param {
+        ParamType::Address => {
+            if word[..12].iter().any(|byte| *byte != 0) {
+                return Err(invalid("address has dirty padding"));
+            }
+            Ok(Token::Address(H160::from_slice(&word[12..])))
+        }
+        ParamType::Uint => Ok(Token::Uint(U256::from_big_endian(word))),
+        ParamType::Bool => match U256::from_big_endian(word) {
+            value if value.is_zero() => Ok(Token::Bool(false)),
+            value if value == U256::one() => Ok(Token::Bool(true)),
+            _ => Err(invalid("bool is neither 0 nor 1")),
+        },
+        ParamType::FixedBytes(size) => {
+            if size == 0 || size > WORD {
+                return Err(invalid("fixed bytes must be 1 to 32 bytes long"));
+            }
+            if word[size..].iter().any(|byte| *byte != 0) {
+                return Err(invalid("fixed bytes have dirty padding"));
+            }
+            Ok(Token::FixedBytes(word[..size].to_vec()))
+        }
+        ParamType::Bytes | ParamType::String => {
+            let start = read_usize(data, offset)?;
+            let length = read_usize(data, start)?;
+            let bytes = (start + WORD)
+                .checked_add(length)
+                .and_then(|end| data.get(start + WORD..end))
+                .ok_or_else(|| invalid("value is longer than the data"))?
+                .to_vec();
+            if param == ParamType::Bytes {
+                Ok(Token::Bytes(bytes))
+            } else {
+                String::from_utf8(bytes)
+                    .map(Token::String)
+                    .map_err(|_| invalid("string is not UTF-8"))
+            }
+        }
+    }
+}
+
+/// Decode a tuple of values of the given types, such as a function's return values
+pub fn decode(params: &[ParamType], data: &[u8]) -> AppResult<Vec<Token>> {
+    params
+        .iter()
+        .enumerate()
+        .map(|(index, param)| decode_value(*param, data, index * WORD))
+        .collect()
+}
+
+/// Split calldata into its selector and encoded arguments
+pub fn split_selector(data: &[u8]) -> Option<([u8; 4], &[u8])> {
+    let selector = data.get(..4)?.try_into().ok()?;
+    Some((selector, &data[4..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transaction::parse_address;
+
+    #[test]
+    fn test_selectors() {
+        assert_eq!(
+            hex::encode(selector("transfer(address,uint256)")),
+            "a9059cbb"
+        );
+        assert_eq!(hex::encode(selector("balanceOf(address)")), "70a08231");
+        assert_eq!(
+            format!("{:#x}", event_topic("Transfer(address,address,uint256)")),
+            "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef"
+        );
+    }
+
+    #[test]
+    fn test_encode_static_values() {
+        let to = parse_address("0x3535353535353535353535353535353535353535").unwrap();
+        let data = encode_call(
+            "transfer(address,uint256)",
+            &[Token::Address(to), Token::Uint(U256::from(1000))],
+        );
+        assert_eq!(
+            hex::encode(&data),
+            concat!(
+                "a9059cbb",
+                "0000000000000000000000003535353535353535353535353535353535353535",
+                "00000000000000000000000000000000000000000000000000000000000003e8",
+            )
+        );
+
+        let (_, arguments) = split_selector(&data).unwrap();
+        let decoded = decode(&[ParamType::Address, ParamType::Uint], arguments).unwrap();
+        assert_eq!(decoded[0].as_address(), Some(to));
+        assert_eq!(decoded[1].as_uint(), Some(U256::from(1000)));
+    }
+
+    #[test]
+    fn test_encode_dynamic_values() {
+        // Static values first, then the dynamic ones the heads point to
+        let tokens = [
+            Token::Uint(U256::from(0x123)),
+            Token::FixedBytes(b"1234567890".to_vec()),
+            Token::Bytes(b"Hello, world!".to_vec()),
+            Token::String("reason".to_string()),
+        ];
+        let encoded = encode(&tokens);
+        assert_eq!(
+            hex::encode(&encoded),
+            concat!(
+                "0000000000000000000000000000000000000000000000000000000000000123",
+                "3132333435363738393000000000000000000000000000000000000000000000",
+                "0000000000000000000000000000000000000000000000000000000000000080",
+                "00000000000000000000000000000000000000000000000000000000000000c0",
+                "000000000000000000000000000000000000000000000000000000000000000d",
+                "48656c6c6f2c20776f726c642100000000000000000000000000000000000000",
+                "0000000000000000000000000000000000000000000000000000000000000006",
+                "726561736f6e0000000000000000000000000000000000000000000000000000",
+            )
+        );
+
+        let params = [
+            ParamType::Uint,
+            ParamType::FixedBytes(10),
+            ParamType::Bytes,
+            ParamType::String,
+        ];
+        assert_eq!(decode(&params, &encoded).unwrap(), tokens);
+    }
+
+    #[test]
+    fn test_rejects_malformed_data() {
+        let mut dirty_address = [0u8; 32];
+        dirty_address[0] = 1;
+        assert!(decode(&[ParamType::Address], &dirty_address).is_err());
+
+        let two = uint_word(U256::from(2));
+        assert!(decode(&[ParamType::Bool], &two).is_err());
+
+        // Too short for a word
+        assert!(decode(&[ParamType::Uint], &[0u8; 31]).is_err());
+
+        // String offset pointing past the end
+        let far_offset = uint_word(U256::from(0x1000));
+        assert!(decode(&[ParamType::String], &far_offset).is_err());
+
+        // Length longer than the data that follows
+        let mut short_string = usize_word(WORD).to_vec();
+        short_string.extend(usize_word(10));
+        short_string.extend([b'a'; 5]);
+        assert!(decode(&[ParamType::String], &short_string).is_err());
+    }
+}
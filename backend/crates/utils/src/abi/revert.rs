@@ -0,0 +1,497 @@
+//! Reasons contracts revert with.
+//!
+//! Revert data is encoded like a call: a selector followed by the error's arguments.
+//! Besides Solidity's `Error(string)` and `Panic(uint256)`, the custom errors of our
+//! `Role.sol` contracts and of the OpenZeppelin contracts they build on are recognised.
+
+use app_error::RevertDetails;
+use std::fmt;
+
+use super::{ParamType, Token, decode, encode_call, split_selector};
+use crate::transaction::{H160, H256, U256, format_address, keccak256};
+
+pub const ERROR: &str = "Error(string)";
+pub const PANIC: &str = "Panic(uint256)";
+pub const ZERO_ADDRESS_PROVIDED: &str = "ZeroAddressProvided(string)";
+pub const INVALID_NUMBER: &str = "InvalidNumber(string,uint256)";
+pub const AMOUNT_EXCEEDS_CAPACITY: &str = "AmountExceedsCapacity(uint256,uint256,string)";
+pub const CAP_IS_NOT_UPDATE: &str = "CapIsNotUpdate(uint256,uint256,string)";
+pub const ACCESS_CONTROL_UNAUTHORIZED_ACCOUNT: &str =
+    "AccessControlUnauthorizedAccount(address,bytes32)";
+pub const ACCESS_CONTROL_BAD_CONFIRMATION: &str = "AccessControlBadConfirmation()";
+pub const ENFORCED_PAUSE: &str = "EnforcedPause()";
+pub const EXPECTED_PAUSE: &str = "ExpectedPause()";
+pub const REENTRANT_CALL: &str = "ReentrancyGuardReentrantCall()";
+
+/// Roles granted by `AdvaRoleController`, besides the zero `DEFAULT_ADMIN_ROLE`
+const ROLES: &[&str] = &[
+    "ADMIN_ROLE",
+    "CAPPER_ROLE",
+    "RECOVER_ROLE",
+    "BANNER_ROLE",
+    "PAUASER_ROLE",
+];
+
+/// Why a call reverted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RevertReason {
+    /// `revert()` or a `require` without a message
+    Empty,
+    /// `revert("...")` or a `require` with a message
+    Error(String),
+    /// A failed `assert` or a check the compiler inserts, such as for overflows
+    Panic(U256),
+    ZeroAddressProvided {
+        reason: String,
+    },
+    InvalidNumber {
+        reason: String,
+        provided_value: U256,
+    },
+    AmountExceedsCapacity {
+        amount: U256,
+        current_capacity: U256,
+        reason: String,
+    },
+    CapIsNotUpdate {
+        amount: U256,
+        current_capacity: U256,
+        reason: String,
+    },
+    AccessControlUnauthorizedAccount {
+        account: H160,
+        needed_role: H256,
+    },
+    AccessControlBadConfirmation,
+    EnforcedPause,
+    ExpectedPause,
+    ReentrantCall,
+    /// Data of an error not known here, or that does not match its signature
+    Unknown(Vec<u8>),
+}
+
+/// Revert data of `revert(reason)`
+pub fn error_data(reason: &str) -> Vec<u8> {
+    encode_call(ERROR, &[Token::String(reason.to_string())])
+}
+
+/// What a `Panic(uint256)` code means
+fn panic_description(code: U256) -> &'static str {
+    if code > U256::from(u8::MAX) {
+        return "unknown panic";
+    }
+    match code.low_u32() {
+        0x00 => "generic compiler panic",
+        0x01 => "assertion failed",
+        0x11 => "arithmetic overflow or underflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "invalid enum value",
+        0x22 => "invalid storage byte array",
+        0x31 => "pop from an empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to an uninitialized function",
+        _ => "unknown panic",
+    }
+}
+
+/// Name of a role of `AdvaRoleController`, if it is one
+fn role_name(role: H256) -> Option<&'static str> {
+    if role.is_zero() {
+        return Some("DEFAULT_ADMIN_ROLE");
+    }
+    ROLES
+        .iter()
+        .find(|name| keccak256(name.as_bytes()) == role)
+        .copied()
+}
+
+impl RevertReason {
+    /// Decode revert data; data that matches no known error is kept as `Unknown`
+    pub fn decode(data: &[u8]) -> Self {
+        if data.is_empty() {
+            return Self::Empty;
+        }
+        Self::decode_known(data).unwrap_or_else(|| Self::Unknown(data.to_vec()))
+    }
+
+    fn decode_known(data: &[u8]) -> Option<Self> {
+        let (selector, arguments) = split_selector(data)?;
+        let is = |signature: &str| selector == super::selector(signature);
+        let args = |params: &[ParamType]| decode(params, arguments).ok();
+        let string = |token: &Token| token.as_str().map(str::to_string);
+
+        let reason = if is(ERROR) {
+            let tokens = args(&[ParamType::String])?;
+            Self::Error(string(&tokens[0])?)
+        } else if is(PANIC) {
+            let tokens = args(&[ParamType::Uint])?;
+            Self::Panic(tokens[0].as_uint()?)
+        } else if is(ZERO_ADDRESS_PROVIDED) {
+            let tokens = args(&[ParamType::String])?;
+            Self::ZeroAddressProvided {
+                reason: string(&tokens[0])?,
+            }
+        } else if is(INVALID_NUMBER) {
+            let tokens = args(&[ParamType::String, ParamType::Uint])?;
+            Self::InvalidNumber {
+                reason: string(&tokens[0])?,
+                provided_value: tokens[1].as_uint()?,
+            }
+        } else if is(AMOUNT_EXCEEDS_CAPACITY) || is(CAP_IS_NOT_UPDATE) {
+            let tokens = args(&[ParamType::Uint, ParamType::Uint, ParamType::String])?;
+            let (amount, current_capacity, reason) = (
+                tokens[0].as_uint()?,
+                tokens[1].as_uint()?,
+                string(&tokens[2])?,
+            );
+            if is(AMOUNT_EXCEEDS_CAPACITY) {
+                Self::AmountExceedsCapacity {
+                    amount,
+                    current_capacity,
+                    reason,
+                }
+            } else {
+                Self::CapIsNotUpdate {
+                    amount,
+                    current_capacity,
+                    reason,
+                }
+            }
+        } else if is(ACCESS_CONTROL_UNAUTHORIZED_ACCOUNT) {
+            let tokens = args(&[ParamType::Address, ParamType::FixedBytes(32)])?;
+            Self::AccessControlUnauthorizedAccount {
+                account: tokens[0].as_address()?,
+                needed_role: H256::from_slice(tokens[1].as_bytes()?),
+            }
+        } else if is(ACCESS_CONTROL_BAD_CONFIRMATION) {
+            Self::AccessControlBadConfirmation
+        } else if is(ENFORCED_PAUSE) {
+            Self::EnforcedPause
+        } else if is(EXPECTED_PAUSE) {
+            Self::ExpectedPause
+        } else if is(REENTRANT_CALL) {
+            Self::ReentrantCall
+        } else {
+            return None;
+        };
+        Some(reason)
+    }
+
+    /// Name of the error, as declared in Solidity
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Empty => "Empty",
+            Self::Error(_) => "Error",
+            Self::Panic(_) => "Panic",
+            Self::ZeroAddressProvided { .. } => "ZeroAddressProvided",
+            Self::InvalidNumber { .. } => "InvalidNumber",
+            Self::AmountExceedsCapacity { .. } => "AmountExceedsCapacity",
+            Self::CapIsNotUpdate { .. } => "CapIsNotUpdate",
+            Self::AccessControlUnauthorizedAccount { .. } => "AccessControlUnauthorizedAccount",
+            Self::AccessControlBadConfirmation => "AccessControlBadConfirmation",
+            Self::EnforcedPause => "EnforcedPause",
+            Self::ExpectedPause => "ExpectedPause",
+            Self::ReentrantCall => "ReentrancyGuardReentrantCall",
+            Self::Unknown(_) => "Unknown",
+        }
+    }
+
+    /// Arguments of the error by their Solidity parameter names
+    pub fn arguments(&self) -> Vec<(&'static str, String)> {
+        match self {
+            Self::Empty
+            | Self::AccessControlBadConfirmation
+            | Self::EnforcedPause
+            | Self::ExpectedPause
+            | Self::ReentrantCall => Vec::new(),
+            Self::Error(reason) | Self::ZeroAddressProvided { reason } => {
+                vec![("reason", reason.clone())]
+            }
+            Self::Panic(code) => vec![("code", format!("{:#04x}", code))],
+            Self::InvalidNumber {
+                reason,
+                provided_value,
+            } => vec![
+                ("reason", reason.clone()),
+                ("providedValue", provided_value.to_string()),
+            ],
+            Self::AmountExceedsCapacity {
+                amount,
+                current_capacity,
+                reason,
+            }
+            | Self::CapIsNotUpdate {
+                amount,
+                current_capacity,
+                reason,
+            } => vec![
+                ("amount", amount.to_string()),
+                ("currentCapacity", current_capacity.to_string()),
+                ("reason", reason.clone()),
+            ],
+            Self::AccessControlUnauthorizedAccount {
+                account,
+                needed_role,
+            } => vec![
+                ("account", format_address(account)),
+                (
+                    "neededRole",
+                    role_name(*needed_role)
+                        .map(str::to_string)
+                        .unwrap_or_else(|| format!("{:#x}", needed_role)),
+                ),
+            ],
+            Self::Unknown(data) => vec![("data", format!("0x{}", hex::encode(data)))],
+        }
+    }
+
+    /// Describe a revert of `action`, such as "USDT transfer", for an `AppError::RevertError`
+    pub fn details(&self, action: &str) -> RevertDetails {
+        RevertDetails {
+            action: action.to_string(),
+            error_name: self.name().to_string(),
+            arguments: self
+                .arguments()
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+            reason: self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for RevertReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "reverted without a reason"),
+            Self::Error(reason) => write!(f, "{}", reason),
+            Self::Panic(code) => write!(f, "panic {:#04x}: {}", code, panic_description(*code)),
+            Self::ZeroAddressProvided { reason } => write!(f, "{}: {}", self.name(), reason),
+            Self::InvalidNumber {
+                reason,
+                provided_value,
+            } => write!(f, "{}: {} (got {})", self.name(), reason, provided_value),
+            Self::AmountExceedsCapacity {
+                amount,
+                current_capacity,
+                reason,
+            }
+            | Self::CapIsNotUpdate {
+                amount,
+                current_capacity,
+                reason,
+            } => write!(
+                f,
+                "{}: {} (amount {}, capacity {})",
+                self.name(),
+                reason,
+                amount,
+                current_capacity
+            ),
+            Self::AccessControlUnauthorizedAccount {
+                account,
+                needed_role,
+            } => match role_name(*needed_role) {
+                Some(role) => write!(
+                    f,
+                    "{}: {} is missing {}",
+                    self.name(),
+                    format_address(account),
+                    role
+                ),
+                None => write!(
+                    f,
+                    "{}: {} is missing role {:#x}",
+                    self.name(),
+                    format_address(account),
+                    needed_role
+                ),
+            },
+            Self::AccessControlBadConfirmation => write!(
+                f,
+                "{}: roles can only be renounced by their holder",
+                self.name()
+            ),
+            Self::EnforcedPause => write!(f, "{}: the contract is paused", self.name()),
+            Self::ExpectedPause => write!(f, "{}: the contract is not paused", self.name()),
+            Self::ReentrantCall => write!(f, "{}: reentrant call", self.name()),
+            Self::Unknown(data) => match split_selector(data) {
+                Some((selector, _)) => {
+                    write!(f, "unknown error 0x{}", hex::encode(selector))
+                }
+                None => write!(f, "malformed revert data 0x{}", hex::encode(data)),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abi::{encode, selector};
+
+    #[test]
+    fn test_standard_reverts() {
+        let data = error_data("ERC20: transfer amount exceeds balance");
+        assert_eq!(hex::encode(&data[..4]), "08c379a0");
+        let reason = RevertReason::decode(&data);
+        assert_eq!(
+            reason,
+            RevertReason::Error("ERC20: transfer amount exceeds balance".to_string())
+        );
+        assert_eq!(reason.to_string(), "ERC20: transfer amount exceeds balance");
+
+        let data = encode_call(PANIC, &[Token::Uint(U256::from(0x11))]);
+        assert_eq!(hex::encode(&data[..4]), "4e487b71");
+        let reason = RevertReason::decode(&data);
+        assert_eq!(reason, RevertReason::Panic(U256::from(0x11)));
+        assert_eq!(
+            reason.to_string(),
+            "panic 0x11: arithmetic overflow or underflow"
+        );
+
+        let data = encode_call(PANIC, &[Token::Uint(U256::one())]);
+        assert_eq!(
+            RevertReason::decode(&data).to_string(),
+            "panic 0x01: assertion failed"
+        );
+
+        assert_eq!(RevertReason::decode(&[]), RevertReason::Empty);
+    }
+
+    #[test]
+    fn test_role_errors() {
+        let data = encode_call(
+            AMOUNT_EXCEEDS_CAPACITY,
+            &[
+                Token::Uint(U256::from(500)),
+                Token::Uint(U256::from(100)),
+                Token::String("Requested amount exceeds available capacity".to_string()),
+            ],
+        );
+        let reason = RevertReason::decode(&data);
+        assert_eq!(
+            reason,
+            RevertReason::AmountExceedsCapacity {
+                amount: U256::from(500),
+                current_capacity: U256::from(100),
+                reason: "Requested amount exceeds available capacity".to_string(),
+            }
+        );
+        assert_eq!(
+            reason.to_string(),
+            "AmountExceedsCapacity: Requested amount exceeds available capacity (amount 500, capacity 100)"
+        );
+        let details = reason.details("USDT transfer");
+        assert_eq!(details.error_name, "AmountExceedsCapacity");
+        assert_eq!(
+            details.arguments,
+            vec![
+                ("amount".to_string(), "500".to_string()),
+                ("currentCapacity".to_string(), "100".to_string()),
+                (
+                    "reason".to_string(),
+                    "Requested amount exceeds available capacity".to_string()
+                ),
+            ]
+        );
+
+        let data = encode_call(
+            CAP_IS_NOT_UPDATE,
+            &[
+                Token::Uint(U256::from(100)),
+                Token::Uint(U256::from(100)),
+                Token::String("Requested amount same as capacity".to_string()),
+            ],
+        );
+        assert_eq!(RevertReason::decode(&data).name(), "CapIsNotUpdate");
+
+        let data = encode_call(
+            ZERO_ADDRESS_PROVIDED,
+            &[Token::String("Address cannot be zero".to_string())],
+        );
+        assert_eq!(
+            RevertReason::decode(&data).to_string(),
+            "ZeroAddressProvided: Address cannot be zero"
+        );
+
+        let data = encode_call(
+            INVALID_NUMBER,
+            &[
+                Token::String("Amount must be greater than zero".to_string()),
+                Token::Uint(U256::zero()),
+            ],
+        );
+        assert_eq!(
+            RevertReason::decode(&data),
+            RevertReason::InvalidNumber {
+                reason: "Amount must be greater than zero".to_string(),
+                provided_value: U256::zero(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_access_control_errors() {
+        let account = H160::repeat_byte(0x11);
+        let data = encode_call(
+            ACCESS_CONTROL_UNAUTHORIZED_ACCOUNT,
+            &[
+                Token::Address(account),
+                Token::FixedBytes(keccak256(b"CAPPER_ROLE").0.to_vec()),
+            ],
+        );
+        assert_eq!(hex::encode(&data[..4]), "e2517d3f");
+        assert_eq!(
+            RevertReason::decode(&data).to_string(),
+            "AccessControlUnauthorizedAccount: 0x1111111111111111111111111111111111111111 is missing CAPPER_ROLE"
+        );
+
+        let data = encode_call(
+            ACCESS_CONTROL_UNAUTHORIZED_ACCOUNT,
+            &[Token::Address(account), Token::FixedBytes(vec![0; 32])],
+        );
+        assert!(
+            RevertReason::decode(&data)
+                .to_string()
+                .ends_with("is missing DEFAULT_ADMIN_ROLE")
+        );
+
+        assert_eq!(
+            RevertReason::decode(&selector(ACCESS_CONTROL_BAD_CONFIRMATION)),
+            RevertReason::AccessControlBadConfirmation
+        );
+        assert_eq!(
+            RevertReason::decode(&selector(ENFORCED_PAUSE)),
+            RevertReason::EnforcedPause
+        );
+    }
+
+    #[test]
+    fn test_unknown_reverts() {
+        // An error declared elsewhere
+        let data = encode_call("Unauthorized()", &[]);
+        assert_eq!(
+            RevertReason::decode(&data),
+            RevertReason::Unknown(data.clone())
+        );
+        assert_eq!(
+            RevertReason::decode(&data).to_string(),
+            format!("unknown error 0x{}", hex::encode(&data))
+        );
+
+        // A known selector whose arguments do not decode
+        let mut data = selector(ERROR).to_vec();
+        data.extend(encode(&[Token::Uint(U256::from(1))]));
+        assert!(matches!(
+            RevertReason::decode(&data),
+            RevertReason::Unknown(_)
+        ));
+
+        assert_eq!(
+            RevertReason::decode(&[0xde, 0xad]).to_string(),
+            "malformed revert data 0xdead"
+        );
+    }
+}
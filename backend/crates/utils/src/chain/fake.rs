@@ -0,0 +1,1315 @@
+use app_error::{AppError, AppResult};
+use async_trait::async_trait;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard};
+use tiny_keccak::{Hasher, Keccak};
+
+use super::{
+    BlockTag, CallOutcome, CallRequest, ChainClient, FeeHistory, Log, Revert, TransactionReceipt,
+};
+use crate::abi::erc20::{self, TransferEvent};
+use crate::abi::revert::{RevertReason, error_data};
+use crate::abi::{ParamType, Token, decode, encode, selector, split_selector};
+use crate::transaction::{H160, H256, SignedTransaction, Transaction, U256};
+
+// Gas every transaction pays before execution, plus the cost of its calldata (EIP-2028)
+const TX_BASE_GAS: u64 = 21_000;
+const TX_CREATE_GAS: u64 = 32_000;
+const TX_DATA_ZERO_GAS: u64 = 4;
+const TX_DATA_NON_ZERO_GAS: u64 = 16;
+
+// Gas a token call uses on top of the intrinsic gas, about what an ERC-20 transfer costs
+const TOKEN_CALL_GAS: u64 = 35_000;
+
+const DEFAULT_BASE_FEE_PER_GAS: u64 = 1_000_000_000;
+const DEFAULT_PRIORITY_FEE_PER_GAS: u64 = 1_000_000_000;
+
+// Share of each block's gas limit the fee history reports as used
+const GAS_USED_RATIO: f64 = 0.5;
+
+// Recent blocks kept so a reorg can replace them
+const MAX_REORG_DEPTH: usize = 64;
+
+// How much both fee caps must rise for a transaction to replace a pending one
+const PRICE_BUMP_PERCENT: u64 = 10;
+
+/// Whether a replacement's fee is enough higher than the fee of the transaction it replaces
+fn is_bumped(current: U256, replacement: U256) -> bool {
+    replacement.saturating_mul(U256::from(100))
+        >= current.saturating_mul(U256::from(100 + PRICE_BUMP_PERCENT))
+}
+
+fn rejected(message: &str) -> AppError {
+    AppError::ChainError(message.to_string())
+}
+
+/// A revert with the message a node gives it, which only spells out `Error(string)` reasons
+fn reverted(data: Vec<u8>) -> Revert {
+    let message = match RevertReason::decode(&data) {
+        RevertReason::Error(reason) => format!("execution reverted: {}", reason),
+        _ => "execution reverted".to_string(),
+    };
+    Revert { message, data }
+}
+
+/// Gas a transaction uses before executing any code
+fn intrinsic_gas(to: Option<H160>, data: &[u8]) -> u64 {
+    let data_gas: u64 = data
+        .iter()
+        .map(|byte| {
+            if *byte == 0 {
+                TX_DATA_ZERO_GAS
+            } else {
+                TX_DATA_NON_ZERO_GAS
+            }
+        })
+        .sum();
+    let create_gas = if to.is_none() { TX_CREATE_GAS } else { 0 };
+    TX_BASE_GAS + create_gas + data_gas
+}
+
+/// Hash of a block; blocks that replace others in a reorg are on a new fork
+fn block_hash(number: u64, fork: u64) -> H256 {
+    let mut hasher = Keccak::v256();
+    hasher.update(b"fake-chain-block");
+    hasher.update(&number.to_be_bytes());
+    hasher.update(&fork.to_be_bytes());
+    let mut hash = [0u8; 32];
+    hasher.finalize(&mut hash);
+    H256(hash)
+}
+
+/// Price per gas a transaction pays in a block with the given base fee
+fn effective_gas_price(transaction: &Transaction, base_fee_per_gas: U256) -> U256 {
+    match transaction {
+        Transaction::DynamicFee(tx) => tx
+            .max_fee_per_gas
+            .min(base_fee_per_gas.saturating_add(tx.max_priority_fee_per_gas)),
+        _ => transaction.max_fee_per_gas(),
+    }
+}
+
+/// An ERC-20 token contract, executed natively
+#[derive(Clone, Default)]
+struct FakeToken {
+    decimals: u8,
+    balances: HashMap<H160, U256>,
+    allowances: HashMap<(H160, H160), U256>,
+    /// Revert data every `transfer` fails with, as when the token is paused or capped
+    transfer_revert: Option<Vec<u8>>,
+}
+
+fn arguments(params: &[ParamType], data: &[u8]) -> Result<Vec<Token>, Vec<u8>> {
+    decode(params, data).map_err(|_| Vec::new())
+}
+
+impl FakeToken {
+    fn balance(&self, holder: &H160) -> U256 {
+        self.balances.get(holder).copied().unwrap_or_default()
+    }
+
+    /// Run a call of the token at `address` from `caller`, returning its output and
+    /// logs, or the revert data
+    fn call(
+        &mut self,
+        address: H160,
+        caller: H160,
+        data: &[u8],
+    ) -> Result<(Vec<u8>, Vec<Log>), Vec<u8>> {
+        let (function, data) = split_selector(data).ok_or_else(Vec::new)?;
+
+        if function == selector(erc20::BALANCE_OF) {
+            let arguments = arguments(&[ParamType::Address], data)?;
+            let balance = arguments[0]
+                .as_address()
+                .map(|holder| self.balance(&holder))
+                .unwrap_or_default();
+            Ok((encode(&[Token::Uint(balance)]), Vec::new()))
+        } else if function == selector(erc20::DECIMALS) {
+            let decimals = U256::from(self.decimals);
+            Ok((encode(&[Token::Uint(decimals)]), Vec::new()))
+        } else if function == selector(erc20::ALLOWANCE) {
+            let arguments = arguments(&[ParamType::Address, ParamType::Address], data)?;
+            let allowance = arguments[0]
+                .as_address()
+                .zip(arguments[1].as_address())
+                .and_then(|key| self.allowances.get(&key).copied())
+                .unwrap_or_default();
+            Ok((encode(&[Token::Uint(allowance)]), Vec::new()))
+        } else if function == selector(erc20::APPROVE) {
+            let arguments = arguments(&[ParamType::Address, ParamType::Uint], data)?;
+            if let (Some(spender), Some(amount)) =
+                (arguments[0].as_address(), arguments[1].as_uint())
+            {
+                self.allowances.insert((caller, spender), amount);
+            }
+            Ok((encode(&[Token::Bool(true)]), Vec::new()))
+        } else if function == selector(erc20::TRANSFER) {
+            if let Some(revert) = &self.transfer_revert {
+                return Err(revert.clone());
+            }
+            let arguments = arguments(&[ParamType::Address, ParamType::Uint], data)?;
+            let (Some(to), Some(value)) = (arguments[0].as_address(), arguments[1].as_uint())
+            else {
+                return Err(Vec::new());
+            };
+            if to.is_zero() {
+                return Err(error_data("ERC20: transfer to the zero address"));
+            }
+            let balance = self.balance(&caller);
+            if balance < value {
+                return Err(error_data("ERC20: transfer amount exceeds balance"));
+            }
+
+            self.balances.insert(caller, balance - value);
+            let credited = self.balance(&to).saturating_add(value);
+            self.balances.insert(to, credited);
+
+            let event = TransferEvent {
+                token: address,
+                from: caller,
+                to,
+                value,
+            };
+            Ok((encode(&[Token::Bool(true)]), vec![event.to_log()]))
+        } else {
+            Err(Vec::new())
+        }
+    }
+}
+
+/// State that mining a block changes, kept for recent blocks to undo them in a reorg
+#[derive(Clone)]
+struct Snapshot {
+    balances: HashMap<H160, U256>,
+    nonces: HashMap<H160, u64>,
+    receipts: HashMap<H256, TransactionReceipt>,
+    tokens: HashMap<H160, FakeToken>,
+}
+
+struct MinedBlock {
+    transactions: Vec<(H160, SignedTransaction)>,
+    /// State before the block was mined
+    parent: Snapshot,
+}
+
+/// An in-process chain for tests and local development.
+///
+/// Plain value transfers are checked and executed the way a node would: signature, chain
+/// ID, nonce, fee cap and balance are validated, gas is charged at the effective price and
+/// every mined transaction gets a receipt. ERC-20 tokens put in place with `deploy_token`
+/// answer `balanceOf`, `decimals`, `allowance`, `approve` and `transfer` and emit
+/// `Transfer` events; other contracts are not executed and `eth_call` answers with
+/// results registered through `set_call_result`. Only the current state is kept, so every
+/// block tag reads it. Transactions are mined as soon as they are sent unless automine is
+/// turned off; ones behind a nonce gap wait in the pool until it is filled, and a pending
+/// transaction is replaced by one with the same nonce and fees at least 10% higher.
+/// `reorg` replaces recent blocks the way a switch to a competing fork does.
+pub struct FakeChain {
+    chain_id: u64,
+    state: Mutex<ChainState>,
+}
+
+struct ChainState {
+    block_number: u64,
+    base_fee_per_gas: U256,
+    priority_fee_per_gas: U256,
+    automine: bool,
+    balances: HashMap<H160, U256>,
+    nonces: HashMap<H160, u64>,
+    pending: Vec<(H160, SignedTransaction)>,
+    receipts: HashMap<H256, TransactionReceipt>,
+    call_results: HashMap<(H160, Vec<u8>), Vec<u8>>,
+    tokens: HashMap<H160, FakeToken>,
+    /// Hashes of the canonical chain's blocks, by number
+    block_hashes: Vec<H256>,
+    recent_blocks: VecDeque<MinedBlock>,
+    /// Bumped by every reorg so replacement blocks get new hashes
+    fork: u64,
+}
+
+impl ChainState {
+    fn balance(&self, address: &H160) -> U256 {
+        self.balances.get(address).copied().unwrap_or_default()
+    }
+
+    fn nonce(&self, address: &H160) -> u64 {
+        self.nonces.get(address).copied().unwrap_or_default()
+    }
+
+    /// Gas a call to `to` uses
+    fn gas_used(&self, to: Option<H160>, data: &[u8]) -> u64 {
+        let call_gas = match to {
+            Some(to) if self.tokens.contains_key(&to) => TOKEN_CALL_GAS,
+            _ => 0,
+        };
+        intrinsic_gas(to, data) + call_gas
+    }
+
+    /// Simulate a call without changing any state; `Ok(None)` if `to` is not a token
+    fn simulate_token_call(
+        &self,
+        to: Option<H160>,
+        caller: H160,
+        data: &[u8],
+    ) -> Result<Option<Vec<u8>>, Revert> {
+        let Some((address, token)) = to.and_then(|to| Some((to, self.tokens.get(&to)?))) else {
+            return Ok(None);
+        };
+        token
+            .clone()
+            .call(address, caller, data)
+            .map(|(output, _)| Some(output))
+            .map_err(reverted)
+    }
+
+    /// Nonce the next transaction of an address should use, counting pending ones up to
+    /// the first gap
+    fn next_nonce(&self, address: &H160) -> u64 {
+        let mut nonce = self.nonce(address);
+        while self.find_pending(address, nonce).is_some() {
+            nonce += 1;
+        }
+        nonce
+    }
+
+    /// Position in the pool of the pending transaction of `address` with `nonce`
+    fn find_pending(&self, address: &H160, nonce: u64) -> Option<usize> {
+        self.pending.iter().position(|(sender, pending)| {
+            sender == address && pending.transaction().nonce() == nonce
+        })
+    }
+
+    /// Whether a transaction is pending or mined
+    fn knows(&self, hash: &H256) -> bool {
+        self.receipts.contains_key(hash)
+            || self
+                .pending
+                .iter()
+                .any(|(_, pending)| pending.hash() == *hash)
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            balances: self.balances.clone(),
+            nonces: self.nonces.clone(),
+            receipts: self.receipts.clone(),
+            tokens: self.tokens.clone(),
+        }
+    }
+
+    fn restore(&mut self, snapshot: Snapshot) {
+        self.balances = snapshot.balances;
+        self.nonces = snapshot.nonces;
+        self.receipts = snapshot.receipts;
+        self.tokens = snapshot.tokens;
+    }
+
+    fn mine_block(&mut self) -> u64 {
+        // Transactions behind a nonce gap stay in the pool
+        let mut nonces: HashMap<H160, u64> = HashMap::new();
+        let mut transactions = Vec::new();
+        loop {
+            let ready = self.pending.iter().position(|(sender, pending)| {
+                let next = *nonces.entry(*sender).or_insert_with(|| self.nonce(sender));
+                pending.transaction().nonce() == next
+            });
+            let Some(index) = ready else {
+                break;
+            };
+            let (sender, signed) = self.pending.remove(index);
+            *nonces.entry(sender).or_default() += 1;
+            transactions.push((sender, signed));
+        }
+        self.mine(transactions)
+    }
+
+    fn mine(&mut self, transactions: Vec<(H160, SignedTransaction)>) -> u64 {
+        let parent = self.snapshot();
+        self.block_number += 1;
+        let number = self.block_number;
+        let hash = block_hash(number, self.fork);
+
+        for (sender, signed) in &transactions {
+            let receipt = self.execute(*sender, signed, number, hash);
+            self.receipts.insert(signed.hash(), receipt);
+        }
+
+        self.block_hashes.push(hash);
+        self.recent_blocks.push_back(MinedBlock {
+            transactions,
+            parent,
+        });
+        if self.recent_blocks.len() > MAX_REORG_DEPTH {
+            self.recent_blocks.pop_front();
+        }
+        number
+    }
+
+    fn reorg(&mut self, depth: usize) {
+        assert!(
+            depth <= self.recent_blocks.len(),
+            "only the latest {} blocks can be replaced",
+            self.recent_blocks.len()
+        );
+
+        // Undo the blocks, newest first, and return their transactions to the pool in
+        // the order they were mined
+        let mut returned = Vec::new();
+        for _ in 0..depth {
+            let Some(block) = self.recent_blocks.pop_back() else {
+                break;
+            };
+            self.restore(block.parent);
+            self.block_hashes.pop();
+            self.block_number -= 1;
+            returned.splice(0..0, block.transactions);
+        }
+        returned.append(&mut self.pending);
+        self.pending = returned;
+
+        // The competing fork is as long as the replaced chain but its blocks are empty
+        self.fork += 1;
+        for _ in 0..depth {
+            self.mine(Vec::new());
+        }
+    }
+
+    fn execute(
+        &mut self,
+        sender: H160,
+        signed: &SignedTransaction,
+        block_number: u64,
+        block_hash: H256,
+    ) -> TransactionReceipt {
+        let transaction = signed.transaction();
+        let gas_price = effective_gas_price(transaction, self.base_fee_per_gas);
+        let value = transaction.value();
+
+        // A transaction that runs out of gas uses all of it
+        let required_gas = self.gas_used(transaction.to(), transaction.data());
+        let gas_used = required_gas.min(transaction.gas_limit());
+        let fee = gas_price.saturating_mul(U256::from(gas_used));
+
+        *self.nonces.entry(sender).or_default() += 1;
+
+        // Gas is paid even when the transaction fails
+        let balance = self.balance(&sender);
+        self.balances.insert(sender, balance.saturating_sub(fee));
+        let affordable = fee.checked_add(value).is_some_and(|cost| balance >= cost);
+
+        let mut logs = Vec::new();
+        let mut status = affordable && required_gas <= transaction.gas_limit();
+        if status {
+            let token = transaction
+                .to()
+                .and_then(|to| Some((to, self.tokens.get_mut(&to)?)));
+            if let Some((address, token)) = token {
+                match token.call(address, sender, transaction.data()) {
+                    Ok((_, emitted)) => logs = emitted,
+                    Err(_) => status = false,
+                }
+            }
+        }
+
+        // A failed transaction moves no value
+        if status {
+            self.balances.insert(sender, balance - fee - value);
+            if let Some(to) = transaction.to() {
+                let credited = self.balance(&to).saturating_add(value);
+                self.balances.insert(to, credited);
+            }
+        }
+
+        TransactionReceipt {
+            transaction_hash: signed.hash(),
+            block_number,
+            block_hash,
+            from: sender,
+            to: transaction.to(),
+            status,
+            gas_used,
+            effective_gas_price: gas_price,
+            logs,
+        }
+    }
+}
+
+impl FakeChain {
+    pub fn new(chain_id: u64) -> Self {
+        Self {
+            chain_id,
+            state: Mutex::new(ChainState {
+                block_number: 0,
+                base_fee_per_gas: U256::from(DEFAULT_BASE_FEE_PER_GAS),
+                priority_fee_per_gas: U256::from(DEFAULT_PRIORITY_FEE_PER_GAS),
+                automine: true,
+                balances: HashMap::new(),
+                nonces: HashMap::new(),
+                pending: Vec::new(),
+                receipts: HashMap::new(),
+                call_results: HashMap::new(),
+                tokens: HashMap::new(),
+                block_hashes: vec![block_hash(0, 0)],
+                recent_blocks: VecDeque::new(),
+                fork: 0,
+            }),
+        }
+    }
+
+    // A panic while holding the lock leaves the state consistent, so keep using it
+    fn state(&self) -> MutexGuard<'_, ChainState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    /// Credit an address, as a faucet or genesis allocation would
+    pub fn set_balance(&self, address: H160, balance: U256) {
+        self.state().balances.insert(address, balance);
+    }
+
+    pub fn balance(&self, address: H160) -> U256 {
+        self.state().balance(&address)
+    }
+
+    pub fn set_base_fee_per_gas(&self, base_fee_per_gas: U256) {
+        self.state().base_fee_per_gas = base_fee_per_gas;
+    }
+
+    /// Median priority fee `eth_feeHistory` reports; other percentiles scale with it, so
+    /// the 25th percentile is half of it and the 75th one and a half times it
+    pub fn set_priority_fee_per_gas(&self, priority_fee_per_gas: U256) {
+        self.state().priority_fee_per_gas = priority_fee_per_gas;
+    }
+
+    /// Whether sent transactions are mined immediately or wait for `mine_block`
+    pub fn set_automine(&self, automine: bool) {
+        self.state().automine = automine;
+    }
+
+    /// Answer `eth_call`s of `data` to `to` with `result`
+    pub fn set_call_result(&self, to: H160, data: Vec<u8>, result: Vec<u8>) {
+        self.state().call_results.insert((to, data), result);
+    }
+
+    /// Put an ERC-20 token contract at `address`
+    pub fn deploy_token(&self, address: H160, decimals: u8) {
+        self.state().tokens.insert(
+            address,
+            FakeToken {
+                decimals,
+                ..Default::default()
+            },
+        );
+    }
+
+    /// Set a holder's balance of a token deployed with `deploy_token`
+    pub fn set_token_balance(&self, token: H160, holder: H160, balance: U256) {
+        self.state()
+            .tokens
+            .get_mut(&token)
+            .expect("token is not deployed")
+            .balances
+            .insert(holder, balance);
+    }
+
+    /// Make every `transfer` of a token deployed with `deploy_token` revert with `data`,
+    /// or behave normally again with `None`
+    pub fn set_transfer_revert(&self, token: H160, data: Option<Vec<u8>>) {
+        self.state()
+            .tokens
+            .get_mut(&token)
+            .expect("token is not deployed")
+            .transfer_revert = data;
+    }
+
+    pub fn token_balance(&self, token: H160, holder: H160) -> U256 {
+        self.state()
+            .tokens
+            .get(&token)
+            .map(|token| token.balance(&holder))
+            .unwrap_or_default()
+    }
+
+    /// Transactions sent but not yet mined
+    pub fn pending_count(&self) -> usize {
+        self.state().pending.len()
+    }
+
+    /// Mine the pending transactions into a new block and return its number
+    pub fn mine_block(&self) -> u64 {
+        self.state().mine_block()
+    }
+
+    /// Replace the latest `depth` blocks with as many empty blocks on a new fork. The
+    /// transactions of the replaced blocks go back to the pending pool, to be mined again
+    /// by `mine_block` or removed with `drop_transaction`.
+    pub fn reorg(&self, depth: usize) {
+        self.state().reorg(depth)
+    }
+
+    /// Remove a transaction from the pending pool, as a node evicting it would, and
+    /// return whether it was there
+    pub fn drop_transaction(&self, hash: H256) -> bool {
+        let mut state = self.state();
+        let count = state.pending.len();
+        state.pending.retain(|(_, pending)| pending.hash() != hash);
+        state.pending.len() < count
+    }
+}
+
+#[async_trait]
+impl ChainClient for FakeChain {
+    async fn get_balance(&self, address: H160, _block: BlockTag) -> AppResult<U256> {
+        Ok(self.state().balance(&address))
+    }
+
+    async fn call(&self, request: &CallRequest, block: BlockTag) -> AppResult<Vec<u8>> {
+        match self.simulate_call(request, block).await? {
+            CallOutcome::Returned(output) => Ok(output),
+            CallOutcome::Reverted(revert) => Err(rejected(&revert.message)),
+        }
+    }
+
+    async fn simulate_call(
+        &self,
+        request: &CallRequest,
+        _block: BlockTag,
+    ) -> AppResult<CallOutcome> {
+        let state = self.state();
+        if request
+            .from
+            .is_some_and(|from| state.balance(&from) < request.value)
+        {
+            return Err(rejected("insufficient funds for transfer"));
+        }
+
+        let caller = request.from.unwrap_or_default();
+        match state.simulate_token_call(request.to, caller, &request.data) {
+            Ok(Some(output)) => return Ok(CallOutcome::Returned(output)),
+            Err(revert) => return Ok(CallOutcome::Reverted(revert)),
+            Ok(None) => {}
+        }
+
+        // Calls to addresses without a registered result behave like calls to an account
+        Ok(CallOutcome::Returned(
+            request
+                .to
+                .and_then(|to| state.call_results.get(&(to, request.data.clone())))
+                .cloned()
+                .unwrap_or_default(),
+        ))
+    }
+
+    async fn get_transaction_count(&self, address: H160, block: BlockTag) -> AppResult<u64> {
+        let state = self.state();
+        Ok(match block {
+            BlockTag::Pending => state.next_nonce(&address),
+            _ => state.nonce(&address),
+        })
+    }
+
+    async fn estimate_gas(&self, request: &CallRequest) -> AppResult<u64> {
+        let state = self.state();
+        if request
+            .from
+            .is_some_and(|from| state.balance(&from) < request.value)
+        {
+            return Err(rejected("insufficient funds for transfer"));
+        }
+
+        // A call that would revert cannot be estimated
+        let caller = request.from.unwrap_or_default();
+        state
+            .simulate_token_call(request.to, caller, &request.data)
+            .map_err(|revert| rejected(&revert.message))?;
+        Ok(state.gas_used(request.to, &request.data))
+    }
+
+    async fn fee_history(
+        &self,
+        block_count: u64,
+        newest_block: BlockTag,
+        reward_percentiles: &[f64],
+    ) -> AppResult<FeeHistory> {
+        let state = self.state();
+        let newest = match newest_block {
+            BlockTag::Number(number) => number.min(state.block_number),
+            _ => state.block_number,
+        };
+        let count = block_count.min(newest + 1);
+        let blocks = count as usize;
+
+        Ok(FeeHistory {
+            oldest_block: newest + 1 - count,
+            base_fee_per_gas: vec![state.base_fee_per_gas; blocks + 1],
+            gas_used_ratio: vec![GAS_USED_RATIO; blocks],
+            reward: if reward_percentiles.is_empty() {
+                Vec::new()
+            } else {
+                let rewards: Vec<U256> = reward_percentiles
+                    .iter()
+                    .map(|percentile| {
+                        state
+                            .priority_fee_per_gas
+                            .saturating_mul(U256::from((percentile * 100.0) as u64))
+                            / U256::from(5000)
+                    })
+                    .collect();
+                vec![rewards; blocks]
+            },
+        })
+    }
+
+    async fn send_raw_transaction(&self, raw: &[u8]) -> AppResult<H256> {
+        let signed = SignedTransaction::decode(raw).map_err(|_| rejected("invalid transaction"))?;
+        let transaction = signed.transaction();
+
+        match transaction.chain_id() {
+            Some(chain_id) if chain_id == self.chain_id => {}
+            Some(_) => return Err(rejected("invalid chain id for signer")),
+            None => {
+                return Err(rejected(
+                    "only replay-protected (EIP-155) transactions allowed over RPC",
+                ));
+            }
+        }
+        let sender = signed.sender().map_err(|_| rejected("invalid sender"))?;
+
+        let mut state = self.state();
+        let hash = signed.hash();
+        if state.knows(&hash) {
+            return Err(rejected("already known"));
+        }
+
+        if transaction.nonce() < state.nonce(&sender) {
+            return Err(rejected("nonce too low"));
+        }
+        let replaced = state.find_pending(&sender, transaction.nonce());
+        if let Some(index) = replaced {
+            let current = state.pending[index].1.transaction();
+            if !is_bumped(current.max_fee_per_gas(), transaction.max_fee_per_gas())
+                || !is_bumped(
+                    current.max_priority_fee_per_gas(),
+                    transaction.max_priority_fee_per_gas(),
+                )
+            {
+                return Err(rejected("replacement transaction underpriced"));
+            }
+        }
+
+        if transaction.gas_limit() < intrinsic_gas(transaction.to(), transaction.data()) {
+            return Err(rejected("intrinsic gas too low"));
+        }
+        if transaction.max_fee_per_gas() < state.base_fee_per_gas {
+            return Err(rejected("max fee per gas less than block base fee"));
+        }
+        if matches!(transaction, Transaction::DynamicFee(tx)
+            if tx.max_priority_fee_per_gas > tx.max_fee_per_gas)
+        {
+            return Err(rejected(
+                "max priority fee per gas higher than max fee per gas",
+            ));
+        }
+
+        let max_cost = transaction
+            .max_fee_per_gas()
+            .checked_mul(U256::from(transaction.gas_limit()))
+            .and_then(|gas_cost| gas_cost.checked_add(transaction.value()));
+        if max_cost.is_none_or(|max_cost| state.balance(&sender) < max_cost) {
+            return Err(rejected("insufficient funds for gas * price + value"));
+        }
+
+        match replaced {
+            Some(index) => state.pending[index] = (sender, signed),
+            None => state.pending.push((sender, signed)),
+        }
+        if state.automine {
+            state.mine_block();
+        }
+        Ok(hash)
+    }
+
+    async fn get_transaction_receipt(&self, hash: H256) -> AppResult<Option<TransactionReceipt>> {
+        Ok(self.state().receipts.get(&hash).cloned())
+    }
+
+    async fn block_number(&self) -> AppResult<u64> {
+        Ok(self.state().block_number)
+    }
+
+    async fn get_block_hash(&self, number: u64) -> AppResult<Option<H256>> {
+        let state = self.state();
+        Ok(usize::try_from(number)
+            .ok()
+            .and_then(|number| state.block_hashes.get(number))
+            .copied())
+    }
+
+    async fn has_transaction(&self, hash: H256) -> AppResult<bool> {
+        Ok(self.state().knows(&hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::PrivateKey;
+    use crate::transaction::{DynamicFeeTransaction, LegacyTransaction};
+
+    const CHAIN_ID: u64 = 1961;
+    const GWEI: u64 = 1_000_000_000;
+
+    fn private_key() -> PrivateKey {
+        PrivateKey::from_hex(&"46".repeat(32)).unwrap()
+    }
+
+    fn sender() -> H160 {
+        crate::transaction::parse_address("0x9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f").unwrap()
+    }
+
+    fn recipient() -> H160 {
+        H160::repeat_byte(0x35)
+    }
+
+    fn transfer(nonce: u64, value: u64) -> SignedTransaction {
+        transfer_with_fees(nonce, value, 2 * GWEI, 10 * GWEI)
+    }
+
+    fn transfer_with_fees(
+        nonce: u64,
+        value: u64,
+        max_priority_fee_per_gas: u64,
+        max_fee_per_gas: u64,
+    ) -> SignedTransaction {
+        Transaction::DynamicFee(DynamicFeeTransaction {
+            chain_id: CHAIN_ID,
+            nonce,
+            max_priority_fee_per_gas: U256::from(max_priority_fee_per_gas),
+            max_fee_per_gas: U256::from(max_fee_per_gas),
+            gas_limit: 21_000,
+            to: Some(recipient()),
+            value: U256::from(value),
+            ..Default::default()
+        })
+        .sign(&private_key())
+        .unwrap()
+    }
+
+    fn token() -> H160 {
+        H160::repeat_byte(0xee)
+    }
+
+    fn token_transfer(nonce: u64, amount: u64, gas_limit: u64) -> SignedTransaction {
+        Transaction::DynamicFee(DynamicFeeTransaction {
+            chain_id: CHAIN_ID,
+            nonce,
+            max_priority_fee_per_gas: U256::from(2 * GWEI),
+            max_fee_per_gas: U256::from(10 * GWEI),
+            gas_limit,
+            to: Some(token()),
+            data: erc20::transfer(recipient(), U256::from(amount)),
+            ..Default::default()
+        })
+        .sign(&private_key())
+        .unwrap()
+    }
+
+    fn funded_chain() -> FakeChain {
+        let chain = FakeChain::new(CHAIN_ID);
+        chain.set_balance(sender(), U256::from(1_000_000 * GWEI));
+        chain
+    }
+
+    #[tokio::test]
+    async fn test_transfer_is_mined_and_charged() {
+        let chain = funded_chain();
+        let signed = transfer(0, 5 * GWEI);
+
+        let hash = chain.send_raw_transaction(signed.raw()).await.unwrap();
+        assert_eq!(hash, signed.hash());
+
+        let receipt = chain.get_transaction_receipt(hash).await.unwrap().unwrap();
+        assert!(receipt.status);
+        assert_eq!(receipt.block_number, 1);
+        assert_eq!(receipt.from, sender());
+        assert_eq!(receipt.gas_used, 21_000);
+        // Base fee of 1 gwei plus the 2 gwei tip, below the 10 gwei cap
+        assert_eq!(receipt.effective_gas_price, U256::from(3 * GWEI));
+
+        let fee = 21_000 * 3 * GWEI;
+        assert_eq!(
+            chain.balance(sender()),
+            U256::from(1_000_000 * GWEI - 5 * GWEI - fee)
+        );
+        assert_eq!(chain.balance(recipient()), U256::from(5 * GWEI));
+        assert_eq!(
+            chain
+                .get_transaction_count(sender(), BlockTag::Latest)
+                .await
+                .unwrap(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejects_invalid_transactions() {
+        let chain = funded_chain();
+        chain
+            .send_raw_transaction(transfer(0, 1).raw())
+            .await
+            .unwrap();
+
+        let cases = [
+            (transfer(0, 2), "nonce too low"),
+            (transfer(1, 1_000_000 * GWEI), "insufficient funds"),
+        ];
+        for (signed, expected) in cases {
+            match chain.send_raw_transaction(signed.raw()).await {
+                Err(AppError::ChainError(message)) => assert!(message.contains(expected)),
+                other => panic!("expected '{}', got {:?}", expected, other),
+            }
+        }
+
+        let other_chain = Transaction::DynamicFee(DynamicFeeTransaction {
+            chain_id: 1,
+            nonce: 1,
+            max_fee_per_gas: U256::from(10 * GWEI),
+            gas_limit: 21_000,
+            ..Default::default()
+        })
+        .sign(&private_key())
+        .unwrap();
+        assert!(chain.send_raw_transaction(other_chain.raw()).await.is_err());
+
+        let unprotected = Transaction::Legacy(LegacyTransaction {
+            nonce: 1,
+            gas_price: U256::from(10 * GWEI),
+            gas_limit: 21_000,
+            ..Default::default()
+        })
+        .sign(&private_key())
+        .unwrap();
+        assert!(chain.send_raw_transaction(unprotected.raw()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pending_transactions_wait_for_a_block() {
+        let chain = funded_chain();
+        chain.set_automine(false);
+
+        let first = transfer(0, 1);
+        chain.send_raw_transaction(first.raw()).await.unwrap();
+        chain
+            .send_raw_transaction(transfer(1, 1).raw())
+            .await
+            .unwrap();
+
+        assert_eq!(chain.pending_count(), 2);
+        assert_eq!(
+            chain
+                .get_transaction_count(sender(), BlockTag::Pending)
+                .await
+                .unwrap(),
+            2
+        );
+        assert!(
+            chain
+                .get_transaction_receipt(first.hash())
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        assert_eq!(chain.mine_block(), 1);
+        assert_eq!(chain.pending_count(), 0);
+        assert!(
+            chain
+                .get_transaction_receipt(first.hash())
+                .await
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_transactions_wait_behind_a_nonce_gap() {
+        let chain = funded_chain();
+        let queued = transfer(1, 1);
+        chain.send_raw_transaction(queued.raw()).await.unwrap();
+
+        // Queued, and not counted as pending until the gap is filled
+        assert_eq!(chain.pending_count(), 1);
+        assert_eq!(
+            chain
+                .get_transaction_count(sender(), BlockTag::Pending)
+                .await
+                .unwrap(),
+            0
+        );
+
+        chain
+            .send_raw_transaction(transfer(0, 1).raw())
+            .await
+            .unwrap();
+        assert_eq!(chain.pending_count(), 0);
+        let receipt = chain
+            .get_transaction_receipt(queued.hash())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(receipt.block_number, 2);
+        assert_eq!(
+            chain
+                .get_transaction_count(sender(), BlockTag::Latest)
+                .await
+                .unwrap(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pending_transactions_are_replaced_for_higher_fees() {
+        let chain = funded_chain();
+        chain.set_automine(false);
+        let original = transfer_with_fees(0, 1, 2 * GWEI, 10 * GWEI);
+        chain.send_raw_transaction(original.raw()).await.unwrap();
+
+        // Both fee caps must rise by at least 10%
+        for (tip, max_fee) in [(2 * GWEI, 20 * GWEI), (3 * GWEI, 10 * GWEI)] {
+            let underpriced = transfer_with_fees(0, 2, tip, max_fee);
+            match chain.send_raw_transaction(underpriced.raw()).await {
+                Err(AppError::ChainError(message)) => {
+                    assert!(message.contains("replacement transaction underpriced"))
+                }
+                other => panic!("expected underpriced replacement, got {:?}", other),
+            }
+        }
+
+        let replacement = transfer_with_fees(0, 2, 2_200_000_000, 11 * GWEI);
+        chain.send_raw_transaction(replacement.raw()).await.unwrap();
+        assert_eq!(chain.pending_count(), 1);
+        assert!(!chain.has_transaction(original.hash()).await.unwrap());
+
+        chain.mine_block();
+        assert!(
+            chain
+                .get_transaction_receipt(replacement.hash())
+                .await
+                .unwrap()
+                .is_some()
+        );
+        assert_eq!(
+            chain
+                .get_balance(recipient(), BlockTag::Latest)
+                .await
+                .unwrap(),
+            U256::from(2)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_reorg_returns_transactions_to_the_pool() {
+        let chain = funded_chain();
+        let signed = transfer(0, 5);
+        chain.send_raw_transaction(signed.raw()).await.unwrap();
+        let receipt = chain
+            .get_transaction_receipt(signed.hash())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            chain.get_block_hash(1).await.unwrap(),
+            Some(receipt.block_hash)
+        );
+
+        chain.reorg(1);
+
+        // The block is replaced by an empty one and its transfer is undone
+        assert_eq!(chain.block_number().await.unwrap(), 1);
+        assert_ne!(
+            chain.get_block_hash(1).await.unwrap(),
+            Some(receipt.block_hash)
+        );
+        assert!(
+            chain
+                .get_transaction_receipt(signed.hash())
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(
+            chain
+                .get_balance(recipient(), BlockTag::Latest)
+                .await
+                .unwrap(),
+            U256::zero()
+        );
+        assert!(chain.has_transaction(signed.hash()).await.unwrap());
+        assert_eq!(chain.pending_count(), 1);
+
+        // Mined again in a later block
+        assert_eq!(chain.mine_block(), 2);
+        let receipt = chain
+            .get_transaction_receipt(signed.hash())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(receipt.block_number, 2);
+        assert_eq!(chain.get_block_hash(3).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_transactions_are_forgotten() {
+        let chain = funded_chain();
+        chain.set_automine(false);
+        let signed = transfer(0, 5);
+        chain.send_raw_transaction(signed.raw()).await.unwrap();
+
+        assert!(chain.drop_transaction(signed.hash()));
+        assert!(!chain.drop_transaction(signed.hash()));
+        assert!(!chain.has_transaction(signed.hash()).await.unwrap());
+        assert_eq!(
+            chain
+                .get_transaction_count(sender(), BlockTag::Pending)
+                .await
+                .unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fee_history_and_estimates() {
+        let chain = funded_chain();
+        chain.mine_block();
+        chain.mine_block();
+        chain.set_base_fee_per_gas(U256::from(7 * GWEI));
+
+        let history = chain
+            .fee_history(5, BlockTag::Latest, &[25.0, 75.0])
+            .await
+            .unwrap();
+        assert_eq!(history.oldest_block, 0);
+        assert_eq!(history.base_fee_per_gas.len(), 4);
+        assert_eq!(history.next_base_fee(), U256::from(7 * GWEI));
+        assert_eq!(
+            history.reward,
+            vec![vec![U256::from(GWEI / 2), U256::from(3 * GWEI / 2)]; 3]
+        );
+
+        let request = CallRequest {
+            from: Some(sender()),
+            to: Some(recipient()),
+            data: vec![0, 1],
+            ..Default::default()
+        };
+        assert_eq!(chain.estimate_gas(&request).await.unwrap(), 21_020);
+    }
+
+    #[tokio::test]
+    async fn test_call_results() {
+        let chain = funded_chain();
+        chain.set_call_result(recipient(), vec![1, 2], vec![3]);
+
+        let request = CallRequest {
+            to: Some(recipient()),
+            data: vec![1, 2],
+            ..Default::default()
+        };
+        assert_eq!(
+            chain.call(&request, BlockTag::Latest).await.unwrap(),
+            vec![3]
+        );
+
+        let other = CallRequest {
+            to: Some(recipient()),
+            ..Default::default()
+        };
+        assert!(
+            chain
+                .call(&other, BlockTag::Latest)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_token_calls() {
+        let chain = funded_chain();
+        chain.deploy_token(token(), 6);
+        chain.set_token_balance(token(), sender(), U256::from(5_000_000));
+
+        let call = |data: Vec<u8>| CallRequest {
+            from: Some(sender()),
+            to: Some(token()),
+            data,
+            ..Default::default()
+        };
+        let output = chain
+            .call(&call(erc20::balance_of(sender())), BlockTag::Latest)
+            .await
+            .unwrap();
+        assert_eq!(
+            erc20::decode_amount(&output).unwrap(),
+            U256::from(5_000_000)
+        );
+        let output = chain
+            .call(&call(erc20::decimals()), BlockTag::Latest)
+            .await
+            .unwrap();
+        assert_eq!(erc20::decode_decimals(&output).unwrap(), 6);
+
+        // Calls and estimates leave balances untouched
+        let transfer_call = call(erc20::transfer(recipient(), U256::from(1_000_000)));
+        let output = chain.call(&transfer_call, BlockTag::Latest).await.unwrap();
+        assert!(erc20::decode_success(&output).unwrap());
+        assert_eq!(
+            chain.estimate_gas(&transfer_call).await.unwrap(),
+            intrinsic_gas(Some(token()), &transfer_call.data) + TOKEN_CALL_GAS
+        );
+        assert_eq!(chain.token_balance(token(), recipient()), U256::zero());
+
+        let too_much = call(erc20::transfer(recipient(), U256::from(6_000_000)));
+        let error = chain.estimate_gas(&too_much).await.unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("transfer amount exceeds balance")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_simulated_calls_report_reverts() {
+        let chain = funded_chain();
+        chain.deploy_token(token(), 6);
+        chain.set_token_balance(token(), sender(), U256::from(5_000_000));
+
+        let transfer = |amount: u64| CallRequest {
+            from: Some(sender()),
+            to: Some(token()),
+            data: erc20::transfer(recipient(), U256::from(amount)),
+            ..Default::default()
+        };
+        let outcome = chain
+            .simulate_call(&transfer(1_000_000), BlockTag::Pending)
+            .await
+            .unwrap();
+        assert!(matches!(outcome, CallOutcome::Returned(_)));
+
+        let outcome = chain
+            .simulate_call(&transfer(6_000_000), BlockTag::Pending)
+            .await
+            .unwrap();
+        let CallOutcome::Reverted(revert) = outcome else {
+            panic!("expected a revert, got {:?}", outcome);
+        };
+        assert_eq!(
+            revert.message,
+            "execution reverted: ERC20: transfer amount exceeds balance"
+        );
+        assert_eq!(
+            revert.data,
+            error_data("ERC20: transfer amount exceeds balance")
+        );
+
+        // Custom errors come back as data only, and fail the transfer once mined too
+        let paused = selector("EnforcedPause()").to_vec();
+        chain.set_transfer_revert(token(), Some(paused.clone()));
+        let outcome = chain
+            .simulate_call(&transfer(1_000_000), BlockTag::Pending)
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome,
+            CallOutcome::Reverted(Revert {
+                message: "execution reverted".to_string(),
+                data: paused,
+            })
+        );
+        let hash = chain
+            .send_raw_transaction(token_transfer(0, 1_000_000, 100_000).raw())
+            .await
+            .unwrap();
+        let receipt = chain.get_transaction_receipt(hash).await.unwrap().unwrap();
+        assert!(!receipt.status);
+
+        chain.set_transfer_revert(token(), None);
+        let outcome = chain
+            .simulate_call(&transfer(1_000_000), BlockTag::Pending)
+            .await
+            .unwrap();
+        assert!(matches!(outcome, CallOutcome::Returned(_)));
+    }
+
+    #[tokio::test]
+    async fn test_token_transfer_moves_tokens_and_emits_event() {
+        let chain = funded_chain();
+        chain.deploy_token(token(), 6);
+        chain.set_token_balance(token(), sender(), U256::from(5_000_000));
+
+        let hash = chain
+            .send_raw_transaction(token_transfer(0, 1_500_000, 100_000).raw())
+            .await
+            .unwrap();
+        let receipt = chain.get_transaction_receipt(hash).await.unwrap().unwrap();
+        assert!(receipt.status);
+        assert_eq!(
+            chain.token_balance(token(), sender()),
+            U256::from(3_500_000)
+        );
+        assert_eq!(
+            chain.token_balance(token(), recipient()),
+            U256::from(1_500_000)
+        );
+
+        let events: Vec<_> = receipt
+            .logs
+            .iter()
+            .filter_map(|log| TransferEvent::from_log(log).unwrap())
+            .collect();
+        assert_eq!(
+            events,
+            vec![TransferEvent {
+                token: token(),
+                from: sender(),
+                to: recipient(),
+                value: U256::from(1_500_000),
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_token_transfers_still_pay_for_gas() {
+        let chain = funded_chain();
+        chain.deploy_token(token(), 6);
+        chain.set_token_balance(token(), sender(), U256::from(1_000));
+
+        // Reverts: more than the balance
+        let reverted = chain
+            .send_raw_transaction(token_transfer(0, 2_000, 100_000).raw())
+            .await
+            .unwrap();
+        // Runs out of gas above the intrinsic cost
+        let out_of_gas = chain
+            .send_raw_transaction(token_transfer(1, 500, 30_000).raw())
+            .await
+            .unwrap();
+
+        let call_data = erc20::transfer(recipient(), U256::from(2_000));
+        let reverted_gas = intrinsic_gas(Some(token()), &call_data) + TOKEN_CALL_GAS;
+        for (hash, gas_used) in [(reverted, reverted_gas), (out_of_gas, 30_000)] {
+            let receipt = chain.get_transaction_receipt(hash).await.unwrap().unwrap();
+            assert!(!receipt.status);
+            assert_eq!(receipt.gas_used, gas_used);
+            assert!(receipt.logs.is_empty());
+        }
+        assert_eq!(chain.token_balance(token(), sender()), U256::from(1_000));
+        assert!(chain.balance(sender()) < U256::from(1_000_000 * GWEI));
+    }
+}
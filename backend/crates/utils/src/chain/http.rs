@@ -0,0 +1,635 @@
+use app_error::{AppError, AppResult};
+use async_trait::async_trait;
+use serde_json::{Value, json};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::{debug, error};
+
+use super::{
+    BlockTag, CallOutcome, CallRequest, ChainClient, FeeHistory, Log, Revert, TransactionReceipt,
+};
+use crate::transaction::{H160, H256, U256, format_address};
+
+/// Chain client for a node's JSON-RPC endpoint over HTTP
+pub struct HttpChainClient {
+    url: String,
+    client: reqwest::Client,
+    next_id: AtomicU64,
+}
+
+fn invalid_response(method: &str, message: &str) -> AppError {
+    AppError::NetworkError(format!("Invalid {} response: {}", method, message))
+}
+
+fn quantity(value: u64) -> String {
+    format!("{:#x}", value)
+}
+
+fn u256_quantity(value: U256) -> String {
+    format!("{:#x}", value)
+}
+
+fn hex_data(data: &[u8]) -> String {
+    format!("0x{}", hex::encode(data))
+}
+
+fn block_param(block: BlockTag) -> String {
+    match block {
+        BlockTag::Latest => "latest".to_string(),
+        BlockTag::Pending => "pending".to_string(),
+        BlockTag::Number(number) => quantity(number),
+    }
+}
+
+fn call_object(request: &CallRequest) -> Value {
+    let mut object = json!({
+        "value": u256_quantity(request.value),
+        "data": hex_data(&request.data),
+    });
+    if let Some(from) = &request.from {
+        object["from"] = json!(format_address(from));
+    }
+    if let Some(to) = &request.to {
+        object["to"] = json!(format_address(to));
+    }
+    if let Some(gas) = request.gas {
+        object["gas"] = json!(quantity(gas));
+    }
+    object
+}
+
+fn parse_u256(value: &Value) -> Option<U256> {
+    let digits = value.as_str()?.strip_prefix("0x")?;
+    if digits.is_empty() || digits.len() > 64 {
+        return None;
+    }
+    U256::from_str_radix(digits, 16).ok()
+}
+
+fn parse_u64(value: &Value) -> Option<u64> {
+    let digits = value.as_str()?.strip_prefix("0x")?;
+    u64::from_str_radix(digits, 16).ok()
+}
+
+fn parse_data(value: &Value) -> Option<Vec<u8>> {
+    hex::decode(value.as_str()?.strip_prefix("0x")?).ok()
+}
+
+fn parse_fixed<const N: usize>(value: &Value) -> Option<[u8; N]> {
+    parse_data(value)?.try_into().ok()
+}
+
+fn parse_address(value: &Value) -> Option<H160> {
+    parse_fixed(value).map(H160)
+}
+
+fn parse_hash(value: &Value) -> Option<H256> {
+    parse_fixed(value).map(H256)
+}
+
+fn parse_log(value: &Value) -> Option<Log> {
+    Some(Log {
+        address: parse_address(&value["address"])?,
+        topics: value["topics"]
+            .as_array()?
+            .iter()
+            .map(parse_hash)
+            .collect::<Option<_>>()?,
+        data: parse_data(&value["data"])?,
+    })
+}
+
+/// The revert an `eth_call` error describes, if it is one. Geth answers with code 3 and
+/// the revert data as a hex string; some nodes nest it in an object or only say so in the
+/// message.
+fn parse_revert(rpc_error: &Value) -> Option<Revert> {
+    let message = rpc_error["message"].as_str().unwrap_or_default();
+    let is_revert =
+        rpc_error["code"].as_i64() == Some(3) || message.to_lowercase().contains("revert");
+    if !is_revert {
+        return None;
+    }
+    let data = parse_data(&rpc_error["data"])
+        .or_else(|| parse_data(&rpc_error["data"]["data"]))
+        .unwrap_or_default();
+    Some(Revert {
+        message: message.to_string(),
+        data,
+    })
+}
+
+fn parse_receipt(value: &Value) -> Option<TransactionReceipt> {
+    Some(TransactionReceipt {
+        transaction_hash: parse_hash(&value["transactionHash"])?,
+        block_number: parse_u64(&value["blockNumber"])?,
+        block_hash: parse_hash(&value["blockHash"])?,
+        from: parse_address(&value["from"])?,
+        to: parse_address(&value["to"]),
+        status: parse_u64(&value["status"])? == 1,
+        gas_used: parse_u64(&value["gasUsed"])?,
+        // Nodes from before EIP-1559 do not report it
+        effective_gas_price: parse_u256(&value["effectiveGasPrice"]).unwrap_or_default(),
+        logs: value["logs"]
+            .as_array()?
+            .iter()
+            .map(parse_log)
+            .collect::<Option<_>>()?,
+    })
+}
+
+impl HttpChainClient {
+    pub fn new(url: &str, timeout: Duration) -> AppResult<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| {
+                AppError::ConfigError(anyhow::anyhow!("Failed to build chain RPC client: {}", e))
+            })?;
+
+        Ok(Self {
+            url: url.to_string(),
+            client,
+            next_id: AtomicU64::new(1),
+        })
+    }
+
+    /// Send a JSON-RPC request and return its `result`
+    async fn request(&self, method: &str, params: Value) -> AppResult<Value> {
+        self.send(method, params).await?.map_err(|rpc_error| {
+            let message = rpc_error["message"].as_str().unwrap_or("unknown error");
+            AppError::ChainError(message.to_string())
+        })
+    }
+
+    /// Send a JSON-RPC request and return its `result`, or the `error` the node answered
+    /// with
+    async fn send(&self, method: &str, params: Value) -> AppResult<Result<Value, Value>> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        debug!("Chain RPC request {} ({})", method, id);
+
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": method,
+                "params": params,
+            }))
+            .send()
+            .await
+            .map_err(|e| {
+                error!("Chain RPC request {} failed: {}", method, e);
+                AppError::NetworkError(format!("Chain RPC request failed: {}", e))
+            })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            error!("Chain RPC request {} returned {}", method, status);
+            return Err(AppError::NetworkError(format!(
+                "Chain RPC node returned status {}",
+                status
+            )));
+        }
+
+        let mut payload: Value = response
+            .json()
+            .await
+            .map_err(|e| invalid_response(method, &e.to_string()))?;
+
+        if let Some(rpc_error) = payload.get_mut("error") {
+            debug!("Chain RPC request {} was rejected: {}", method, rpc_error);
+            return Ok(Err(rpc_error.take()));
+        }
+
+        Ok(Ok(payload["result"].take()))
+    }
+}
+
+#[async_trait]
+impl ChainClient for HttpChainClient {
+    async fn get_balance(&self, address: H160, block: BlockTag) -> AppResult<U256> {
+        let result = self
+            .request(
+                "eth_getBalance",
+                json!([format_address(&address), block_param(block)]),
+            )
+            .await?;
+        parse_u256(&result).ok_or_else(|| invalid_response("eth_getBalance", "not a quantity"))
+    }
+
+    async fn call(&self, request: &CallRequest, block: BlockTag) -> AppResult<Vec<u8>> {
+        let result = self
+            .request(
+                "eth_call",
+                json!([call_object(request), block_param(block)]),
+            )
+            .await?;
+        parse_data(&result).ok_or_else(|| invalid_response("eth_call", "not hex data"))
+    }
+
+    async fn simulate_call(
+        &self,
+        request: &CallRequest,
+        block: BlockTag,
+    ) -> AppResult<CallOutcome> {
+        let response = self
+            .send(
+                "eth_call",
+                json!([call_object(request), block_param(block)]),
+            )
+            .await?;
+        match response {
+            Ok(result) => parse_data(&result)
+                .map(CallOutcome::Returned)
+                .ok_or_else(|| invalid_response("eth_call", "not hex data")),
+            Err(rpc_error) => match parse_revert(&rpc_error) {
+                Some(revert) => Ok(CallOutcome::Reverted(revert)),
+                None => {
+                    let message = rpc_error["message"].as_str().unwrap_or("unknown error");
+                    Err(AppError::ChainError(message.to_string()))
+                }
+            },
+        }
+    }
+
+    async fn get_transaction_count(&self, address: H160, block: BlockTag) -> AppResult<u64> {
+        let result = self
+            .request(
+                "eth_getTransactionCount",
+                json!([format_address(&address), block_param(block)]),
+            )
+            .await?;
+        parse_u64(&result)
+            .ok_or_else(|| invalid_response("eth_getTransactionCount", "not a quantity"))
+    }
+
+    async fn estimate_gas(&self, request: &CallRequest) -> AppResult<u64> {
+        let result = self
+            .request("eth_estimateGas", json!([call_object(request)]))
+            .await?;
+        parse_u64(&result).ok_or_else(|| invalid_response("eth_estimateGas", "not a quantity"))
+    }
+
+    async fn fee_history(
+        &self,
+        block_count: u64,
+        newest_block: BlockTag,
+        reward_percentiles: &[f64],
+    ) -> AppResult<FeeHistory> {
+        let result = self
+            .request(
+                "eth_feeHistory",
+                json!([
+                    quantity(block_count),
+                    block_param(newest_block),
+                    reward_percentiles
+                ]),
+            )
+            .await?;
+
+        let parse = || -> Option<FeeHistory> {
+            Some(FeeHistory {
+                oldest_block: parse_u64(&result["oldestBlock"])?,
+                base_fee_per_gas: result["baseFeePerGas"]
+                    .as_array()?
+                    .iter()
+                    .map(parse_u256)
+                    .collect::<Option<_>>()?,
+                gas_used_ratio: result["gasUsedRatio"]
+                    .as_array()?
+                    .iter()
+                    .map(Value::as_f64)
+                    .collect::<Option<_>>()?,
+                // Only present when percentiles were requested
+                reward: match result.get("reward").and_then(Value::as_array) {
+                    Some(blocks) => blocks
+                        .iter()
+                        .map(|rewards| {
+                            rewards
+                                .as_array()?
+                                .iter()
+                                .map(parse_u256)
+                                .collect::<Option<_>>()
+                        })
+                        .collect::<Option<_>>()?,
+                    None => Vec::new(),
+                },
+            })
+        };
+        parse().ok_or_else(|| invalid_response("eth_feeHistory", "malformed fee history"))
+    }
+
+    async fn send_raw_transaction(&self, raw: &[u8]) -> AppResult<H256> {
+        let result = self
+            .request("eth_sendRawTransaction", json!([hex_data(raw)]))
+            .await?;
+        parse_hash(&result).ok_or_else(|| invalid_response("eth_sendRawTransaction", "not a hash"))
+    }
+
+    async fn get_transaction_receipt(&self, hash: H256) -> AppResult<Option<TransactionReceipt>> {
+        let result = self
+            .request("eth_getTransactionReceipt", json!([format!("{:#x}", hash)]))
+            .await?;
+        if result.is_null() {
+            return Ok(None);
+        }
+        parse_receipt(&result)
+            .map(Some)
+            .ok_or_else(|| invalid_response("eth_getTransactionReceipt", "malformed receipt"))
+    }
+
+    async fn block_number(&self) -> AppResult<u64> {
+        let result = self.request("eth_blockNumber", json!([])).await?;
+        parse_u64(&result).ok_or_else(|| invalid_response("eth_blockNumber", "not a quantity"))
+    }
+
+    async fn get_block_hash(&self, number: u64) -> AppResult<Option<H256>> {
+        // Without full transaction objects, which are not needed
+        let result = self
+            .request("eth_getBlockByNumber", json!([quantity(number), false]))
+            .await?;
+        if result.is_null() {
+            return Ok(None);
+        }
+        parse_hash(&result["hash"])
+            .map(Some)
+            .ok_or_else(|| invalid_response("eth_getBlockByNumber", "malformed block"))
+    }
+
+    async fn has_transaction(&self, hash: H256) -> AppResult<bool> {
+        let result = self
+            .request("eth_getTransactionByHash", json!([format!("{:#x}", hash)]))
+            .await?;
+        Ok(!result.is_null())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_partial_json, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    async fn client_for(server: &MockServer) -> HttpChainClient {
+        HttpChainClient::new(&server.uri(), Duration::from_secs(5)).unwrap()
+    }
+
+    // Answer every call of `rpc_method` with `response` (a `result` or an `error`)
+    async fn respond_to(server: &MockServer, rpc_method: &str, response: Value) {
+        let mut body = json!({ "jsonrpc": "2.0", "id": 1 });
+        if let (Some(body), Value::Object(response)) = (body.as_object_mut(), response) {
+            body.extend(response);
+        }
+
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({ "method": rpc_method })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(body))
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_get_balance_and_nonce() {
+        let server = MockServer::start().await;
+        respond_to(
+            &server,
+            "eth_getBalance",
+            json!({ "result": "0xde0b6b3a7640000" }),
+        )
+        .await;
+        respond_to(
+            &server,
+            "eth_getTransactionCount",
+            json!({ "result": "0x9" }),
+        )
+        .await;
+        let client = client_for(&server).await;
+
+        let address = H160::repeat_byte(0x35);
+        let balance = client.get_balance(address, BlockTag::Latest).await.unwrap();
+        assert_eq!(balance, U256::from(1_000_000_000_000_000_000u64));
+        let nonce = client
+            .get_transaction_count(address, BlockTag::Pending)
+            .await
+            .unwrap();
+        assert_eq!(nonce, 9);
+    }
+
+    #[tokio::test]
+    async fn test_fee_history() {
+        let server = MockServer::start().await;
+        respond_to(
+            &server,
+            "eth_feeHistory",
+            json!({ "result": {
+                "oldestBlock": "0x10",
+                "baseFeePerGas": ["0x3b9aca00", "0x3b9aca00", "0x4a817c80"],
+                "gasUsedRatio": [0.5, 0.9],
+                "reward": [["0x1"], ["0x2"]],
+            }}),
+        )
+        .await;
+        let client = client_for(&server).await;
+
+        let history = client
+            .fee_history(2, BlockTag::Latest, &[50.0])
+            .await
+            .unwrap();
+        assert_eq!(history.oldest_block, 16);
+        assert_eq!(history.next_base_fee(), U256::from(1_250_000_000u64));
+        assert_eq!(history.reward, vec![vec![U256::one()], vec![U256::from(2)]]);
+    }
+
+    #[tokio::test]
+    async fn test_transaction_receipt() {
+        let server = MockServer::start().await;
+        respond_to(
+            &server,
+            "eth_getTransactionReceipt",
+            json!({ "result": {
+                "transactionHash": format!("0x{}", "11".repeat(32)),
+                "blockNumber": "0x2a",
+                "blockHash": format!("0x{}", "22".repeat(32)),
+                "from": format!("0x{}", "33".repeat(20)),
+                "to": null,
+                "status": "0x0",
+                "gasUsed": "0x5208",
+                "effectiveGasPrice": "0x3b9aca00",
+                "logs": [{
+                    "address": format!("0x{}", "44".repeat(20)),
+                    "topics": [format!("0x{}", "55".repeat(32))],
+                    "data": "0x01",
+                }],
+            }}),
+        )
+        .await;
+        let client = client_for(&server).await;
+
+        let receipt = client
+            .get_transaction_receipt(H256::repeat_byte(0x11))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(receipt.block_number, 42);
+        assert!(!receipt.status);
+        assert_eq!(receipt.to, None);
+        assert_eq!(receipt.gas_used, 21_000);
+        assert_eq!(receipt.logs[0].topics, vec![H256::repeat_byte(0x55)]);
+    }
+
+    #[tokio::test]
+    async fn test_pending_receipt_is_none() {
+        let server = MockServer::start().await;
+        respond_to(
+            &server,
+            "eth_getTransactionReceipt",
+            json!({ "result": null }),
+        )
+        .await;
+        let client = client_for(&server).await;
+
+        let receipt = client.get_transaction_receipt(H256::zero()).await.unwrap();
+        assert!(receipt.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_blocks_and_transactions() {
+        let server = MockServer::start().await;
+        respond_to(&server, "eth_blockNumber", json!({ "result": "0x2a" })).await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(
+                json!({ "method": "eth_getBlockByNumber", "params": ["0x2a", false] }),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "result": { "number": "0x2a", "hash": format!("0x{}", "22".repeat(32)) },
+            })))
+            .mount(&server)
+            .await;
+        respond_to(&server, "eth_getBlockByNumber", json!({ "result": null })).await;
+        respond_to(
+            &server,
+            "eth_getTransactionByHash",
+            json!({ "result": { "hash": format!("0x{}", "11".repeat(32)) } }),
+        )
+        .await;
+        let client = client_for(&server).await;
+
+        assert_eq!(client.block_number().await.unwrap(), 42);
+        assert_eq!(
+            client.get_block_hash(42).await.unwrap(),
+            Some(H256::repeat_byte(0x22))
+        );
+        assert_eq!(client.get_block_hash(43).await.unwrap(), None);
+        assert!(
+            client
+                .has_transaction(H256::repeat_byte(0x11))
+                .await
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rpc_errors_are_chain_errors() {
+        let server = MockServer::start().await;
+        respond_to(
+            &server,
+            "eth_sendRawTransaction",
+            json!({ "error": { "code": -32000, "message": "nonce too low" } }),
+        )
+        .await;
+        let client = client_for(&server).await;
+
+        match client.send_raw_transaction(&[0x02, 0xc0]).await {
+            Err(AppError::ChainError(message)) => assert_eq!(message, "nonce too low"),
+            other => panic!("expected a chain error, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simulated_call_reverts() {
+        let server = MockServer::start().await;
+        let data = format!("0x08c379a0{}", "00".repeat(64));
+        Mock::given(method("POST"))
+            .and(body_partial_json(
+                json!({ "method": "eth_call", "params": [{ "data": "0x01" }] }),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "error": { "code": 3, "message": "execution reverted", "data": data },
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(
+                json!({ "method": "eth_call", "params": [{ "data": "0x02" }] }),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "error": {
+                    "code": -32603,
+                    "message": "VM Exception while processing transaction: revert",
+                    "data": { "data": "0xd93c0665" },
+                },
+            })))
+            .mount(&server)
+            .await;
+        respond_to(
+            &server,
+            "eth_call",
+            json!({ "error": { "code": -32000, "message": "header not found" } }),
+        )
+        .await;
+        let client = client_for(&server).await;
+
+        let call = |data: Vec<u8>| CallRequest {
+            data,
+            ..Default::default()
+        };
+        let outcome = client
+            .simulate_call(&call(vec![0x01]), BlockTag::Pending)
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome,
+            CallOutcome::Reverted(Revert {
+                message: "execution reverted".to_string(),
+                data: parse_data(&json!(data)).unwrap(),
+            })
+        );
+
+        let outcome = client
+            .simulate_call(&call(vec![0x02]), BlockTag::Pending)
+            .await
+            .unwrap();
+        let CallOutcome::Reverted(revert) = outcome else {
+            panic!("expected a revert, got {:?}", outcome);
+        };
+        assert_eq!(revert.data, vec![0xd9, 0x3c, 0x06, 0x65]);
+
+        // Other errors still fail the call
+        let result = client
+            .simulate_call(&call(vec![0x03]), BlockTag::Pending)
+            .await;
+        assert!(
+            matches!(result, Err(AppError::ChainError(message)) if message == "header not found")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http_failures_are_network_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+        let client = client_for(&server).await;
+
+        let result = client.get_balance(H160::zero(), BlockTag::Latest).await;
+        assert!(matches!(result, Err(AppError::NetworkError(_))));
+    }
+}
@@ -0,0 +1,179 @@
+//! Access to an Ethereum-compatible chain: the JSON-RPC calls the wallet service needs
+//! to read balances, estimate fees and broadcast signed transactions.
+//!
+//! `HttpChainClient` talks to a node; `FakeChain` keeps a chain in process so tests and
+//! local development can run real transfers without a network. `NonceManager` hands out
+//! the nonces of outgoing transactions.
+
+mod fake;
+mod http;
+mod nonce;
+
+pub use fake::FakeChain;
+pub use http::HttpChainClient;
+pub use nonce::{MemoryNonceStore, NonceManager, NonceStore, RedisNonceStore, is_nonce_too_low};
+
+use app_config::ChainConfig;
+use app_error::{AppError, AppResult};
+use async_trait::async_trait;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::transaction::{H160, H256, U256};
+
+/// `rpc_url` that selects the in-process chain
+pub const MEMORY_RPC_URL: &str = "memory";
+
+/// The only environment the in-process chain may be selected in
+const MEMORY_CHAIN_ENVIRONMENT: &str = "development";
+
+/// Block a read refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockTag {
+    Latest,
+    /// The latest block plus the node's pending transactions
+    Pending,
+    Number(u64),
+}
+
+/// A message call or transaction to simulate, for `eth_call` and `eth_estimateGas`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CallRequest {
+    pub from: Option<H160>,
+    pub to: Option<H160>,
+    pub value: U256,
+    pub data: Vec<u8>,
+    pub gas: Option<u64>,
+}
+
+/// Base fees and priority fee percentiles of recent blocks, from `eth_feeHistory`
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct FeeHistory {
+    pub oldest_block: u64,
+    /// One entry per block plus the base fee of the block after the newest
+    pub base_fee_per_gas: Vec<U256>,
+    pub gas_used_ratio: Vec<f64>,
+    /// Per block, the priority fee at each requested percentile
+    pub reward: Vec<Vec<U256>>,
+}
+
+impl FeeHistory {
+    /// Base fee of the next block
+    pub fn next_base_fee(&self) -> U256 {
+        self.base_fee_per_gas.last().copied().unwrap_or_default()
+    }
+}
+
+/// An event emitted by a transaction
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Log {
+    pub address: H160,
+    pub topics: Vec<H256>,
+    pub data: Vec<u8>,
+}
+
+/// Outcome of a mined transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransactionReceipt {
+    pub transaction_hash: H256,
+    pub block_number: u64,
+    pub block_hash: H256,
+    pub from: H160,
+    pub to: Option<H160>,
+    /// Whether execution succeeded; a reverted transaction is still mined and pays for gas
+    pub status: bool,
+    pub gas_used: u64,
+    pub effective_gas_price: U256,
+    pub logs: Vec<Log>,
+}
+
+/// A call that reverted: the node's message and the data the contract reverted with,
+/// which `abi::revert::RevertReason` decodes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Revert {
+    pub message: String,
+    pub data: Vec<u8>,
+}
+
+/// What a simulated call did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CallOutcome {
+    Returned(Vec<u8>),
+    Reverted(Revert),
+}
+
+/// The JSON-RPC methods the wallet service uses. Node-side rejections surface as
+/// `AppError::ChainError`, transport failures as `AppError::NetworkError`.
+#[async_trait]
+pub trait ChainClient: Send + Sync {
+    /// `eth_getBalance`, in wei
+    async fn get_balance(&self, address: H160, block: BlockTag) -> AppResult<U256>;
+
+    /// `eth_call`, returning the call's output
+    async fn call(&self, request: &CallRequest, block: BlockTag) -> AppResult<Vec<u8>>;
+
+    /// `eth_call` of a transaction about to be sent, reporting a revert and its data
+    /// instead of failing
+    async fn simulate_call(&self, request: &CallRequest, block: BlockTag)
+    -> AppResult<CallOutcome>;
+
+    /// `eth_getTransactionCount`, the next nonce of an address
+    async fn get_transaction_count(&self, address: H160, block: BlockTag) -> AppResult<u64>;
+
+    /// `eth_estimateGas`
+    async fn estimate_gas(&self, request: &CallRequest) -> AppResult<u64>;
+
+    /// `eth_feeHistory` over the `block_count` blocks up to `newest_block`
+    async fn fee_history(
+        &self,
+        block_count: u64,
+        newest_block: BlockTag,
+        reward_percentiles: &[f64],
+    ) -> AppResult<FeeHistory>;
+
+    /// `eth_sendRawTransaction`, returning the transaction hash
+    async fn send_raw_transaction(&self, raw: &[u8]) -> AppResult<H256>;
+
+    /// `eth_getTransactionReceipt`; `None` while the transaction is not mined
+    async fn get_transaction_receipt(&self, hash: H256) -> AppResult<Option<TransactionReceipt>>;
+
+    /// `eth_blockNumber`, the number of the latest block
+    async fn block_number(&self) -> AppResult<u64>;
+
+    /// Hash of the block with this number on the node's current chain, from
+    /// `eth_getBlockByNumber`; `None` if there is no such block yet
+    async fn get_block_hash(&self, number: u64) -> AppResult<Option<H256>>;
+
+    /// Whether the node knows a transaction, pending or mined, from `eth_getTransactionByHash`
+    async fn has_transaction(&self, hash: H256) -> AppResult<bool>;
+}
+
+/// Create the chain client described by the `chain` configuration section. The
+/// in-process chain is refused outside development, where transfers must reach a node.
+pub fn chain_client_from_config(
+    config: &ChainConfig,
+    environment: &str,
+) -> AppResult<Arc<dyn ChainClient>> {
+    if config.rpc_url == MEMORY_RPC_URL {
+        if environment != MEMORY_CHAIN_ENVIRONMENT {
+            return Err(AppError::ConfigError(anyhow::anyhow!(
+                "Chain RPC URL '{}' selects the in-process chain, which is only allowed in {}, not {}",
+                MEMORY_RPC_URL,
+                MEMORY_CHAIN_ENVIRONMENT,
+                environment
+            )));
+        }
+        warn!(
+            "Using in-process chain with ID {}; transfers are not sent to any network",
+            config.chain_id
+        );
+        return Ok(Arc::new(FakeChain::new(config.chain_id)));
+    }
+
+    info!("Using chain {} at {}", config.chain_id, config.rpc_url);
+    Ok(Arc::new(HttpChainClient::new(
+        &config.rpc_url,
+        Duration::from_millis(config.timeout_ms),
+    )?))
+}
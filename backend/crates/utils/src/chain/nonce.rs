@@ -0,0 +1,376 @@
+use app_error::{AppError, AppResult};
+use async_trait::async_trait;
+use redis::{Client, Script, aio::ConnectionManager};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn};
+
+use super::{BlockTag, ChainClient};
+use crate::transaction::{H160, format_address};
+
+/// Key prefix for nonce counters in Redis
+const NONCE_PREFIX: &str = "nonce";
+
+// Hands out the stored nonce and stores the one after it. Starts over from the node's
+// pending count (ARGV[1]) when nothing is stored, the node has caught up, or the node's
+// count has not moved for the gap timeout (ARGV[3], in milliseconds) since the oldest
+// reservation it has not seen. `since` is only restarted by those, never by a later
+// reservation, so an address that keeps sending still gets its gap filled.
+const RESERVE_SCRIPT: &str = r"
+local nonce = tonumber(redis.call('HGET', KEYS[1], 'next'))
+local seen = tonumber(redis.call('HGET', KEYS[1], 'floor'))
+local since = tonumber(redis.call('HGET', KEYS[1], 'since'))
+local floor = tonumber(ARGV[1])
+local now = tonumber(ARGV[2])
+if seen ~= floor then
+    since = now
+end
+if nonce == nil or nonce <= floor or now - since >= tonumber(ARGV[3]) then
+    nonce = floor
+    since = now
+end
+redis.call('HSET', KEYS[1], 'next', nonce + 1, 'floor', floor, 'since', since)
+redis.call('PEXPIRE', KEYS[1], ARGV[4])
+return nonce
+";
+
+// Takes back ARGV[1] if it is the latest nonce handed out
+const RELEASE_SCRIPT: &str = r"
+if tonumber(redis.call('HGET', KEYS[1], 'next')) == tonumber(ARGV[1]) + 1 then
+    redis.call('HSET', KEYS[1], 'next', ARGV[1])
+end
+return 0
+";
+
+/// Whether a node rejected a transaction because its nonce is already used on chain
+pub fn is_nonce_too_low(error: &AppError) -> bool {
+    matches!(error, AppError::ChainError(reason) if reason.to_lowercase().contains("nonce too low"))
+}
+
+/// Shared record of the nonces handed out per sender
+#[async_trait]
+pub trait NonceStore: Send + Sync {
+    /// Hand out the next nonce of `key` and count it as used. `floor` is the node's
+    /// pending count; it is handed out instead when nothing is stored, when it has caught
+    /// up with the stored nonce, or when it has not moved for `gap_timeout` since the
+    /// oldest nonce it has not seen was handed out, which means that nonce never reached
+    /// the node.
+    async fn reserve(&self, key: &str, floor: u64, gap_timeout: Duration) -> AppResult<u64>;
+
+    /// Hand `nonce` out again if it is the latest one handed out for `key`
+    async fn release(&self, key: &str, nonce: u64) -> AppResult<()>;
+
+    /// Forget the nonces of `key`, so the next one comes from the node again
+    async fn reset(&self, key: &str) -> AppResult<()>;
+}
+
+struct MemoryEntry {
+    next: u64,
+    // Node count seen at the last reservation, and since when it has been stuck there
+    floor: u64,
+    since: Instant,
+}
+
+/// Nonce store for a single instance of the service, such as in tests and local development
+#[derive(Default)]
+pub struct MemoryNonceStore {
+    entries: Mutex<HashMap<String, MemoryEntry>>,
+}
+
+impl MemoryNonceStore {
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, MemoryEntry>> {
+        // A panic while holding the lock cannot leave an entry half-updated
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait]
+impl NonceStore for MemoryNonceStore {
+    async fn reserve(&self, key: &str, floor: u64, gap_timeout: Duration) -> AppResult<u64> {
+        let mut entries = self.entries();
+        let now = Instant::now();
+        // Same rules as the Redis script
+        let (nonce, since) = match entries.get(key) {
+            Some(entry) if entry.next > floor => {
+                let since = if entry.floor == floor {
+                    entry.since
+                } else {
+                    now
+                };
+                if now.duration_since(since) < gap_timeout {
+                    (entry.next, since)
+                } else {
+                    (floor, now)
+                }
+            }
+            _ => (floor, now),
+        };
+        entries.insert(
+            key.to_string(),
+            MemoryEntry {
+                next: nonce + 1,
+                floor,
+                since,
+            },
+        );
+        Ok(nonce)
+    }
+
+    async fn release(&self, key: &str, nonce: u64) -> AppResult<()> {
+        if let Some(entry) = self
+            .entries()
+            .get_mut(key)
+            .filter(|entry| entry.next == nonce + 1)
+        {
+            entry.next = nonce;
+        }
+        Ok(())
+    }
+
+    async fn reset(&self, key: &str) -> AppResult<()> {
+        self.entries().remove(key);
+        Ok(())
+    }
+}
+
+/// Nonce store shared by every instance of the service through Redis. Each reservation
+/// runs as one script, so concurrent transfers never get the same nonce.
+#[derive(Clone)]
+pub struct RedisNonceStore {
+    redis_manager: ConnectionManager,
+    /// How long the nonces of an idle address are kept
+    ttl: Duration,
+}
+
+impl RedisNonceStore {
+    /// Connect to Redis
+    pub async fn new(redis_url: &str, ttl: Duration) -> AppResult<Self> {
+        let client = Client::open(redis_url).map_err(|e| {
+            error!("Failed to connect to Redis: {}", e);
+            AppError::ConfigError(anyhow::anyhow!("Redis connection failed: {}", e))
+        })?;
+
+        let manager = ConnectionManager::new(client).await.map_err(|e| {
+            error!("Failed to create Redis connection manager: {}", e);
+            AppError::ConfigError(anyhow::anyhow!("Redis connection manager failed: {}", e))
+        })?;
+
+        info!("Successfully connected to Redis for transaction nonces");
+
+        Ok(Self {
+            redis_manager: manager,
+            ttl,
+        })
+    }
+}
+
+fn redis_error(action: &str, e: redis::RedisError) -> AppError {
+    error!("Redis error when {} a nonce: {}", action, e);
+    AppError::ServerError(anyhow::anyhow!("Nonce tracking error"))
+}
+
+#[async_trait]
+impl NonceStore for RedisNonceStore {
+    async fn reserve(&self, key: &str, floor: u64, gap_timeout: Duration) -> AppResult<u64> {
+        let mut conn = self.redis_manager.clone();
+        let now = chrono::Utc::now().timestamp_millis();
+        Script::new(RESERVE_SCRIPT)
+            .key(key)
+            .arg(floor)
+            .arg(now)
+            .arg(gap_timeout.as_millis() as u64)
+            .arg(self.ttl.as_millis() as u64)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| redis_error("reserving", e))
+    }
+
+    async fn release(&self, key: &str, nonce: u64) -> AppResult<()> {
+        let mut conn = self.redis_manager.clone();
+        let _: i64 = Script::new(RELEASE_SCRIPT)
+            .key(key)
+            .arg(nonce)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| redis_error("releasing", e))?;
+        Ok(())
+    }
+
+    async fn reset(&self, key: &str) -> AppResult<()> {
+        let mut conn = self.redis_manager.clone();
+        redis::cmd("DEL")
+            .arg(key)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| redis_error("resetting", e))
+    }
+}
+
+/// Hands out transaction nonces so concurrent transfers from one address never share one.
+///
+/// Nonces start from the node's pending count and are counted in a `NonceStore` from
+/// there, since the node only learns of a nonce once its transaction is broadcast. The
+/// node's count wins whenever it is ahead, and a nonce that was handed out but never
+/// broadcast is given back or, failing that, handed out again after the gap timeout.
+pub struct NonceManager {
+    store: Arc<dyn NonceStore>,
+    /// Distinguishes the nonces of different chains in a shared store
+    chain_id: u64,
+    gap_timeout: Duration,
+}
+
+impl NonceManager {
+    pub fn new(store: Arc<dyn NonceStore>, chain_id: u64) -> Self {
+        Self {
+            store,
+            chain_id,
+            gap_timeout: Duration::from_secs(60),
+        }
+    }
+
+    /// Set how long reserved nonces may stay ahead of the node before they are reused
+    pub fn with_gap_timeout(mut self, gap_timeout: Duration) -> Self {
+        self.gap_timeout = gap_timeout;
+        self
+    }
+
+    fn key(&self, address: &H160) -> String {
+        format!(
+            "{}:{}:{}",
+            NONCE_PREFIX,
+            self.chain_id,
+            format_address(address)
+        )
+    }
+
+    /// Reserve the nonce of the next transaction from `address`
+    pub async fn next_nonce(
+        &self,
+        chain_client: &dyn ChainClient,
+        address: H160,
+    ) -> AppResult<u64> {
+        let pending = chain_client
+            .get_transaction_count(address, BlockTag::Pending)
+            .await?;
+        let nonce = self
+            .store
+            .reserve(&self.key(&address), pending, self.gap_timeout)
+            .await?;
+        if nonce > pending {
+            debug!(
+                "Nonce {} of {} is ahead of the node's {}",
+                nonce,
+                format_address(&address),
+                pending
+            );
+        }
+        Ok(nonce)
+    }
+
+    /// Give back a nonce whose transaction never reached the node
+    pub async fn release(&self, address: H160, nonce: u64) -> AppResult<()> {
+        self.store.release(&self.key(&address), nonce).await
+    }
+
+    /// Start counting from the node again, after it rejected a nonce as too low
+    pub async fn resync(&self, address: H160) -> AppResult<()> {
+        warn!(
+            "Resyncing nonces of {} from the node",
+            format_address(&address)
+        );
+        self.store.reset(&self.key(&address)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chain::FakeChain;
+
+    fn address() -> H160 {
+        H160::repeat_byte(0x35)
+    }
+
+    fn manager(gap_timeout: Duration) -> NonceManager {
+        NonceManager::new(Arc::new(MemoryNonceStore::default()), 1961).with_gap_timeout(gap_timeout)
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_reservations_get_distinct_nonces() {
+        let chain = Arc::new(FakeChain::new(1961));
+        let manager = Arc::new(manager(Duration::from_secs(60)));
+
+        let handles: Vec<_> = (0..20)
+            .map(|_| {
+                let (chain, manager) = (chain.clone(), manager.clone());
+                tokio::spawn(async move { manager.next_nonce(chain.as_ref(), address()).await })
+            })
+            .collect();
+        let mut nonces = Vec::new();
+        for handle in handles {
+            nonces.push(handle.await.unwrap().unwrap());
+        }
+        nonces.sort();
+        assert_eq!(nonces, (0..20).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_release_and_resync() {
+        let chain = FakeChain::new(1961);
+        let manager = manager(Duration::from_secs(60));
+
+        assert_eq!(manager.next_nonce(&chain, address()).await.unwrap(), 0);
+        assert_eq!(manager.next_nonce(&chain, address()).await.unwrap(), 1);
+
+        // Only the latest nonce can be given back
+        manager.release(address(), 0).await.unwrap();
+        assert_eq!(manager.next_nonce(&chain, address()).await.unwrap(), 2);
+        manager.release(address(), 2).await.unwrap();
+        assert_eq!(manager.next_nonce(&chain, address()).await.unwrap(), 2);
+
+        // Resyncing starts over from the node
+        manager.resync(address()).await.unwrap();
+        assert_eq!(manager.next_nonce(&chain, address()).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_gaps_are_reused_after_the_timeout() {
+        let chain = FakeChain::new(1961);
+        let manager = manager(Duration::ZERO);
+
+        // Reserved but never broadcast, so the node is still at 0
+        assert_eq!(manager.next_nonce(&chain, address()).await.unwrap(), 0);
+        assert_eq!(manager.next_nonce(&chain, address()).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_gaps_are_reused_while_the_address_keeps_sending() {
+        let chain = FakeChain::new(1961);
+        let manager = manager(Duration::from_millis(100));
+
+        // Later reservations do not restart the timeout of the one the node never saw
+        assert_eq!(manager.next_nonce(&chain, address()).await.unwrap(), 0);
+        let mut nonces = Vec::new();
+        for _ in 0..8 {
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            nonces.push(manager.next_nonce(&chain, address()).await.unwrap());
+        }
+        assert!(nonces.contains(&0), "gap never filled: {:?}", nonces);
+    }
+
+    #[test]
+    fn test_nonce_too_low_errors() {
+        assert!(is_nonce_too_low(&AppError::ChainError(
+            "Nonce too low: next nonce 5, tx nonce 4".to_string()
+        )));
+        assert!(!is_nonce_too_low(&AppError::ChainError(
+            "nonce too high".to_string()
+        )));
+        assert!(!is_nonce_too_low(&AppError::NetworkError(
+            "nonce too low".to_string()
+        )));
+    }
+}
@@ -0,0 +1,197 @@
+use app_config::DekCacheConfig;
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use zeroize::Zeroizing;
+
+// Defaults used when the cache is not configured explicitly
+const DEFAULT_CAPACITY: usize = 1024;
+const DEFAULT_TTL: Duration = Duration::from_secs(300);
+
+struct CachedDek {
+    dek: Zeroizing<Vec<u8>>,
+    cached_at: Instant,
+}
+
+/// Point-in-time counters of a `DekCache`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DekCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    // Entries dropped to make room for newer ones
+    pub evictions: u64,
+    // Entries dropped because they outlived the TTL
+    pub expirations: u64,
+    pub size: usize,
+    pub capacity: usize,
+}
+
+/// Cache of plaintext DEKs so that the master key (possibly a remote KMS) is not asked
+/// to unwrap the same DEK on every request. Holds at most `capacity` DEKs, evicting the
+/// least recently used one, and forgets each DEK `ttl` after it was cached. DEKs are
+/// zeroized when they are evicted, expire, are purged or the cache is dropped.
+pub struct DekCache {
+    entries: Mutex<LruCache<String, CachedDek>>,
+    ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+}
+
+impl DekCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+            ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            expirations: AtomicU64::new(0),
+        }
+    }
+
+    pub fn from_config(config: &DekCacheConfig) -> Self {
+        Self::new(config.capacity, Duration::from_secs(config.ttl_secs))
+    }
+
+    pub async fn get(&self, dek_id: &str) -> Option<Zeroizing<Vec<u8>>> {
+        let mut entries = self.entries.lock().await;
+
+        let expired = match entries.get(dek_id) {
+            Some(entry) if entry.cached_at.elapsed() < self.ttl => {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Some(entry.dek.clone());
+            }
+            Some(_) => true,
+            None => false,
+        };
+
+        if expired {
+            entries.pop(dek_id);
+            self.expirations.fetch_add(1, Ordering::Relaxed);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    pub async fn set(&self, dek_id: String, dek: Vec<u8>) {
+        let entry = CachedDek {
+            dek: Zeroizing::new(dek),
+            cached_at: Instant::now(),
+        };
+
+        let mut entries = self.entries.lock().await;
+        // `push` hands back the least recently used entry when the cache is full,
+        // or the previous entry for the same ID, which is simply replaced
+        let displaced = entries.push(dek_id.clone(), entry);
+        if displaced.is_some_and(|(evicted_id, _)| evicted_id != dek_id) {
+            self.evictions.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Forget a single DEK
+    pub async fn remove(&self, dek_id: &str) -> bool {
+        self.entries.lock().await.pop(dek_id).is_some()
+    }
+
+    /// Forget every cached DEK, returning how many were dropped
+    pub async fn purge(&self) -> usize {
+        let mut entries = self.entries.lock().await;
+        let purged = entries.len();
+        entries.clear();
+        purged
+    }
+
+    pub async fn stats(&self) -> DekCacheStats {
+        let entries = self.entries.lock().await;
+
+        DekCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            expirations: self.expirations.load(Ordering::Relaxed),
+            size: entries.len(),
+            capacity: entries.cap().get(),
+        }
+    }
+}
+
+impl Default for DekCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY, DEFAULT_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_hits_and_misses_are_counted() {
+        let cache = DekCache::default();
+        cache.set("dek-1".to_string(), vec![1u8; 32]).await;
+
+        assert_eq!(cache.get("dek-1").await.unwrap().as_slice(), &[1u8; 32]);
+        assert!(cache.get("dek-2").await.is_none());
+
+        let stats = cache.stats().await;
+        assert_eq!((stats.hits, stats.misses, stats.size), (1, 1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_dek_is_evicted() {
+        let cache = DekCache::new(2, DEFAULT_TTL);
+        cache.set("dek-1".to_string(), vec![1u8; 32]).await;
+        cache.set("dek-2".to_string(), vec![2u8; 32]).await;
+
+        // Touch dek-1 so dek-2 becomes the least recently used
+        assert!(cache.get("dek-1").await.is_some());
+        cache.set("dek-3".to_string(), vec![3u8; 32]).await;
+
+        assert!(cache.get("dek-2").await.is_none());
+        assert!(cache.get("dek-1").await.is_some());
+        assert!(cache.get("dek-3").await.is_some());
+
+        let stats = cache.stats().await;
+        assert_eq!((stats.evictions, stats.size, stats.capacity), (1, 2, 2));
+    }
+
+    #[tokio::test]
+    async fn test_replacing_a_dek_is_not_an_eviction() {
+        let cache = DekCache::new(2, DEFAULT_TTL);
+        cache.set("dek-1".to_string(), vec![1u8; 32]).await;
+        cache.set("dek-1".to_string(), vec![9u8; 32]).await;
+
+        assert_eq!(cache.get("dek-1").await.unwrap().as_slice(), &[9u8; 32]);
+        assert_eq!(cache.stats().await.evictions, 0);
+    }
+
+    #[tokio::test]
+    async fn test_expired_dek_is_dropped() {
+        let cache = DekCache::new(4, Duration::from_millis(20));
+        cache.set("dek-1".to_string(), vec![1u8; 32]).await;
+
+        std::thread::sleep(Duration::from_millis(40));
+
+        assert!(cache.get("dek-1").await.is_none());
+        let stats = cache.stats().await;
+        assert_eq!((stats.expirations, stats.misses, stats.size), (1, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_purge_and_remove() {
+        let cache = DekCache::default();
+        cache.set("dek-1".to_string(), vec![1u8; 32]).await;
+        cache.set("dek-2".to_string(), vec![2u8; 32]).await;
+
+        assert!(cache.remove("dek-1").await);
+        assert!(!cache.remove("dek-1").await);
+        assert_eq!(cache.purge().await, 1);
+        assert!(cache.get("dek-2").await.is_none());
+    }
+}
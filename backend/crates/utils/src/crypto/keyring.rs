@@ -0,0 +1,165 @@
+use app_config::{EncryptSecretsConfig, KeyProviderConfig};
+use app_error::{AppError, AppResult};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::provider::{KeyProvider, MasterKey};
+use super::transit::TransitKeyProvider;
+
+/// The set of master keys known to the service: one primary key that all new
+/// records are wrapped with, plus retired keys that are only used to unwrap
+/// records that have not been re-wrapped to the primary yet
+#[derive(Debug, Clone)]
+pub struct MasterKeyRing {
+    primary: Arc<dyn KeyProvider>,
+    retired: HashMap<String, Arc<dyn KeyProvider>>,
+}
+
+impl MasterKeyRing {
+    /// Create a key ring with only a primary key
+    pub fn new(primary: impl KeyProvider + 'static) -> Self {
+        Self::with_primary(Arc::new(primary))
+    }
+
+    fn with_primary(primary: Arc<dyn KeyProvider>) -> Self {
+        Self {
+            primary,
+            retired: HashMap::new(),
+        }
+    }
+
+    /// Add a retired key that can still decrypt existing records
+    pub fn with_retired_key(self, key: impl KeyProvider + 'static) -> AppResult<Self> {
+        self.with_retired(Arc::new(key))
+    }
+
+    fn with_retired(mut self, key: Arc<dyn KeyProvider>) -> AppResult<Self> {
+        if key.key_id() == self.primary.key_id() || self.retired.contains_key(key.key_id()) {
+            return Err(AppError::ConfigError(anyhow::anyhow!(
+                "Duplicate master key name '{}'",
+                key.key_id()
+            )));
+        }
+
+        self.retired.insert(key.key_id().to_string(), key);
+        Ok(self)
+    }
+
+    /// Build the key ring from the `encrypt_secrets` configuration section
+    pub fn from_config(config: &EncryptSecretsConfig) -> AppResult<Self> {
+        let primary = Self::load_provider(
+            &config.master_key_name,
+            &config.master_key,
+            &config.provider,
+        )?;
+
+        config
+            .retired_master_keys
+            .iter()
+            .try_fold(Self::with_primary(primary), |ring, retired| {
+                ring.with_retired(Self::load_provider(
+                    &retired.name,
+                    &retired.key,
+                    &retired.provider,
+                )?)
+            })
+    }
+
+    /// Create the provider for one configured master key
+    fn load_provider(
+        name: &str,
+        inline_key: &str,
+        provider: &KeyProviderConfig,
+    ) -> AppResult<Arc<dyn KeyProvider>> {
+        Ok(match provider {
+            KeyProviderConfig::Inline => Arc::new(MasterKey::new(name, inline_key.as_bytes())),
+            KeyProviderConfig::File { path } => Arc::new(MasterKey::from_file(name, path)?),
+            KeyProviderConfig::Env { var } => Arc::new(MasterKey::from_env(name, var)?),
+            KeyProviderConfig::Transit {
+                url,
+                key_name,
+                token_env,
+                timeout_ms,
+            } => Arc::new(TransitKeyProvider::from_env(
+                name,
+                url,
+                key_name,
+                token_env,
+                Duration::from_millis(*timeout_ms),
+            )?),
+        })
+    }
+
+    /// The key used for all new encryption
+    pub fn primary(&self) -> &Arc<dyn KeyProvider> {
+        &self.primary
+    }
+
+    /// Look up a key by identifier, whether primary or retired
+    pub fn get(&self, id: &str) -> Option<&Arc<dyn KeyProvider>> {
+        if self.primary.key_id() == id {
+            Some(&self.primary)
+        } else {
+            self.retired.get(id)
+        }
+    }
+
+    /// Whether `id` names the primary key
+    pub fn is_primary(&self, id: &str) -> bool {
+        self.primary.key_id() == id
+    }
+
+    /// Identifiers of the retired keys
+    pub fn retired_ids(&self) -> impl Iterator<Item = &str> {
+        self.retired.keys().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use app_config::RetiredMasterKeyConfig;
+
+    #[test]
+    fn test_from_config_loads_primary_and_retired_keys() {
+        let config = EncryptSecretsConfig {
+            master_key_name: "key-2".to_string(),
+            master_key: "second".to_string(),
+            provider: KeyProviderConfig::Inline,
+            retired_master_keys: vec![RetiredMasterKeyConfig {
+                name: "key-1".to_string(),
+                key: "first".to_string(),
+                provider: KeyProviderConfig::Inline,
+            }],
+            rotation: Default::default(),
+            dek_cache: Default::default(),
+            pin_kdf: Default::default(),
+        };
+
+        let ring = MasterKeyRing::from_config(&config).unwrap();
+        assert_eq!(ring.primary().key_id(), "key-2");
+        assert!(ring.is_primary("key-2"));
+        assert!(!ring.is_primary("key-1"));
+        assert_eq!(ring.get("key-1").unwrap().key_id(), "key-1");
+        assert!(ring.get("key-3").is_none());
+    }
+
+    #[test]
+    fn test_duplicate_key_names_are_rejected() {
+        let ring = MasterKeyRing::new(MasterKey::new("key-1", b"first"));
+        assert!(
+            ring.clone()
+                .with_retired_key(MasterKey::new("key-1", b"other"))
+                .is_err()
+        );
+
+        let ring = ring
+            .with_retired_key(MasterKey::new("key-0", b"zero"))
+            .unwrap();
+        assert!(
+            ring.with_retired_key(MasterKey::new("key-0", b"again"))
+                .is_err()
+        );
+    }
+}
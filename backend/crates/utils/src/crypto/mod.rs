@@ -0,0 +1,1403 @@
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use app_error::{AppError, AppResult};
+use hex;
+use rand::{RngCore, rng};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tracing::error;
+use uuid::Uuid;
+use zeroize::Zeroizing;
+
+use crate::secret::{Pin, PrivateKey, WalletSecret};
+
+mod dek_cache;
+mod keyring;
+mod pin_kdf;
+mod provider;
+mod transit;
+pub use dek_cache::{DekCache, DekCacheStats};
+pub use keyring::MasterKeyRing;
+pub use pin_kdf::PinKdf;
+pub use provider::{KeyProvider, MasterKey, WrappedDek};
+pub use transit::TransitKeyProvider;
+
+// Constants for encryption
+const SALT_LENGTH: usize = 16;
+const IV_LENGTH: usize = 12;
+const KEY_LENGTH: usize = 32; // 256 bits
+const TAG_LENGTH: usize = 16; // GCM authentication tag
+
+/// Envelope format byte: AES-256-GCM without associated data
+pub const ENVELOPE_VERSION_V1: u8 = 0x01;
+/// Envelope format byte: AES-256-GCM bound to the wallet, DEK and master key IDs
+pub const ENVELOPE_VERSION_V2: u8 = 0x02;
+/// Format byte prepended to every newly written ciphertext blob
+pub const ENVELOPE_VERSION_CURRENT: u8 = ENVELOPE_VERSION_V2;
+
+// The legacy scheme wrote DEKs without a version byte, so their blobs are one byte shorter
+const LEGACY_WRAPPED_DEK_LENGTH: usize = KEY_LENGTH + TAG_LENGTH;
+
+/// Algorithm of records written by `encrypt_wallet_secret`
+pub const RECORD_ALGORITHM: &str = "AES-256-GCM";
+/// Algorithm of migrated records whose PIN layer is still the legacy XOR blob, which is
+/// replaced the next time the PIN is used
+pub const LEGACY_PIN_RECORD_ALGORITHM: &str = "AES-256-GCM+legacy-pin";
+
+/// Wallet encryption service for handling the secure storage of wallet private keys
+pub struct WalletEncryptionService {
+    // Primary master key for new records plus retired keys for existing ones
+    key_ring: Arc<MasterKeyRing>,
+    // Bounded, expiring in-memory cache of data encryption keys
+    dek_cache: Arc<DekCache>,
+    // PIN key derivation used for new records
+    pin_kdf: PinKdf,
+}
+
+impl WalletEncryptionService {
+    /// Creates a new WalletEncryptionService instance with a single master key
+    pub fn new(master_key_id: &str, master_key: &[u8]) -> Self {
+        Self::from_key_ring(MasterKeyRing::new(MasterKey::new(
+            master_key_id,
+            master_key,
+        )))
+    }
+
+    /// Creates a new WalletEncryptionService instance backed by a master key ring
+    pub fn from_key_ring(key_ring: MasterKeyRing) -> Self {
+        Self {
+            key_ring: Arc::new(key_ring),
+            dek_cache: Arc::new(DekCache::default()),
+            pin_kdf: PinKdf::default(),
+        }
+    }
+
+    /// Set the KDF and cost parameters used for the PIN layer of new records
+    pub fn with_pin_kdf(mut self, pin_kdf: PinKdf) -> Self {
+        self.pin_kdf = pin_kdf;
+        self
+    }
+
+    /// Whether a record's PIN layer was derived with weaker parameters than the current
+    /// ones, or is still a legacy blob, and should be re-encrypted the next time the PIN
+    /// is known
+    pub fn needs_pin_upgrade(&self, encrypted_data: &WalletEncryptedData) -> bool {
+        encrypted_data.has_legacy_pin_layer()
+            || PinKdf::parse(&encrypted_data.pin_kdf)
+                .map(|kdf| kdf.is_weaker_than(&self.pin_kdf))
+                .unwrap_or(true)
+    }
+
+    /// Replace the default DEK cache, e.g. with one sized from configuration
+    pub fn with_dek_cache(mut self, dek_cache: DekCache) -> Self {
+        self.dek_cache = Arc::new(dek_cache);
+        self
+    }
+
+    /// Hit, miss and eviction counters of the DEK cache
+    pub async fn dek_cache_stats(&self) -> DekCacheStats {
+        self.dek_cache.stats().await
+    }
+
+    /// Drop every cached plaintext DEK, returning how many were dropped
+    pub async fn purge_dek_cache(&self) -> usize {
+        self.dek_cache.purge().await
+    }
+
+    /// Identifier of the primary master key, which all new records are wrapped with
+    pub fn master_key_id(&self) -> &str {
+        self.key_ring.primary().key_id()
+    }
+
+    /// Whether a record is wrapped with a retired master key and should be re-wrapped
+    pub fn needs_rewrap(&self, encrypted_data: &WalletEncryptedData) -> bool {
+        !self
+            .key_ring
+            .is_primary(&encrypted_data.master_key_identifier)
+    }
+
+    /// Encrypt a single private key with user PIN and then with DEK and master key
+    pub async fn encrypt_private_key(
+        &self,
+        private_key: &PrivateKey,
+        pin: &Pin,
+        wallet_id: &str,
+    ) -> AppResult<WalletEncryptedData> {
+        self.encrypt_wallet_secret(
+            &WalletSecret::PrivateKey(private_key.clone()),
+            pin,
+            wallet_id,
+        )
+        .await
+    }
+
+    /// Encrypt a wallet's seed or private key with user PIN and then with DEK and master key.
+    /// Every layer is bound to `wallet_id`, so the result only decrypts for that wallet.
+    pub async fn encrypt_wallet_secret(
+        &self,
+        secret: &WalletSecret,
+        pin: &Pin,
+        wallet_id: &str,
+    ) -> AppResult<WalletEncryptedData> {
+        // Step 1: PIN encryption - derive a key from the PIN
+        let pin_salt = Self::generate_random_bytes(SALT_LENGTH);
+        let pin_key = Self::derive_pin_key(self.pin_kdf, pin, &pin_salt).await?;
+
+        // Step 2: Encrypt the secret with the PIN-derived key
+        let pin_iv = Self::generate_random_bytes(IV_LENGTH);
+        let pin_aad = Self::pin_layer_aad(wallet_id);
+        let encoded_secret = secret.encode();
+        let pin_encrypted = Self::seal_envelope(
+            encoded_secret.expose_secret().as_bytes(),
+            &pin_key,
+            &pin_iv,
+            &pin_aad,
+        )?;
+
+        // Step 3: Generate a random DEK (Data Encryption Key)
+        let dek = Self::generate_random_bytes(KEY_LENGTH);
+
+        // Step 4: Encrypt the PIN-encrypted data with the DEK and wrap the DEK
+        let encrypted_data = self
+            .wrap_with_new_dek(
+                wallet_id,
+                &pin_encrypted,
+                dek,
+                self.pin_kdf.to_string(),
+                hex::encode(pin_salt),
+                hex::encode(pin_iv),
+            )
+            .await?;
+
+        self.seal_record(encrypted_data).await
+    }
+
+    /// Decrypt a record holding a single private key
+    pub async fn decrypt_private_key(
+        &self,
+        encrypted_data: &WalletEncryptedData,
+        pin: &Pin,
+    ) -> AppResult<PrivateKey> {
+        match self.decrypt_wallet_secret(encrypted_data, pin).await? {
+            WalletSecret::PrivateKey(private_key) => Ok(private_key),
+            WalletSecret::Seed(_) => Err(AppError::ValidationError(
+                "Wallet holds an HD seed rather than a single private key".to_string(),
+            )),
+        }
+    }
+
+    /// Decrypt a wallet's seed or private key using the reverse process
+    pub async fn decrypt_wallet_secret(
+        &self,
+        encrypted_data: &WalletEncryptedData,
+        pin: &Pin,
+    ) -> AppResult<WalletSecret> {
+        // Reject records whose fields were modified or moved between wallets
+        self.verify_integrity(encrypted_data).await?;
+
+        // Steps 1-2: Unwrap the DEK and strip the DEK layer
+        let pin_encrypted = self.unwrap_dek_layer(encrypted_data).await?;
+
+        // Step 3: Derive the key from the PIN
+        let pin_salt = hex::decode(&encrypted_data.pin_salt)
+            .map_err(|_| AppError::ValidationError("Invalid PIN salt format".to_string()))?;
+        let pin_kdf = PinKdf::parse(&encrypted_data.pin_kdf)?;
+        let pin_key = Self::derive_pin_key(pin_kdf, pin, &pin_salt).await?;
+
+        // Step 4: Decrypt the PIN-encrypted data
+        let pin_iv = hex::decode(&encrypted_data.pin_iv)
+            .map_err(|_| AppError::ValidationError("Invalid PIN IV format".to_string()))?;
+        let pin_aad = Self::pin_layer_aad(&encrypted_data.wallet_id);
+
+        let encoded_secret = Zeroizing::new(Self::open_pin_layer(
+            &pin_encrypted,
+            &pin_key,
+            &pin_iv,
+            &pin_aad,
+            encrypted_data.has_legacy_pin_layer(),
+        )?);
+
+        // The PIN layer holds the encoded seed or key
+        std::str::from_utf8(&encoded_secret)
+            .map_err(|_| AppError::ValidationError("Invalid private key data".to_string()))
+            .and_then(WalletSecret::decode)
+    }
+
+    /// Re-encrypt a record written by an older scheme into the current envelope format,
+    /// bound to the record's wallet and sealed with an integrity MAC.
+    ///
+    /// The master and DEK layers are replaced with a fresh DEK. The PIN layer cannot be
+    /// opened without the user's PIN, so a legacy one is carried over inside the new DEK
+    /// layer and the record is marked with `LEGACY_PIN_RECORD_ALGORITHM` until the PIN
+    /// layer is re-encrypted.
+    pub async fn migrate_legacy_record(
+        &self,
+        encrypted_data: &WalletEncryptedData,
+    ) -> AppResult<WalletEncryptedData> {
+        if !encrypted_data.is_legacy() {
+            return Err(AppError::ValidationError(
+                "Encrypted data is already in the current format".to_string(),
+            ));
+        }
+
+        if encrypted_data.wallet_id.is_empty() {
+            return Err(AppError::ValidationError(
+                "Encrypted data is not associated with a wallet".to_string(),
+            ));
+        }
+
+        self.verify_integrity(encrypted_data).await?;
+        let pin_encrypted = self.unwrap_dek_layer(encrypted_data).await?;
+
+        let dek = Self::generate_random_bytes(KEY_LENGTH);
+        let mut migrated = self
+            .wrap_with_new_dek(
+                &encrypted_data.wallet_id,
+                &pin_encrypted,
+                dek,
+                encrypted_data.pin_kdf.clone(),
+                encrypted_data.pin_salt.clone(),
+                encrypted_data.pin_iv.clone(),
+            )
+            .await?;
+        if encrypted_data.has_legacy_pin_layer() {
+            migrated.algorithm = LEGACY_PIN_RECORD_ALGORITHM.to_string();
+        }
+
+        Ok(self
+            .seal_record(migrated)
+            .await?
+            .with_user_id(&encrypted_data.user_id))
+    }
+
+    /// Re-wrap the DEK of a record written under a retired master key with the primary
+    /// master key. Only the master layer changes; the DEK, the DEK layer and the PIN
+    /// layer are kept, so no PIN is needed.
+    pub async fn rewrap_dek(
+        &self,
+        encrypted_data: &WalletEncryptedData,
+    ) -> AppResult<WalletEncryptedData> {
+        if encrypted_data.is_legacy() {
+            return Err(AppError::ValidationError(
+                "Legacy records must be migrated before they can be re-wrapped".to_string(),
+            ));
+        }
+
+        self.verify_integrity(encrypted_data).await?;
+        let dek = self.unwrap_dek(encrypted_data).await?;
+
+        let primary = self.key_ring.primary();
+        let master_aad = Self::master_layer_aad(
+            &encrypted_data.wallet_id,
+            &encrypted_data.dek_id,
+            primary.key_id(),
+        );
+        let wrapped = primary.wrap_dek(&dek, &master_aad).await?;
+
+        let mut rewrapped = encrypted_data.clone();
+        rewrapped.encrypted_dek = hex::encode(wrapped.ciphertext);
+        rewrapped.master_key_identifier = primary.key_id().to_string();
+        rewrapped.master_iv = hex::encode(wrapped.iv);
+
+        self.seal_record(rewrapped).await
+    }
+
+    /// Check the integrity MAC of a record. Only records from before versioned envelopes
+    /// may lack one, so they can still be read and migrated.
+    pub async fn verify_integrity(&self, encrypted_data: &WalletEncryptedData) -> AppResult<()> {
+        let master_key = self.master_key_for(encrypted_data)?;
+
+        if encrypted_data.integrity_mac.is_empty() {
+            if encrypted_data.is_pre_envelope() {
+                return Ok(());
+            }
+            error!(
+                "Wallet key record of wallet {} has no integrity MAC",
+                encrypted_data.wallet_id
+            );
+            return Err(AppError::IntegrityError(
+                "Wallet key record is missing its integrity MAC".to_string(),
+            ));
+        }
+
+        let mac = hex::decode(&encrypted_data.integrity_mac).map_err(|_| {
+            AppError::IntegrityError("Wallet key record has a malformed integrity MAC".to_string())
+        })?;
+
+        if !master_key
+            .verify_record_mac(&Self::record_mac_input(encrypted_data), &mac)
+            .await?
+        {
+            error!(
+                "Integrity check failed for wallet key record of wallet {}",
+                encrypted_data.wallet_id
+            );
+            return Err(AppError::IntegrityError(
+                "Wallet key record failed its integrity check".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The master key a record was wrapped with, which may be primary or retired
+    fn master_key_for(
+        &self,
+        encrypted_data: &WalletEncryptedData,
+    ) -> AppResult<&Arc<dyn KeyProvider>> {
+        self.key_ring
+            .get(&encrypted_data.master_key_identifier)
+            .ok_or_else(|| AppError::ValidationError("Invalid master key identifier".to_string()))
+    }
+
+    /// Encrypt PIN-layer ciphertext under a new DEK and wrap that DEK with the master key.
+    /// The record still has to be sealed with `seal_record`.
+    async fn wrap_with_new_dek(
+        &self,
+        wallet_id: &str,
+        pin_encrypted: &[u8],
+        dek: Vec<u8>,
+        pin_kdf: String,
+        pin_salt: String,
+        pin_iv: String,
+    ) -> AppResult<WalletEncryptedData> {
+        let dek_id = Uuid::new_v4().to_string();
+
+        let dek_iv = Self::generate_random_bytes(IV_LENGTH);
+        let dek_aad = Self::dek_layer_aad(wallet_id, &dek_id);
+        let dek_encrypted = Self::seal_envelope(pin_encrypted, &dek, &dek_iv, &dek_aad)?;
+
+        let primary = self.key_ring.primary();
+        let master_aad = Self::master_layer_aad(wallet_id, &dek_id, primary.key_id());
+        let wrapped = primary.wrap_dek(&dek, &master_aad).await?;
+
+        // Cache the DEK for future use
+        self.dek_cache.set(dek_id.clone(), dek).await;
+
+        Ok(WalletEncryptedData {
+            user_id: "".to_string(), // Set this when associating with a user
+            wallet_id: wallet_id.to_string(),
+            encrypted_private_key: hex::encode(dek_encrypted),
+            encrypted_dek: hex::encode(wrapped.ciphertext),
+            master_key_identifier: primary.key_id().to_string(),
+            dek_id,
+            algorithm: RECORD_ALGORITHM.to_string(),
+            pin_kdf,
+            pin_salt,
+            pin_iv,
+            dek_iv: hex::encode(dek_iv),
+            master_iv: hex::encode(wrapped.iv),
+            integrity_mac: "".to_string(),
+        })
+    }
+
+    /// Compute the integrity MAC of a record with the primary master key
+    async fn seal_record(
+        &self,
+        mut encrypted_data: WalletEncryptedData,
+    ) -> AppResult<WalletEncryptedData> {
+        encrypted_data.integrity_mac = hex::encode(
+            self.key_ring
+                .primary()
+                .record_mac(&Self::record_mac_input(&encrypted_data))
+                .await?,
+        );
+
+        Ok(encrypted_data)
+    }
+
+    /// Recover the DEK of a record, from cache or via the master key it was wrapped with
+    async fn unwrap_dek(
+        &self,
+        encrypted_data: &WalletEncryptedData,
+    ) -> AppResult<Zeroizing<Vec<u8>>> {
+        let dek_id = &encrypted_data.dek_id;
+
+        // Try to get DEK from cache first
+        if let Some(dek) = self.dek_cache.get(dek_id).await {
+            return Ok(dek);
+        }
+
+        // If not in cache, decrypt it using the master key
+        let master_key = self.master_key_for(encrypted_data)?;
+        let encrypted_dek = hex::decode(&encrypted_data.encrypted_dek)
+            .map_err(|_| AppError::ValidationError("Invalid DEK format".to_string()))?;
+        let master_iv = hex::decode(&encrypted_data.master_iv)
+            .map_err(|_| AppError::ValidationError("Invalid master IV format".to_string()))?;
+
+        let dek = if encrypted_data.is_pre_envelope() {
+            master_key
+                .unwrap_legacy_dek(&encrypted_dek, &master_iv)
+                .await
+                .map_err(Self::legacy_failure)?
+        } else {
+            let master_aad =
+                Self::master_layer_aad(&encrypted_data.wallet_id, dek_id, master_key.key_id());
+            let wrapped = WrappedDek {
+                ciphertext: encrypted_dek,
+                iv: master_iv,
+            };
+            master_key.unwrap_dek(&wrapped, &master_aad).await?
+        };
+        let dek = Zeroizing::new(dek);
+
+        // Add to cache for future use
+        self.dek_cache.set(dek_id.clone(), dek.to_vec()).await;
+
+        Ok(dek)
+    }
+
+    /// Recover the DEK and strip the DEK layer, returning the PIN-layer ciphertext
+    async fn unwrap_dek_layer(&self, encrypted_data: &WalletEncryptedData) -> AppResult<Vec<u8>> {
+        let legacy = encrypted_data.is_pre_envelope();
+        let wallet_id = &encrypted_data.wallet_id;
+        let dek_id = &encrypted_data.dek_id;
+
+        let dek = self.unwrap_dek(encrypted_data).await?;
+
+        // Decrypt the encrypted private key with the DEK
+        let dek_encrypted = hex::decode(&encrypted_data.encrypted_private_key)
+            .map_err(|_| AppError::ValidationError("Invalid encrypted data format".to_string()))?;
+        let dek_iv = hex::decode(&encrypted_data.dek_iv)
+            .map_err(|_| AppError::ValidationError("Invalid DEK IV format".to_string()))?;
+
+        if legacy {
+            Self::legacy_decrypt(&dek_encrypted, &dek, &dek_iv).map_err(Self::legacy_failure)
+        } else {
+            let dek_aad = Self::dek_layer_aad(wallet_id, dek_id);
+            Self::open_envelope(&dek_encrypted, &dek, &dek_iv, &dek_aad)
+        }
+    }
+
+    /// Records from before versioned envelopes carry no MAC, so a layer of one that does
+    /// not decrypt may have been tampered with and is reported as loudly as a bad MAC
+    fn legacy_failure(err: AppError) -> AppError {
+        match err {
+            AppError::ValidationError(message) => {
+                error!("Legacy wallet key record failed to decrypt: {}", message);
+                AppError::IntegrityError(format!(
+                    "Legacy wallet key record failed to decrypt: {}",
+                    message
+                ))
+            }
+            err => err,
+        }
+    }
+
+    /// Generate random bytes for cryptographic operations
+    fn generate_random_bytes(length: usize) -> Vec<u8> {
+        let mut bytes = vec![0u8; length];
+        rng().fill_bytes(&mut bytes);
+        bytes
+    }
+
+    /// Every stored field of a wallet key record, encoded as the input to its integrity MAC
+    fn record_mac_input(encrypted_data: &WalletEncryptedData) -> Vec<u8> {
+        let mut fields: Vec<&[u8]> = vec![
+            encrypted_data.wallet_id.as_bytes(),
+            encrypted_data.encrypted_private_key.as_bytes(),
+            encrypted_data.encrypted_dek.as_bytes(),
+            encrypted_data.master_key_identifier.as_bytes(),
+            encrypted_data.dek_id.as_bytes(),
+            encrypted_data.algorithm.as_bytes(),
+            encrypted_data.pin_salt.as_bytes(),
+            encrypted_data.pin_iv.as_bytes(),
+            encrypted_data.dek_iv.as_bytes(),
+            encrypted_data.master_iv.as_bytes(),
+        ];
+        // Records written before the PIN KDF was stored keep their original MAC input
+        if !encrypted_data.pin_kdf.is_empty() {
+            fields.push(encrypted_data.pin_kdf.as_bytes());
+        }
+        Self::encode_fields(&fields)
+    }
+
+    /// Derive the PIN-layer key. Argon2id is deliberately slow, so keep it off the async
+    /// workers.
+    async fn derive_pin_key(
+        pin_kdf: PinKdf,
+        pin: &Pin,
+        salt: &[u8],
+    ) -> AppResult<Zeroizing<Vec<u8>>> {
+        let pin = pin.clone();
+        let salt = salt.to_vec();
+        tokio::task::spawn_blocking(move || {
+            pin_kdf.derive_key(pin.expose_secret().as_bytes(), &salt)
+        })
+        .await
+        .map_err(|e| AppError::CryptoError(format!("Failed to derive key from PIN: {}", e)))?
+    }
+
+    /// Associated data for the PIN layer
+    fn pin_layer_aad(wallet_id: &str) -> Vec<u8> {
+        Self::encode_fields(&[b"pin", wallet_id.as_bytes()])
+    }
+
+    /// Associated data for the DEK layer
+    fn dek_layer_aad(wallet_id: &str, dek_id: &str) -> Vec<u8> {
+        Self::encode_fields(&[b"dek", wallet_id.as_bytes(), dek_id.as_bytes()])
+    }
+
+    /// Associated data for the master key layer
+    fn master_layer_aad(wallet_id: &str, dek_id: &str, master_key_id: &str) -> Vec<u8> {
+        Self::encode_fields(&[
+            b"master",
+            wallet_id.as_bytes(),
+            dek_id.as_bytes(),
+            master_key_id.as_bytes(),
+        ])
+    }
+
+    /// Length-prefix each field so that different field splits never encode the same bytes
+    fn encode_fields(fields: &[&[u8]]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for field in fields {
+            encoded.extend_from_slice(&(field.len() as u32).to_be_bytes());
+            encoded.extend_from_slice(field);
+        }
+        encoded
+    }
+
+    /// AES-256-GCM encryption into a versioned envelope: `version || ciphertext || tag`
+    fn seal_envelope(data: &[u8], key: &[u8], iv: &[u8], aad: &[u8]) -> AppResult<Vec<u8>> {
+        if iv.len() != IV_LENGTH {
+            return Err(AppError::CryptoError("Invalid IV length".to_string()));
+        }
+
+        let cipher = Aes256Gcm::new_from_slice(key)
+            .map_err(|_| AppError::CryptoError("Invalid encryption key length".to_string()))?;
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(iv), Payload { msg: data, aad })
+            .map_err(|_| AppError::CryptoError("Encryption failed".to_string()))?;
+
+        let mut envelope = Vec::with_capacity(1 + ciphertext.len());
+        envelope.push(ENVELOPE_VERSION_CURRENT);
+        envelope.extend_from_slice(&ciphertext);
+        Ok(envelope)
+    }
+
+    /// AES-256-GCM decryption of a versioned envelope. Version 1 envelopes predate
+    /// associated data and are opened without it.
+    fn open_envelope(envelope: &[u8], key: &[u8], iv: &[u8], aad: &[u8]) -> AppResult<Vec<u8>> {
+        Self::try_open_envelope(envelope, key, iv, aad)?
+            .ok_or_else(|| AppError::CryptoError("Invalid authentication tag".to_string()))
+    }
+
+    /// Like `open_envelope`, but returns `None` when the authentication tag does not match
+    fn try_open_envelope(
+        envelope: &[u8],
+        key: &[u8],
+        iv: &[u8],
+        aad: &[u8],
+    ) -> AppResult<Option<Vec<u8>>> {
+        if iv.len() != IV_LENGTH {
+            return Err(AppError::CryptoError("Invalid IV length".to_string()));
+        }
+
+        let (aad, ciphertext) = match envelope.split_first() {
+            Some((&version, ciphertext)) if ciphertext.len() < TAG_LENGTH => {
+                return Err(AppError::ValidationError(format!(
+                    "Invalid ciphertext format for version {}",
+                    version
+                )));
+            }
+            Some((&ENVELOPE_VERSION_V1, ciphertext)) => (&[][..], ciphertext),
+            Some((&ENVELOPE_VERSION_V2, ciphertext)) => (aad, ciphertext),
+            Some((version, _)) => {
+                return Err(AppError::CryptoError(format!(
+                    "Unsupported ciphertext version: {}",
+                    version
+                )));
+            }
+            None => {
+                return Err(AppError::ValidationError(
+                    "Invalid ciphertext format".to_string(),
+                ));
+            }
+        };
+
+        let cipher = Aes256Gcm::new_from_slice(key)
+            .map_err(|_| AppError::CryptoError("Invalid encryption key length".to_string()))?;
+        Ok(cipher
+            .decrypt(
+                Nonce::from_slice(iv),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .ok())
+    }
+
+    /// Open the PIN layer with the one scheme the record says it uses, so an enveloped
+    /// record can never be downgraded to the legacy one. A tag that does not match means
+    /// the PIN was wrong, which is the only failure reported as `AuthenticationError`.
+    fn open_pin_layer(
+        blob: &[u8],
+        pin_key: &[u8],
+        pin_iv: &[u8],
+        aad: &[u8],
+        legacy: bool,
+    ) -> AppResult<Vec<u8>> {
+        let plaintext = if legacy {
+            Self::try_legacy_decrypt(blob, pin_key, pin_iv)?
+        } else {
+            Self::try_open_envelope(blob, pin_key, pin_iv, aad)?
+        };
+
+        plaintext.ok_or_else(|| AppError::AuthenticationError("Incorrect PIN".to_string()))
+    }
+
+    /// Decrypt a blob written by the legacy scheme, which XORed the data with the key
+    /// and appended a truncated SHA-256 "tag". Kept only to read and migrate old records.
+    fn legacy_decrypt(ciphertext: &[u8], key: &[u8], iv: &[u8]) -> AppResult<Vec<u8>> {
+        Self::try_legacy_decrypt(ciphertext, key, iv)?
+            .ok_or_else(|| AppError::ValidationError("Invalid authentication tag".to_string()))
+    }
+
+    /// Like `legacy_decrypt`, but returns `None` when the tag does not match
+    fn try_legacy_decrypt(ciphertext: &[u8], key: &[u8], iv: &[u8]) -> AppResult<Option<Vec<u8>>> {
+        // Split ciphertext and tag
+        if ciphertext.len() < TAG_LENGTH || key.is_empty() {
+            return Err(AppError::ValidationError(
+                "Invalid ciphertext format".to_string(),
+            ));
+        }
+
+        let (encrypted_data, tag) = ciphertext.split_at(ciphertext.len() - TAG_LENGTH);
+
+        let mut result = Vec::with_capacity(encrypted_data.len());
+        for (i, byte) in encrypted_data.iter().enumerate() {
+            result.push(byte ^ key[i % key.len()]);
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(&result);
+        hasher.update(key);
+        hasher.update(iv);
+        let expected_tag = hasher.finalize();
+
+        // Compare without short-circuiting on the first mismatching byte
+        let diff = tag
+            .iter()
+            .zip(&expected_tag[0..TAG_LENGTH])
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        if diff != 0 {
+            return Ok(None);
+        }
+
+        Ok(Some(result))
+    }
+}
+
+/// Structure to hold encrypted wallet data
+#[derive(Debug, Clone)]
+pub struct WalletEncryptedData {
+    pub user_id: String,
+    pub wallet_id: String, // Wallet the ciphertexts are bound to (authenticated as associated data)
+    pub encrypted_private_key: String, // Hex-encoded AES-GCM encrypted private key (encrypted with DEK)
+    pub encrypted_dek: String, // Hex-encoded AES-GCM encrypted DEK (encrypted with master key)
+    pub master_key_identifier: String, // Identifier for the master key used
+    pub dek_id: String,        // ID for the DEK (used for caching)
+    pub algorithm: String, // Encryption algorithm used (`RECORD_ALGORITHM` or `LEGACY_PIN_RECORD_ALGORITHM`)
+    pub pin_kdf: String,   // PIN KDF and its parameters, empty for legacy PBKDF2
+    pub pin_salt: String,  // Hex-encoded salt for PIN key derivation
+    pub pin_iv: String,    // Hex-encoded IV for PIN encryption
+    pub dek_iv: String,    // Hex-encoded IV for DEK encryption
+    pub master_iv: String, // Hex-encoded IV for master key encryption
+    pub integrity_mac: String, // Hex-encoded HMAC-SHA256 over all of the fields above
+}
+
+impl WalletEncryptedData {
+    /// Set the user ID associated with this encrypted data
+    pub fn with_user_id(mut self, user_id: &str) -> Self {
+        self.user_id = user_id.to_string();
+        self
+    }
+
+    /// Whether the record was written by the legacy scheme, which predates versioned
+    /// envelopes and the integrity MAC. Told by the length of the wrapped DEK rather than
+    /// by a version byte, which is part of the ciphertext and could be rewritten.
+    pub fn is_pre_envelope(&self) -> bool {
+        hex::decode(&self.encrypted_dek)
+            .is_ok_and(|encrypted_dek| encrypted_dek.len() == LEGACY_WRAPPED_DEK_LENGTH)
+    }
+
+    /// Whether the PIN layer is still a legacy XOR blob: either the whole record is from
+    /// the legacy scheme or it was migrated without the PIN. The marker is covered by the
+    /// integrity MAC, so it cannot be set on a record to downgrade it.
+    pub fn has_legacy_pin_layer(&self) -> bool {
+        self.is_pre_envelope() || self.algorithm == LEGACY_PIN_RECORD_ALGORITHM
+    }
+
+    /// Format version of the DEK layer, or `None` for records written by the legacy
+    /// scheme. The master layer is not used here because its format belongs to the key
+    /// provider.
+    pub fn envelope_version(&self) -> Option<u8> {
+        if self.is_pre_envelope() {
+            return None;
+        }
+        let dek_encrypted = hex::decode(&self.encrypted_private_key).ok()?;
+        dek_encrypted.first().copied()
+    }
+
+    /// Whether this record still needs `migrate_legacy_record`
+    pub fn is_legacy(&self) -> bool {
+        self.envelope_version() != Some(ENVELOPE_VERSION_CURRENT)
+    }
+
+    /// Convert to a string representation for storage
+    pub fn to_storage_string(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// Create from a storage string
+    pub fn from_storage_string(data: &str) -> AppResult<Self> {
+        serde_json::from_str(data)
+            .map_err(|_| AppError::ValidationError("Invalid encrypted data format".to_string()))
+    }
+}
+
+// Make WalletEncryptedData serializable
+impl serde::Serialize for WalletEncryptedData {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("WalletEncryptedData", 13)?;
+        state.serialize_field("user_id", &self.user_id)?;
+        state.serialize_field("wallet_id", &self.wallet_id)?;
+        state.serialize_field("encrypted_private_key", &self.encrypted_private_key)?;
+        state.serialize_field("encrypted_dek", &self.encrypted_dek)?;
+        state.serialize_field("master_key_identifier", &self.master_key_identifier)?;
+        state.serialize_field("dek_id", &self.dek_id)?;
+        state.serialize_field("algorithm", &self.algorithm)?;
+        state.serialize_field("pin_kdf", &self.pin_kdf)?;
+        state.serialize_field("pin_salt", &self.pin_salt)?;
+        state.serialize_field("pin_iv", &self.pin_iv)?;
+        state.serialize_field("dek_iv", &self.dek_iv)?;
+        state.serialize_field("master_iv", &self.master_iv)?;
+        state.serialize_field("integrity_mac", &self.integrity_mac)?;
+        state.end()
+    }
+}
+
+// Make WalletEncryptedData deserializable
+impl<'de> serde::Deserialize<'de> for WalletEncryptedData {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        use serde::de::{self, MapAccess, Visitor};
+        use std::fmt;
+
+        struct WalletEncryptedDataVisitor;
+
+        impl<'de> Visitor<'de> for WalletEncryptedDataVisitor {
+            type Value = WalletEncryptedData;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("struct WalletEncryptedData")
+            }
+
+            fn visit_map<V>(self, mut map: V) -> Result<WalletEncryptedData, V::Error>
+            where
+                V: MapAccess<'de>,
+            {
+                let mut user_id = None;
+                let mut wallet_id = None;
+                let mut encrypted_private_key = None;
+                let mut encrypted_dek = None;
+                let mut master_key_identifier = None;
+                let mut dek_id = None;
+                let mut algorithm = None;
+                let mut pin_kdf = None;
+                let mut pin_salt = None;
+                let mut pin_iv = None;
+                let mut dek_iv = None;
+                let mut master_iv = None;
+                let mut integrity_mac = None;
+
+                while let Some(key) = map.next_key()? {
+                    match key {
+                        "user_id" => {
+                            user_id = Some(map.next_value()?);
+                        }
+                        "wallet_id" => {
+                            wallet_id = Some(map.next_value()?);
+                        }
+                        "encrypted_private_key" => {
+                            encrypted_private_key = Some(map.next_value()?);
+                        }
+                        "encrypted_dek" => {
+                            encrypted_dek = Some(map.next_value()?);
+                        }
+                        "master_key_identifier" => {
+                            master_key_identifier = Some(map.next_value()?);
+                        }
+                        "dek_id" => {
+                            dek_id = Some(map.next_value()?);
+                        }
+                        "algorithm" => {
+                            algorithm = Some(map.next_value()?);
+                        }
+                        "pin_kdf" => {
+                            pin_kdf = Some(map.next_value()?);
+                        }
+                        "pin_salt" => {
+                            pin_salt = Some(map.next_value()?);
+                        }
+                        "pin_iv" => {
+                            pin_iv = Some(map.next_value()?);
+                        }
+                        "dek_iv" => {
+                            dek_iv = Some(map.next_value()?);
+                        }
+                        "master_iv" => {
+                            master_iv = Some(map.next_value()?);
+                        }
+                        "integrity_mac" => {
+                            integrity_mac = Some(map.next_value()?);
+                        }
+                        _ => {
+                            let _: de::IgnoredAny = map.next_value()?;
+                        }
+                    }
+                }
+
+                let user_id = user_id.ok_or_else(|| de::Error::missing_field("user_id"))?;
+                let encrypted_private_key = encrypted_private_key
+                    .ok_or_else(|| de::Error::missing_field("encrypted_private_key"))?;
+                let encrypted_dek =
+                    encrypted_dek.ok_or_else(|| de::Error::missing_field("encrypted_dek"))?;
+                let master_key_identifier = master_key_identifier
+                    .ok_or_else(|| de::Error::missing_field("master_key_identifier"))?;
+                let dek_id = dek_id.ok_or_else(|| de::Error::missing_field("dek_id"))?;
+                let algorithm = algorithm.ok_or_else(|| de::Error::missing_field("algorithm"))?;
+                let pin_salt = pin_salt.ok_or_else(|| de::Error::missing_field("pin_salt"))?;
+                let pin_iv = pin_iv.ok_or_else(|| de::Error::missing_field("pin_iv"))?;
+                let dek_iv = dek_iv.ok_or_else(|| de::Error::missing_field("dek_iv"))?;
+                let master_iv = master_iv.ok_or_else(|| de::Error::missing_field("master_iv"))?;
+                // Absent in data stored before records were bound to their wallet
+                let wallet_id = wallet_id.unwrap_or_default();
+                let integrity_mac = integrity_mac.unwrap_or_default();
+                let pin_kdf = pin_kdf.unwrap_or_default();
+
+                Ok(WalletEncryptedData {
+                    user_id,
+                    wallet_id,
+                    encrypted_private_key,
+                    encrypted_dek,
+                    master_key_identifier,
+                    dek_id,
+                    algorithm,
+                    pin_kdf,
+                    pin_salt,
+                    pin_iv,
+                    dek_iv,
+                    master_iv,
+                    integrity_mac,
+                })
+            }
+        }
+
+        deserializer.deserialize_map(WalletEncryptedDataVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Seed;
+
+    const PRIVATE_KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+    const PIN: &str = "123456";
+
+    fn private_key() -> PrivateKey {
+        PrivateKey::from_hex(PRIVATE_KEY).unwrap()
+    }
+
+    fn pin() -> Pin {
+        Pin::from(PIN)
+    }
+    const WALLET_ID: &str = "wallet-a";
+
+    fn create_test_service() -> WalletEncryptionService {
+        WalletEncryptionService::new("test_master_key", b"test-master-key-material")
+    }
+
+    // Mirror of the old XOR scheme, used to build records as they exist in the database today
+    fn legacy_encrypt(data: &[u8], key: &[u8], iv: &[u8]) -> Vec<u8> {
+        let mut result: Vec<u8> = data
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ key[i % key.len()])
+            .collect();
+
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        hasher.update(key);
+        hasher.update(iv);
+        result.extend_from_slice(&hasher.finalize()[0..TAG_LENGTH]);
+        result
+    }
+
+    fn create_legacy_record(master_key: &[u8]) -> WalletEncryptedData {
+        let pin_salt = WalletEncryptionService::generate_random_bytes(SALT_LENGTH);
+        let pin_key = PinKdf::LEGACY
+            .derive_key(PIN.as_bytes(), &pin_salt)
+            .unwrap();
+        let pin_iv = WalletEncryptionService::generate_random_bytes(IV_LENGTH);
+        let pin_encrypted = legacy_encrypt(PRIVATE_KEY.as_bytes(), &pin_key, &pin_iv);
+
+        let dek = WalletEncryptionService::generate_random_bytes(KEY_LENGTH);
+        let dek_iv = WalletEncryptionService::generate_random_bytes(IV_LENGTH);
+        let dek_encrypted = legacy_encrypt(&pin_encrypted, &dek, &dek_iv);
+
+        let master_iv = WalletEncryptionService::generate_random_bytes(IV_LENGTH);
+        let encrypted_dek = legacy_encrypt(&dek, master_key, &master_iv);
+
+        WalletEncryptedData {
+            user_id: "".to_string(),
+            wallet_id: WALLET_ID.to_string(),
+            encrypted_private_key: hex::encode(dek_encrypted),
+            encrypted_dek: hex::encode(encrypted_dek),
+            master_key_identifier: "test_master_key".to_string(),
+            dek_id: Uuid::new_v4().to_string(),
+            algorithm: "AES-256-GCM".to_string(),
+            pin_kdf: "".to_string(),
+            pin_salt: hex::encode(pin_salt),
+            pin_iv: hex::encode(pin_iv),
+            dek_iv: hex::encode(dek_iv),
+            master_iv: hex::encode(master_iv),
+            integrity_mac: "".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_seed_roundtrip() {
+        let service = create_test_service();
+        let seed = Seed::from_bytes(&[7u8; 64]).unwrap();
+        let encrypted = service
+            .encrypt_wallet_secret(&WalletSecret::Seed(seed.clone()), &pin(), WALLET_ID)
+            .await
+            .unwrap();
+
+        match service.decrypt_wallet_secret(&encrypted, &pin()).await {
+            Ok(WalletSecret::Seed(decrypted)) => {
+                assert_eq!(decrypted.expose_secret(), seed.expose_secret())
+            }
+            _ => panic!("expected the seed back"),
+        }
+
+        // A seed is never handed out as a private key
+        assert!(
+            service
+                .decrypt_private_key(&encrypted, &pin())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_encrypt_decrypt_roundtrip() {
+        let service = create_test_service();
+        let encrypted = service
+            .encrypt_private_key(&private_key(), &pin(), WALLET_ID)
+            .await
+            .unwrap();
+
+        assert!(!encrypted.is_legacy());
+        assert_eq!(encrypted.envelope_version(), Some(ENVELOPE_VERSION_CURRENT));
+
+        let decrypted = service
+            .decrypt_private_key(&encrypted, &pin())
+            .await
+            .unwrap();
+        assert_eq!(decrypted.to_hex().expose_secret(), PRIVATE_KEY);
+    }
+
+    #[tokio::test]
+    async fn test_ciphertext_does_not_leak_plaintext() {
+        let service = create_test_service();
+        let encrypted = service
+            .encrypt_private_key(&private_key(), &pin(), WALLET_ID)
+            .await
+            .unwrap();
+
+        // Every layer is a versioned envelope
+        for blob in [&encrypted.encrypted_private_key, &encrypted.encrypted_dek] {
+            assert!(blob.starts_with("02"));
+        }
+        assert!(
+            !encrypted
+                .encrypted_private_key
+                .contains(&hex::encode(PRIVATE_KEY))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wrong_pin_fails() {
+        let service = create_test_service();
+        let encrypted = service
+            .encrypt_private_key(&private_key(), &pin(), WALLET_ID)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            service
+                .decrypt_private_key(&encrypted, &Pin::from("654321"))
+                .await,
+            Err(AppError::AuthenticationError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tampered_ciphertext_fails() {
+        let service = create_test_service();
+        let mut encrypted = service
+            .encrypt_private_key(&private_key(), &pin(), WALLET_ID)
+            .await
+            .unwrap();
+
+        let mut blob = hex::decode(&encrypted.encrypted_private_key).unwrap();
+        let last = blob.len() - 1;
+        blob[last] ^= 0x01;
+        encrypted.encrypted_private_key = hex::encode(blob);
+
+        assert!(matches!(
+            service.decrypt_private_key(&encrypted, &pin()).await,
+            Err(AppError::IntegrityError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_ciphertext_swapped_between_wallets_fails() {
+        let service = create_test_service();
+        let wallet_a = service
+            .encrypt_private_key(&private_key(), &pin(), WALLET_ID)
+            .await
+            .unwrap();
+        let wallet_b = service
+            .encrypt_private_key(&private_key(), &pin(), "wallet-b")
+            .await
+            .unwrap();
+
+        // Moving wallet B's ciphertexts into wallet A's record breaks the MAC
+        let mut swapped = wallet_a.clone();
+        swapped.encrypted_private_key = wallet_b.encrypted_private_key.clone();
+        swapped.encrypted_dek = wallet_b.encrypted_dek.clone();
+        assert!(matches!(
+            service.decrypt_private_key(&swapped, &pin()).await,
+            Err(AppError::IntegrityError(_))
+        ));
+
+        // Relabelling a whole record with another wallet ID breaks the MAC too
+        let mut relabelled = wallet_b.clone();
+        relabelled.wallet_id = WALLET_ID.to_string();
+        assert!(matches!(
+            service.decrypt_private_key(&relabelled, &pin()).await,
+            Err(AppError::IntegrityError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_associated_data_binds_wallet_without_mac() {
+        let service = create_test_service();
+        let wallet_b = service
+            .encrypt_private_key(&private_key(), &pin(), "wallet-b")
+            .await
+            .unwrap();
+
+        // Even a forged MAC does not help: the AEAD layers are bound to wallet B
+        let mut forged = wallet_b.clone();
+        forged.wallet_id = WALLET_ID.to_string();
+        let mac_input = WalletEncryptionService::record_mac_input(&forged);
+        forged.integrity_mac = hex::encode(
+            service
+                .key_ring
+                .primary()
+                .record_mac(&mac_input)
+                .await
+                .unwrap(),
+        );
+
+        let fresh = create_test_service();
+        assert!(fresh.decrypt_private_key(&forged, &pin()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_master_key_layer_is_authenticated() {
+        let service = create_test_service();
+        let encrypted = service
+            .encrypt_private_key(&private_key(), &pin(), WALLET_ID)
+            .await
+            .unwrap();
+
+        // A fresh service has an empty DEK cache, so it has to unwrap with its own master key
+        let other = WalletEncryptionService::new("test_master_key", b"another-master-key");
+        assert!(other.decrypt_private_key(&encrypted, &pin()).await.is_err());
+
+        let same = create_test_service();
+        assert_eq!(
+            same.decrypt_private_key(&encrypted, &pin())
+                .await
+                .unwrap()
+                .to_hex()
+                .expose_secret(),
+            PRIVATE_KEY
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_version_is_rejected() {
+        let service = create_test_service();
+        let mut encrypted = service
+            .encrypt_private_key(&private_key(), &pin(), WALLET_ID)
+            .await
+            .unwrap();
+        encrypted.encrypted_dek.replace_range(0..2, "7f");
+        let mac_input = WalletEncryptionService::record_mac_input(&encrypted);
+        encrypted.integrity_mac = hex::encode(
+            service
+                .key_ring
+                .primary()
+                .record_mac(&mac_input)
+                .await
+                .unwrap(),
+        );
+
+        let fresh = create_test_service();
+        assert!(matches!(
+            fresh.decrypt_private_key(&encrypted, &pin()).await,
+            Err(AppError::CryptoError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_missing_mac_is_refused() {
+        let service = create_test_service();
+        let encrypted = service
+            .encrypt_private_key(&private_key(), &pin(), WALLET_ID)
+            .await
+            .unwrap();
+
+        // Dropping the MAC, even with the version byte rewritten to an older format,
+        // does not make a record pass as one from before the MAC
+        let mut stripped = encrypted.clone();
+        stripped.integrity_mac = "".to_string();
+        let mut downgraded = stripped.clone();
+        downgraded.encrypted_private_key.replace_range(0..2, "01");
+        for record in [stripped, downgraded] {
+            assert!(!record.is_pre_envelope());
+            assert!(matches!(
+                service.decrypt_private_key(&record, &pin()).await,
+                Err(AppError::IntegrityError(_))
+            ));
+        }
+
+        // A legacy record has no MAC, so a layer that does not decrypt is reported as
+        // tampering too
+        let mut legacy = create_legacy_record(b"test-master-key-material");
+        let mut blob = hex::decode(&legacy.encrypted_private_key).unwrap();
+        blob[0] ^= 0x01;
+        legacy.encrypted_private_key = hex::encode(blob);
+        assert!(matches!(
+            create_test_service()
+                .decrypt_private_key(&legacy, &pin())
+                .await,
+            Err(AppError::IntegrityError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_legacy_record_is_detected_and_readable() {
+        let service = create_test_service();
+        let legacy = create_legacy_record(b"test-master-key-material");
+
+        assert!(legacy.is_legacy());
+        assert_eq!(
+            service
+                .decrypt_private_key(&legacy, &pin())
+                .await
+                .unwrap()
+                .to_hex()
+                .expose_secret(),
+            PRIVATE_KEY
+        );
+    }
+
+    #[tokio::test]
+    async fn test_migrate_legacy_record() {
+        let service = create_test_service();
+        let legacy = create_legacy_record(b"test-master-key-material");
+
+        let migrated = service.migrate_legacy_record(&legacy).await.unwrap();
+        assert!(!migrated.is_legacy());
+        assert_ne!(migrated.dek_id, legacy.dek_id);
+        assert_eq!(migrated.pin_salt, legacy.pin_salt);
+
+        // The carried-over PIN layer is flagged for re-encryption with the PIN
+        assert!(migrated.has_legacy_pin_layer());
+        assert!(service.needs_pin_upgrade(&migrated));
+
+        // Decrypt with a fresh service so the DEK must be unwrapped with AES-GCM
+        let fresh = create_test_service();
+        assert_eq!(
+            fresh
+                .decrypt_private_key(&migrated, &pin())
+                .await
+                .unwrap()
+                .to_hex()
+                .expose_secret(),
+            PRIVATE_KEY
+        );
+        assert!(matches!(
+            fresh
+                .decrypt_private_key(&migrated, &Pin::from("000000"))
+                .await,
+            Err(AppError::AuthenticationError(_))
+        ));
+
+        // Migrating twice is refused
+        assert!(service.migrate_legacy_record(&migrated).await.is_err());
+
+        // Without the marker the legacy PIN layer is never tried, and the marker cannot
+        // be dropped or added without breaking the MAC
+        let mut unmarked = migrated.clone();
+        unmarked.algorithm = RECORD_ALGORITHM.to_string();
+        assert!(matches!(
+            fresh.decrypt_private_key(&unmarked, &pin()).await,
+            Err(AppError::IntegrityError(_))
+        ));
+        let resealed = fresh.seal_record(unmarked).await.unwrap();
+        assert!(fresh.decrypt_private_key(&resealed, &pin()).await.is_err());
+    }
+
+    fn create_rotated_service() -> WalletEncryptionService {
+        let ring = MasterKeyRing::new(MasterKey::new("new_master_key", b"new-master-key-material"))
+            .with_retired_key(MasterKey::new(
+                "test_master_key",
+                b"test-master-key-material",
+            ))
+            .unwrap();
+        WalletEncryptionService::from_key_ring(ring)
+    }
+
+    #[tokio::test]
+    async fn test_retired_key_still_decrypts() {
+        let encrypted = create_test_service()
+            .encrypt_private_key(&private_key(), &pin(), WALLET_ID)
+            .await
+            .unwrap();
+
+        let rotated = create_rotated_service();
+        assert!(rotated.needs_rewrap(&encrypted));
+        assert_eq!(
+            rotated
+                .decrypt_private_key(&encrypted, &pin())
+                .await
+                .unwrap()
+                .to_hex()
+                .expose_secret(),
+            PRIVATE_KEY
+        );
+
+        // New records always use the primary key
+        let fresh = rotated
+            .encrypt_private_key(&private_key(), &pin(), WALLET_ID)
+            .await
+            .unwrap();
+        assert_eq!(fresh.master_key_identifier, "new_master_key");
+        assert!(!rotated.needs_rewrap(&fresh));
+    }
+
+    #[tokio::test]
+    async fn test_unknown_master_key_is_rejected() {
+        let encrypted = create_test_service()
+            .encrypt_private_key(&private_key(), &pin(), WALLET_ID)
+            .await
+            .unwrap();
+
+        let other = WalletEncryptionService::new("other_master_key", b"other-material");
+        assert!(matches!(
+            other.decrypt_private_key(&encrypted, &pin()).await,
+            Err(AppError::ValidationError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rewrap_dek_moves_record_to_primary_key() {
+        let encrypted = create_test_service()
+            .encrypt_private_key(&private_key(), &pin(), WALLET_ID)
+            .await
+            .unwrap();
+
+        let rewrapped = create_rotated_service()
+            .rewrap_dek(&encrypted)
+            .await
+            .unwrap();
+        assert_eq!(rewrapped.master_key_identifier, "new_master_key");
+        assert_eq!(rewrapped.dek_id, encrypted.dek_id);
+        assert_eq!(
+            rewrapped.encrypted_private_key,
+            encrypted.encrypted_private_key
+        );
+        assert_ne!(rewrapped.master_iv, encrypted.master_iv);
+
+        // Readable with only the new key, no longer with the old one
+        let new_only = WalletEncryptionService::new("new_master_key", b"new-master-key-material");
+        assert_eq!(
+            new_only
+                .decrypt_private_key(&rewrapped, &pin())
+                .await
+                .unwrap()
+                .to_hex()
+                .expose_secret(),
+            PRIVATE_KEY
+        );
+        assert!(
+            create_test_service()
+                .decrypt_private_key(&rewrapped, &pin())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_new_records_use_current_pin_kdf() {
+        let service = create_test_service();
+        let encrypted = service
+            .encrypt_private_key(&private_key(), &pin(), WALLET_ID)
+            .await
+            .unwrap();
+
+        assert_eq!(encrypted.pin_kdf, PinKdf::default().to_string());
+        assert!(!service.needs_pin_upgrade(&encrypted));
+        assert!(service.needs_pin_upgrade(&create_legacy_record(b"test-master-key-material")));
+
+        // Raising the cost makes existing records due for an upgrade
+        let stronger = create_test_service().with_pin_kdf(PinKdf::Argon2id {
+            memory_kib: 32768,
+            iterations: 3,
+            parallelism: 1,
+        });
+        assert!(stronger.needs_pin_upgrade(&encrypted));
+    }
+
+    #[tokio::test]
+    async fn test_pin_kdf_downgrade_is_detected() {
+        let service = create_test_service();
+        let mut encrypted = service
+            .encrypt_private_key(&private_key(), &pin(), WALLET_ID)
+            .await
+            .unwrap();
+        encrypted.pin_kdf = PinKdf::LEGACY.to_string();
+
+        assert!(matches!(
+            service.decrypt_private_key(&encrypted, &pin()).await,
+            Err(AppError::IntegrityError(_))
+        ));
+    }
+}
@@ -0,0 +1,192 @@
+use app_config::PinKdfConfig;
+use app_error::{AppError, AppResult};
+use argon2::{Algorithm, Argon2, Params, Version};
+use hmac::Hmac;
+use pbkdf2::pbkdf2;
+use sha2::Sha512;
+use std::fmt;
+use zeroize::Zeroizing;
+
+use super::KEY_LENGTH;
+
+// Iteration count of the PBKDF2 derivation used before Argon2id
+const LEGACY_PBKDF2_ITERATIONS: u32 = 10000;
+
+/// Key derivation function and cost parameters used to turn a PIN into the PIN-layer key.
+/// Stored on every wallet key record as e.g. `argon2id$m=19456,t=2,p=1`; records written
+/// before the parameters were stored have an empty value and use legacy PBKDF2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinKdf {
+    Pbkdf2Sha512 {
+        iterations: u32,
+    },
+    Argon2id {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+}
+
+impl PinKdf {
+    /// Parameters of records that do not store any
+    pub const LEGACY: Self = Self::Pbkdf2Sha512 {
+        iterations: LEGACY_PBKDF2_ITERATIONS,
+    };
+
+    pub fn from_config(config: &PinKdfConfig) -> Self {
+        Self::Argon2id {
+            memory_kib: config.memory_kib,
+            iterations: config.iterations,
+            parallelism: config.parallelism,
+        }
+    }
+
+    /// Parse the stored form written by `Display`
+    pub fn parse(encoded: &str) -> AppResult<Self> {
+        if encoded.is_empty() {
+            return Ok(Self::LEGACY);
+        }
+
+        let invalid = || AppError::ValidationError(format!("Invalid PIN KDF '{}'", encoded));
+        let (algorithm, params) = encoded.split_once('$').ok_or_else(invalid)?;
+
+        let mut values = std::collections::HashMap::new();
+        for param in params.split(',') {
+            let (name, value) = param.split_once('=').ok_or_else(invalid)?;
+            values.insert(name, value.parse::<u32>().map_err(|_| invalid())?);
+        }
+        let value = |name: &str| values.get(name).copied().ok_or_else(invalid);
+
+        match algorithm {
+            "pbkdf2-sha512" => Ok(Self::Pbkdf2Sha512 {
+                iterations: value("i")?,
+            }),
+            "argon2id" => Ok(Self::Argon2id {
+                memory_kib: value("m")?,
+                iterations: value("t")?,
+                parallelism: value("p")?,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Whether a key derived with these parameters is cheaper to brute force than one
+    /// derived with `other`
+    pub fn is_weaker_than(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Pbkdf2Sha512 { .. }, Self::Argon2id { .. }) => true,
+            (Self::Argon2id { .. }, Self::Pbkdf2Sha512 { .. }) => false,
+            (Self::Pbkdf2Sha512 { iterations: a }, Self::Pbkdf2Sha512 { iterations: b }) => a < b,
+            (
+                Self::Argon2id {
+                    memory_kib: m1,
+                    iterations: t1,
+                    ..
+                },
+                Self::Argon2id {
+                    memory_kib: m2,
+                    iterations: t2,
+                    ..
+                },
+            ) => m1 < m2 || t1 < t2,
+        }
+    }
+
+    /// Derive a 256-bit key from a PIN
+    pub(crate) fn derive_key(&self, pin: &[u8], salt: &[u8]) -> AppResult<Zeroizing<Vec<u8>>> {
+        let mut key = Zeroizing::new(vec![0u8; KEY_LENGTH]);
+
+        match *self {
+            Self::Pbkdf2Sha512 { iterations } => {
+                pbkdf2::<Hmac<Sha512>>(pin, salt, iterations, &mut key).map_err(|_| {
+                    AppError::CryptoError("Failed to derive key from PIN".to_string())
+                })?;
+            }
+            Self::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => {
+                let params = Params::new(memory_kib, iterations, parallelism, Some(KEY_LENGTH))
+                    .map_err(|e| {
+                        AppError::CryptoError(format!("Invalid Argon2id parameters: {}", e))
+                    })?;
+
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+                    .hash_password_into(pin, salt, &mut key)
+                    .map_err(|_| {
+                        AppError::CryptoError("Failed to derive key from PIN".to_string())
+                    })?;
+            }
+        }
+
+        Ok(key)
+    }
+}
+
+impl Default for PinKdf {
+    fn default() -> Self {
+        Self::from_config(&PinKdfConfig::default())
+    }
+}
+
+impl fmt::Display for PinKdf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Pbkdf2Sha512 { iterations } => write!(f, "pbkdf2-sha512$i={}", iterations),
+            Self::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            } => write!(
+                f,
+                "argon2id$m={},t={},p={}",
+                memory_kib, iterations, parallelism
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAST_ARGON2: PinKdf = PinKdf::Argon2id {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    fn test_encoding_roundtrip() {
+        for kdf in [PinKdf::LEGACY, PinKdf::default(), FAST_ARGON2] {
+            assert_eq!(PinKdf::parse(&kdf.to_string()).unwrap(), kdf);
+        }
+
+        assert_eq!(PinKdf::parse("").unwrap(), PinKdf::LEGACY);
+        assert!(PinKdf::parse("argon2id$m=64,t=1").is_err());
+        assert!(PinKdf::parse("scrypt$n=1024").is_err());
+    }
+
+    #[test]
+    fn test_weaker_parameters() {
+        let current = PinKdf::default();
+
+        assert!(PinKdf::LEGACY.is_weaker_than(&current));
+        assert!(FAST_ARGON2.is_weaker_than(&current));
+        assert!(!current.is_weaker_than(&current));
+        assert!(!current.is_weaker_than(&FAST_ARGON2));
+        assert!(!current.is_weaker_than(&PinKdf::LEGACY));
+    }
+
+    #[test]
+    fn test_derived_key_depends_on_parameters() {
+        let salt = [3u8; 16];
+        let key = FAST_ARGON2.derive_key(b"123456", &salt).unwrap();
+
+        assert_eq!(key.len(), KEY_LENGTH);
+        assert_eq!(*key, *FAST_ARGON2.derive_key(b"123456", &salt).unwrap());
+        assert_ne!(*key, *PinKdf::LEGACY.derive_key(b"123456", &salt).unwrap());
+        assert_ne!(*key, *FAST_ARGON2.derive_key(b"654321", &salt).unwrap());
+    }
+}
@@ -0,0 +1,246 @@
+use app_error::{AppError, AppResult};
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+use super::{IV_LENGTH, KEY_LENGTH, WalletEncryptionService};
+
+// Domain separation label for deriving the record MAC key from the master key
+const INTEGRITY_KEY_LABEL: &[u8] = b"wallet-key-integrity";
+
+/// A DEK wrapped by a key provider, as stored in `encrypted_dek` and `master_iv`.
+/// `iv` is empty for providers that manage their own nonces inside the ciphertext.
+#[derive(Debug, Clone)]
+pub struct WrappedDek {
+    pub ciphertext: Vec<u8>,
+    pub iv: Vec<u8>,
+}
+
+/// Holder of a master key. The wallet encryption service only ever asks a provider to
+/// wrap, unwrap and MAC on its behalf, so implementations backed by a remote KMS never
+/// expose the key to this process.
+#[async_trait]
+pub trait KeyProvider: Send + Sync + fmt::Debug {
+    /// Identifier stored in `master_key_identifier` of every record this key wraps
+    fn key_id(&self) -> &str;
+
+    /// Encrypt a DEK, authenticating `aad` alongside it
+    async fn wrap_dek(&self, dek: &[u8], aad: &[u8]) -> AppResult<WrappedDek>;
+
+    /// Decrypt a DEK produced by `wrap_dek` with the same `aad`
+    async fn unwrap_dek(&self, wrapped: &WrappedDek, aad: &[u8]) -> AppResult<Vec<u8>>;
+
+    /// MAC over the encoded fields of a wallet key record
+    async fn record_mac(&self, data: &[u8]) -> AppResult<Vec<u8>>;
+
+    /// Check a MAC produced by `record_mac`
+    async fn verify_record_mac(&self, data: &[u8], mac: &[u8]) -> AppResult<bool> {
+        let expected = self.record_mac(data).await?;
+
+        // Compare without short-circuiting on the first mismatching byte
+        let diff = expected
+            .iter()
+            .zip(mac)
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        Ok(expected.len() == mac.len() && diff == 0)
+    }
+
+    /// Decrypt a DEK written by the legacy XOR scheme. Only possible when the raw key
+    /// material is available locally.
+    async fn unwrap_legacy_dek(&self, _wrapped: &[u8], _iv: &[u8]) -> AppResult<Vec<u8>> {
+        Err(AppError::CryptoError(format!(
+            "Master key '{}' cannot unwrap legacy records",
+            self.key_id()
+        )))
+    }
+}
+
+/// A master key held in process memory, loaded from the config file, a key file
+/// or an environment variable. Every copy of the key is wiped when it is dropped.
+pub struct MasterKey {
+    id: String,
+    // Raw master key material as configured (only used to unwrap legacy records)
+    material: Zeroizing<Vec<u8>>,
+    // 256-bit AES key derived from the master key material
+    cipher_key: Zeroizing<[u8; KEY_LENGTH]>,
+    // HMAC key for the record integrity MAC, derived from the master key material
+    integrity_key: Zeroizing<[u8; KEY_LENGTH]>,
+}
+
+impl MasterKey {
+    pub fn new(id: &str, material: &[u8]) -> Self {
+        let cipher_key = Self::derive_cipher_key(material);
+        let integrity_key = Self::derive_integrity_key(cipher_key.as_slice());
+
+        Self {
+            id: id.to_string(),
+            material: Zeroizing::new(material.to_vec()),
+            cipher_key,
+            integrity_key,
+        }
+    }
+
+    /// Load the key material from a file. Trailing whitespace is ignored, so a key
+    /// written with a trailing newline reads the same as one without.
+    pub fn from_file(id: &str, path: impl AsRef<Path>) -> AppResult<Self> {
+        let path = path.as_ref();
+        let material = Zeroizing::new(std::fs::read(path).map_err(|e| {
+            AppError::ConfigError(anyhow::anyhow!(
+                "Failed to read master key '{}' from {}: {}",
+                id,
+                path.display(),
+                e
+            ))
+        })?);
+
+        Self::from_material(id, material.trim_ascii_end())
+    }
+
+    /// Load the key material from an environment variable
+    pub fn from_env(id: &str, var: &str) -> AppResult<Self> {
+        let material = Zeroizing::new(std::env::var(var).map_err(|_| {
+            AppError::ConfigError(anyhow::anyhow!(
+                "Environment variable '{}' for master key '{}' is not set",
+                var,
+                id
+            ))
+        })?);
+
+        Self::from_material(id, material.as_bytes())
+    }
+
+    fn from_material(id: &str, material: &[u8]) -> AppResult<Self> {
+        if material.is_empty() {
+            return Err(AppError::ConfigError(anyhow::anyhow!(
+                "Master key '{}' is empty",
+                id
+            )));
+        }
+
+        Ok(Self::new(id, material))
+    }
+
+    /// Turn the configured master key material into a 256-bit AES key.
+    /// 32-byte keys are used directly, anything else is hashed with SHA-256.
+    fn derive_cipher_key(material: &[u8]) -> Zeroizing<[u8; KEY_LENGTH]> {
+        let mut key = Zeroizing::new([0u8; KEY_LENGTH]);
+        if material.len() == KEY_LENGTH {
+            key.copy_from_slice(material);
+        } else {
+            key.copy_from_slice(&Sha256::digest(material));
+        }
+        key
+    }
+
+    /// Derive the record MAC key so it is never the same as the master encryption key
+    fn derive_integrity_key(cipher_key: &[u8]) -> Zeroizing<[u8; KEY_LENGTH]> {
+        let mut mac = Self::hmac(cipher_key);
+        mac.update(INTEGRITY_KEY_LABEL);
+        Zeroizing::new(mac.finalize().into_bytes().into())
+    }
+
+    fn hmac(key: &[u8]) -> Hmac<Sha256> {
+        <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length")
+    }
+}
+
+#[async_trait]
+impl KeyProvider for MasterKey {
+    fn key_id(&self) -> &str {
+        &self.id
+    }
+
+    async fn wrap_dek(&self, dek: &[u8], aad: &[u8]) -> AppResult<WrappedDek> {
+        let iv = WalletEncryptionService::generate_random_bytes(IV_LENGTH);
+        let ciphertext =
+            WalletEncryptionService::seal_envelope(dek, self.cipher_key.as_slice(), &iv, aad)?;
+
+        Ok(WrappedDek { ciphertext, iv })
+    }
+
+    async fn unwrap_dek(&self, wrapped: &WrappedDek, aad: &[u8]) -> AppResult<Vec<u8>> {
+        WalletEncryptionService::open_envelope(
+            &wrapped.ciphertext,
+            self.cipher_key.as_slice(),
+            &wrapped.iv,
+            aad,
+        )
+    }
+
+    async fn record_mac(&self, data: &[u8]) -> AppResult<Vec<u8>> {
+        let mut mac = Self::hmac(self.integrity_key.as_slice());
+        mac.update(data);
+        Ok(mac.finalize().into_bytes().to_vec())
+    }
+
+    async fn verify_record_mac(&self, data: &[u8], mac: &[u8]) -> AppResult<bool> {
+        let mut expected = Self::hmac(self.integrity_key.as_slice());
+        expected.update(data);
+        Ok(expected.verify_slice(mac).is_ok())
+    }
+
+    async fn unwrap_legacy_dek(&self, wrapped: &[u8], iv: &[u8]) -> AppResult<Vec<u8>> {
+        WalletEncryptionService::legacy_decrypt(wrapped, &self.material, iv)
+    }
+}
+
+// Never print key material
+impl fmt::Debug for MasterKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MasterKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_wrap_unwrap_roundtrip() {
+        let key = MasterKey::new("key-1", b"master-key-material");
+        let dek = [7u8; KEY_LENGTH];
+
+        let wrapped = key.wrap_dek(&dek, b"aad").await.unwrap();
+        assert_eq!(key.unwrap_dek(&wrapped, b"aad").await.unwrap(), dek);
+        assert!(key.unwrap_dek(&wrapped, b"other aad").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_record_mac_verifies() {
+        let key = MasterKey::new("key-1", b"master-key-material");
+
+        let mac = key.record_mac(b"record").await.unwrap();
+        assert!(key.verify_record_mac(b"record", &mac).await.unwrap());
+        assert!(!key.verify_record_mac(b"tampered", &mac).await.unwrap());
+    }
+
+    #[test]
+    fn test_from_file_ignores_trailing_newline() {
+        let path = std::env::temp_dir().join(format!("master-key-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&path, b"file-key-material\n").unwrap();
+
+        let from_file = MasterKey::from_file("key-1", &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(from_file.material.as_slice(), b"file-key-material");
+        assert!(MasterKey::from_file("key-1", &path).is_err());
+    }
+
+    #[test]
+    fn test_from_env_requires_variable() {
+        assert!(MasterKey::from_env("key-1", "WALLET_TEST_UNSET_MASTER_KEY").is_err());
+    }
+
+    #[test]
+    fn test_debug_hides_key_material() {
+        let key = MasterKey::new("key-1", b"super-secret-material");
+        let debug = format!("{:?}", key);
+        assert!(debug.contains("key-1"));
+        assert!(!debug.contains("super-secret-material"));
+    }
+}
@@ -0,0 +1,365 @@
+use app_error::{AppError, AppResult};
+use async_trait::async_trait;
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use reqwest::header::HeaderValue;
+use serde_json::{Value, json};
+use std::fmt;
+use std::time::Duration;
+use tracing::error;
+use zeroize::Zeroizing;
+
+use super::provider::{KeyProvider, WrappedDek};
+
+/// Key provider backed by a remote "transit" KMS (the HashiCorp Vault transit engine
+/// API). The master key stays inside the KMS; this client only sends DEKs to be
+/// encrypted or decrypted and record fields to be MACed. The access token is wiped
+/// when the provider is dropped.
+pub struct TransitKeyProvider {
+    key_id: String,
+    base_url: String,
+    key_name: String,
+    token: Zeroizing<String>,
+    client: reqwest::Client,
+}
+
+impl TransitKeyProvider {
+    /// `key_id` is the name recorded in `master_key_identifier`; `key_name` is the
+    /// name of the key inside the KMS
+    pub fn new(
+        key_id: &str,
+        base_url: &str,
+        key_name: &str,
+        token: &str,
+        timeout: Duration,
+    ) -> AppResult<Self> {
+        let client = reqwest::Client::builder()
+            .timeout(timeout)
+            .build()
+            .map_err(|e| {
+                AppError::ConfigError(anyhow::anyhow!("Failed to build transit client: {}", e))
+            })?;
+
+        Ok(Self {
+            key_id: key_id.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            key_name: key_name.to_string(),
+            token: Zeroizing::new(token.to_string()),
+            client,
+        })
+    }
+
+    /// Like `new`, reading the KMS access token from an environment variable
+    pub fn from_env(
+        key_id: &str,
+        base_url: &str,
+        key_name: &str,
+        token_env: &str,
+        timeout: Duration,
+    ) -> AppResult<Self> {
+        let token = Zeroizing::new(std::env::var(token_env).map_err(|_| {
+            AppError::ConfigError(anyhow::anyhow!(
+                "Environment variable '{}' for transit key '{}' is not set",
+                token_env,
+                key_id
+            ))
+        })?);
+
+        Self::new(key_id, base_url, key_name, &token, timeout)
+    }
+
+    /// POST to a transit endpoint and return the `data` object of the response
+    async fn post(&self, operation: &str, body: Value) -> AppResult<Value> {
+        let url = format!(
+            "{}/v1/transit/{}/{}",
+            self.base_url, operation, self.key_name
+        );
+
+        // Sensitive header values are left out of reqwest's debug output
+        let mut token = HeaderValue::from_str(&self.token).map_err(|_| {
+            AppError::ConfigError(anyhow::anyhow!(
+                "Access token of transit key '{}' is not a valid header value",
+                self.key_id
+            ))
+        })?;
+        token.set_sensitive(true);
+
+        let response = self
+            .client
+            .post(&url)
+            .header("X-Vault-Token", token)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| {
+                error!(
+                    "Transit {} request for key {} failed: {}",
+                    operation, self.key_id, e
+                );
+                AppError::NetworkError(format!("Transit KMS request failed: {}", e))
+            })?;
+
+        let status = response.status();
+        if !status.is_success() {
+            error!(
+                "Transit {} request for key {} returned {}",
+                operation, self.key_id, status
+            );
+            return Err(AppError::CryptoError(format!(
+                "Transit KMS rejected the {} request with status {}",
+                operation, status
+            )));
+        }
+
+        let mut payload: Value = response
+            .json()
+            .await
+            .map_err(|e| AppError::CryptoError(format!("Invalid transit KMS response: {}", e)))?;
+
+        Ok(payload["data"].take())
+    }
+
+    fn string_field(data: &Value, field: &str) -> AppResult<String> {
+        data[field].as_str().map(str::to_string).ok_or_else(|| {
+            AppError::CryptoError(format!("Transit KMS response is missing '{}'", field))
+        })
+    }
+}
+
+#[async_trait]
+impl KeyProvider for TransitKeyProvider {
+    fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    async fn wrap_dek(&self, dek: &[u8], aad: &[u8]) -> AppResult<WrappedDek> {
+        let data = self
+            .post(
+                "encrypt",
+                json!({
+                    "plaintext": BASE64.encode(dek),
+                    "associated_data": BASE64.encode(aad),
+                }),
+            )
+            .await?;
+
+        // The KMS embeds its own nonce and key version in the ciphertext
+        Ok(WrappedDek {
+            ciphertext: Self::string_field(&data, "ciphertext")?.into_bytes(),
+            iv: Vec::new(),
+        })
+    }
+
+    async fn unwrap_dek(&self, wrapped: &WrappedDek, aad: &[u8]) -> AppResult<Vec<u8>> {
+        let ciphertext = std::str::from_utf8(&wrapped.ciphertext)
+            .map_err(|_| AppError::ValidationError("Invalid DEK format".to_string()))?;
+
+        let data = self
+            .post(
+                "decrypt",
+                json!({
+                    "ciphertext": ciphertext,
+                    "associated_data": BASE64.encode(aad),
+                }),
+            )
+            .await?;
+
+        BASE64
+            .decode(Self::string_field(&data, "plaintext")?)
+            .map_err(|_| AppError::CryptoError("Invalid transit KMS plaintext".to_string()))
+    }
+
+    async fn record_mac(&self, data: &[u8]) -> AppResult<Vec<u8>> {
+        let data = self
+            .post("hmac", json!({ "input": BASE64.encode(data) }))
+            .await?;
+
+        Ok(Self::string_field(&data, "hmac")?.into_bytes())
+    }
+
+    async fn verify_record_mac(&self, data: &[u8], mac: &[u8]) -> AppResult<bool> {
+        let Ok(mac) = std::str::from_utf8(mac) else {
+            return Ok(false);
+        };
+
+        let data = self
+            .post(
+                "verify",
+                json!({ "input": BASE64.encode(data), "hmac": mac }),
+            )
+            .await?;
+
+        Ok(data["valid"].as_bool().unwrap_or(false))
+    }
+}
+
+// Never print the access token
+impl fmt::Debug for TransitKeyProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TransitKeyProvider")
+            .field("key_id", &self.key_id)
+            .field("base_url", &self.base_url)
+            .field("key_name", &self.key_name)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes_gcm::aead::{Aead, Payload};
+    use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+    use wiremock::matchers::{header, method, path_regex};
+    use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+    const TOKEN: &str = "test-token";
+
+    /// Minimal stand-in for a transit KMS: a real AES-GCM key and HMAC key that
+    /// only this "server" knows
+    struct TransitStandIn {
+        key: [u8; 32],
+    }
+
+    impl TransitStandIn {
+        fn field(body: &Value, name: &str) -> Vec<u8> {
+            BASE64.decode(body[name].as_str().unwrap_or("")).unwrap()
+        }
+
+        fn hmac(&self, input: &[u8]) -> String {
+            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&self.key).unwrap();
+            mac.update(input);
+            format!("standin:v1:{}", BASE64.encode(mac.finalize().into_bytes()))
+        }
+    }
+
+    impl Respond for TransitStandIn {
+        fn respond(&self, request: &Request) -> ResponseTemplate {
+            let body: Value = serde_json::from_slice(&request.body).unwrap();
+            let cipher = Aes256Gcm::new_from_slice(&self.key).unwrap();
+            let operation = request.url.path().split('/').nth(3).unwrap_or("");
+
+            let data = match operation {
+                "encrypt" => {
+                    let nonce = [9u8; 12];
+                    let aad = Self::field(&body, "associated_data");
+                    let plaintext = Self::field(&body, "plaintext");
+                    let mut blob = nonce.to_vec();
+                    blob.extend(
+                        cipher
+                            .encrypt(
+                                Nonce::from_slice(&nonce),
+                                Payload {
+                                    msg: &plaintext,
+                                    aad: &aad,
+                                },
+                            )
+                            .unwrap(),
+                    );
+                    json!({ "ciphertext": format!("standin:v1:{}", BASE64.encode(blob)) })
+                }
+                "decrypt" => {
+                    let aad = Self::field(&body, "associated_data");
+                    let token = body["ciphertext"].as_str().unwrap_or("");
+                    let blob = BASE64
+                        .decode(token.trim_start_matches("standin:v1:"))
+                        .unwrap_or_default();
+                    if blob.len() < 12 {
+                        return ResponseTemplate::new(400);
+                    }
+                    let (nonce, ciphertext) = blob.split_at(12);
+                    match cipher.decrypt(
+                        Nonce::from_slice(nonce),
+                        Payload {
+                            msg: ciphertext,
+                            aad: &aad,
+                        },
+                    ) {
+                        Ok(plaintext) => json!({ "plaintext": BASE64.encode(plaintext) }),
+                        Err(_) => return ResponseTemplate::new(400),
+                    }
+                }
+                "hmac" => json!({ "hmac": self.hmac(&Self::field(&body, "input")) }),
+                "verify" => {
+                    let expected = self.hmac(&Self::field(&body, "input"));
+                    json!({ "valid": body["hmac"].as_str() == Some(expected.as_str()) })
+                }
+                _ => return ResponseTemplate::new(404),
+            };
+
+            ResponseTemplate::new(200).set_body_json(json!({ "data": data }))
+        }
+    }
+
+    async fn start_stand_in() -> MockServer {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path_regex(
+                r"^/v1/transit/(encrypt|decrypt|hmac|verify)/wallet-master$",
+            ))
+            .and(header("X-Vault-Token", TOKEN))
+            .respond_with(TransitStandIn { key: [42u8; 32] })
+            .mount(&server)
+            .await;
+        server
+    }
+
+    fn provider(server: &MockServer, token: &str) -> TransitKeyProvider {
+        TransitKeyProvider::new(
+            "transit-key",
+            &server.uri(),
+            "wallet-master",
+            token,
+            Duration::from_secs(5),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_wrap_unwrap_roundtrip() {
+        let server = start_stand_in().await;
+        let provider = provider(&server, TOKEN);
+        let dek = [5u8; 32];
+
+        let wrapped = provider.wrap_dek(&dek, b"aad").await.unwrap();
+        assert!(wrapped.iv.is_empty());
+        assert!(wrapped.ciphertext.starts_with(b"standin:v1:"));
+
+        assert_eq!(provider.unwrap_dek(&wrapped, b"aad").await.unwrap(), dek);
+        assert!(provider.unwrap_dek(&wrapped, b"other").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_record_mac_verifies_remotely() {
+        let server = start_stand_in().await;
+        let provider = provider(&server, TOKEN);
+
+        let mac = provider.record_mac(b"record").await.unwrap();
+        assert!(provider.verify_record_mac(b"record", &mac).await.unwrap());
+        assert!(!provider.verify_record_mac(b"tampered", &mac).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_rejected_token_is_an_error() {
+        let server = start_stand_in().await;
+        let provider = provider(&server, "wrong-token");
+
+        assert!(matches!(
+            provider.wrap_dek(&[1u8; 32], b"aad").await,
+            Err(AppError::CryptoError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_legacy_records_are_not_supported() {
+        let server = start_stand_in().await;
+        let provider = provider(&server, TOKEN);
+
+        assert!(
+            provider
+                .unwrap_legacy_dek(&[0u8; 48], &[0u8; 12])
+                .await
+                .is_err()
+        );
+    }
+}
@@ -7,28 +7,49 @@ use tiny_keccak::{Hasher, Keccak};
 use hmac::Hmac;
 use pbkdf2::pbkdf2;
 use sha2::Sha512;
+use std::fmt;
+use zeroize::Zeroizing;
 
-#[derive(Debug, Clone)]
+use crate::secret::{MnemonicPhrase, PrivateKey, SecretString, Seed, WalletSecret};
+
+/// BIP-44 path of the first Ethereum account
+pub const DEFAULT_DERIVATION_PATH: &str = "m/44'/60'/0'/0/0";
+
+/// Highest account index that can be derived without hardening (BIP-32 uses the top bit)
+pub const MAX_ACCOUNT_INDEX: u32 = 0x7fff_ffff;
+
+/// BIP-44 path of an Ethereum account, counting addresses the way most wallets do
+pub fn account_derivation_path(index: u32) -> String {
+    format!("m/44'/60'/0'/0/{}", index)
+}
+
+#[derive(Clone)]
 pub struct EthereumWallet {
-    mnemonic: Mnemonic,
-    private_key: [u8; 32],
+    mnemonic: Option<Mnemonic>, // Zeroized on drop by bip39's `zeroize` feature; None if imported from a key
+    seed: Option<Seed>,         // Set for HD wallets, whose accounts are all derived from it
+    account_index: u32,
+    private_key: PrivateKey,
     public_key: [u8; 65],
     address: String,
 }
 
+// Only the public parts of the wallet are ever printed
+impl fmt::Debug for EthereumWallet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EthereumWallet")
+            .field("address", &self.address)
+            .field("account_index", &self.account_index)
+            .finish_non_exhaustive()
+    }
+}
+
 impl EthereumWallet {
     pub fn new() -> Self {
         let mnemonic = Self::generate_mnemonic();
-        let private_key = Self::derive_private_key(&mnemonic);
-        let public_key = Self::derive_public_key(&private_key);
-        let address = Self::derive_address(&public_key);
-
-        Self {
-            mnemonic,
-            private_key,
-            public_key,
-            address,
-        }
+        let seed = Self::mnemonic_seed(&mnemonic, "");
+        let mut wallet = Self::from_hd_seed(seed, 0).unwrap();
+        wallet.mnemonic = Some(mnemonic);
+        wallet
     }
 
     // We're using the same method to generate mnemonic as your code originally did
@@ -37,42 +58,97 @@ impl EthereumWallet {
         Mnemonic::generate_in_with(&mut rng, Language::English, 24).unwrap()
     }
 
+    // Restore a wallet from its BIP-39 mnemonic, e.g. to recover a forgotten PIN
+    pub fn from_mnemonic(phrase: &MnemonicPhrase) -> Result<Self, &'static str> {
+        Self::from_mnemonic_with_path(phrase, &SecretString::from(""), DEFAULT_DERIVATION_PATH)
+    }
+
+    // Restore a wallet from a BIP-39 mnemonic with an optional passphrase and any
+    // derivation path, as used by other wallet software
+    pub fn from_mnemonic_with_path(
+        phrase: &MnemonicPhrase,
+        passphrase: &SecretString,
+        path: &str,
+    ) -> Result<Self, &'static str> {
+        let normalized = Zeroizing::new(phrase.expose_secret().to_lowercase());
+        let mnemonic = Mnemonic::parse_in_normalized(Language::English, &normalized)
+            .map_err(|_| "Invalid mnemonic phrase")?;
+        let seed = Self::mnemonic_seed(&mnemonic, passphrase.expose_secret());
+
+        // Further accounts are numbered along the default path, so a wallet restored on
+        // any other path only holds the one key
+        let mut wallet = if path == DEFAULT_DERIVATION_PATH {
+            Self::from_hd_seed(seed, 0)?
+        } else {
+            Self::from_private_key(Self::derive_private_key(&seed, path)?)?
+        };
+        wallet.mnemonic = Some(mnemonic);
+        Ok(wallet)
+    }
+
+    // Import a wallet from a raw private key; it has no mnemonic
+    pub fn from_private_key(private_key: PrivateKey) -> Result<Self, &'static str> {
+        // Rejects zero and keys outside the curve order
+        SecretKey::from_slice(private_key.expose_secret()).map_err(|_| "Invalid private key")?;
+
+        let public_key = Self::derive_public_key(&private_key);
+        let address = Self::derive_address(&public_key);
+
+        Ok(Self {
+            mnemonic: None,
+            seed: None,
+            account_index: 0,
+            private_key,
+            public_key,
+            address,
+        })
+    }
+
     // Method to directly create a wallet from seed bytes
-    // This bypasses the need to parse a mnemonic phrase
+    // This bypasses the need to parse a mnemonic phrase, so the wallet has none
     pub fn from_seed(seed: &[u8]) -> Result<Self, &'static str> {
         if seed.len() < 32 {
             return Err("Seed too short");
         }
 
-        let path = "m/44'/60'/0'/0/0";
-        let ext = match ExtendedPrivKey::derive(seed, path) {
-            Ok(key) => key,
-            Err(_) => return Err("Failed to derive extended key from seed"),
-        };
+        let seed = Seed::from_bytes(seed).map_err(|_| "Seed too long")?;
+        Self::from_hd_seed(seed, 0)
+    }
+
+    // Derive the account at `index` of the HD wallet with this seed
+    pub fn from_hd_seed(seed: Seed, index: u32) -> Result<Self, &'static str> {
+        if index > MAX_ACCOUNT_INDEX {
+            return Err("Account index out of range");
+        }
 
-        let private_key = ext.secret();
+        let private_key = Self::derive_private_key(&seed, &account_derivation_path(index))?;
         let public_key = Self::derive_public_key(&private_key);
         let address = Self::derive_address(&public_key);
 
-        // We still need a mnemonic for the struct, but since we can't parse it,
-        // we'll generate a new one (this won't match the original phrase)
-        let mut rng = bip39::rand::thread_rng();
-        let mnemonic = Mnemonic::generate_in_with(&mut rng, Language::English, 24).unwrap();
-
         Ok(Self {
-            mnemonic, // Note: This won't match the seed that was used
+            mnemonic: None,
+            seed: Some(seed),
+            account_index: index,
             private_key,
             public_key,
             address,
         })
     }
 
+    // Another account of the same HD wallet
+    pub fn derive_account(&self, index: u32) -> Result<Self, &'static str> {
+        let seed = self.seed.clone().ok_or("Wallet has no HD seed")?;
+        let mut account = Self::from_hd_seed(seed, index)?;
+        account.mnemonic = self.mnemonic.clone();
+        Ok(account)
+    }
+
     // Proper BIP-39 implementation of mnemonic to seed conversion using PBKDF2
-    pub fn seed_from_phrase(phrase: &str, passphrase: &str) -> Vec<u8> {
-        let salt = format!("mnemonic{}", passphrase);
+    pub fn seed_from_phrase(phrase: &str, passphrase: &str) -> Zeroizing<Vec<u8>> {
+        let salt = Zeroizing::new(format!("mnemonic{}", passphrase));
 
         // Create a 64-byte (512-bit) output buffer
-        let mut seed = vec![0u8; 64];
+        let mut seed = Zeroizing::new(vec![0u8; 64]);
 
         // Perform PBKDF2 derivation
         let _ = pbkdf2::<Hmac<Sha512>>(
@@ -85,15 +161,20 @@ impl EthereumWallet {
         seed
     }
 
-    fn derive_private_key(mnemonic: &Mnemonic) -> [u8; 32] {
-        let path = "m/44'/60'/0'/0/0";
-        let ext = ExtendedPrivKey::derive(mnemonic.to_seed("").as_ref(), path).unwrap();
-        ext.secret()
+    fn mnemonic_seed(mnemonic: &Mnemonic, passphrase: &str) -> Seed {
+        let seed = Zeroizing::new(mnemonic.to_seed(passphrase));
+        Seed::from_bytes(seed.as_ref()).unwrap()
+    }
+
+    fn derive_private_key(seed: &Seed, path: &str) -> Result<PrivateKey, &'static str> {
+        let ext = ExtendedPrivKey::derive(seed.expose_secret(), path)
+            .map_err(|_| "Invalid derivation path")?;
+        Ok(PrivateKey::from_bytes(ext.secret()))
     }
 
-    fn derive_public_key(private_key: &[u8; 32]) -> [u8; 65] {
+    fn derive_public_key(private_key: &PrivateKey) -> [u8; 65] {
         let secp = Secp256k1::new();
-        let secret_key = SecretKey::from_slice(private_key).unwrap();
+        let secret_key = SecretKey::from_slice(private_key.expose_secret()).unwrap();
         let public_key = PublicKey::from_secret_key(&secp, &secret_key);
         public_key.serialize_uncompressed()
     }
@@ -114,12 +195,45 @@ impl EthereumWallet {
     }
 
     // Getters
-    pub fn mnemonic_phrase(&self) -> String {
-        self.mnemonic.to_string()
+    pub fn mnemonic_phrase(&self) -> Option<MnemonicPhrase> {
+        self.mnemonic
+            .as_ref()
+            .map(|mnemonic| MnemonicPhrase::new(mnemonic.to_string()))
+    }
+
+    pub fn private_key(&self) -> &PrivateKey {
+        &self.private_key
+    }
+
+    pub fn seed(&self) -> Option<&Seed> {
+        self.seed.as_ref()
+    }
+
+    pub fn is_hd(&self) -> bool {
+        self.seed.is_some()
+    }
+
+    pub fn account_index(&self) -> u32 {
+        self.account_index
     }
 
-    pub fn private_key_hex(&self) -> String {
-        hex::encode(self.private_key)
+    // None for wallets holding a single key, whose path is unknown
+    pub fn derivation_path(&self) -> Option<String> {
+        self.seed
+            .as_ref()
+            .map(|_| account_derivation_path(self.account_index))
+    }
+
+    // What gets encrypted under the PIN: the seed of an HD wallet, otherwise its key
+    pub fn secret(&self) -> WalletSecret {
+        match &self.seed {
+            Some(seed) => WalletSecret::Seed(seed.clone()),
+            None => WalletSecret::PrivateKey(self.private_key.clone()),
+        }
+    }
+
+    pub fn private_key_hex(&self) -> SecretString {
+        self.private_key.to_hex()
     }
 
     pub fn address(&self) -> &str {
@@ -140,10 +254,18 @@ mod tests {
         let wallet = EthereumWallet::new();
 
         // Check that the mnemonic has 24 words
-        assert_eq!(wallet.mnemonic_phrase().split_whitespace().count(), 24);
+        assert_eq!(
+            wallet
+                .mnemonic_phrase()
+                .unwrap()
+                .expose_secret()
+                .split_whitespace()
+                .count(),
+            24
+        );
 
         // Check that private key is 32 bytes (64 hex chars)
-        assert_eq!(wallet.private_key_hex().len(), 64);
+        assert_eq!(wallet.private_key_hex().expose_secret().len(), 64);
 
         // Check that the address starts with "0x" and is 42 chars long
         assert!(wallet.address().starts_with("0x"));
@@ -156,11 +278,11 @@ mod tests {
     #[test]
     fn test_address_derivation() {
         // Use a known private key and expected address for deterministic testing
-        let private_key = [
+        let private_key = PrivateKey::from_bytes([
             0x1a, 0x2b, 0x3c, 0x4d, 0x5e, 0x6f, 0x70, 0x81, 0x92, 0xa3, 0xb4, 0xc5, 0xd6, 0xe7,
             0xf8, 0x09, 0x1a, 0x2b, 0x3c, 0x4d, 0x5e, 0x6f, 0x70, 0x81, 0x92, 0xa3, 0xb4, 0xc5,
             0xd6, 0xe7, 0xf8, 0x09,
-        ];
+        ]);
 
         let public_key = EthereumWallet::derive_public_key(&private_key);
         let address = EthereumWallet::derive_address(&public_key);
@@ -182,10 +304,13 @@ mod tests {
         // Create wallet from seed
         let wallet = EthereumWallet::from_seed(&seed).expect("Failed to create wallet from seed");
 
-        // Basic validation
-        assert_eq!(wallet.mnemonic_phrase().split_whitespace().count(), 24);
-        assert!(wallet.address().starts_with("0x"));
-        assert_eq!(wallet.address().len(), 42);
+        // Basic validation - no mnemonic is invented for a bare seed
+        assert!(wallet.mnemonic_phrase().is_none());
+        assert!(wallet.is_hd());
+        assert_eq!(
+            wallet.address(),
+            "0xb73f8cc7b63c5ed98d6f7c7ba59c8094972b1166"
+        );
     }
 
     #[test]
@@ -201,7 +326,7 @@ mod tests {
         let expected_hex = "5eb00bbddcf069084889a8ab9155568165f5c453ccb85e70811aaed6f6da5fc19a5ac40b389cd370d086206dec8aa6c43daea6690f20ad3d8d48b2d2ce9e38e4";
         let expected = hex::decode(expected_hex).unwrap();
 
-        assert_eq!(seed, expected);
+        assert_eq!(*seed, expected);
     }
 
     #[test]
@@ -231,7 +356,153 @@ mod tests {
 
         // Both wallets should have the same address and private key
         assert_eq!(wallet1.address(), wallet2.address());
-        assert_eq!(wallet1.private_key_hex(), wallet2.private_key_hex());
+        assert_eq!(
+            wallet1.private_key_hex().expose_secret(),
+            wallet2.private_key_hex().expose_secret()
+        );
+    }
+
+    #[test]
+    fn test_from_mnemonic() {
+        // Well-known address of the BIP-39 test mnemonic at m/44'/60'/0'/0/0
+        let phrase = MnemonicPhrase::from(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+        );
+        let wallet = EthereumWallet::from_mnemonic(&phrase).expect("Failed to restore wallet");
+        assert_eq!(
+            wallet.address(),
+            "0x9858effd232b4033e47d90003d41ec34ecaeda94"
+        );
+
+        // A generated wallet can be restored from its own phrase
+        let original = EthereumWallet::new();
+        let restored = EthereumWallet::from_mnemonic(&original.mnemonic_phrase().unwrap()).unwrap();
+        assert_eq!(restored.address(), original.address());
+
+        // Bad checksum and unknown words are rejected
+        let bad_checksum = MnemonicPhrase::from(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon",
+        );
+        assert!(EthereumWallet::from_mnemonic(&bad_checksum).is_err());
+        assert!(EthereumWallet::from_mnemonic(&MnemonicPhrase::from("not a phrase")).is_err());
+    }
+
+    #[test]
+    fn test_from_mnemonic_with_path() {
+        let phrase = MnemonicPhrase::from(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+        );
+        let no_passphrase = SecretString::from("");
+
+        // The default path matches `from_mnemonic`
+        let default = EthereumWallet::from_mnemonic_with_path(
+            &phrase,
+            &no_passphrase,
+            DEFAULT_DERIVATION_PATH,
+        )
+        .unwrap();
+        assert_eq!(
+            default.address(),
+            EthereumWallet::from_mnemonic(&phrase).unwrap().address()
+        );
+
+        // Other paths and passphrases select other accounts
+        let second =
+            EthereumWallet::from_mnemonic_with_path(&phrase, &no_passphrase, "m/44'/60'/0'/0/1")
+                .unwrap();
+        let with_passphrase = EthereumWallet::from_mnemonic_with_path(
+            &phrase,
+            &SecretString::from("TestingPassphrase"),
+            DEFAULT_DERIVATION_PATH,
+        )
+        .unwrap();
+        assert_ne!(second.address(), default.address());
+        assert_ne!(with_passphrase.address(), default.address());
+
+        assert!(
+            EthereumWallet::from_mnemonic_with_path(&phrase, &no_passphrase, "not/a/path").is_err()
+        );
+    }
+
+    #[test]
+    fn test_derive_accounts() {
+        // Well-known addresses of the BIP-39 test mnemonic at m/44'/60'/0'/0/{0,1,2}
+        let phrase = MnemonicPhrase::from(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+        );
+        let wallet = EthereumWallet::from_mnemonic(&phrase).unwrap();
+        assert_eq!(wallet.account_index(), 0);
+        assert_eq!(
+            wallet.derivation_path().as_deref(),
+            Some(DEFAULT_DERIVATION_PATH)
+        );
+
+        let second = wallet.derive_account(1).unwrap();
+        let third = wallet.derive_account(2).unwrap();
+        assert_eq!(
+            second.address(),
+            "0x6fac4d18c912343bf86fa7049364dd4e424ab9c0"
+        );
+        assert_eq!(
+            third.address(),
+            "0xb6716976a3ebe8d39aceb04372f22ff8e6802d7a"
+        );
+        assert_eq!(second.derivation_path().unwrap(), "m/44'/60'/0'/0/1");
+
+        // The stored seed alone is enough to derive the same accounts again
+        let restored = EthereumWallet::from_hd_seed(wallet.seed().unwrap().clone(), 2).unwrap();
+        assert_eq!(restored.address(), third.address());
+
+        // Hardened indexes are out of range
+        assert!(wallet.derive_account(MAX_ACCOUNT_INDEX + 1).is_err());
+    }
+
+    #[test]
+    fn test_single_key_wallets_have_no_accounts() {
+        let phrase = MnemonicPhrase::from(
+            "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+        );
+        let custom_path = EthereumWallet::from_mnemonic_with_path(
+            &phrase,
+            &SecretString::from(""),
+            "m/44'/60'/1'/0/0",
+        )
+        .unwrap();
+        assert!(!custom_path.is_hd());
+        assert!(custom_path.derivation_path().is_none());
+        assert!(custom_path.derive_account(1).is_err());
+        assert!(!custom_path.secret().is_seed());
+
+        assert!(EthereumWallet::new().secret().is_seed());
+    }
+
+    #[test]
+    fn test_from_private_key() {
+        // Private key of the Web3 Secret Storage test vectors
+        let private_key = PrivateKey::from_hex(
+            "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d",
+        )
+        .unwrap();
+        let wallet = EthereumWallet::from_private_key(private_key).unwrap();
+
+        assert_eq!(
+            wallet.address(),
+            "0x008aeeda4d805471df9b2a5b0f38a0c3bcba786b"
+        );
+        assert!(wallet.mnemonic_phrase().is_none());
+        assert!(!wallet.is_hd());
+
+        // Zero is not a valid secp256k1 key
+        assert!(EthereumWallet::from_private_key(PrivateKey::from_bytes([0u8; 32])).is_err());
+    }
+
+    #[test]
+    fn test_debug_hides_private_key() {
+        let wallet = EthereumWallet::new();
+        let debug = format!("{:?}", wallet);
+
+        assert!(debug.contains(wallet.address()));
+        assert!(!debug.contains(wallet.private_key_hex().expose_secret()));
     }
 
     #[test]
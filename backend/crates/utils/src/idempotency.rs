@@ -0,0 +1,208 @@
+//! Storage for idempotency keys: the first request with a key claims it, and later
+//! requests with the same key find what the first one stored.
+
+use app_error::{AppError, AppResult};
+use async_trait::async_trait;
+use redis::{Client, Script, aio::ConnectionManager};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+// Stores ARGV[1] under KEYS[1] for ARGV[2] milliseconds unless something is stored there
+// already, which is returned instead
+const CLAIM_SCRIPT: &str = r"
+local existing = redis.call('GET', KEYS[1])
+if existing then
+    return existing
+end
+redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+return false
+";
+
+/// Values stored under idempotency keys, each kept for a retention window
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    /// Store `value` under `key` for `ttl` if nothing is stored there yet, returning
+    /// `None`; otherwise return what is stored, unchanged
+    async fn claim(&self, key: &str, value: &str, ttl: Duration) -> AppResult<Option<String>>;
+
+    /// Replace what is stored under `key`, keeping it for `ttl` from now
+    async fn store(&self, key: &str, value: &str, ttl: Duration) -> AppResult<()>;
+}
+
+struct MemoryEntry {
+    value: String,
+    expires_at: Instant,
+}
+
+/// Idempotency store for a single instance of the service, such as in tests and local
+/// development
+#[derive(Default)]
+pub struct MemoryIdempotencyStore {
+    entries: Mutex<HashMap<String, MemoryEntry>>,
+}
+
+impl MemoryIdempotencyStore {
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, MemoryEntry>> {
+        // A panic while holding the lock cannot leave an entry half-updated
+        self.entries
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for MemoryIdempotencyStore {
+    async fn claim(&self, key: &str, value: &str, ttl: Duration) -> AppResult<Option<String>> {
+        let mut entries = self.entries();
+        let now = Instant::now();
+        entries.retain(|_, entry| entry.expires_at > now);
+        if let Some(entry) = entries.get(key) {
+            return Ok(Some(entry.value.clone()));
+        }
+        entries.insert(
+            key.to_string(),
+            MemoryEntry {
+                value: value.to_string(),
+                expires_at: now + ttl,
+            },
+        );
+        Ok(None)
+    }
+
+    async fn store(&self, key: &str, value: &str, ttl: Duration) -> AppResult<()> {
+        self.entries().insert(
+            key.to_string(),
+            MemoryEntry {
+                value: value.to_string(),
+                expires_at: Instant::now() + ttl,
+            },
+        );
+        Ok(())
+    }
+}
+
+/// Idempotency store shared by every instance of the service through Redis. Claiming
+/// runs as one script, so only one of several concurrent requests with a key claims it.
+#[derive(Clone)]
+pub struct RedisIdempotencyStore {
+    redis_manager: ConnectionManager,
+}
+
+impl RedisIdempotencyStore {
+    /// Connect to Redis
+    pub async fn new(redis_url: &str) -> AppResult<Self> {
+        let client = Client::open(redis_url).map_err(|e| {
+            error!("Failed to connect to Redis: {}", e);
+            AppError::ConfigError(anyhow::anyhow!("Redis connection failed: {}", e))
+        })?;
+
+        let manager = ConnectionManager::new(client).await.map_err(|e| {
+            error!("Failed to create Redis connection manager: {}", e);
+            AppError::ConfigError(anyhow::anyhow!("Redis connection manager failed: {}", e))
+        })?;
+
+        info!("Successfully connected to Redis for idempotency keys");
+
+        Ok(Self {
+            redis_manager: manager,
+        })
+    }
+}
+
+fn redis_error(action: &str, e: redis::RedisError) -> AppError {
+    error!("Redis error when {} an idempotency key: {}", action, e);
+    AppError::ServerError(anyhow::anyhow!("Idempotency key tracking error"))
+}
+
+#[async_trait]
+impl IdempotencyStore for RedisIdempotencyStore {
+    async fn claim(&self, key: &str, value: &str, ttl: Duration) -> AppResult<Option<String>> {
+        let mut conn = self.redis_manager.clone();
+        Script::new(CLAIM_SCRIPT)
+            .key(key)
+            .arg(value)
+            .arg(ttl.as_millis() as u64)
+            .invoke_async(&mut conn)
+            .await
+            .map_err(|e| redis_error("claiming", e))
+    }
+
+    async fn store(&self, key: &str, value: &str, ttl: Duration) -> AppResult<()> {
+        let mut conn = self.redis_manager.clone();
+        redis::cmd("SET")
+            .arg(key)
+            .arg(value)
+            .arg("PX")
+            .arg(ttl.as_millis() as u64)
+            .query_async(&mut conn)
+            .await
+            .map_err(|e| redis_error("storing", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_first_claim_wins() {
+        let store = MemoryIdempotencyStore::default();
+        let ttl = Duration::from_secs(60);
+
+        assert_eq!(store.claim("key", "first", ttl).await.unwrap(), None);
+        assert_eq!(
+            store.claim("key", "second", ttl).await.unwrap().as_deref(),
+            Some("first")
+        );
+
+        store.store("key", "done", ttl).await.unwrap();
+        assert_eq!(
+            store.claim("key", "third", ttl).await.unwrap().as_deref(),
+            Some("done")
+        );
+        assert_eq!(store.claim("other", "first", ttl).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_claims_expire() {
+        let store = MemoryIdempotencyStore::default();
+
+        assert_eq!(
+            store.claim("key", "first", Duration::ZERO).await.unwrap(),
+            None
+        );
+        assert_eq!(
+            store
+                .claim("key", "second", Duration::from_secs(60))
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_claims() {
+        let store = Arc::new(MemoryIdempotencyStore::default());
+
+        let handles: Vec<_> = (0..20)
+            .map(|i| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    store
+                        .claim("key", &i.to_string(), Duration::from_secs(60))
+                        .await
+                })
+            })
+            .collect();
+        let mut claimed = 0;
+        for handle in handles {
+            if handle.await.unwrap().unwrap().is_none() {
+                claimed += 1;
+            }
+        }
+        assert_eq!(claimed, 1);
+    }
+}
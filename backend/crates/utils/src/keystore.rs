@@ -0,0 +1,394 @@
+//! Web3 Secret Storage (Keystore V3) files, as written by geth and MetaMask.
+//!
+//! The private key is encrypted with AES-128-CTR under the first half of a key derived
+//! from the password with scrypt or PBKDF2-HMAC-SHA256; the second half authenticates
+//! the ciphertext with a Keccak-256 MAC.
+
+use aes::cipher::{KeyIvInit, StreamCipher};
+use app_error::{AppError, AppResult};
+use hmac::Hmac;
+use rand::{RngCore, rng};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::Sha256;
+use tiny_keccak::{Hasher, Keccak};
+use zeroize::Zeroizing;
+
+use crate::secret::{PRIVATE_KEY_LENGTH, PrivateKey, SecretString};
+
+type Aes128Ctr = ctr::Ctr128BE<aes::Aes128>;
+
+const KEYSTORE_VERSION: u32 = 3;
+const CIPHER: &str = "aes-128-ctr";
+const DERIVED_KEY_LENGTH: usize = 32;
+const SALT_LENGTH: usize = 32;
+const IV_LENGTH: usize = 16;
+
+// scrypt cost of exported files; r = 8, p = 1 is what geth and MetaMask use
+const EXPORT_SCRYPT_R: u32 = 8;
+const EXPORT_SCRYPT_P: u32 = 1;
+
+// Upper bounds on the KDF cost of imported files, so a crafted file cannot tie up the
+// service; they are well above what geth and MetaMask write. scrypt needs 128 * r * N
+// bytes for each of its p lanes, so its memory is bounded rather than r and p alone;
+// exports at the highest N with r = 8 just fit.
+pub const MAX_SCRYPT_LOG_N: u8 = 20;
+const MAX_SCRYPT_MEMORY_BYTES: u64 = 1 << 30;
+const MAX_PBKDF2_ITERATIONS: u32 = 10_000_000;
+
+#[derive(Serialize, Deserialize)]
+struct KeystoreFile {
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(default)]
+    address: Option<String>,
+    #[serde(alias = "Crypto")]
+    crypto: KeystoreCrypto,
+}
+
+#[derive(Serialize, Deserialize)]
+struct KeystoreCrypto {
+    cipher: String,
+    cipherparams: CipherParams,
+    ciphertext: String,
+    kdf: String,
+    kdfparams: serde_json::Value,
+    mac: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CipherParams {
+    iv: String,
+}
+
+#[derive(Deserialize)]
+struct ScryptParams {
+    dklen: usize,
+    n: u32,
+    r: u32,
+    p: u32,
+    salt: String,
+}
+
+#[derive(Deserialize)]
+struct Pbkdf2Params {
+    dklen: usize,
+    c: u32,
+    prf: String,
+    salt: String,
+}
+
+/// A private key read from a keystore file
+pub struct DecryptedKeystore {
+    pub private_key: PrivateKey,
+    /// Address recorded in the file (lowercase hex without `0x`), if any
+    pub address: Option<String>,
+}
+
+fn invalid(message: &str) -> AppError {
+    AppError::ValidationError(format!("Invalid keystore: {}", message))
+}
+
+fn decode_hex(value: &str, field: &str) -> AppResult<Vec<u8>> {
+    hex::decode(value.strip_prefix("0x").unwrap_or(value))
+        .map_err(|_| invalid(&format!("'{}' is not valid hex", field)))
+}
+
+fn keccak256(parts: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Keccak::v256();
+    for part in parts {
+        hasher.update(part);
+    }
+    let mut hash = [0u8; 32];
+    hasher.finalize(&mut hash);
+    hash
+}
+
+// Compare without returning early on the first differing byte
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn derive_key(
+    password: &[u8],
+    kdf: &str,
+    params: &serde_json::Value,
+) -> AppResult<Zeroizing<Vec<u8>>> {
+    let parse_error = |_| invalid("malformed kdfparams");
+
+    match kdf {
+        "scrypt" => {
+            let params: ScryptParams =
+                serde_json::from_value(params.clone()).map_err(parse_error)?;
+            if params.dklen != DERIVED_KEY_LENGTH || !params.n.is_power_of_two() {
+                return Err(invalid("unsupported scrypt parameters"));
+            }
+            let log_n = params.n.trailing_zeros() as u8;
+            let memory = 128 * u64::from(params.r) * u64::from(params.n) * u64::from(params.p);
+            if log_n > MAX_SCRYPT_LOG_N || memory > MAX_SCRYPT_MEMORY_BYTES {
+                return Err(invalid("scrypt parameters are too expensive"));
+            }
+
+            let salt = decode_hex(&params.salt, "salt")?;
+            let scrypt_params = scrypt::Params::new(log_n, params.r, params.p, params.dklen)
+                .map_err(|_| invalid("unsupported scrypt parameters"))?;
+
+            let mut key = Zeroizing::new(vec![0u8; params.dklen]);
+            scrypt::scrypt(password, &salt, &scrypt_params, &mut key)
+                .map_err(|_| invalid("unsupported scrypt parameters"))?;
+            Ok(key)
+        }
+        "pbkdf2" => {
+            let params: Pbkdf2Params =
+                serde_json::from_value(params.clone()).map_err(parse_error)?;
+            if params.dklen != DERIVED_KEY_LENGTH || params.prf != "hmac-sha256" {
+                return Err(invalid("unsupported pbkdf2 parameters"));
+            }
+            if params.c == 0 || params.c > MAX_PBKDF2_ITERATIONS {
+                return Err(invalid("pbkdf2 parameters are too expensive"));
+            }
+
+            let salt = decode_hex(&params.salt, "salt")?;
+            let mut key = Zeroizing::new(vec![0u8; params.dklen]);
+            pbkdf2::pbkdf2::<Hmac<Sha256>>(password, &salt, params.c, &mut key)
+                .map_err(|_| invalid("unsupported pbkdf2 parameters"))?;
+            Ok(key)
+        }
+        _ => Err(invalid(&format!("unsupported kdf '{}'", kdf))),
+    }
+}
+
+/// Decrypt the private key in a Keystore V3 JSON file
+pub fn decrypt_keystore(json: &str, password: &SecretString) -> AppResult<DecryptedKeystore> {
+    let file: KeystoreFile = serde_json::from_str(json).map_err(|_| invalid("malformed JSON"))?;
+
+    if file.version != KEYSTORE_VERSION {
+        return Err(invalid(&format!("unsupported version {}", file.version)));
+    }
+    let crypto = &file.crypto;
+    if crypto.cipher != CIPHER {
+        return Err(invalid(&format!("unsupported cipher '{}'", crypto.cipher)));
+    }
+
+    let iv = decode_hex(&crypto.cipherparams.iv, "iv")?;
+    let ciphertext = decode_hex(&crypto.ciphertext, "ciphertext")?;
+    let mac = decode_hex(&crypto.mac, "mac")?;
+    if iv.len() != IV_LENGTH || ciphertext.len() != PRIVATE_KEY_LENGTH {
+        return Err(invalid("unexpected iv or ciphertext length"));
+    }
+
+    let derived_key = derive_key(
+        password.expose_secret().as_bytes(),
+        &crypto.kdf,
+        &crypto.kdfparams,
+    )?;
+
+    // A wrong password shows up as a MAC mismatch
+    if !constant_time_eq(&keccak256(&[&derived_key[16..32], &ciphertext]), &mac) {
+        return Err(AppError::ValidationError(
+            "Incorrect keystore password".to_string(),
+        ));
+    }
+
+    let mut bytes = Zeroizing::new([0u8; PRIVATE_KEY_LENGTH]);
+    bytes.copy_from_slice(&ciphertext);
+    Aes128Ctr::new(derived_key[..16].into(), iv.as_slice().into()).apply_keystream(bytes.as_mut());
+
+    Ok(DecryptedKeystore {
+        private_key: PrivateKey::from_bytes(*bytes),
+        address: file.address.map(|address| {
+            address
+                .strip_prefix("0x")
+                .unwrap_or(&address)
+                .to_lowercase()
+        }),
+    })
+}
+
+/// Encrypt a private key into a Keystore V3 JSON file using scrypt with `N = 2^scrypt_log_n`.
+/// `address` is the wallet address, with or without `0x`.
+pub fn encrypt_keystore(
+    private_key: &PrivateKey,
+    address: &str,
+    password: &SecretString,
+    scrypt_log_n: u8,
+) -> AppResult<String> {
+    if !(1..=MAX_SCRYPT_LOG_N).contains(&scrypt_log_n) {
+        return Err(AppError::CryptoError(format!(
+            "Unsupported keystore scrypt cost 2^{}",
+            scrypt_log_n
+        )));
+    }
+
+    let mut salt = [0u8; SALT_LENGTH];
+    let mut iv = [0u8; IV_LENGTH];
+    rng().fill_bytes(&mut salt);
+    rng().fill_bytes(&mut iv);
+
+    let kdfparams = json!({
+        "dklen": DERIVED_KEY_LENGTH,
+        "n": 1u32 << scrypt_log_n,
+        "r": EXPORT_SCRYPT_R,
+        "p": EXPORT_SCRYPT_P,
+        "salt": hex::encode(salt),
+    });
+    let derived_key = derive_key(password.expose_secret().as_bytes(), "scrypt", &kdfparams)?;
+
+    let mut ciphertext = Zeroizing::new(*private_key.expose_secret());
+    Aes128Ctr::new(derived_key[..16].into(), (&iv).into()).apply_keystream(ciphertext.as_mut());
+    let mac = keccak256(&[&derived_key[16..32], ciphertext.as_ref()]);
+
+    let file = KeystoreFile {
+        version: KEYSTORE_VERSION,
+        id: Some(uuid::Uuid::new_v4().to_string()),
+        address: Some(address.trim_start_matches("0x").to_lowercase()),
+        crypto: KeystoreCrypto {
+            cipher: CIPHER.to_string(),
+            cipherparams: CipherParams {
+                iv: hex::encode(iv),
+            },
+            ciphertext: hex::encode(ciphertext.as_ref()),
+            kdf: "scrypt".to_string(),
+            kdfparams,
+            mac: hex::encode(mac),
+        },
+    };
+
+    serde_json::to_string(&file)
+        .map_err(|e| AppError::CryptoError(format!("Failed to serialize keystore: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test vectors from the Web3 Secret Storage definition
+    const PASSWORD: &str = "testpassword";
+    const PRIVATE_KEY: &str = "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d";
+
+    const PBKDF2_KEYSTORE: &str = r#"{
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": { "iv": "6087dab2f9fdbbfaddc31a909735c1e6" },
+            "ciphertext": "5318b4d5bcd28de64ee5559e671353e16f075ecae9f99c7a79a38af5f869aa46",
+            "kdf": "pbkdf2",
+            "kdfparams": {
+                "c": 262144,
+                "dklen": 32,
+                "prf": "hmac-sha256",
+                "salt": "ae3cd4e7013836a3df6bd7241b12db061dbe2c6785853cce422d148a624ce0bd"
+            },
+            "mac": "517ead924a9d0dc3124507e3393d175ce3ff7c1e96529c6c555ce9e51205e9b2"
+        },
+        "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+        "version": 3
+    }"#;
+
+    // Same key under scrypt with r = 8 as geth writes it (the definition's own scrypt
+    // vector uses r = 1, which RFC 7914 does not allow for its n)
+    const SCRYPT_KEYSTORE: &str = r#"{
+        "crypto": {
+            "cipher": "aes-128-ctr",
+            "cipherparams": { "iv": "101112131415161718191a1b1c1d1e1f" },
+            "ciphertext": "16a1019d638bc868e68adb1173878edba3f4c182c0b20add91b8893192bb8b1b",
+            "kdf": "scrypt",
+            "kdfparams": {
+                "dklen": 32,
+                "n": 8192,
+                "p": 1,
+                "r": 8,
+                "salt": "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"
+            },
+            "mac": "ce9933739cdef366e337b3946f475ffcdca0e6720f28f6fadc7504b014805798"
+        },
+        "address": "0x008aeeda4d805471df9b2a5b0f38a0c3bcba786b",
+        "version": 3
+    }"#;
+
+    #[test]
+    fn test_decrypt_pbkdf2_keystore() {
+        let decrypted = decrypt_keystore(PBKDF2_KEYSTORE, &SecretString::from(PASSWORD)).unwrap();
+        assert_eq!(decrypted.private_key.to_hex().expose_secret(), PRIVATE_KEY);
+        assert!(decrypted.address.is_none());
+    }
+
+    #[test]
+    fn test_decrypt_scrypt_keystore() {
+        let decrypted = decrypt_keystore(SCRYPT_KEYSTORE, &SecretString::from(PASSWORD)).unwrap();
+        assert_eq!(decrypted.private_key.to_hex().expose_secret(), PRIVATE_KEY);
+        assert_eq!(
+            decrypted.address.as_deref(),
+            Some("008aeeda4d805471df9b2a5b0f38a0c3bcba786b")
+        );
+    }
+
+    #[test]
+    fn test_encrypt_roundtrip() {
+        let private_key = PrivateKey::from_hex(PRIVATE_KEY).unwrap();
+        let password = SecretString::from(PASSWORD);
+        let json = encrypt_keystore(
+            &private_key,
+            "0x008AEEDA4D805471DF9B2A5B0F38A0C3BCBA786B",
+            &password,
+            10,
+        )
+        .unwrap();
+
+        let file: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(file["version"], 3);
+        assert_eq!(file["address"], "008aeeda4d805471df9b2a5b0f38a0c3bcba786b");
+        assert_eq!(file["crypto"]["kdfparams"]["n"], 1024);
+
+        let decrypted = decrypt_keystore(&json, &password).unwrap();
+        assert_eq!(decrypted.private_key.to_hex().expose_secret(), PRIVATE_KEY);
+        assert!(decrypt_keystore(&json, &SecretString::from("wrong")).is_err());
+
+        // Fresh salt and IV every time
+        let again = encrypt_keystore(&private_key, "", &password, 10).unwrap();
+        let again: serde_json::Value = serde_json::from_str(&again).unwrap();
+        assert_ne!(again["crypto"]["ciphertext"], file["crypto"]["ciphertext"]);
+
+        assert!(encrypt_keystore(&private_key, "", &password, MAX_SCRYPT_LOG_N + 1).is_err());
+    }
+
+    #[test]
+    fn test_wrong_password_and_bad_files_are_rejected() {
+        assert!(decrypt_keystore(PBKDF2_KEYSTORE, &SecretString::from("wrong")).is_err());
+
+        let password = SecretString::from(PASSWORD);
+        assert!(decrypt_keystore("{}", &password).is_err());
+        assert!(
+            decrypt_keystore(
+                &PBKDF2_KEYSTORE.replace("\"version\": 3", "\"version\": 1"),
+                &password
+            )
+            .is_err()
+        );
+        assert!(
+            decrypt_keystore(
+                &PBKDF2_KEYSTORE.replace("\"c\": 262144", "\"c\": 4294967295"),
+                &password
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_scrypt_memory_is_bounded_before_deriving() {
+        // n = 2^20 with r = 64 would need 8 GiB; it must fail at once rather than allocate
+        let expensive = SCRYPT_KEYSTORE
+            .replace("\"n\": 8192", "\"n\": 1048576")
+            .replace("\"r\": 8", "\"r\": 64");
+        let result = decrypt_keystore(&expensive, &SecretString::from(PASSWORD));
+        assert!(
+            matches!(result, Err(AppError::ValidationError(message)) if message.contains("too expensive"))
+        );
+
+        // r * p alone stays small, but many lanes at the highest n add up
+        let many_lanes = SCRYPT_KEYSTORE
+            .replace("\"n\": 8192", "\"n\": 1048576")
+            .replace("\"p\": 1", "\"p\": 8");
+        assert!(decrypt_keystore(&many_lanes, &SecretString::from(PASSWORD)).is_err());
+    }
+}
@@ -1,2 +1,8 @@
+pub mod abi;
+pub mod chain;
 pub mod crypto;
 pub mod generate;
+pub mod idempotency;
+pub mod keystore;
+pub mod secret;
+pub mod transaction;
@@ -0,0 +1,212 @@
+//! Wrapper types for secrets that must never be logged or left behind in memory.
+//!
+//! None of these types implement `Debug` or `Display`, so formatting one with `{}`
+//! or `{:?}` (including through `tracing` macros) fails to compile:
+//!
+//! ```compile_fail
+//! let pin = app_utils::secret::Pin::from("123456");
+//! println!("{:?}", pin);
+//! ```
+//!
+//! Their contents are zeroized when they are dropped. Reading the secret requires an
+//! explicit `expose_secret` call, which is easy to spot in review.
+
+use app_error::{AppError, AppResult};
+use zeroize::Zeroizing;
+
+/// Length of a secp256k1 private key in bytes
+pub const PRIVATE_KEY_LENGTH: usize = 32;
+
+/// Shortest and longest seed accepted by BIP-32
+pub const MIN_SEED_LENGTH: usize = 16;
+pub const MAX_SEED_LENGTH: usize = 64;
+
+/// Marks an encoded `WalletSecret` that holds a seed rather than a bare private key
+const SEED_ENCODING_PREFIX: &str = "seed:";
+
+/// A secret string, zeroized on drop
+#[derive(Clone)]
+pub struct SecretString(Zeroizing<String>);
+
+/// A wallet PIN
+pub type Pin = SecretString;
+
+/// A BIP-39 mnemonic phrase
+pub type MnemonicPhrase = SecretString;
+
+impl SecretString {
+    pub fn new(secret: String) -> Self {
+        Self(Zeroizing::new(secret))
+    }
+
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(secret: String) -> Self {
+        Self::new(secret)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(secret: &str) -> Self {
+        Self::new(secret.to_string())
+    }
+}
+
+/// A 32-byte secp256k1 private key, zeroized on drop
+#[derive(Clone)]
+pub struct PrivateKey(Zeroizing<[u8; PRIVATE_KEY_LENGTH]>);
+
+impl PrivateKey {
+    pub fn from_bytes(bytes: [u8; PRIVATE_KEY_LENGTH]) -> Self {
+        Self(Zeroizing::new(bytes))
+    }
+
+    /// Parse a hex-encoded private key, with or without a `0x` prefix
+    pub fn from_hex(hex_key: &str) -> AppResult<Self> {
+        let hex_key = hex_key.strip_prefix("0x").unwrap_or(hex_key);
+
+        let mut bytes = Zeroizing::new([0u8; PRIVATE_KEY_LENGTH]);
+        hex::decode_to_slice(hex_key, bytes.as_mut())
+            .map_err(|_| AppError::ValidationError("Invalid private key format".to_string()))?;
+
+        Ok(Self(bytes))
+    }
+
+    pub fn expose_secret(&self) -> &[u8; PRIVATE_KEY_LENGTH] {
+        &self.0
+    }
+
+    /// Hex encoding of the key without a `0x` prefix
+    pub fn to_hex(&self) -> SecretString {
+        SecretString::new(hex::encode(self.0.as_ref()))
+    }
+}
+
+/// A BIP-32 seed from which the accounts of an HD wallet are derived, zeroized on drop
+#[derive(Clone)]
+pub struct Seed(Zeroizing<Vec<u8>>);
+
+impl Seed {
+    pub fn from_bytes(bytes: &[u8]) -> AppResult<Self> {
+        if !(MIN_SEED_LENGTH..=MAX_SEED_LENGTH).contains(&bytes.len()) {
+            return Err(AppError::ValidationError(format!(
+                "Seed must be between {} and {} bytes",
+                MIN_SEED_LENGTH, MAX_SEED_LENGTH
+            )));
+        }
+
+        Ok(Self(Zeroizing::new(bytes.to_vec())))
+    }
+
+    /// Parse a hex-encoded seed
+    pub fn from_hex(hex_seed: &str) -> AppResult<Self> {
+        let bytes = Zeroizing::new(
+            hex::decode(hex_seed)
+                .map_err(|_| AppError::ValidationError("Invalid seed format".to_string()))?,
+        );
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn expose_secret(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Hex encoding of the seed
+    pub fn to_hex(&self) -> SecretString {
+        SecretString::new(hex::encode(self.0.as_slice()))
+    }
+}
+
+/// The secret a wallet keeps under its PIN: the seed of an HD wallet, or a single private
+/// key for wallets imported from one and wallets created before HD support
+#[derive(Clone)]
+pub enum WalletSecret {
+    Seed(Seed),
+    PrivateKey(PrivateKey),
+}
+
+impl WalletSecret {
+    /// Encode for encryption. Private keys stay bare hex, as in records written before
+    /// seeds were stored, and seeds are prefixed so the two can always be told apart.
+    pub fn encode(&self) -> SecretString {
+        match self {
+            Self::Seed(seed) => SecretString::new(format!(
+                "{}{}",
+                SEED_ENCODING_PREFIX,
+                seed.to_hex().expose_secret()
+            )),
+            Self::PrivateKey(private_key) => private_key.to_hex(),
+        }
+    }
+
+    /// Parse the output of `encode`
+    pub fn decode(encoded: &str) -> AppResult<Self> {
+        match encoded.strip_prefix(SEED_ENCODING_PREFIX) {
+            Some(hex_seed) => Seed::from_hex(hex_seed).map(Self::Seed),
+            None => PrivateKey::from_hex(encoded).map(Self::PrivateKey),
+        }
+    }
+
+    pub fn is_seed(&self) -> bool {
+        matches!(self, Self::Seed(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX_KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    #[test]
+    fn test_private_key_hex_roundtrip() {
+        let key = PrivateKey::from_hex(HEX_KEY).unwrap();
+        assert_eq!(key.to_hex().expose_secret(), HEX_KEY);
+
+        let prefixed = PrivateKey::from_hex(&format!("0x{}", HEX_KEY)).unwrap();
+        assert_eq!(prefixed.expose_secret(), key.expose_secret());
+    }
+
+    #[test]
+    fn test_invalid_private_key_is_rejected() {
+        assert!(PrivateKey::from_hex("not-hex").is_err());
+        assert!(PrivateKey::from_hex(&HEX_KEY[..62]).is_err());
+        assert!(PrivateKey::from_hex(&format!("{}00", HEX_KEY)).is_err());
+    }
+
+    #[test]
+    fn test_secret_string_exposes_value() {
+        let pin = Pin::from("123456");
+        assert_eq!(pin.expose_secret(), "123456");
+        assert_eq!(pin.clone().expose_secret(), "123456");
+    }
+
+    #[test]
+    fn test_wallet_secret_encoding() {
+        let key = WalletSecret::PrivateKey(PrivateKey::from_hex(HEX_KEY).unwrap());
+        // Keys keep the encoding of records written before HD wallets
+        assert_eq!(key.encode().expose_secret(), HEX_KEY);
+
+        let decoded = WalletSecret::decode(key.encode().expose_secret()).unwrap();
+        assert!(!decoded.is_seed());
+
+        // A 32-byte seed must not be mistaken for a private key
+        let seed = WalletSecret::Seed(Seed::from_hex(HEX_KEY).unwrap());
+        match WalletSecret::decode(seed.encode().expose_secret()).unwrap() {
+            WalletSecret::Seed(decoded) => assert_eq!(decoded.to_hex().expose_secret(), HEX_KEY),
+            WalletSecret::PrivateKey(_) => panic!("seed decoded as a private key"),
+        }
+    }
+
+    #[test]
+    fn test_invalid_seed_is_rejected() {
+        assert!(Seed::from_bytes(&[0u8; MIN_SEED_LENGTH - 1]).is_err());
+        assert!(Seed::from_bytes(&[0u8; MAX_SEED_LENGTH + 1]).is_err());
+        assert!(Seed::from_bytes(&[0u8; MAX_SEED_LENGTH]).is_ok());
+        assert!(WalletSecret::decode("seed:not-hex").is_err());
+    }
+}
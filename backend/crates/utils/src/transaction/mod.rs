@@ -0,0 +1,779 @@
+//! Ethereum transactions: building, signing and decoding legacy (EIP-155), access-list
+//! (EIP-2930) and dynamic-fee (EIP-1559) transactions.
+//!
+//! The raw bytes of a signed transaction are what `eth_sendRawTransaction` takes, and its
+//! hash is the Keccak-256 of those bytes. Typed transactions are prefixed with their type
+//! byte (EIP-2718) and sign `type || rlp(fields)`; legacy transactions sign
+//! `rlp(fields, chain_id, 0, 0)` and fold the chain ID into `v`.
+
+pub mod rlp;
+
+use app_error::{AppError, AppResult};
+use secp256k1::ecdsa::{RecoverableSignature, RecoveryId};
+use secp256k1::{Message, Secp256k1, SecretKey};
+use tiny_keccak::{Hasher, Keccak};
+
+pub use primitive_types::{H160, H256, U256};
+
+use crate::secret::PrivateKey;
+use rlp::RlpItem;
+
+pub const ACCESS_LIST_TX_TYPE: u8 = 0x01;
+pub const DYNAMIC_FEE_TX_TYPE: u8 = 0x02;
+
+// Largest chain ID whose EIP-155 `v` (chain_id * 2 + 36) still fits in a u64
+pub const MAX_CHAIN_ID: u64 = (u64::MAX - 36) / 2;
+
+// Half the secp256k1 group order; signatures with a larger `s` are malleable (EIP-2)
+const SECP256K1_HALF_ORDER: [u8; 32] = [
+    0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+    0x5d, 0x57, 0x6e, 0x73, 0x57, 0xa4, 0x50, 0x1d, 0xdf, 0xe9, 0x2f, 0x46, 0x68, 0x1b, 0x20, 0xa0,
+];
+
+fn invalid(message: &str) -> AppError {
+    AppError::ValidationError(format!("Invalid transaction: {}", message))
+}
+
+pub(crate) fn keccak256(data: &[u8]) -> H256 {
+    let mut hasher = Keccak::v256();
+    hasher.update(data);
+    let mut hash = [0u8; 32];
+    hasher.finalize(&mut hash);
+    H256(hash)
+}
+
+/// Parse a `0x`-prefixed, 20-byte hex address
+pub fn parse_address(address: &str) -> AppResult<H160> {
+    let bytes = address
+        .strip_prefix("0x")
+        .and_then(|hex_address| hex::decode(hex_address).ok())
+        .filter(|bytes| bytes.len() == 20)
+        .ok_or_else(|| AppError::ValidationError(format!("Invalid address '{}'", address)))?;
+    Ok(H160::from_slice(&bytes))
+}
+
+/// Parse a `0x`-prefixed, 32-byte hex transaction hash
+pub fn parse_hash(hash: &str) -> AppResult<H256> {
+    let bytes = hash
+        .strip_prefix("0x")
+        .and_then(|hex_hash| hex::decode(hex_hash).ok())
+        .filter(|bytes| bytes.len() == 32)
+        .ok_or_else(|| AppError::ValidationError(format!("Invalid transaction hash '{}'", hash)))?;
+    Ok(H256::from_slice(&bytes))
+}
+
+/// Format an address as lowercase `0x`-prefixed hex
+pub fn format_address(address: &H160) -> String {
+    format!("{:#x}", address)
+}
+
+/// An address and the storage slots a transaction declares it will touch (EIP-2930)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AccessListItem {
+    pub address: H160,
+    pub storage_keys: Vec<H256>,
+}
+
+/// A pre-typed transaction. Without a chain ID it is signed the pre-EIP-155 way and can be
+/// replayed on any chain
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LegacyTransaction {
+    pub chain_id: Option<u64>,
+    pub nonce: u64,
+    pub gas_price: U256,
+    pub gas_limit: u64,
+    pub to: Option<H160>,
+    pub value: U256,
+    pub data: Vec<u8>,
+}
+
+/// A type 1 transaction with an access list (EIP-2930)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct AccessListTransaction {
+    pub chain_id: u64,
+    pub nonce: u64,
+    pub gas_price: U256,
+    pub gas_limit: u64,
+    pub to: Option<H160>,
+    pub value: U256,
+    pub data: Vec<u8>,
+    pub access_list: Vec<AccessListItem>,
+}
+
+/// A type 2 transaction paying a base fee plus a priority fee (EIP-1559)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct DynamicFeeTransaction {
+    pub chain_id: u64,
+    pub nonce: u64,
+    pub max_priority_fee_per_gas: U256,
+    pub max_fee_per_gas: U256,
+    pub gas_limit: u64,
+    pub to: Option<H160>,
+    pub value: U256,
+    pub data: Vec<u8>,
+    pub access_list: Vec<AccessListItem>,
+}
+
+/// An unsigned transaction of any supported type
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Transaction {
+    Legacy(LegacyTransaction),
+    AccessList(AccessListTransaction),
+    DynamicFee(DynamicFeeTransaction),
+}
+
+/// A recoverable secp256k1 signature; `y_parity` selects which of the two candidate public
+/// keys signed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Signature {
+    pub y_parity: bool,
+    pub r: U256,
+    pub s: U256,
+}
+
+/// A signed transaction, its raw encoding and its hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedTransaction {
+    transaction: Transaction,
+    signature: Signature,
+    raw: Vec<u8>,
+    hash: H256,
+}
+
+fn encode_to(to: &Option<H160>) -> RlpItem {
+    RlpItem::bytes(
+        to.map(|address| address.as_bytes().to_vec())
+            .unwrap_or_default(),
+    )
+}
+
+fn encode_access_list(access_list: &[AccessListItem]) -> RlpItem {
+    RlpItem::List(
+        access_list
+            .iter()
+            .map(|item| {
+                RlpItem::List(vec![
+                    RlpItem::bytes(item.address.as_bytes()),
+                    RlpItem::List(
+                        item.storage_keys
+                            .iter()
+                            .map(|key| RlpItem::bytes(key.as_bytes()))
+                            .collect(),
+                    ),
+                ])
+            })
+            .collect(),
+    )
+}
+
+fn decode_to(item: &RlpItem) -> AppResult<Option<H160>> {
+    match item.as_bytes()? {
+        [] => Ok(None),
+        bytes if bytes.len() == 20 => Ok(Some(H160::from_slice(bytes))),
+        _ => Err(invalid("recipient is not a 20-byte address")),
+    }
+}
+
+fn decode_access_list(item: &RlpItem) -> AppResult<Vec<AccessListItem>> {
+    item.as_list()?
+        .iter()
+        .map(|entry| {
+            let [address, storage_keys] = entry.as_list()? else {
+                return Err(invalid("access list entry must have two fields"));
+            };
+            let address = match address.as_bytes()? {
+                bytes if bytes.len() == 20 => H160::from_slice(bytes),
+                _ => return Err(invalid("access list address is not 20 bytes")),
+            };
+            let storage_keys = storage_keys
+                .as_list()?
+                .iter()
+                .map(|key| match key.as_bytes()? {
+                    bytes if bytes.len() == 32 => Ok(H256::from_slice(bytes)),
+                    _ => Err(invalid("storage key is not 32 bytes")),
+                })
+                .collect::<AppResult<_>>()?;
+            Ok(AccessListItem {
+                address,
+                storage_keys,
+            })
+        })
+        .collect()
+}
+
+fn legacy_v(chain_id: Option<u64>, y_parity: bool) -> u64 {
+    match chain_id {
+        Some(chain_id) => chain_id * 2 + 35 + u64::from(y_parity),
+        None => 27 + u64::from(y_parity),
+    }
+}
+
+fn y_parity_from_item(item: &RlpItem) -> AppResult<bool> {
+    match item.as_u64()? {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(invalid("y parity must be 0 or 1")),
+    }
+}
+
+fn signature_from_items(y_parity: bool, r: &RlpItem, s: &RlpItem) -> AppResult<Signature> {
+    let signature = Signature {
+        y_parity,
+        r: r.as_u256()?,
+        s: s.as_u256()?,
+    };
+    if signature.r.is_zero() || signature.s.is_zero() {
+        return Err(invalid("signature values must not be zero"));
+    }
+    if signature.s > U256::from_big_endian(&SECP256K1_HALF_ORDER) {
+        return Err(invalid(
+            "signature s value is not in the lower half of the order",
+        ));
+    }
+    Ok(signature)
+}
+
+impl Transaction {
+    /// EIP-2718 type byte, `None` for legacy transactions
+    pub fn transaction_type(&self) -> Option<u8> {
+        match self {
+            Transaction::Legacy(_) => None,
+            Transaction::AccessList(_) => Some(ACCESS_LIST_TX_TYPE),
+            Transaction::DynamicFee(_) => Some(DYNAMIC_FEE_TX_TYPE),
+        }
+    }
+
+    pub fn chain_id(&self) -> Option<u64> {
+        match self {
+            Transaction::Legacy(tx) => tx.chain_id,
+            Transaction::AccessList(tx) => Some(tx.chain_id),
+            Transaction::DynamicFee(tx) => Some(tx.chain_id),
+        }
+    }
+
+    pub fn nonce(&self) -> u64 {
+        match self {
+            Transaction::Legacy(tx) => tx.nonce,
+            Transaction::AccessList(tx) => tx.nonce,
+            Transaction::DynamicFee(tx) => tx.nonce,
+        }
+    }
+
+    pub fn gas_limit(&self) -> u64 {
+        match self {
+            Transaction::Legacy(tx) => tx.gas_limit,
+            Transaction::AccessList(tx) => tx.gas_limit,
+            Transaction::DynamicFee(tx) => tx.gas_limit,
+        }
+    }
+
+    pub fn to(&self) -> Option<H160> {
+        match self {
+            Transaction::Legacy(tx) => tx.to,
+            Transaction::AccessList(tx) => tx.to,
+            Transaction::DynamicFee(tx) => tx.to,
+        }
+    }
+
+    pub fn value(&self) -> U256 {
+        match self {
+            Transaction::Legacy(tx) => tx.value,
+            Transaction::AccessList(tx) => tx.value,
+            Transaction::DynamicFee(tx) => tx.value,
+        }
+    }
+
+    pub fn data(&self) -> &[u8] {
+        match self {
+            Transaction::Legacy(tx) => &tx.data,
+            Transaction::AccessList(tx) => &tx.data,
+            Transaction::DynamicFee(tx) => &tx.data,
+        }
+    }
+
+    /// Highest price per gas the sender may pay: the gas price, or the EIP-1559 fee cap
+    pub fn max_fee_per_gas(&self) -> U256 {
+        match self {
+            Transaction::Legacy(tx) => tx.gas_price,
+            Transaction::AccessList(tx) => tx.gas_price,
+            Transaction::DynamicFee(tx) => tx.max_fee_per_gas,
+        }
+    }
+
+    /// Most the sender pays the block producer per gas; the whole gas price before EIP-1559
+    pub fn max_priority_fee_per_gas(&self) -> U256 {
+        match self {
+            Transaction::Legacy(tx) => tx.gas_price,
+            Transaction::AccessList(tx) => tx.gas_price,
+            Transaction::DynamicFee(tx) => tx.max_priority_fee_per_gas,
+        }
+    }
+
+    // The fields every transaction type is signed over, in order
+    fn payload_fields(&self) -> Vec<RlpItem> {
+        match self {
+            Transaction::Legacy(tx) => vec![
+                RlpItem::uint(tx.nonce),
+                RlpItem::u256(tx.gas_price),
+                RlpItem::uint(tx.gas_limit),
+                encode_to(&tx.to),
+                RlpItem::u256(tx.value),
+                RlpItem::bytes(tx.data.clone()),
+            ],
+            Transaction::AccessList(tx) => vec![
+                RlpItem::uint(tx.chain_id),
+                RlpItem::uint(tx.nonce),
+                RlpItem::u256(tx.gas_price),
+                RlpItem::uint(tx.gas_limit),
+                encode_to(&tx.to),
+                RlpItem::u256(tx.value),
+                RlpItem::bytes(tx.data.clone()),
+                encode_access_list(&tx.access_list),
+            ],
+            Transaction::DynamicFee(tx) => vec![
+                RlpItem::uint(tx.chain_id),
+                RlpItem::uint(tx.nonce),
+                RlpItem::u256(tx.max_priority_fee_per_gas),
+                RlpItem::u256(tx.max_fee_per_gas),
+                RlpItem::uint(tx.gas_limit),
+                encode_to(&tx.to),
+                RlpItem::u256(tx.value),
+                RlpItem::bytes(tx.data.clone()),
+                encode_access_list(&tx.access_list),
+            ],
+        }
+    }
+
+    // Prefix the RLP list with the type byte for typed transactions
+    fn envelope(&self, fields: Vec<RlpItem>) -> Vec<u8> {
+        let list = RlpItem::List(fields).encode();
+        match self.transaction_type() {
+            Some(tx_type) => [&[tx_type][..], &list].concat(),
+            None => list,
+        }
+    }
+
+    /// The hash a sender signs
+    pub fn signing_hash(&self) -> H256 {
+        let mut fields = self.payload_fields();
+        if let Transaction::Legacy(LegacyTransaction {
+            chain_id: Some(chain_id),
+            ..
+        }) = self
+        {
+            fields.extend([RlpItem::uint(*chain_id), RlpItem::uint(0), RlpItem::uint(0)]);
+        }
+        keccak256(&self.envelope(fields))
+    }
+
+    /// Sign the transaction with a private key
+    pub fn sign(self, private_key: &PrivateKey) -> AppResult<SignedTransaction> {
+        if self
+            .chain_id()
+            .is_some_and(|chain_id| chain_id > MAX_CHAIN_ID)
+        {
+            return Err(invalid("chain ID is too large"));
+        }
+
+        let secret_key = SecretKey::from_byte_array(private_key.expose_secret())
+            .map_err(|e| AppError::CryptoError(format!("Invalid private key: {}", e)))?;
+        let message = Message::from_digest(self.signing_hash().0);
+        let (recovery_id, compact) = Secp256k1::new()
+            .sign_ecdsa_recoverable(&message, &secret_key)
+            .serialize_compact();
+
+        let signature = Signature {
+            y_parity: i32::from(recovery_id) == 1,
+            r: U256::from_big_endian(&compact[..32]),
+            s: U256::from_big_endian(&compact[32..]),
+        };
+        Ok(SignedTransaction::new(self, signature))
+    }
+}
+
+impl SignedTransaction {
+    fn new(transaction: Transaction, signature: Signature) -> Self {
+        let mut fields = transaction.payload_fields();
+        let v = match &transaction {
+            Transaction::Legacy(tx) => legacy_v(tx.chain_id, signature.y_parity),
+            _ => u64::from(signature.y_parity),
+        };
+        fields.extend([
+            RlpItem::uint(v),
+            RlpItem::u256(signature.r),
+            RlpItem::u256(signature.s),
+        ]);
+
+        let raw = transaction.envelope(fields);
+        let hash = keccak256(&raw);
+        Self {
+            transaction,
+            signature,
+            raw,
+            hash,
+        }
+    }
+
+    /// Decode a raw signed transaction as returned by `eth_getRawTransactionByHash`
+    pub fn decode(raw: &[u8]) -> AppResult<Self> {
+        let signed = match raw.first() {
+            None => return Err(invalid("empty input")),
+            Some(prefix) if *prefix >= 0xc0 => Self::decode_legacy(raw)?,
+            Some(&ACCESS_LIST_TX_TYPE) => Self::decode_access_list(&raw[1..])?,
+            Some(&DYNAMIC_FEE_TX_TYPE) => Self::decode_dynamic_fee(&raw[1..])?,
+            Some(tx_type) => {
+                return Err(invalid(&format!(
+                    "unsupported transaction type {:#04x}",
+                    tx_type
+                )));
+            }
+        };
+
+        // Canonical RLP re-encodes byte for byte, so this only trips on a bug
+        if signed.raw != raw {
+            return Err(invalid("encoding is not canonical"));
+        }
+        Ok(signed)
+    }
+
+    fn decode_legacy(raw: &[u8]) -> AppResult<Self> {
+        let item = rlp::decode(raw)?;
+        let [nonce, gas_price, gas_limit, to, value, data, v, r, s] = item.as_list()? else {
+            return Err(invalid("legacy transaction must have nine fields"));
+        };
+
+        let (chain_id, y_parity) = match v.as_u64()? {
+            27 => (None, false),
+            28 => (None, true),
+            v if v >= 35 => (Some((v - 35) / 2), (v - 35) % 2 == 1),
+            _ => return Err(invalid("unsupported v value")),
+        };
+
+        let transaction = Transaction::Legacy(LegacyTransaction {
+            chain_id,
+            nonce: nonce.as_u64()?,
+            gas_price: gas_price.as_u256()?,
+            gas_limit: gas_limit.as_u64()?,
+            to: decode_to(to)?,
+            value: value.as_u256()?,
+            data: data.as_bytes()?.to_vec(),
+        });
+        Ok(Self::new(
+            transaction,
+            signature_from_items(y_parity, r, s)?,
+        ))
+    }
+
+    fn decode_access_list(payload: &[u8]) -> AppResult<Self> {
+        let item = rlp::decode(payload)?;
+        let [
+            chain_id,
+            nonce,
+            gas_price,
+            gas_limit,
+            to,
+            value,
+            data,
+            access_list,
+            y_parity,
+            r,
+            s,
+        ] = item.as_list()?
+        else {
+            return Err(invalid("access list transaction must have eleven fields"));
+        };
+
+        let transaction = Transaction::AccessList(AccessListTransaction {
+            chain_id: chain_id.as_u64()?,
+            nonce: nonce.as_u64()?,
+            gas_price: gas_price.as_u256()?,
+            gas_limit: gas_limit.as_u64()?,
+            to: decode_to(to)?,
+            value: value.as_u256()?,
+            data: data.as_bytes()?.to_vec(),
+            access_list: decode_access_list(access_list)?,
+        });
+        Ok(Self::new(
+            transaction,
+            signature_from_items(y_parity_from_item(y_parity)?, r, s)?,
+        ))
+    }
+
+    fn decode_dynamic_fee(payload: &[u8]) -> AppResult<Self> {
+        let item = rlp::decode(payload)?;
+        let [
+            chain_id,
+            nonce,
+            max_priority_fee_per_gas,
+            max_fee_per_gas,
+            gas_limit,
+            to,
+            value,
+            data,
+            access_list,
+            y_parity,
+            r,
+            s,
+        ] = item.as_list()?
+        else {
+            return Err(invalid("dynamic fee transaction must have twelve fields"));
+        };
+
+        let transaction = Transaction::DynamicFee(DynamicFeeTransaction {
+            chain_id: chain_id.as_u64()?,
+            nonce: nonce.as_u64()?,
+            max_priority_fee_per_gas: max_priority_fee_per_gas.as_u256()?,
+            max_fee_per_gas: max_fee_per_gas.as_u256()?,
+            gas_limit: gas_limit.as_u64()?,
+            to: decode_to(to)?,
+            value: value.as_u256()?,
+            data: data.as_bytes()?.to_vec(),
+            access_list: decode_access_list(access_list)?,
+        });
+        Ok(Self::new(
+            transaction,
+            signature_from_items(y_parity_from_item(y_parity)?, r, s)?,
+        ))
+    }
+
+    pub fn transaction(&self) -> &Transaction {
+        &self.transaction
+    }
+
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    /// The bytes to broadcast with `eth_sendRawTransaction`
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// The raw bytes as `0x`-prefixed hex
+    pub fn raw_hex(&self) -> String {
+        format!("0x{}", hex::encode(&self.raw))
+    }
+
+    /// The transaction hash, as nodes and block explorers report it
+    pub fn hash(&self) -> H256 {
+        self.hash
+    }
+
+    /// The transaction hash as `0x`-prefixed hex
+    pub fn hash_hex(&self) -> String {
+        format!("{:#x}", self.hash)
+    }
+
+    /// Recover the address that signed the transaction
+    pub fn sender(&self) -> AppResult<H160> {
+        let mut compact = [0u8; 64];
+        compact[..32].copy_from_slice(&self.signature.r.to_big_endian());
+        compact[32..].copy_from_slice(&self.signature.s.to_big_endian());
+
+        let recovery_id = RecoveryId::try_from(i32::from(self.signature.y_parity))
+            .map_err(|e| AppError::CryptoError(e.to_string()))?;
+        let signature = RecoverableSignature::from_compact(&compact, recovery_id)
+            .map_err(|_| invalid("malformed signature"))?;
+        let message = Message::from_digest(self.transaction.signing_hash().0);
+        let public_key = Secp256k1::new()
+            .recover_ecdsa(&message, &signature)
+            .map_err(|_| invalid("signature does not recover to a public key"))?;
+
+        let hash = keccak256(&public_key.serialize_uncompressed()[1..]);
+        Ok(H160::from_slice(&hash.as_bytes()[12..]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Key and transaction from the EIP-155 example
+    const EIP155_PRIVATE_KEY: &str =
+        "4646464646464646464646464646464646464646464646464646464646464646";
+    const EIP155_SENDER: &str = "0x9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f";
+    const EIP155_SIGNED: &str = "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83";
+
+    // Expected encodings below were produced by an independent RFC 6979 implementation
+    // that reproduces the EIP-155 example above
+    const PRE_EIP155_SIGNED: &str = "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a7640000801ba08383adc8b8ae116f918fb44ca7ff9dfd8012596a5c130c6246a2cc717ba41cdaa053ddfacf5bd4aa7e46d1575acf52636ea659b91f29e2fb91c75567a279738f38";
+    const ACCESS_LIST_SIGNED: &str = "01f8ce01098504a817c800827530943535353535353535353535353535353535353535880de0b6b3a764000084deadbeeff85bf85994dedededededededededededededededededededef842a00000000000000000000000000000000000000000000000000000000000000000a0010101010101010101010101010101010101010101010101010101010101010180a05c4c8ad9f2d0ecd7fe1fc19808f76f210b476f685ba9f9030b28766d96610767a077fc9ed9c891d58997509f47208f1f47518e415234c0873d05e6633fb99c6faa";
+    const ACCESS_LIST_HASH: &str =
+        "0xc51a1427bcedc9d0567d1d0582778955a1d2bd1839d803f6f97b4eb28c5b17e9";
+    const DYNAMIC_FEE_SIGNED: &str = "02f8758207a9808459682f008506fc23ac00825208943535353535353535353535353535353535353535880de0b6b3a764000080c001a0cbf0c7123518f50ba0451e7003be1c4af2cd9f09290d6cfa998f0aff3c509eeda0657c2a84db66b2e8b5a09ea5a7b1ddb6a545d83edd355193274b6a8cea960195";
+    const DYNAMIC_FEE_HASH: &str =
+        "0x7837fb111edf7fcfd9635d1e3d33e879fcce6f90cc7d3c2f2665a21b9f92881c";
+
+    fn private_key() -> PrivateKey {
+        PrivateKey::from_hex(EIP155_PRIVATE_KEY).unwrap()
+    }
+
+    fn recipient() -> H160 {
+        H160::repeat_byte(0x35)
+    }
+
+    fn one_ether() -> U256 {
+        U256::from(1_000_000_000_000_000_000u64)
+    }
+
+    fn eip155_transaction() -> Transaction {
+        Transaction::Legacy(LegacyTransaction {
+            chain_id: Some(1),
+            nonce: 9,
+            gas_price: U256::from(20_000_000_000u64),
+            gas_limit: 21_000,
+            to: Some(recipient()),
+            value: one_ether(),
+            data: Vec::new(),
+        })
+    }
+
+    fn access_list() -> Vec<AccessListItem> {
+        vec![AccessListItem {
+            address: H160::repeat_byte(0xde),
+            storage_keys: vec![H256::zero(), H256::repeat_byte(0x01)],
+        }]
+    }
+
+    fn assert_roundtrip(signed: &SignedTransaction) {
+        let decoded = SignedTransaction::decode(signed.raw()).unwrap();
+        assert_eq!(&decoded, signed);
+        assert_eq!(format_address(&decoded.sender().unwrap()), EIP155_SENDER);
+    }
+
+    #[test]
+    fn test_sign_eip155_transaction() {
+        let transaction = eip155_transaction();
+        assert_eq!(
+            format!("{:#x}", transaction.signing_hash()),
+            "0xdaf5a779ae972f972197303d7b574746c7ef83eadac0f2791ad23db92e4c8e53"
+        );
+
+        let signed = transaction.sign(&private_key()).unwrap();
+        assert_eq!(hex::encode(signed.raw()), EIP155_SIGNED);
+        assert_eq!(
+            signed.hash_hex(),
+            "0x33469b22e9f636356c4160a87eb19df52b7412e8eac32a4a55ffe88ea8350788"
+        );
+        assert_roundtrip(&signed);
+    }
+
+    #[test]
+    fn test_sign_pre_eip155_transaction() {
+        let Transaction::Legacy(mut transaction) = eip155_transaction() else {
+            unreachable!()
+        };
+        transaction.chain_id = None;
+
+        let signed = Transaction::Legacy(transaction)
+            .sign(&private_key())
+            .unwrap();
+        assert_eq!(signed.transaction().chain_id(), None);
+        assert_eq!(hex::encode(signed.raw()), PRE_EIP155_SIGNED);
+        assert_roundtrip(&signed);
+    }
+
+    #[test]
+    fn test_sign_access_list_transaction() {
+        let signed = Transaction::AccessList(AccessListTransaction {
+            chain_id: 1,
+            nonce: 9,
+            gas_price: U256::from(20_000_000_000u64),
+            gas_limit: 30_000,
+            to: Some(recipient()),
+            value: one_ether(),
+            data: vec![0xde, 0xad, 0xbe, 0xef],
+            access_list: access_list(),
+        })
+        .sign(&private_key())
+        .unwrap();
+
+        assert_eq!(signed.raw()[0], ACCESS_LIST_TX_TYPE);
+        assert_eq!(hex::encode(signed.raw()), ACCESS_LIST_SIGNED);
+        assert_eq!(signed.hash_hex(), ACCESS_LIST_HASH);
+        assert_roundtrip(&signed);
+    }
+
+    #[test]
+    fn test_sign_dynamic_fee_transaction() {
+        let signed = Transaction::DynamicFee(DynamicFeeTransaction {
+            chain_id: 1961,
+            nonce: 0,
+            max_priority_fee_per_gas: U256::from(1_500_000_000u64),
+            max_fee_per_gas: U256::from(30_000_000_000u64),
+            gas_limit: 21_000,
+            to: Some(recipient()),
+            value: one_ether(),
+            data: Vec::new(),
+            access_list: Vec::new(),
+        })
+        .sign(&private_key())
+        .unwrap();
+
+        assert_eq!(signed.raw()[0], DYNAMIC_FEE_TX_TYPE);
+        assert_eq!(hex::encode(signed.raw()), DYNAMIC_FEE_SIGNED);
+        assert_eq!(signed.hash_hex(), DYNAMIC_FEE_HASH);
+        assert_roundtrip(&signed);
+    }
+
+    #[test]
+    fn test_chain_id_is_signed() {
+        let Transaction::Legacy(mut other_chain) = eip155_transaction() else {
+            unreachable!()
+        };
+        other_chain.chain_id = Some(5);
+
+        let signed = eip155_transaction().sign(&private_key()).unwrap();
+        let replayed = Transaction::Legacy(other_chain)
+            .sign(&private_key())
+            .unwrap();
+        assert_ne!(signed.hash(), replayed.hash());
+
+        // Moving a signature to another chain recovers some other sender
+        let forged = SignedTransaction::new(replayed.transaction().clone(), *signed.signature());
+        assert_ne!(format_address(&forged.sender().unwrap()), EIP155_SENDER);
+    }
+
+    #[test]
+    fn test_contract_creation_has_no_recipient() {
+        let signed = Transaction::DynamicFee(DynamicFeeTransaction {
+            chain_id: 1,
+            gas_limit: 100_000,
+            data: vec![0x60, 0x00],
+            ..Default::default()
+        })
+        .sign(&private_key())
+        .unwrap();
+
+        let decoded = SignedTransaction::decode(signed.raw()).unwrap();
+        assert_eq!(decoded.transaction().to(), None);
+    }
+
+    #[test]
+    fn test_decode_rejects_invalid_transactions() {
+        let mut raw = hex::decode(EIP155_SIGNED).unwrap();
+        assert!(SignedTransaction::decode(&[]).is_err());
+        assert!(SignedTransaction::decode(&[0x03, 0xc0]).is_err());
+        assert!(SignedTransaction::decode(&raw[..raw.len() - 1]).is_err());
+
+        raw.push(0x00);
+        assert!(SignedTransaction::decode(&raw).is_err());
+    }
+
+    #[test]
+    fn test_parse_address() {
+        let address = parse_address("0x3535353535353535353535353535353535353535").unwrap();
+        assert_eq!(address, recipient());
+        assert!(parse_address("3535353535353535353535353535353535353535").is_err());
+        assert!(parse_address("0x35353535").is_err());
+        assert!(parse_address("0xzz35353535353535353535353535353535353535").is_err());
+    }
+
+    #[test]
+    fn test_parse_hash() {
+        let hash = parse_hash(DYNAMIC_FEE_HASH).unwrap();
+        assert_eq!(format!("{:#x}", hash), DYNAMIC_FEE_HASH);
+        assert!(parse_hash(&"11".repeat(32)).is_err());
+        assert!(parse_hash("0x1111").is_err());
+    }
+}
@@ -0,0 +1,289 @@
+//! Recursive Length Prefix encoding, the serialization Ethereum hashes and signs
+//! transactions in.
+//!
+//! Decoding only accepts the canonical encoding of a value, so a decoded transaction
+//! re-encodes to exactly the bytes it was read from.
+
+use app_error::{AppError, AppResult};
+use primitive_types::U256;
+
+const BYTES_OFFSET: u8 = 0x80;
+const LIST_OFFSET: u8 = 0xc0;
+// Payloads shorter than this carry their length in the prefix byte itself
+const SHORT_PAYLOAD_LIMIT: usize = 56;
+
+/// An RLP value: a byte string or a list of values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RlpItem {
+    Bytes(Vec<u8>),
+    List(Vec<RlpItem>),
+}
+
+fn invalid(message: &str) -> AppError {
+    AppError::ValidationError(format!("Invalid RLP: {}", message))
+}
+
+// Big-endian bytes without leading zeros; zero is the empty string
+fn trim_leading_zeros(bytes: &[u8]) -> &[u8] {
+    let start = bytes
+        .iter()
+        .position(|byte| *byte != 0)
+        .unwrap_or(bytes.len());
+    &bytes[start..]
+}
+
+fn encode_header(offset: u8, length: usize, out: &mut Vec<u8>) {
+    if length < SHORT_PAYLOAD_LIMIT {
+        out.push(offset + length as u8);
+    } else {
+        let length_bytes = (length as u64).to_be_bytes();
+        let length_bytes = trim_leading_zeros(&length_bytes);
+        out.push(offset + SHORT_PAYLOAD_LIMIT as u8 - 1 + length_bytes.len() as u8);
+        out.extend_from_slice(length_bytes);
+    }
+}
+
+impl RlpItem {
+    pub fn bytes(bytes: impl Into<Vec<u8>>) -> Self {
+        RlpItem::Bytes(bytes.into())
+    }
+
+    /// An unsigned integer, encoded big-endian without leading zeros
+    pub fn uint(value: u64) -> Self {
+        RlpItem::Bytes(trim_leading_zeros(&value.to_be_bytes()).to_vec())
+    }
+
+    /// A 256-bit unsigned integer, encoded big-endian without leading zeros
+    pub fn u256(value: U256) -> Self {
+        RlpItem::Bytes(trim_leading_zeros(&value.to_big_endian()).to_vec())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode_into(&mut out);
+        out
+    }
+
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        match self {
+            RlpItem::Bytes(bytes) if bytes.len() == 1 && bytes[0] < BYTES_OFFSET => {
+                out.push(bytes[0]);
+            }
+            RlpItem::Bytes(bytes) => {
+                encode_header(BYTES_OFFSET, bytes.len(), out);
+                out.extend_from_slice(bytes);
+            }
+            RlpItem::List(items) => {
+                let mut payload = Vec::new();
+                for item in items {
+                    item.encode_into(&mut payload);
+                }
+                encode_header(LIST_OFFSET, payload.len(), out);
+                out.extend_from_slice(&payload);
+            }
+        }
+    }
+
+    pub fn as_bytes(&self) -> AppResult<&[u8]> {
+        match self {
+            RlpItem::Bytes(bytes) => Ok(bytes),
+            RlpItem::List(_) => Err(invalid("expected a byte string, found a list")),
+        }
+    }
+
+    pub fn as_list(&self) -> AppResult<&[RlpItem]> {
+        match self {
+            RlpItem::List(items) => Ok(items),
+            RlpItem::Bytes(_) => Err(invalid("expected a list, found a byte string")),
+        }
+    }
+
+    pub fn as_u64(&self) -> AppResult<u64> {
+        let bytes = self.as_integer_bytes(8)?;
+        Ok(bytes
+            .iter()
+            .fold(0u64, |value, byte| (value << 8) | u64::from(*byte)))
+    }
+
+    pub fn as_u256(&self) -> AppResult<U256> {
+        Ok(U256::from_big_endian(self.as_integer_bytes(32)?))
+    }
+
+    fn as_integer_bytes(&self, max_length: usize) -> AppResult<&[u8]> {
+        let bytes = self.as_bytes()?;
+        if bytes.len() > max_length {
+            return Err(invalid("integer is too large"));
+        }
+        if bytes.first() == Some(&0) {
+            return Err(invalid("integer has leading zeros"));
+        }
+        Ok(bytes)
+    }
+}
+
+/// Decode a single RLP value that spans all of `data`
+pub fn decode(data: &[u8]) -> AppResult<RlpItem> {
+    let (item, rest) = decode_item(data)?;
+    if !rest.is_empty() {
+        return Err(invalid("trailing bytes after value"));
+    }
+    Ok(item)
+}
+
+fn split_payload(data: &[u8], offset: usize, length: usize) -> AppResult<(&[u8], &[u8])> {
+    let end = offset
+        .checked_add(length)
+        .filter(|end| *end <= data.len())
+        .ok_or_else(|| invalid("value is longer than the input"))?;
+    Ok((&data[offset..end], &data[end..]))
+}
+
+// Length of a long payload, stored in the `length_of_length` bytes after the prefix
+fn read_long_length(data: &[u8], length_of_length: usize) -> AppResult<usize> {
+    let (length_bytes, _) = split_payload(data, 1, length_of_length)?;
+    if length_bytes[0] == 0 {
+        return Err(invalid("length has leading zeros"));
+    }
+    if length_of_length > std::mem::size_of::<usize>() {
+        return Err(invalid("length is too large"));
+    }
+
+    let length = length_bytes
+        .iter()
+        .fold(0usize, |length, byte| (length << 8) | usize::from(*byte));
+    if length < SHORT_PAYLOAD_LIMIT {
+        return Err(invalid("short value uses a long length"));
+    }
+    Ok(length)
+}
+
+fn decode_item(data: &[u8]) -> AppResult<(RlpItem, &[u8])> {
+    let prefix = *data
+        .first()
+        .ok_or_else(|| invalid("unexpected end of input"))?;
+
+    match prefix {
+        0x00..=0x7f => Ok((RlpItem::Bytes(vec![prefix]), &data[1..])),
+        0x80..=0xb7 => {
+            let length = usize::from(prefix - BYTES_OFFSET);
+            let (payload, rest) = split_payload(data, 1, length)?;
+            if length == 1 && payload[0] < BYTES_OFFSET {
+                return Err(invalid("single byte is wrapped in a string"));
+            }
+            Ok((RlpItem::Bytes(payload.to_vec()), rest))
+        }
+        0xb8..=0xbf => {
+            let length_of_length = usize::from(prefix - 0xb7);
+            let length = read_long_length(data, length_of_length)?;
+            let (payload, rest) = split_payload(data, 1 + length_of_length, length)?;
+            Ok((RlpItem::Bytes(payload.to_vec()), rest))
+        }
+        0xc0..=0xf7 => {
+            let length = usize::from(prefix - LIST_OFFSET);
+            let (payload, rest) = split_payload(data, 1, length)?;
+            Ok((RlpItem::List(decode_list(payload)?), rest))
+        }
+        0xf8..=0xff => {
+            let length_of_length = usize::from(prefix - 0xf7);
+            let length = read_long_length(data, length_of_length)?;
+            let (payload, rest) = split_payload(data, 1 + length_of_length, length)?;
+            Ok((RlpItem::List(decode_list(payload)?), rest))
+        }
+    }
+}
+
+fn decode_list(mut payload: &[u8]) -> AppResult<Vec<RlpItem>> {
+    let mut items = Vec::new();
+    while !payload.is_empty() {
+        let (item, rest) = decode_item(payload)?;
+        items.push(item);
+        payload = rest;
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(item: RlpItem, expected: &str) {
+        let encoded = item.encode();
+        assert_eq!(hex::encode(&encoded), expected);
+        assert_eq!(decode(&encoded).unwrap(), item);
+    }
+
+    #[test]
+    fn test_encode_strings() {
+        // Examples from the Ethereum wiki's RLP page
+        roundtrip(RlpItem::bytes(b"dog".to_vec()), "83646f67");
+        roundtrip(RlpItem::bytes(Vec::new()), "80");
+        roundtrip(RlpItem::bytes(vec![0x0f]), "0f");
+        roundtrip(RlpItem::bytes(vec![0x80]), "8180");
+
+        let lorem = b"Lorem ipsum dolor sit amet, consectetur adipisicing elit".to_vec();
+        let expected = format!("b838{}", hex::encode(&lorem));
+        roundtrip(RlpItem::bytes(lorem), &expected);
+    }
+
+    #[test]
+    fn test_encode_integers() {
+        roundtrip(RlpItem::uint(0), "80");
+        roundtrip(RlpItem::uint(15), "0f");
+        roundtrip(RlpItem::uint(1024), "820400");
+        roundtrip(RlpItem::u256(U256::MAX), &format!("a0{}", "ff".repeat(32)));
+
+        assert_eq!(
+            decode(&hex::decode("820400").unwrap())
+                .unwrap()
+                .as_u64()
+                .unwrap(),
+            1024
+        );
+    }
+
+    #[test]
+    fn test_encode_lists() {
+        roundtrip(RlpItem::List(Vec::new()), "c0");
+        roundtrip(
+            RlpItem::List(vec![
+                RlpItem::bytes(b"cat".to_vec()),
+                RlpItem::bytes(b"dog".to_vec()),
+            ]),
+            "c88363617483646f67",
+        );
+        // The set-theoretical representation of three
+        roundtrip(
+            RlpItem::List(vec![
+                RlpItem::List(Vec::new()),
+                RlpItem::List(vec![RlpItem::List(Vec::new())]),
+                RlpItem::List(vec![
+                    RlpItem::List(Vec::new()),
+                    RlpItem::List(vec![RlpItem::List(Vec::new())]),
+                ]),
+            ]),
+            "c7c0c1c0c3c0c1c0",
+        );
+    }
+
+    #[test]
+    fn test_rejects_non_canonical_encodings() {
+        for encoded in [
+            "8100",       // single byte below 0x80 wrapped in a string
+            "b800",       // long length for a short string
+            "83646f",     // string longer than the input
+            "83646f6700", // trailing bytes
+            "c3646f",     // list longer than the input
+            "",
+        ] {
+            assert!(
+                decode(&hex::decode(encoded).unwrap()).is_err(),
+                "accepted {}",
+                encoded
+            );
+        }
+
+        let leading_zero = decode(&hex::decode("820004").unwrap()).unwrap();
+        assert!(leading_zero.as_u64().is_err());
+        assert!(RlpItem::bytes(vec![1; 9]).as_u64().is_err());
+    }
+}
@@ -180,7 +180,7 @@ impl AuthService {
         // Generate JWT token
         let token = self
             .jwt_service
-            .generate_token(&user.id.id.to_string(), &user.username)?;
+            .generate_token(&user.id.id.to_raw(), &user.username)?;
 
         // Create user profile
         let profile = UserProfile::from(user.clone());
@@ -190,14 +190,6 @@ impl AuthService {
             user: profile,
         })
     }
-
-    // Helper to format user ID correctly
-    fn clean_user_id(user_id: &str) -> String {
-        user_id
-            .trim_start_matches('⟨')
-            .trim_end_matches('⟩')
-            .to_string()
-    }
 }
 
 #[async_trait]
@@ -305,10 +297,8 @@ impl AuthServiceTrait for AuthService {
 
     async fn get_user_by_id(&self, user_id: &str) -> AppResult<UserProfile> {
         if let Some(user_db) = &self.user_db {
-            let clean_id = Self::clean_user_id(user_id);
-
             let user = user_db
-                .get_record_by_id(&clean_id)
+                .get_record_by_id(user_id)
                 .await
                 .map_err(|e| {
                     error!("Database error when fetching user by ID: {}", e);
@@ -366,7 +356,7 @@ pub mod mocks {
             let profile = UserProfile::from(user.clone());
             let token = self
                 .jwt_service
-                .generate_token(&user.id.id.to_string(), &user.username)?;
+                .generate_token(&user.id.id.to_raw(), &user.username)?;
 
             // Store the user
             self.users.lock().unwrap().push(user);
@@ -400,7 +390,7 @@ pub mod mocks {
             let profile = UserProfile::from(user.clone());
             let token = self
                 .jwt_service
-                .generate_token(&user.id.id.to_string(), &user.username)?;
+                .generate_token(&user.id.id.to_raw(), &user.username)?;
 
             Ok(AuthResponse {
                 token,
@@ -412,7 +402,7 @@ pub mod mocks {
             let users = self.users.lock().unwrap();
             let user = users
                 .iter()
-                .find(|u| u.id.id.to_string() == user_id)
+                .find(|u| u.id.id.to_raw() == user_id)
                 .ok_or_else(|| AppError::NotFoundError("User not found".to_string()))?;
 
             Ok(UserProfile::from(user.clone()))
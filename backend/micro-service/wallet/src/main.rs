@@ -6,13 +6,21 @@ use app_database::{
     service::DbService,
 };
 use app_error::AppError;
-use app_middleware::{JwtService, limits::rate_limiter::create_redis_api_rate_limiter};
-use app_models::{WalletKey, user::User, wallet::Wallet};
-use app_utils::crypto::WalletEncryptionService;
+use app_middleware::{
+    JwtService,
+    limits::rate_limiter::{create_redis_api_rate_limiter, create_redis_pin_rate_limiter},
+};
+use app_models::{
+    KeyRotationJob, TransferQuote, WalletAccount, WalletKey, WalletTransaction, user::User,
+    wallet::Wallet,
+};
+use app_utils::chain::{NonceManager, RedisNonceStore, chain_client_from_config};
+use app_utils::crypto::{DekCache, MasterKeyRing, PinKdf, WalletEncryptionService};
+use app_utils::idempotency::RedisIdempotencyStore;
 use micro_wallet::{routes, schema::create_schema, service::WalletService};
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 use tokio::net::TcpListener;
-use tracing::{Level, error, info};
+use tracing::{Level, error, info, warn};
 use tracing_subscriber::{FmtSubscriber, layer::SubscriberExt};
 
 #[tokio::main]
@@ -76,6 +84,22 @@ async fn main() -> Result<(), AppError> {
         .await;
     let wallet_db = Arc::new(DbService::<Wallet>::new(&wallet_db_arc, "wallets"));
     let wallet_key_db = Arc::new(DbService::<WalletKey>::new(&wallet_db_arc, "wallet_keys"));
+    let wallet_account_db = Arc::new(DbService::<WalletAccount>::new(
+        wallet_db_arc,
+        "wallet_accounts",
+    ));
+    let key_rotation_job_db = Arc::new(DbService::<KeyRotationJob>::new(
+        wallet_db_arc,
+        "key_rotation_jobs",
+    ));
+    let transaction_db = Arc::new(DbService::<WalletTransaction>::new(
+        wallet_db_arc,
+        "transactions",
+    ));
+    let quote_db = Arc::new(DbService::<TransferQuote>::new(
+        wallet_db_arc,
+        "transfer_quotes",
+    ));
 
     // Configure path-specific rate limits from our config file
     let mut path_limits = HashMap::new();
@@ -91,29 +115,137 @@ async fn main() -> Result<(), AppError> {
     let api_rate_limiter =
         Arc::new(create_redis_api_rate_limiter(&config.redis.url, Some(path_limits)).await?);
 
+    // Count failed wallet PIN attempts in Redis so every instance sees the same back-off
+    let pin_lockout_config = config.security.rate_limiting.pin.clone();
+    let pin_rate_limiter = Arc::new(
+        create_redis_pin_rate_limiter(
+            &config.redis.url,
+            pin_lockout_config.max_attempts,
+            Duration::from_secs(pin_lockout_config.window_duration),
+        )
+        .await?,
+    );
+
     // Create JWT service for token validation
     let jwt_service = Arc::new(JwtService::new(
         config.security.jwt.secret.as_bytes(),
         config.security.jwt.expiry_hours,
     ));
 
-    // Check for master key ID in environment variables or use a default
-    // Update this with your preferred config structure for master key ID
-    // This is a placeholder - modify as needed for your configuration approach
-    let master_key_id = config.encrypt_secrets.master_key_name;
-    let master_key = config.encrypt_secrets.master_key.as_bytes();
+    // Load the primary master key plus any retired keys still needed for decryption
+    let master_key_ring = MasterKeyRing::from_config(&config.encrypt_secrets)?;
+
+    // Create encryption service backed by the master key ring, with a bounded DEK cache
+    let encryption_service = Arc::new(
+        WalletEncryptionService::from_key_ring(master_key_ring)
+            .with_dek_cache(DekCache::from_config(&config.encrypt_secrets.dek_cache))
+            .with_pin_kdf(PinKdf::from_config(&config.encrypt_secrets.pin_kdf)),
+    );
+
+    // Node (or in-process chain) balances are read from and transfers are sent to
+    let chain_client = chain_client_from_config(&config.chain, &config.environment)?;
 
-    // Create encryption service with the specified master key ID
-    let encryption_service = Arc::new(WalletEncryptionService::new(&master_key_id, master_key));
+    // Count nonces in Redis so concurrent transfers on any instance never share one
+    let nonce_config = &config.chain.nonces;
+    let nonce_store = RedisNonceStore::new(
+        &config.redis.url,
+        Duration::from_secs(nonce_config.ttl_secs),
+    )
+    .await?;
+    let nonce_manager = Arc::new(
+        NonceManager::new(Arc::new(nonce_store), config.chain.chain_id)
+            .with_gap_timeout(Duration::from_secs(nonce_config.gap_timeout_secs)),
+    );
+
+    // Keep the results of transfers with idempotency keys where every instance sees them
+    let idempotency_store = Arc::new(RedisIdempotencyStore::new(&config.redis.url).await?);
 
     // Create wallet service
     let wallet_service = WalletService::new(encryption_service)
         .with_wallet_db(wallet_db)
         .with_wallet_key_db(wallet_key_db)
-        .with_user_db(user_db);
+        .with_wallet_account_db(wallet_account_db)
+        .with_user_db(user_db)
+        .with_key_rotation_job_db(key_rotation_job_db)
+        .with_transaction_db(transaction_db)
+        .with_quote_db(quote_db)
+        .with_key_rotation_config(config.encrypt_secrets.rotation.clone())
+        .with_pin_rate_limiter(pin_rate_limiter)
+        .with_pin_lockout_config(pin_lockout_config)
+        .with_keystore_export_config(config.security.keystore_export.clone())
+        .with_admin_user_ids(config.security.admin_user_ids.clone())
+        .with_chain_client(chain_client)
+        .with_nonce_manager(nonce_manager)
+        .with_idempotency_store(idempotency_store)
+        .with_idempotency_config(config.security.idempotency.clone())
+        .with_chain_config(config.chain.clone())
+        .with_tokens(config.tokens.clone());
 
     let wallet_service = Arc::new(wallet_service);
 
+    // Transfers with idempotency keys count on this to never be sent twice
+    wallet_service.define_transaction_indexes().await?;
+    // Only one key rotation job may be running or paused
+    wallet_service.define_key_rotation_indexes().await?;
+
+    // One-shot re-encryption of wallet keys written before versioned AES-GCM envelopes
+    if std::env::args().any(|arg| arg == "--migrate-legacy-keys") {
+        let report = wallet_service.migrate_legacy_wallet_keys().await?;
+        info!(
+            "Legacy wallet key migration finished: {} migrated, {} failed",
+            report.migrated,
+            report.failed.len()
+        );
+        if !report.awaiting_pin.is_empty() {
+            warn!(
+                "Wallets whose PIN layer is re-encrypted on next PIN use: {:?}",
+                report.awaiting_pin
+            );
+        }
+        if !report.failed.is_empty() {
+            error!("Wallets that failed migration: {:?}", report.failed);
+        }
+        return Ok(());
+    }
+
+    // One-shot link of wallets written before ownership moved from emails to user IDs
+    if std::env::args().any(|arg| arg == "--migrate-wallet-owners") {
+        let (migrated, failed) = wallet_service.migrate_wallet_owners().await?;
+        info!(
+            "Wallet owner migration finished: {} migrated, {} failed",
+            migrated,
+            failed.len()
+        );
+        if !failed.is_empty() {
+            error!("Wallets that failed migration: {:?}", failed);
+        }
+        return Ok(());
+    }
+
+    // One-shot re-wrap of every wallet key onto the primary master key
+    if std::env::args().any(|arg| arg == "--rotate-master-key") {
+        let (rotated, failed) = wallet_service.rotate_all_master_keys().await?;
+        info!(
+            "Master key rotation finished: {} rotated, {} failed",
+            rotated,
+            failed.len()
+        );
+        if !failed.is_empty() {
+            error!("Wallets that failed rotation: {:?}", failed);
+        }
+        return Ok(());
+    }
+
+    // Pick up key rotation jobs that were interrupted by a restart
+    match wallet_service.resume_interrupted_key_rotation_jobs().await {
+        Ok(0) => {}
+        Ok(resumed) => info!("Resumed {} interrupted key rotation job(s)", resumed),
+        Err(e) => error!("Failed to resume interrupted key rotation jobs: {}", e),
+    }
+
+    // Follow submitted transfers until they are confirmed, failed or dropped
+    wallet_service.spawn_confirmation_tracker();
+
     // Create GraphQL schema
     let schema = create_schema();
 
@@ -1,5 +1,6 @@
 use app_error::AppError;
 use app_middleware::Claims;
+use app_utils::secret::Pin;
 use axum::{
     extract::{Request, State},
     middleware::Next,
@@ -48,32 +49,22 @@ pub async fn wallet_owner_middleware(
             };
 
             // Check if the authenticated user is the wallet owner
-            // First try to get user by ID, then check email matches wallet's user_email
-            match wallet_service.get_user_by_id(&claims.sub).await {
-                Ok(user) => {
-                    if user.email != wallet_info.user_email {
-                        warn!(
-                            "Access denied: User {} attempted to access wallet {}",
-                            claims.username, wallet_id
-                        );
-                        return Err(AppError::AuthorizationError(
-                            "You do not have permission to access this wallet".to_string(),
-                        ));
-                    }
-                    // User is the wallet owner, continue
-                    debug!(
-                        "Access granted: User {} owns wallet {}",
-                        claims.username, wallet_id
-                    );
-                    Ok(next.run(req).await)
-                }
-                Err(_) => {
-                    warn!("User validation failed for user ID: {}", claims.sub);
-                    Err(AppError::AuthorizationError(
-                        "Authentication validation failed".to_string(),
-                    ))
-                }
+            if wallet_info.user_id != claims.sub {
+                warn!(
+                    "Access denied: User {} attempted to access wallet {}",
+                    claims.username, wallet_id
+                );
+                return Err(AppError::AuthorizationError(
+                    "You do not have permission to access this wallet".to_string(),
+                ));
             }
+
+            // User is the wallet owner, continue
+            debug!(
+                "Access granted: User {} owns wallet {}",
+                claims.username, wallet_id
+            );
+            Ok(next.run(req).await)
         }
 
         None => {
@@ -86,7 +77,8 @@ pub async fn wallet_owner_middleware(
 }
 
 // Helper function to validate PIN format
-pub fn validate_pin(pin: &str) -> Result<(), AppError> {
+pub fn validate_pin(pin: &Pin) -> Result<(), AppError> {
+    let pin = pin.expose_secret();
     if pin.len() != 6 || !pin.chars().all(|c| c.is_digit(10)) {
         return Err(AppError::ValidationError(
             "PIN must be a 6-digit number".to_string(),
@@ -100,13 +92,8 @@ impl WalletService {
     /// Get a user by ID
     pub async fn get_user_by_id(&self, user_id: &str) -> Result<app_models::user::User, AppError> {
         if let Some(user_db) = &self.user_db {
-            let clean_id = user_id
-                .trim_start_matches('⟨')
-                .trim_end_matches('⟩')
-                .to_string();
-
             let user = user_db
-                .get_record_by_id(&clean_id)
+                .get_record_by_id(user_id)
                 .await
                 .map_err(|e| {
                     tracing::error!("Database error when fetching user by ID: {}", e);
@@ -1,9 +1,15 @@
 pub mod mutation;
 pub mod query;
 
-use async_graphql::{EmptySubscription, Schema};
+use app_error::AppError;
+use app_middleware::Claims;
+use async_graphql::{Context, EmptySubscription, Schema};
 use mutation::{Mutation, create_mutation};
 use query::{Query, create_query};
+use std::sync::Arc;
+use tracing::{error, warn};
+
+use crate::service::WalletService;
 
 // Type alias for our GraphQL schema
 pub type ApiSchema = Schema<Query, Mutation, EmptySubscription>;
@@ -17,3 +23,26 @@ pub fn create_schema() -> ApiSchema {
 
     builder.finish()
 }
+
+// Resolve the wallet service for an admin-only field, rejecting non-admin callers
+pub(crate) fn admin_wallet_service<'a>(
+    ctx: &Context<'a>,
+) -> Result<&'a Arc<WalletService>, AppError> {
+    let claims = ctx.data::<Claims>().map_err(|_| {
+        AppError::AuthenticationError("Authentication required for admin operations".to_string())
+    })?;
+
+    let wallet_service = ctx.data::<Arc<WalletService>>().map_err(|e| {
+        error!("Failed to get wallet service: {:?}", e);
+        AppError::ServerError(anyhow::anyhow!("Wallet service not available"))
+    })?;
+
+    wallet_service.require_admin(&claims.sub).inspect_err(|_| {
+        warn!(
+            "User {} attempted an admin operation without admin privileges",
+            claims.username
+        );
+    })?;
+
+    Ok(wallet_service)
+}
@@ -0,0 +1,70 @@
+use async_graphql::{Context, Object, Result};
+
+use app_error::AppError;
+use app_models::KeyRotationJobInfo;
+use app_models::wallet::WalletInfo;
+
+use crate::schema::admin_wallet_service;
+
+pub struct AdminMutation;
+
+#[Object]
+impl AdminMutation {
+    // Start re-wrapping every wallet key onto the primary master key in the background
+    async fn start_key_rotation(&self, ctx: &Context<'_>) -> Result<KeyRotationJobInfo, AppError> {
+        let wallet_service = admin_wallet_service(ctx)?;
+
+        wallet_service.start_key_rotation_job().await
+    }
+
+    // Pause a running key rotation job after its current batch
+    async fn pause_key_rotation(
+        &self,
+        ctx: &Context<'_>,
+        job_id: String,
+    ) -> Result<KeyRotationJobInfo, AppError> {
+        let wallet_service = admin_wallet_service(ctx)?;
+
+        wallet_service.pause_key_rotation_job(&job_id).await
+    }
+
+    // Resume a paused key rotation job from where it stopped
+    async fn resume_key_rotation(
+        &self,
+        ctx: &Context<'_>,
+        job_id: String,
+    ) -> Result<KeyRotationJobInfo, AppError> {
+        let wallet_service = admin_wallet_service(ctx)?;
+
+        wallet_service.resume_key_rotation_job(&job_id).await
+    }
+
+    // Cancel a running or paused key rotation job
+    async fn cancel_key_rotation(
+        &self,
+        ctx: &Context<'_>,
+        job_id: String,
+    ) -> Result<KeyRotationJobInfo, AppError> {
+        let wallet_service = admin_wallet_service(ctx)?;
+
+        wallet_service.cancel_key_rotation_job(&job_id).await
+    }
+
+    // Drop every plaintext DEK held in memory; returns how many were dropped
+    async fn purge_dek_cache(&self, ctx: &Context<'_>) -> Result<usize, AppError> {
+        let wallet_service = admin_wallet_service(ctx)?;
+
+        Ok(wallet_service.purge_dek_cache().await)
+    }
+
+    // Clear a PIN lockout and reset the wallet's failed PIN attempts
+    async fn unlock_wallet_pin(
+        &self,
+        ctx: &Context<'_>,
+        wallet_id: String,
+    ) -> Result<WalletInfo, AppError> {
+        let wallet_service = admin_wallet_service(ctx)?;
+
+        wallet_service.unlock_wallet_pin(&wallet_id).await
+    }
+}
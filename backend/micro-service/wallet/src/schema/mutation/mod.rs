@@ -1,10 +1,11 @@
+pub mod admin;
 pub mod wallet;
 
 use async_graphql::MergedObject;
 
 #[derive(MergedObject)]
-pub struct Mutation(wallet::WalletMutation);
+pub struct Mutation(wallet::WalletMutation, admin::AdminMutation);
 
 pub fn create_mutation() -> Mutation {
-    Mutation(wallet::WalletMutation)
+    Mutation(wallet::WalletMutation, admin::AdminMutation)
 }
@@ -1,49 +1,147 @@
-use async_graphql::{Context, InputObject, Object, Result};
+use async_graphql::{Context, InputObject, Object, OneofObject, Result};
 use std::sync::Arc;
 use tracing::error;
 
 use app_error::AppError;
 use app_middleware::Claims;
-use app_models::wallet::WalletInfo;
+use app_models::wallet::{CreatedWalletInfo, WalletAccountInfo, WalletInfo};
+use app_models::{Amount, FeeSpeed};
+use app_utils::secret::{MnemonicPhrase, Pin, PrivateKey, SecretString};
 
 use crate::middleware::validate_pin;
-use crate::service::{WalletService, WalletServiceTrait};
+use crate::service::{
+    QuoteChoice, TransferRequest, WalletImportSource, WalletService, WalletServiceTrait,
+};
 
 #[derive(InputObject)]
 pub struct TransferInput {
+    pub wallet_id: Option<String>, // Defaults to the user's default wallet
+    #[graphql(default)]
+    pub account_index: u32, // Sending account of the wallet; the first one by default
     pub to_address: String,
-    pub amount: f64,
+    pub token: Option<String>, // Token symbol or contract address; the native coin by default
+    pub amount: Amount,        // Decimal string in whole coins or tokens, such as "12.5"
+    pub pin: String,
+    pub idempotency_key: Option<String>, // Retries with the same key return the first result
+    pub quote_id: Option<String>, // Send with the gas limit and fees of this transfer's quote
+    #[graphql(default)]
+    pub fee_speed: FeeSpeed, // Which of the quote's fees to pay; normal by default
+}
+
+#[derive(InputObject)]
+pub struct ReplaceTransactionInput {
+    pub wallet_id: Option<String>, // Defaults to the user's default wallet
+    pub hash: String,              // Pending outgoing transaction to replace
     pub pin: String,
 }
 
 #[derive(InputObject)]
 pub struct CreateWalletInput {
     pub pin: String,
+    pub label: Option<String>,
+}
+
+#[derive(InputObject)]
+pub struct MnemonicImportInput {
+    pub phrase: String,
+    pub passphrase: Option<String>,
+    pub derivation_path: Option<String>, // Defaults to m/44'/60'/0'/0/0
+}
+
+#[derive(InputObject)]
+pub struct KeystoreImportInput {
+    pub json: String,
+    pub password: String,
+}
+
+// Exactly one source must be given
+#[derive(OneofObject)]
+pub enum WalletImportSourceInput {
+    Mnemonic(MnemonicImportInput),
+    PrivateKey(String),
+    Keystore(KeystoreImportInput),
+}
+
+impl TryFrom<WalletImportSourceInput> for WalletImportSource {
+    type Error = AppError;
+
+    fn try_from(input: WalletImportSourceInput) -> Result<Self, AppError> {
+        Ok(match input {
+            WalletImportSourceInput::Mnemonic(mnemonic) => WalletImportSource::Mnemonic {
+                phrase: MnemonicPhrase::from(mnemonic.phrase),
+                passphrase: SecretString::from(mnemonic.passphrase.unwrap_or_default()),
+                derivation_path: mnemonic.derivation_path,
+            },
+            WalletImportSourceInput::PrivateKey(private_key) => {
+                let private_key = SecretString::from(private_key);
+                WalletImportSource::PrivateKey(PrivateKey::from_hex(
+                    private_key.expose_secret().trim(),
+                )?)
+            }
+            WalletImportSourceInput::Keystore(keystore) => WalletImportSource::Keystore {
+                json: keystore.json,
+                password: SecretString::from(keystore.password),
+            },
+        })
+    }
+}
+
+#[derive(InputObject)]
+pub struct ImportWalletInput {
+    pub source: WalletImportSourceInput,
+    pub pin: String,
+    pub label: Option<String>,
 }
 
 #[derive(InputObject)]
 pub struct ChangePinInput {
+    pub wallet_id: Option<String>, // Defaults to the user's default wallet
     pub old_pin: String,
     pub new_pin: String,
 }
 
+#[derive(InputObject)]
+pub struct ResetPinInput {
+    pub wallet_id: Option<String>, // Defaults to the user's default wallet
+    pub recovery_phrase: Option<String>, // Phrase returned by createWallet
+    pub source: Option<WalletImportSourceInput>, // Instead of the phrase, what the wallet was imported from
+    pub new_pin: String,
+}
+
+#[derive(InputObject)]
+pub struct CreateAccountInput {
+    pub wallet_id: Option<String>, // Defaults to the user's default wallet
+    pub pin: String,
+    pub label: Option<String>,
+}
+
+#[derive(InputObject)]
+pub struct ExportKeystoreInput {
+    pub wallet_id: Option<String>, // Defaults to the user's default wallet
+    #[graphql(default)]
+    pub account_index: u32,
+    pub pin: String,
+    pub password: String,
+}
+
 pub struct WalletMutation;
 
 #[Object]
 impl WalletMutation {
-    // Create a wallet for the current user
+    // Create a wallet for the current user; the recovery phrase is only returned here
     async fn create_wallet(
         &self,
         ctx: &Context<'_>,
         input: CreateWalletInput,
-    ) -> Result<WalletInfo, AppError> {
+    ) -> Result<CreatedWalletInfo, AppError> {
         // Get the claims from the context
         let claims = ctx.data::<Claims>().map_err(|_| {
             AppError::AuthenticationError("Authentication required to create a wallet".to_string())
         })?;
 
         // Validate PIN
-        validate_pin(&input.pin)?;
+        let pin = Pin::from(input.pin);
+        validate_pin(&pin)?;
 
         // Get the wallet service
         let wallet_service = ctx.data::<Arc<WalletService>>().map_err(|e| {
@@ -51,19 +149,44 @@ impl WalletMutation {
             AppError::ServerError(anyhow::anyhow!("Wallet service not available"))
         })?;
 
-        // Get user by ID from the claims
-        let user = wallet_service.get_user_by_id(&claims.sub).await?;
-
         // Create wallet for the user with PIN
-        let wallet_info = wallet_service
-            .create_wallet(&user.email, &input.pin)
+        let (wallet_info, recovery_phrase) = wallet_service
+            .create_wallet(&claims.sub, input.label, &pin)
             .await?;
 
-        wallet_service
-            .associate_wallet_with_user(&claims.sub, &wallet_info.id)
-            .await?;
+        Ok(CreatedWalletInfo {
+            wallet: wallet_info,
+            recovery_phrase: recovery_phrase.expose_secret().to_string(),
+        })
+    }
 
-        Ok(wallet_info)
+    // Import an existing wallet for the current user
+    async fn import_wallet(
+        &self,
+        ctx: &Context<'_>,
+        input: ImportWalletInput,
+    ) -> Result<WalletInfo, AppError> {
+        // Get the claims from the context
+        let claims = ctx.data::<Claims>().map_err(|_| {
+            AppError::AuthenticationError("Authentication required to import a wallet".to_string())
+        })?;
+
+        // Validate PIN
+        let pin = Pin::from(input.pin);
+        validate_pin(&pin)?;
+
+        // Get the wallet service
+        let wallet_service = ctx.data::<Arc<WalletService>>().map_err(|e| {
+            error!("Failed to get wallet service: {:?}", e);
+            AppError::ServerError(anyhow::anyhow!("Wallet service not available"))
+        })?;
+
+        let source = WalletImportSource::try_from(input.source)?;
+
+        // Import the wallet for the user with PIN
+        wallet_service
+            .import_wallet(&claims.sub, input.label, source, &pin)
+            .await
     }
 
     // Transfer funds from wallet (requires PIN)
@@ -80,25 +203,74 @@ impl WalletMutation {
         })?;
 
         // Validate PIN format
-        validate_pin(&input.pin)?;
+        let pin = Pin::from(input.pin);
+        validate_pin(&pin)?;
 
-        // Get user by ID from the claims
-        let user = wallet_service.get_user_by_id(&claims.sub).await?;
+        // Get the named or default wallet of the user in the claims
+        let wallet = wallet_service
+            .get_user_wallet(&claims.sub, input.wallet_id.as_deref())
+            .await?;
 
-        // Get the user's wallet
-        let wallet = wallet_service.get_wallet_by_user_email(&user.email).await?;
+        // Signing in again after a PIN lockout unlocks the wallet
+        wallet_service
+            .unlock_wallet_pin_on_reauth(&wallet.id, claims.iat)
+            .await?;
 
-        // Verify the PIN is correct before proceeding with transfer
-        let is_pin_valid = wallet_service.verify_pin(&wallet.id, &input.pin).await?;
-        if !is_pin_valid {
-            return Err(AppError::AuthenticationError(
-                "Invalid PIN. Transfer canceled for security reasons.".to_string(),
-            ));
+        let request = TransferRequest {
+            wallet_id: wallet.id,
+            account_index: input.account_index,
+            to_address: input.to_address,
+            token: input.token,
+            amount: input.amount,
+        };
+        let quote = input.quote_id.map(|quote_id| QuoteChoice {
+            quote_id,
+            speed: input.fee_speed,
+        });
+
+        // Perform the transfer, at most once per idempotency key
+        if let Some(idempotency_key) = input.idempotency_key {
+            return wallet_service
+                .transfer_with_idempotency_key(
+                    &claims.sub,
+                    &idempotency_key,
+                    &request,
+                    quote.as_ref(),
+                    &pin,
+                )
+                .await;
         }
+        wallet_service
+            .send_transfer(&request, quote.as_ref(), &pin)
+            .await
+            .map(|outgoing| outgoing.hash)
+    }
 
-        // Perform the transfer
+    // Send a stuck transfer again with higher fees, returning the new transaction hash
+    // (requires PIN)
+    async fn speed_up_transaction(
+        &self,
+        ctx: &Context<'_>,
+        input: ReplaceTransactionInput,
+    ) -> Result<String, AppError> {
+        let (wallet_service, wallet_id, pin) =
+            unlock_for_replacement(ctx, input.wallet_id, input.pin).await?;
         wallet_service
-            .transfer(&wallet.id, &input.to_address, input.amount, &input.pin)
+            .speed_up_transaction(&wallet_id, &input.hash, &pin)
+            .await
+    }
+
+    // Cancel a stuck transfer by replacing it with an empty transaction to the sender,
+    // returning the new transaction hash (requires PIN)
+    async fn cancel_transaction(
+        &self,
+        ctx: &Context<'_>,
+        input: ReplaceTransactionInput,
+    ) -> Result<String, AppError> {
+        let (wallet_service, wallet_id, pin) =
+            unlock_for_replacement(ctx, input.wallet_id, input.pin).await?;
+        wallet_service
+            .cancel_transaction(&wallet_id, &input.hash, &pin)
             .await
     }
 
@@ -121,32 +293,80 @@ impl WalletMutation {
             AppError::ServerError(anyhow::anyhow!("Wallet service not available"))
         })?;
 
-        // Get user by ID from the claims
-        let user = wallet_service.get_user_by_id(&claims.sub).await?;
+        let old_pin = Pin::from(input.old_pin);
+        let new_pin = Pin::from(input.new_pin);
+
+        // Get the named or default wallet of the user in the claims
+        let wallet = wallet_service
+            .get_user_wallet(&claims.sub, input.wallet_id.as_deref())
+            .await?;
+
+        // Signing in again after a PIN lockout unlocks the wallet
+        wallet_service
+            .unlock_wallet_pin_on_reauth(&wallet.id, claims.iat)
+            .await?;
+
+        // Change the PIN, which checks the old PIN against the attempt limits
+        wallet_service
+            .change_wallet_pin(&wallet.id, &old_pin, &new_pin)
+            .await?;
 
-        // Get the user's wallet
-        let wallet = wallet_service.get_wallet_by_user_email(&user.email).await?;
+        Ok(true)
+    }
 
-        // Verify the old PIN is correct before allowing PIN change
-        let is_pin_valid = wallet_service
-            .verify_pin(&wallet.id, &input.old_pin)
+    // Set a new wallet PIN with the recovery phrase or imported key (requires a fresh sign-in)
+    async fn reset_wallet_pin(
+        &self,
+        ctx: &Context<'_>,
+        input: ResetPinInput,
+    ) -> Result<bool, AppError> {
+        // Get the claims from the context
+        let claims = ctx.data::<Claims>().map_err(|_| {
+            AppError::AuthenticationError("Authentication required to reset wallet PIN".to_string())
+        })?;
+
+        // Get the wallet service
+        let wallet_service = ctx.data::<Arc<WalletService>>().map_err(|e| {
+            error!("Failed to get wallet service: {:?}", e);
+            AppError::ServerError(anyhow::anyhow!("Wallet service not available"))
+        })?;
+
+        let recovery = match (input.recovery_phrase, input.source) {
+            (Some(recovery_phrase), None) => WalletImportSource::Mnemonic {
+                phrase: MnemonicPhrase::from(recovery_phrase),
+                passphrase: SecretString::from(""),
+                derivation_path: None,
+            },
+            (None, Some(source)) => WalletImportSource::try_from(source)?,
+            _ => {
+                return Err(AppError::ValidationError(
+                    "Give exactly one of recoveryPhrase and source".to_string(),
+                ));
+            }
+        };
+        let new_pin = Pin::from(input.new_pin);
+        validate_pin(&new_pin)?;
+
+        // Get the named or default wallet of the user in the claims
+        let wallet = wallet_service
+            .get_user_wallet(&claims.sub, input.wallet_id.as_deref())
             .await?;
-        if !is_pin_valid {
-            return Err(AppError::AuthenticationError(
-                "Current PIN is incorrect. PIN change canceled for security reasons.".to_string(),
-            ));
-        }
 
-        // Change the PIN
+        // Reset the PIN
         wallet_service
-            .change_wallet_pin(&wallet.id, &input.old_pin, &input.new_pin)
+            .reset_wallet_pin(&wallet.id, recovery, &new_pin, claims.iat)
             .await?;
 
         Ok(true)
     }
 
     // Verify wallet PIN (useful for client-side validation)
-    async fn verify_wallet_pin(&self, ctx: &Context<'_>, pin: String) -> Result<bool, AppError> {
+    async fn verify_wallet_pin(
+        &self,
+        ctx: &Context<'_>,
+        pin: String,
+        wallet_id: Option<String>,
+    ) -> Result<bool, AppError> {
         // Get the claims from the context
         let claims = ctx.data::<Claims>().map_err(|_| {
             AppError::AuthenticationError(
@@ -160,13 +380,176 @@ impl WalletMutation {
             AppError::ServerError(anyhow::anyhow!("Wallet service not available"))
         })?;
 
-        // Get user by ID from the claims
-        let user = wallet_service.get_user_by_id(&claims.sub).await?;
+        // Get the named or default wallet of the user in the claims
+        let wallet = wallet_service
+            .get_user_wallet(&claims.sub, wallet_id.as_deref())
+            .await?;
 
-        // Get the user's wallet
-        let wallet = wallet_service.get_wallet_by_user_email(&user.email).await?;
+        // Signing in again after a PIN lockout unlocks the wallet
+        wallet_service
+            .unlock_wallet_pin_on_reauth(&wallet.id, claims.iat)
+            .await?;
 
         // Verify the PIN
-        wallet_service.verify_pin(&wallet.id, &pin).await
+        wallet_service.verify_pin(&wallet.id, &Pin::from(pin)).await
+    }
+
+    // Make one of the current user's wallets their default
+    async fn set_default_wallet(
+        &self,
+        ctx: &Context<'_>,
+        wallet_id: String,
+    ) -> Result<WalletInfo, AppError> {
+        // Get the claims from the context
+        let claims = ctx.data::<Claims>().map_err(|_| {
+            AppError::AuthenticationError(
+                "Authentication required to change the default wallet".to_string(),
+            )
+        })?;
+
+        // Get the wallet service
+        let wallet_service = ctx.data::<Arc<WalletService>>().map_err(|e| {
+            error!("Failed to get wallet service: {:?}", e);
+            AppError::ServerError(anyhow::anyhow!("Wallet service not available"))
+        })?;
+
+        wallet_service
+            .set_default_wallet(&claims.sub, &wallet_id)
+            .await
+    }
+
+    // Change the label of one of the current user's wallets
+    async fn rename_wallet(
+        &self,
+        ctx: &Context<'_>,
+        wallet_id: String,
+        label: Option<String>,
+    ) -> Result<WalletInfo, AppError> {
+        // Get the claims from the context
+        let claims = ctx.data::<Claims>().map_err(|_| {
+            AppError::AuthenticationError("Authentication required to rename a wallet".to_string())
+        })?;
+
+        // Get the wallet service
+        let wallet_service = ctx.data::<Arc<WalletService>>().map_err(|e| {
+            error!("Failed to get wallet service: {:?}", e);
+            AppError::ServerError(anyhow::anyhow!("Wallet service not available"))
+        })?;
+
+        wallet_service
+            .rename_wallet(&claims.sub, &wallet_id, label)
+            .await
+    }
+
+    // Derive the next account of an HD wallet (requires PIN)
+    async fn create_wallet_account(
+        &self,
+        ctx: &Context<'_>,
+        input: CreateAccountInput,
+    ) -> Result<WalletAccountInfo, AppError> {
+        // Get the claims from the context
+        let claims = ctx.data::<Claims>().map_err(|_| {
+            AppError::AuthenticationError(
+                "Authentication required to create a wallet account".to_string(),
+            )
+        })?;
+
+        // Get the wallet service
+        let wallet_service = ctx.data::<Arc<WalletService>>().map_err(|e| {
+            error!("Failed to get wallet service: {:?}", e);
+            AppError::ServerError(anyhow::anyhow!("Wallet service not available"))
+        })?;
+
+        // Validate PIN format
+        let pin = Pin::from(input.pin);
+        validate_pin(&pin)?;
+
+        // Get the named or default wallet of the user in the claims
+        let wallet = wallet_service
+            .get_user_wallet(&claims.sub, input.wallet_id.as_deref())
+            .await?;
+
+        // Signing in again after a PIN lockout unlocks the wallet
+        wallet_service
+            .unlock_wallet_pin_on_reauth(&wallet.id, claims.iat)
+            .await?;
+
+        // Derive the account
+        wallet_service
+            .create_wallet_account(&wallet.id, &pin, input.label)
+            .await
+    }
+
+    // Export the private key of a wallet account as a Keystore V3 JSON file
+    async fn export_wallet_keystore(
+        &self,
+        ctx: &Context<'_>,
+        input: ExportKeystoreInput,
+    ) -> Result<String, AppError> {
+        // Get the claims from the context
+        let claims = ctx.data::<Claims>().map_err(|_| {
+            AppError::AuthenticationError(
+                "Authentication required to export wallet keystore".to_string(),
+            )
+        })?;
+
+        // Get the wallet service
+        let wallet_service = ctx.data::<Arc<WalletService>>().map_err(|e| {
+            error!("Failed to get wallet service: {:?}", e);
+            AppError::ServerError(anyhow::anyhow!("Wallet service not available"))
+        })?;
+
+        let pin = Pin::from(input.pin);
+        let password = SecretString::from(input.password);
+
+        // Get the named or default wallet of the user in the claims
+        let wallet = wallet_service
+            .get_user_wallet(&claims.sub, input.wallet_id.as_deref())
+            .await?;
+
+        // Signing in again after a PIN lockout unlocks the wallet
+        wallet_service
+            .unlock_wallet_pin_on_reauth(&wallet.id, claims.iat)
+            .await?;
+
+        // Export the key
+        wallet_service
+            .export_wallet_keystore(&wallet.id, input.account_index, &pin, &password)
+            .await
     }
 }
+
+// The wallet service, the caller's wallet and their PIN, for replacing one of the
+// wallet's transactions
+async fn unlock_for_replacement<'a>(
+    ctx: &Context<'a>,
+    wallet_id: Option<String>,
+    pin: String,
+) -> Result<(&'a Arc<WalletService>, String, Pin), AppError> {
+    // Get the claims from the context
+    let claims = ctx.data::<Claims>().map_err(|_| {
+        AppError::AuthenticationError("Authentication required to replace transactions".to_string())
+    })?;
+
+    // Get the wallet service
+    let wallet_service = ctx.data::<Arc<WalletService>>().map_err(|e| {
+        error!("Failed to get wallet service: {:?}", e);
+        AppError::ServerError(anyhow::anyhow!("Wallet service not available"))
+    })?;
+
+    // Validate PIN format
+    let pin = Pin::from(pin);
+    validate_pin(&pin)?;
+
+    // Get the named or default wallet of the user in the claims
+    let wallet = wallet_service
+        .get_user_wallet(&claims.sub, wallet_id.as_deref())
+        .await?;
+
+    // Signing in again after a PIN lockout unlocks the wallet
+    wallet_service
+        .unlock_wallet_pin_on_reauth(&wallet.id, claims.iat)
+        .await?;
+
+    Ok((wallet_service, wallet.id, pin))
+}
@@ -0,0 +1,44 @@
+use async_graphql::{Context, FieldError, Object, Result};
+
+use app_models::{DekCacheStatsInfo, KeyRotationJobInfo};
+
+use crate::schema::admin_wallet_service;
+
+pub struct AdminQuery;
+
+#[Object]
+impl AdminQuery {
+    // Get the status of a master key rotation job (admin only)
+    async fn key_rotation_job(
+        &self,
+        ctx: &Context<'_>,
+        job_id: String,
+    ) -> Result<KeyRotationJobInfo, FieldError> {
+        let wallet_service = admin_wallet_service(ctx).map_err(|err| err.to_field_error())?;
+
+        wallet_service
+            .get_key_rotation_job(&job_id)
+            .await
+            .map_err(|err| err.to_field_error())
+    }
+
+    // List all master key rotation jobs, newest first (admin only)
+    async fn key_rotation_jobs(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<Vec<KeyRotationJobInfo>, FieldError> {
+        let wallet_service = admin_wallet_service(ctx).map_err(|err| err.to_field_error())?;
+
+        wallet_service
+            .list_key_rotation_jobs()
+            .await
+            .map_err(|err| err.to_field_error())
+    }
+
+    // Hit, miss and eviction counters of the in-memory DEK cache (admin only)
+    async fn dek_cache_stats(&self, ctx: &Context<'_>) -> Result<DekCacheStatsInfo, FieldError> {
+        let wallet_service = admin_wallet_service(ctx).map_err(|err| err.to_field_error())?;
+
+        Ok(wallet_service.get_dek_cache_stats().await)
+    }
+}
@@ -1,10 +1,11 @@
+pub mod admin;
 pub mod wallet;
 
 use async_graphql::MergedObject;
 
 #[derive(MergedObject)]
-pub struct Query(wallet::WalletQuery);
+pub struct Query(wallet::WalletQuery, admin::AdminQuery);
 
 pub fn create_query() -> Query {
-    Query(wallet::WalletQuery)
+    Query(wallet::WalletQuery, admin::AdminQuery)
 }
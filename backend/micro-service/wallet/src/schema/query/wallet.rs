@@ -1,17 +1,38 @@
-use async_graphql::{Context, FieldError, Object, Result};
+use async_graphql::{Context, FieldError, InputObject, Object, Result};
+use chrono::{DateTime, Utc};
 use std::sync::Arc;
 
 use app_error::AppError;
 use app_middleware::Claims;
 use app_models::wallet::WalletInfo;
+use app_models::{Amount, TransactionStatus, TransferQuoteInfo, WalletTransactionPage};
 
-use crate::service::{WalletService, WalletServiceTrait};
+use crate::service::{TransactionFilter, TransferRequest, WalletService, WalletServiceTrait};
+
+#[derive(InputObject)]
+pub struct TransactionFilterInput {
+    pub status: Option<TransactionStatus>,
+    pub token: Option<String>, // Token symbol or contract address, or the native coin's symbol
+    pub since: Option<DateTime<Utc>>, // Recorded at or after this time
+    pub until: Option<DateTime<Utc>>, // Recorded before this time
+}
+
+impl From<TransactionFilterInput> for TransactionFilter {
+    fn from(input: TransactionFilterInput) -> Self {
+        Self {
+            status: input.status,
+            token: input.token,
+            since: input.since,
+            until: input.until,
+        }
+    }
+}
 
 pub struct WalletQuery;
 
 #[Object]
 impl WalletQuery {
-    // Get the current user's wallet (requires auth)
+    // Get the current user's default wallet (requires auth)
     async fn my_wallet(&self, ctx: &Context<'_>) -> Result<WalletInfo, FieldError> {
         // Get the claims from the context
         let claims = ctx.data::<Claims>().map_err(|_| {
@@ -29,25 +50,70 @@ impl WalletQuery {
             .to_field_error()
         })?;
 
-        // Get user by ID from the claims
-        let user = wallet_service
-            .get_user_by_id(&claims.sub)
+        // Get the default wallet of the user in the claims
+        wallet_service
+            .get_user_wallet(&claims.sub, None)
             .await
-            .map_err(|err| err.to_field_error())?;
+            .map_err(|err| err.to_field_error())
+    }
+
+    // Get all wallets of the current user (requires auth)
+    async fn my_wallets(&self, ctx: &Context<'_>) -> Result<Vec<WalletInfo>, FieldError> {
+        // Get the claims from the context
+        let claims = ctx.data::<Claims>().map_err(|_| {
+            AppError::AuthenticationError(
+                "Authentication required. Please log in to view your wallets.".to_string(),
+            )
+            .to_field_error()
+        })?;
+
+        // Get the wallet service
+        let wallet_service = ctx.data::<Arc<WalletService>>().map_err(|_| {
+            AppError::ServerError(anyhow::anyhow!(
+                "Internal configuration error: Wallet service not available"
+            ))
+            .to_field_error()
+        })?;
+
+        // Get the wallets of the user in the claims
+        wallet_service
+            .get_user_wallets(&claims.sub)
+            .await
+            .map_err(|err| err.to_field_error())
+    }
+
+    // Get one of the current user's wallets by ID (requires auth)
+    async fn wallet(&self, ctx: &Context<'_>, id: String) -> Result<WalletInfo, FieldError> {
+        // Get the claims from the context
+        let claims = ctx.data::<Claims>().map_err(|_| {
+            AppError::AuthenticationError(
+                "Authentication required. Please log in to view your wallet.".to_string(),
+            )
+            .to_field_error()
+        })?;
+
+        // Get the wallet service
+        let wallet_service = ctx.data::<Arc<WalletService>>().map_err(|_| {
+            AppError::ServerError(anyhow::anyhow!(
+                "Internal configuration error: Wallet service not available"
+            ))
+            .to_field_error()
+        })?;
 
-        // Get wallet by user email
+        // Get the wallet, checking it belongs to the user in the claims
         wallet_service
-            .get_wallet_by_user_email(&user.email)
+            .get_user_wallet(&claims.sub, Some(&id))
             .await
             .map_err(|err| err.to_field_error())
     }
 
-    // Get wallet balance
+    // Get wallet balance of the native coin, or of a token given by symbol or contract address
     async fn wallet_balance(
         &self,
         ctx: &Context<'_>,
         wallet_id: String,
-    ) -> Result<f64, FieldError> {
+        token: Option<String>,
+    ) -> Result<Amount, FieldError> {
         // Get the claims from the context
         let claims = ctx.data::<Claims>().map_err(|_| {
             AppError::AuthenticationError(
@@ -64,29 +130,100 @@ impl WalletQuery {
             .to_field_error()
         })?;
 
-        // Get user by ID from the claims
-        let user = wallet_service
-            .get_user_by_id(&claims.sub)
+        // Get the wallet, checking it belongs to the user in the claims
+        let wallet = wallet_service
+            .get_user_wallet(&claims.sub, Some(&wallet_id))
             .await
             .map_err(|err| err.to_field_error())?;
 
-        // Get the wallet
+        // Get the balance
+        wallet_service
+            .get_balance(&wallet.id, token.as_deref())
+            .await
+            .map_err(|err| err.to_field_error())
+    }
+
+    // Get a page of a wallet's transfers, newest first (requires auth). Pass the returned
+    // `nextCursor` as `after` to get the following page.
+    async fn wallet_transactions(
+        &self,
+        ctx: &Context<'_>,
+        wallet_id: Option<String>, // Defaults to the user's default wallet
+        filter: Option<TransactionFilterInput>,
+        first: Option<usize>,
+        after: Option<String>,
+    ) -> Result<WalletTransactionPage, FieldError> {
+        // Get the claims from the context
+        let claims = ctx.data::<Claims>().map_err(|_| {
+            AppError::AuthenticationError(
+                "Authentication required. Please log in to view wallet transactions.".to_string(),
+            )
+            .to_field_error()
+        })?;
+
+        // Get the wallet service
+        let wallet_service = ctx.data::<Arc<WalletService>>().map_err(|_| {
+            AppError::ServerError(anyhow::anyhow!(
+                "Internal configuration error: Wallet service not available"
+            ))
+            .to_field_error()
+        })?;
+
+        // Get the wallet, checking it belongs to the user in the claims
         let wallet = wallet_service
-            .get_wallet_by_id(&wallet_id)
+            .get_user_wallet(&claims.sub, wallet_id.as_deref())
             .await
             .map_err(|err| err.to_field_error())?;
 
-        // Verify ownership
-        if wallet.user_email != user.email {
-            return Err(AppError::AuthorizationError(
-                "You do not have permission to view this wallet's balance".to_string(),
+        let filter: TransactionFilter = filter.map(Into::into).unwrap_or_default();
+        wallet_service
+            .list_wallet_transactions(&wallet.id, &filter, first, after.as_deref())
+            .await
+            .map_err(|err| err.to_field_error())
+    }
+
+    // Estimate the gas and slow, normal and fast fees of a transfer before sending it
+    // (requires auth). Pass the returned `id` as `quoteId` to transfer with these fees.
+    async fn quote_transfer(
+        &self,
+        ctx: &Context<'_>,
+        wallet_id: Option<String>, // Defaults to the user's default wallet
+        #[graphql(default)] account_index: u32, // Sending account; the first one by default
+        to: String,
+        token: Option<String>, // Token symbol or contract address; the native coin by default
+        amount: Amount,
+    ) -> Result<TransferQuoteInfo, FieldError> {
+        // Get the claims from the context
+        let claims = ctx.data::<Claims>().map_err(|_| {
+            AppError::AuthenticationError(
+                "Authentication required. Please log in to quote a transfer.".to_string(),
             )
-            .to_field_error());
-        }
+            .to_field_error()
+        })?;
 
-        // Get the balance
+        // Get the wallet service
+        let wallet_service = ctx.data::<Arc<WalletService>>().map_err(|_| {
+            AppError::ServerError(anyhow::anyhow!(
+                "Internal configuration error: Wallet service not available"
+            ))
+            .to_field_error()
+        })?;
+
+        // Get the wallet, checking it belongs to the user in the claims
+        let wallet = wallet_service
+            .get_user_wallet(&claims.sub, wallet_id.as_deref())
+            .await
+            .map_err(|err| err.to_field_error())?;
+
+        let request = TransferRequest {
+            wallet_id: wallet.id,
+            account_index,
+            to_address: to,
+            token,
+            amount,
+        };
         wallet_service
-            .get_balance(&wallet_id)
+            .quote_transfer(&request)
             .await
             .map_err(|err| err.to_field_error())
     }
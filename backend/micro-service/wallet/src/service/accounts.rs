@@ -0,0 +1,246 @@
+use app_database::service::DbService;
+use app_error::{AppError, AppResult};
+use app_models::wallet::{Wallet, WalletAccount, WalletAccountInfo, WalletInfo};
+use app_utils::generate::{DEFAULT_DERIVATION_PATH, EthereumWallet};
+use app_utils::secret::{Pin, Seed, WalletSecret};
+use serde_json::json;
+use std::sync::Arc;
+use tracing::{error, info};
+
+use crate::service::{WalletService, normalize_label};
+
+/// Extension to WalletService for the accounts of HD wallets
+impl WalletService {
+    /// Add a wallet account database service
+    pub fn with_wallet_account_db(
+        mut self,
+        wallet_account_db: Arc<DbService<'static, WalletAccount>>,
+    ) -> Self {
+        self.wallet_account_db = Some(wallet_account_db);
+        self
+    }
+
+    pub(crate) fn wallet_account_db(&self) -> AppResult<&Arc<DbService<'static, WalletAccount>>> {
+        self.wallet_account_db.as_ref().ok_or_else(|| {
+            error!("Wallet account database not available");
+            AppError::ServerError(anyhow::anyhow!("Wallet account database not available"))
+        })
+    }
+
+    /// Recorded accounts of a wallet, ordered by index. They are kept in their own table
+    /// so addresses can be listed without the PIN that decrypts the seed.
+    async fn stored_wallet_accounts(&self, wallet_id: &str) -> AppResult<Vec<WalletAccount>> {
+        let mut accounts = self
+            .wallet_account_db()?
+            .get_records_by_field("wallet_id", wallet_id.to_string())
+            .await
+            .map_err(|e| {
+                error!("Database error when fetching wallet accounts: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+
+        accounts.sort_by_key(|account| account.account_index);
+        Ok(accounts)
+    }
+
+    /// Accounts of a wallet, falling back to its own address if none are recorded
+    pub(crate) async fn wallet_accounts(&self, wallet: &Wallet) -> AppResult<Vec<WalletAccount>> {
+        let accounts = self.stored_wallet_accounts(&wallet.id.id.to_raw()).await?;
+        if !accounts.is_empty() {
+            return Ok(accounts);
+        }
+
+        // Wallets created before accounts were recorded show their address as account 0
+        let mut first = WalletAccount::new(
+            wallet.id.id.to_raw(),
+            0,
+            wallet.address.clone(),
+            wallet.hd.then(|| DEFAULT_DERIVATION_PATH.to_string()),
+        );
+        first.created_at = wallet.created_at;
+        Ok(vec![first])
+    }
+
+    /// Wallet details including its accounts
+    pub(crate) async fn wallet_info(&self, wallet: Wallet) -> AppResult<WalletInfo> {
+        let accounts = self
+            .wallet_accounts(&wallet)
+            .await?
+            .into_iter()
+            .map(WalletAccountInfo::from)
+            .collect();
+
+        Ok(WalletInfo::from(wallet).with_accounts(accounts))
+    }
+
+    /// Record an account of a wallet
+    pub(crate) async fn store_wallet_account(
+        &self,
+        wallet_id: &str,
+        account: &EthereumWallet,
+        label: Option<String>,
+    ) -> AppResult<WalletAccount> {
+        let record = WalletAccount::new(
+            wallet_id.to_string(),
+            account.account_index(),
+            account.address().to_string(),
+            account.derivation_path(),
+        )
+        .with_label(label);
+
+        self.wallet_account_db()?
+            .create_record(record)
+            .await
+            .map_err(|e| {
+                error!("Failed to store wallet account: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?
+            .ok_or_else(|| {
+                AppError::DatabaseError(anyhow::anyhow!("Failed to store wallet account"))
+            })
+    }
+
+    /// Mark a wallet as holding an HD seed, e.g. after its PIN was reset with the phrase
+    pub(crate) async fn mark_wallet_hd(&self, wallet_id: &str) -> AppResult<()> {
+        self.merge_wallet(wallet_id, json!({ "hd": true }))
+            .await
+            .map(|_| ())
+    }
+
+    /// Check a PIN against the attempt limits and decrypt the seed or private key the
+    /// wallet keeps under it, returning `None` if the PIN is incorrect or the wallet has
+    /// no key
+    pub(crate) async fn unlock_wallet_secret(
+        &self,
+        wallet_id: &str,
+        pin: &Pin,
+    ) -> AppResult<Option<WalletSecret>> {
+        // Validate PIN format
+        Self::validate_pin(pin)?;
+
+        // Get the encrypted data
+        let encrypted_data = match self.get_wallet_encrypted_data(wallet_id).await {
+            Ok(encrypted_data) => encrypted_data,
+            Err(AppError::NotFoundError(_)) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        // Refuse to try the PIN while the wallet is locked or backing off
+        let attempts = self.reserve_pin_attempt(wallet_id).await?;
+
+        match self
+            .encryption_service
+            .decrypt_wallet_secret(&encrypted_data, pin)
+            .await
+        {
+            Ok(secret) => {
+                self.record_successful_pin_attempt(wallet_id).await;
+                self.upgrade_pin_kdf_if_needed(wallet_id, &encrypted_data, &secret, pin)
+                    .await;
+                Ok(Some(secret))
+            }
+            // Only a PIN layer that fails to authenticate is a wrong PIN; key provider, DEK
+            // and integrity failures must not count against the user
+            Err(AppError::AuthenticationError(_)) => {
+                self.record_failed_pin_attempt(wallet_id, attempts).await?;
+                Ok(None)
+            }
+            Err(e) => {
+                self.release_pin_attempt(wallet_id).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// One account of a wallet, by index
+    pub(crate) async fn find_wallet_account(
+        &self,
+        wallet: &Wallet,
+        account_index: u32,
+    ) -> AppResult<WalletAccount> {
+        self.wallet_accounts(wallet)
+            .await?
+            .into_iter()
+            .find(|account| account.account_index == account_index)
+            .ok_or_else(|| {
+                AppError::NotFoundError(format!(
+                    "Account {} of wallet '{}' not found",
+                    account_index,
+                    wallet.id.id.to_raw()
+                ))
+            })
+    }
+
+    /// Derive one account of a wallet, with its private key, for signing
+    pub(crate) async fn get_account_signer(
+        &self,
+        wallet_id: &str,
+        account_index: u32,
+        secret: WalletSecret,
+    ) -> AppResult<EthereumWallet> {
+        let wallet = self.fetch_wallet(wallet_id).await?;
+        let account = self.find_wallet_account(&wallet, account_index).await?;
+
+        let signer = match secret {
+            WalletSecret::Seed(seed) => EthereumWallet::from_hd_seed(seed, account_index),
+            WalletSecret::PrivateKey(private_key) => EthereumWallet::from_private_key(private_key),
+        }
+        .map_err(|e| AppError::CryptoError(e.to_string()))?;
+
+        // Never sign for an address other than the one the user sees
+        if signer.address() != account.address {
+            error!(
+                "Key of account {} of wallet {} does not match address {}",
+                account_index, wallet_id, account.address
+            );
+            return Err(AppError::IntegrityError(
+                "Wallet key does not match the account address".to_string(),
+            ));
+        }
+
+        Ok(signer)
+    }
+
+    /// Derive and record the next account of an HD wallet
+    pub(crate) async fn derive_next_account(
+        &self,
+        wallet: &Wallet,
+        seed: Seed,
+        label: Option<String>,
+    ) -> AppResult<WalletAccountInfo> {
+        let label = normalize_label(label)?;
+
+        let wallet_id = wallet.id.id.to_raw();
+        let first = EthereumWallet::from_hd_seed(seed, 0)
+            .map_err(|e| AppError::CryptoError(e.to_string()))?;
+        if first.address() != wallet.address {
+            return Err(AppError::IntegrityError(
+                "Wallet seed does not match the wallet address".to_string(),
+            ));
+        }
+
+        // Wallets from before accounts were recorded get their first account written now
+        let mut accounts = self.stored_wallet_accounts(&wallet_id).await?;
+        if accounts.is_empty() {
+            accounts.push(self.store_wallet_account(&wallet_id, &first, None).await?);
+        }
+
+        let next_index = accounts
+            .last()
+            .map_or(0, |account| account.account_index + 1);
+        let account = first
+            .derive_account(next_index)
+            .map_err(|e| AppError::ValidationError(e.to_string()))?;
+        let stored = self
+            .store_wallet_account(&wallet_id, &account, label)
+            .await?;
+
+        info!(
+            "Derived account {} ({}) of wallet {}",
+            next_index,
+            account.address(),
+            wallet_id
+        );
+        Ok(WalletAccountInfo::from(stored))
+    }
+}
@@ -0,0 +1,443 @@
+use app_config::{ChainConfig, TokenConfig};
+use app_error::{AppError, AppResult, RevertDetails};
+use app_models::FeeSpeed;
+use app_utils::abi::erc20;
+use app_utils::abi::revert::RevertReason;
+use app_utils::chain::{BlockTag, CallOutcome, CallRequest, ChainClient, NonceManager};
+use app_utils::generate::EthereumWallet;
+use app_utils::transaction::{
+    DynamicFeeTransaction, H160, SignedTransaction, Transaction, U256, format_address,
+    parse_address,
+};
+use std::sync::Arc;
+use tracing::{debug, error, info, warn};
+
+use crate::service::WalletService;
+
+/// Blocks of fee history fee suggestions are based on
+const FEE_HISTORY_BLOCKS: u64 = 10;
+
+/// Percentile of each block's priority fees the tip of each speed is based on
+const FEE_SPEED_PERCENTILES: [(FeeSpeed, f64); 3] = [
+    (FeeSpeed::Slow, 10.0),
+    (FeeSpeed::Normal, 50.0),
+    (FeeSpeed::Fast, 90.0),
+];
+
+/// EIP-1559 fees of a transaction, in wei per gas
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct GasFees {
+    pub max_priority_fee_per_gas: U256,
+    pub max_fee_per_gas: U256,
+}
+
+/// Gas limit and fees a transaction is signed with instead of fresh estimates, such as
+/// those of a quote the user accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct GasPlan {
+    pub gas_limit: u64,
+    pub fees: GasFees,
+}
+
+/// What a balance or transfer is denominated in: the chain's native coin or an ERC-20
+/// token from the registry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Asset {
+    pub symbol: String,
+    pub decimals: u8,
+    /// Token contract; `None` for the native coin
+    pub contract: Option<H160>,
+}
+
+/// Extension to WalletService for reading from and sending to the chain
+impl WalletService {
+    /// Add the client used to reach the chain
+    pub fn with_chain_client(mut self, chain_client: Arc<dyn ChainClient>) -> Self {
+        self.chain_client = Some(chain_client);
+        self
+    }
+
+    /// Set the chain transfers are signed for and the decimals of its native coin
+    pub fn with_chain_config(mut self, chain_config: ChainConfig) -> Self {
+        self.chain_config = chain_config;
+        self
+    }
+
+    /// Set the ERC-20 tokens wallets can hold; tokens of other chains are ignored
+    pub fn with_tokens(mut self, tokens: Vec<TokenConfig>) -> Self {
+        self.tokens = tokens;
+        self
+    }
+
+    /// Set where the nonces of outgoing transactions are counted, shared by every
+    /// instance of the service that signs for the same wallets
+    pub fn with_nonce_manager(mut self, nonce_manager: Arc<NonceManager>) -> Self {
+        self.nonce_manager = nonce_manager;
+        self
+    }
+
+    pub(crate) fn chain_client(&self) -> AppResult<&Arc<dyn ChainClient>> {
+        self.chain_client.as_ref().ok_or_else(|| {
+            error!("Chain client not available");
+            AppError::ServerError(anyhow::anyhow!("Chain client not available"))
+        })
+    }
+
+    fn native_asset(&self) -> Asset {
+        Asset {
+            symbol: self.chain_config.native_symbol.clone(),
+            decimals: self.chain_config.native_decimals,
+            contract: None,
+        }
+    }
+
+    /// Find an asset by symbol or token contract address; no token means the native coin
+    pub(crate) fn resolve_asset(&self, token: Option<&str>) -> AppResult<Asset> {
+        let Some(token) = token.map(str::trim).filter(|token| !token.is_empty()) else {
+            return Ok(self.native_asset());
+        };
+        if token.eq_ignore_ascii_case(&self.chain_config.native_symbol) {
+            return Ok(self.native_asset());
+        }
+
+        // Addresses are matched by value, so any capitalization works
+        let address = token
+            .starts_with("0x")
+            .then(|| parse_address(token))
+            .transpose()?;
+
+        self.tokens
+            .iter()
+            .filter(|config| config.chain_id == self.chain_config.chain_id)
+            .find_map(|config| {
+                let contract = parse_address(&config.address).ok()?;
+                let matches = match address {
+                    Some(address) => address == contract,
+                    None => config.symbol.trim().eq_ignore_ascii_case(token),
+                };
+                matches.then(|| Asset {
+                    symbol: config.symbol.trim().to_string(),
+                    decimals: config.decimals,
+                    contract: Some(contract),
+                })
+            })
+            .ok_or_else(|| {
+                AppError::ValidationError(format!(
+                    "Token '{}' is not supported on this chain",
+                    token
+                ))
+            })
+    }
+
+    /// Balance of an address in an asset, in base units
+    pub(crate) async fn asset_balance(
+        &self,
+        address: H160,
+        asset: &Asset,
+        block: BlockTag,
+    ) -> AppResult<U256> {
+        let chain_client = self.chain_client()?;
+        match asset.contract {
+            None => chain_client.get_balance(address, block).await,
+            Some(contract) => {
+                let call = CallRequest {
+                    to: Some(contract),
+                    data: erc20::balance_of(address),
+                    ..Default::default()
+                };
+                let output = chain_client.call(&call, block).await?;
+                erc20::decode_amount(&output).map_err(|e| {
+                    error!(
+                        "Token {} returned an invalid balance: {}",
+                        format_address(&contract),
+                        e
+                    );
+                    AppError::ChainError(format!(
+                        "Token {} returned an invalid balance",
+                        asset.symbol
+                    ))
+                })
+            }
+        }
+    }
+
+    /// Suggested `(max_priority_fee_per_gas, max_fee_per_gas)` for a transaction sent now
+    /// at normal speed
+    pub(crate) async fn suggest_fees(&self) -> AppResult<(U256, U256)> {
+        let (_, tiers) = self.suggest_fee_tiers().await?;
+        let fees = tiers
+            .into_iter()
+            .find_map(|(speed, fees)| (speed == FeeSpeed::Normal).then_some(fees))
+            .unwrap_or_default();
+        Ok((fees.max_priority_fee_per_gas, fees.max_fee_per_gas))
+    }
+
+    /// The base fee of the next block and suggested fees at each speed for a transaction
+    /// sent now.
+    ///
+    /// A speed's tip is the median over recent blocks of the tip at its percentile of each
+    /// block; the fee cap leaves room for the base fee to double before the transaction is
+    /// mined.
+    pub(crate) async fn suggest_fee_tiers(&self) -> AppResult<(U256, Vec<(FeeSpeed, GasFees)>)> {
+        let percentiles: Vec<f64> = FEE_SPEED_PERCENTILES
+            .iter()
+            .map(|(_, percentile)| *percentile)
+            .collect();
+        let history = self
+            .chain_client()?
+            .fee_history(FEE_HISTORY_BLOCKS, BlockTag::Latest, &percentiles)
+            .await?;
+        let base_fee = history.next_base_fee();
+
+        let tiers = FEE_SPEED_PERCENTILES
+            .iter()
+            .enumerate()
+            .map(|(column, (speed, _))| {
+                let mut tips: Vec<U256> = history
+                    .reward
+                    .iter()
+                    .filter_map(|rewards| rewards.get(column).copied())
+                    .collect();
+                tips.sort();
+                let priority_fee = tips.get(tips.len() / 2).copied().unwrap_or_default();
+
+                let max_fee = base_fee
+                    .saturating_mul(U256::from(2))
+                    .saturating_add(priority_fee);
+                let fees = GasFees {
+                    max_priority_fee_per_gas: priority_fee,
+                    max_fee_per_gas: max_fee,
+                };
+                (*speed, fees)
+            })
+            .collect();
+        Ok((base_fee, tiers))
+    }
+
+    /// The call that transfers `amount` base units of `asset` from `from` to `to`
+    pub(crate) fn transfer_call(from: H160, asset: &Asset, to: H160, amount: U256) -> CallRequest {
+        match asset.contract {
+            None => CallRequest {
+                from: Some(from),
+                to: Some(to),
+                value: amount,
+                ..Default::default()
+            },
+            Some(contract) => CallRequest {
+                from: Some(from),
+                to: Some(contract),
+                data: erc20::transfer(to, amount),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Sign a transfer of `amount` base units of `asset` from `signer`, ready to broadcast,
+    /// with the gas limit and fees of `plan` if given
+    pub(crate) async fn sign_transfer(
+        &self,
+        signer: &EthereumWallet,
+        asset: &Asset,
+        to: H160,
+        amount: U256,
+        plan: Option<GasPlan>,
+    ) -> AppResult<SignedTransaction> {
+        let from = parse_address(signer.address())?;
+
+        let balance = self.asset_balance(from, asset, BlockTag::Pending).await?;
+        if balance < amount {
+            return Err(AppError::ValidationError(format!(
+                "Insufficient {} balance",
+                asset.symbol
+            )));
+        }
+
+        // A transfer that would revert fails here with its reason instead of paying for gas
+        let call = Self::transfer_call(from, asset, to, amount);
+        self.simulate_transfer(&call, asset).await?;
+        self.sign_call(signer, call, plan).await
+    }
+
+    /// Run a transfer's call against the pending state, failing with the decoded reason
+    /// if it would revert or the token refuses it
+    pub(crate) async fn simulate_transfer(
+        &self,
+        call: &CallRequest,
+        asset: &Asset,
+    ) -> AppResult<()> {
+        let outcome = self
+            .chain_client()?
+            .simulate_call(call, BlockTag::Pending)
+            .await?;
+
+        let action = format!("{} transfer", asset.symbol);
+        let details = match outcome {
+            CallOutcome::Returned(output) => {
+                // Tokens from before the standard may refuse a transfer by returning false
+                if asset.contract.is_none() || erc20::decode_success(&output).unwrap_or(true) {
+                    return Ok(());
+                }
+                RevertDetails {
+                    action,
+                    error_name: "ReturnedFalse".to_string(),
+                    arguments: Vec::new(),
+                    reason: "the token refused the transfer".to_string(),
+                }
+            }
+            CallOutcome::Reverted(revert) => match RevertReason::decode(&revert.data) {
+                // Nodes that leave out the data may still give the reason in the message
+                RevertReason::Empty if !revert.message.is_empty() => {
+                    RevertReason::Error(revert.message).details(&action)
+                }
+                reason => reason.details(&action),
+            },
+        };
+
+        warn!(
+            "{} from {} failed its simulation: {}",
+            details.action,
+            call.from.as_ref().map(format_address).unwrap_or_default(),
+            details.reason
+        );
+        Err(AppError::RevertError(details))
+    }
+
+    /// Sign `call` as a transaction from `signer` with the nonce the node expects and the
+    /// gas limit and fees of `plan`, or those the node suggests if there is no plan
+    async fn sign_call(
+        &self,
+        signer: &EthereumWallet,
+        call: CallRequest,
+        plan: Option<GasPlan>,
+    ) -> AppResult<SignedTransaction> {
+        let chain_client = self.chain_client()?;
+        let from = parse_address(signer.address())?;
+
+        let (gas_limit, max_priority_fee_per_gas, max_fee_per_gas) = match plan {
+            Some(plan) => (
+                plan.gas_limit,
+                plan.fees.max_priority_fee_per_gas,
+                plan.fees.max_fee_per_gas,
+            ),
+            None => {
+                let gas_limit = chain_client.estimate_gas(&call).await?;
+                let (max_priority_fee_per_gas, max_fee_per_gas) = self.suggest_fees().await?;
+                (gas_limit, max_priority_fee_per_gas, max_fee_per_gas)
+            }
+        };
+        self.check_native_balance(
+            from,
+            BlockTag::Pending,
+            gas_limit,
+            max_fee_per_gas,
+            call.value,
+        )
+        .await?;
+
+        // Reserved last, so a transfer that cannot be sent does not hold up the next one.
+        // The manager counts from the node's nonce, so concurrent transfers from one
+        // account never share one.
+        let nonce = self
+            .nonce_manager
+            .next_nonce(chain_client.as_ref(), from)
+            .await?;
+        let transaction = DynamicFeeTransaction {
+            chain_id: self.chain_config.chain_id,
+            nonce,
+            max_priority_fee_per_gas,
+            max_fee_per_gas,
+            gas_limit,
+            to: call.to,
+            value: call.value,
+            data: call.data,
+            access_list: Vec::new(),
+        };
+        match self.sign_transaction(signer, transaction) {
+            Ok(signed) => Ok(signed),
+            Err(e) => {
+                self.release_nonce(from, nonce).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Sign a transaction whose nonce and fees are already chosen
+    pub(crate) fn sign_transaction(
+        &self,
+        signer: &EthereumWallet,
+        transaction: DynamicFeeTransaction,
+    ) -> AppResult<SignedTransaction> {
+        let signed = Transaction::DynamicFee(transaction).sign(signer.private_key())?;
+        debug!(
+            "Signed transaction {}: {}",
+            signed.hash_hex(),
+            signed.raw_hex()
+        );
+        Ok(signed)
+    }
+
+    /// Fail unless `from` holds enough of the native coin at `block` to send `value` and
+    /// pay for `gas_limit` gas at up to `max_fee_per_gas`
+    pub(crate) async fn check_native_balance(
+        &self,
+        from: H160,
+        block: BlockTag,
+        gas_limit: u64,
+        max_fee_per_gas: U256,
+        value: U256,
+    ) -> AppResult<()> {
+        // The sender pays for gas on top of any value sent, at up to the fee cap
+        let native_balance = self.chain_client()?.get_balance(from, block).await?;
+        let max_cost = U256::from(gas_limit)
+            .checked_mul(max_fee_per_gas)
+            .and_then(|gas_cost| gas_cost.checked_add(value));
+        if max_cost.is_none_or(|max_cost| native_balance < max_cost) {
+            return Err(AppError::ValidationError(format!(
+                "Insufficient {} to cover the amount and network fees",
+                self.chain_config.native_symbol
+            )));
+        }
+        Ok(())
+    }
+
+    /// Give back the nonce of a transaction that never reached the node, logging (rather
+    /// than returning) errors as the caller is already failing
+    pub(crate) async fn release_nonce(&self, from: H160, nonce: u64) {
+        if let Err(e) = self.nonce_manager.release(from, nonce).await {
+            error!(
+                "Failed to release nonce {} of {}: {}",
+                nonce,
+                format_address(&from),
+                e
+            );
+        }
+    }
+
+    /// Send a signed transaction to the node
+    pub(crate) async fn broadcast_transaction(&self, signed: &SignedTransaction) -> AppResult<()> {
+        let hash = self
+            .chain_client()?
+            .send_raw_transaction(signed.raw())
+            .await?;
+        if hash != signed.hash() {
+            warn!(
+                "Node reported hash {:#x} for transaction {}",
+                hash,
+                signed.hash_hex()
+            );
+        }
+
+        info!(
+            "Broadcast transaction {} with nonce {}",
+            signed.hash_hex(),
+            signed.transaction().nonce()
+        );
+        Ok(())
+    }
+}
+
+/// Most a signed transaction can cost its sender in gas
+pub(crate) fn max_fee(signed: &SignedTransaction) -> U256 {
+    let transaction = signed.transaction();
+    U256::from(transaction.gas_limit()).saturating_mul(transaction.max_fee_per_gas())
+}
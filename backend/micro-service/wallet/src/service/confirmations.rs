@@ -0,0 +1,326 @@
+use app_error::{AppError, AppResult};
+use app_models::{Amount, TransactionDirection, TransactionStatus, WalletTransaction};
+use app_utils::chain::{BlockTag, TransactionReceipt};
+use app_utils::transaction::{U256, parse_address, parse_hash};
+use chrono::Utc;
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+use tracing::{error, info, warn};
+
+use crate::service::WalletService;
+
+/// What the node says about a transaction
+enum Sighting {
+    /// Mined in a block of the node's current chain
+    Mined(TransactionReceipt),
+    NotMined {
+        /// The node still has it, pending
+        known: bool,
+        /// Another transaction of the sender's was mined with its nonce
+        replaced: bool,
+    },
+}
+
+/// Extension to WalletService for following transfers until they are final
+impl WalletService {
+    /// Poll for confirmations in a background task, logging (rather than returning) errors
+    pub fn spawn_confirmation_tracker(self: &Arc<Self>) {
+        let service = Arc::clone(self);
+        let poll_interval = Duration::from_millis(self.chain_config.confirmations.poll_interval_ms);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(poll_interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                if let Err(e) = service.track_confirmations().await {
+                    error!("Confirmation tracking failed: {}", e);
+                }
+            }
+        });
+    }
+
+    /// Check every transaction that is not final yet once, returning how many records
+    /// were updated
+    pub async fn track_confirmations(&self) -> AppResult<usize> {
+        let latest = self.chain_client()?.block_number().await?;
+        let batch_size = self.chain_config.confirmations.batch_size;
+
+        let mut updated = 0;
+        let mut cursor = None;
+        loop {
+            let batch = self
+                .fetch_unfinished_transactions(cursor.as_deref(), batch_size)
+                .await?;
+            let Some(last) = batch.last() else {
+                break;
+            };
+            cursor = Some(last.id.id.to_raw());
+            let is_last_batch = batch.len() < batch_size;
+
+            // Both sides of a transfer between the service's wallets share a hash
+            let mut by_hash: BTreeMap<String, Vec<WalletTransaction>> = BTreeMap::new();
+            for record in batch {
+                by_hash.entry(record.hash.clone()).or_default().push(record);
+            }
+            for (hash, records) in by_hash {
+                match self.track_transaction(&hash, &records, latest).await {
+                    Ok(count) => updated += count,
+                    // One transaction the node cannot answer for must not hold up the rest
+                    Err(e) => warn!("Failed to check transaction {}: {}", hash, e),
+                }
+            }
+
+            if is_last_batch {
+                break;
+            }
+        }
+
+        if updated > 0 {
+            info!("Confirmation tracking updated {} transaction(s)", updated);
+        }
+        Ok(updated)
+    }
+
+    async fn fetch_unfinished_transactions(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> AppResult<Vec<WalletTransaction>> {
+        let mut bindings = vec![
+            (
+                "statuses".to_string(),
+                json!([TransactionStatus::Pending, TransactionStatus::Submitted]),
+            ),
+            ("chain_id".to_string(), json!(self.chain_config.chain_id)),
+            ("limit".to_string(), json!(limit)),
+        ];
+        let sql = match cursor {
+            Some(cursor) => {
+                bindings.push(("cursor".to_string(), json!(cursor)));
+                "SELECT * FROM transactions WHERE status IN $statuses AND chain_id = $chain_id AND id > type::thing('transactions', $cursor) ORDER BY id LIMIT $limit"
+            }
+            None => {
+                "SELECT * FROM transactions WHERE status IN $statuses AND chain_id = $chain_id ORDER BY id LIMIT $limit"
+            }
+        };
+
+        self.transaction_db()?
+            .run_custom_query(sql, bindings)
+            .await
+            .map_err(|e| {
+                error!(
+                    "Database error when fetching unfinished transactions: {}",
+                    e
+                );
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })
+    }
+
+    /// Bring the records of one transaction up to date with the node, returning how many
+    /// changed
+    async fn track_transaction(
+        &self,
+        hash: &str,
+        records: &[WalletTransaction],
+        latest: u64,
+    ) -> AppResult<usize> {
+        let chain_client = self.chain_client()?;
+        let transaction_hash = parse_hash(hash)?;
+
+        // A receipt only counts while its block is still on the node's chain
+        let receipt = match chain_client
+            .get_transaction_receipt(transaction_hash)
+            .await?
+        {
+            Some(receipt)
+                if chain_client.get_block_hash(receipt.block_number).await?
+                    == Some(receipt.block_hash) =>
+            {
+                Some(receipt)
+            }
+            Some(receipt) => {
+                warn!(
+                    "Block {} of transaction {} is no longer on the chain",
+                    receipt.block_number, hash
+                );
+                None
+            }
+            None => None,
+        };
+
+        let sighting = match receipt {
+            Some(receipt) => Sighting::Mined(receipt),
+            None => {
+                let known = chain_client.has_transaction(transaction_hash).await?;
+                let replaced = match (known, records.first()) {
+                    (false, Some(record)) => {
+                        // The sender is the counterparty of an incoming record
+                        let sender = match record.direction {
+                            TransactionDirection::Outgoing => &record.address,
+                            TransactionDirection::Incoming => &record.counterparty,
+                        };
+                        chain_client
+                            .get_transaction_count(parse_address(sender)?, BlockTag::Latest)
+                            .await?
+                            > record.nonce
+                    }
+                    _ => false,
+                };
+                Sighting::NotMined { known, replaced }
+            }
+        };
+
+        let mut updated = 0;
+        for record in records {
+            let changes = match &sighting {
+                Sighting::Mined(receipt) => self.mined_changes(record, receipt, latest),
+                Sighting::NotMined { known, replaced } => {
+                    self.unmined_changes(record, *known, *replaced)
+                }
+            };
+            if let Some(changes) = changes {
+                self.update_transaction(record, changes).await?;
+                updated += 1;
+            }
+        }
+        Ok(updated)
+    }
+
+    /// Changes to a record whose transaction has a receipt, if any
+    fn mined_changes(
+        &self,
+        record: &WalletTransaction,
+        receipt: &TransactionReceipt,
+        latest: u64,
+    ) -> Option<Value> {
+        let block_hash = format!("{:#x}", receipt.block_hash);
+        if let Some(previous) = record
+            .block_hash
+            .as_deref()
+            .filter(|previous| *previous != block_hash)
+        {
+            info!(
+                "Transaction {} moved from block {} to block {} {}",
+                record.hash, previous, receipt.block_number, block_hash
+            );
+        }
+
+        let confirmations = latest.saturating_sub(receipt.block_number) + 1;
+        let (status, error) = if confirmations < self.chain_config.confirmations.required {
+            // A revert is only final once its block is, since a reorg may re-run it
+            (TransactionStatus::Submitted, None)
+        } else if receipt.status {
+            (TransactionStatus::Confirmed, None)
+        } else {
+            (
+                TransactionStatus::Failed,
+                Some("Transaction reverted".to_string()),
+            )
+        };
+        if status == record.status && record.block_hash.as_ref() == Some(&block_hash) {
+            return None;
+        }
+
+        let mut changes = json!({
+            "status": status,
+            "error": error,
+            "block_number": receipt.block_number,
+            "block_hash": block_hash,
+            "confirmed_at": status.is_final().then(Utc::now),
+        });
+        // The sender pays for the gas used, at the price of the block it was mined in
+        if record.direction == TransactionDirection::Outgoing {
+            let paid = U256::from(receipt.gas_used).saturating_mul(receipt.effective_gas_price);
+            changes["fee"] = json!(Amount::new(paid, self.chain_config.native_decimals));
+        }
+        Some(changes)
+    }
+
+    /// Changes to a record whose transaction has no receipt, if any
+    fn unmined_changes(
+        &self,
+        record: &WalletTransaction,
+        known: bool,
+        replaced: bool,
+    ) -> Option<Value> {
+        let (status, error) = if record.block_hash.is_some() {
+            // Mined before, but a reorg took its block away
+            if known {
+                warn!(
+                    "Transaction {} was reorged out and is pending again",
+                    record.hash
+                );
+                (TransactionStatus::Pending, None)
+            } else {
+                (
+                    TransactionStatus::Dropped,
+                    Some("Removed from the chain by a reorg".to_string()),
+                )
+            }
+        } else if known {
+            // Still waiting to be mined; a pending record was broadcast even if marking
+            // it as submitted failed
+            if record.status == TransactionStatus::Submitted {
+                return None;
+            }
+            (TransactionStatus::Submitted, None)
+        } else if replaced {
+            // Its nonce was used by another transaction, so it can never be mined
+            (
+                TransactionStatus::Dropped,
+                Some("Replaced by another transaction with the same nonce".to_string()),
+            )
+        } else {
+            let drop_after = self.chain_config.confirmations.drop_after_secs;
+            let age = Utc::now().signed_duration_since(record.created_at);
+            if age.num_seconds() < i64::try_from(drop_after).unwrap_or(i64::MAX) {
+                // Possibly not broadcast yet, or the node is catching up
+                return None;
+            }
+            (
+                TransactionStatus::Dropped,
+                Some(format!(
+                    "Not known to the node after {} seconds",
+                    drop_after
+                )),
+            )
+        };
+
+        if status == TransactionStatus::Dropped {
+            warn!("Transaction {} was dropped: {:?}", record.hash, error);
+        }
+        Some(json!({
+            "status": status,
+            "error": error,
+            "block_number": null,
+            "block_hash": null,
+            "confirmed_at": null,
+        }))
+    }
+
+    async fn update_transaction(
+        &self,
+        record: &WalletTransaction,
+        mut changes: Value,
+    ) -> AppResult<()> {
+        let id = record.id.id.to_raw();
+        changes["updated_at"] = json!(Utc::now());
+        self.transaction_db()?
+            .run_custom_query(
+                "UPDATE type::thing('transactions', $id) MERGE $changes",
+                vec![
+                    ("id".to_string(), json!(id)),
+                    ("changes".to_string(), changes),
+                ],
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to update transaction {}: {}", record.hash, e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+        Ok(())
+    }
+}
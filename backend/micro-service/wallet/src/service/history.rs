@@ -0,0 +1,345 @@
+use app_database::service::DbService;
+use app_error::{AppError, AppResult};
+use app_models::{
+    Amount, TransactionDirection, TransactionStatus, WalletTransaction, WalletTransactionPage,
+};
+use app_utils::transaction::{H160, SignedTransaction, format_address, parse_address};
+use chrono::{DateTime, Utc};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use tracing::{error, info};
+
+use crate::service::WalletService;
+use crate::service::chain::{Asset, max_fee};
+
+/// Transactions listed per page when the caller does not choose
+const DEFAULT_PAGE_SIZE: usize = 20;
+
+/// Most transactions listed per page
+const MAX_PAGE_SIZE: usize = 100;
+
+/// Narrows down the transactions listed for a wallet
+#[derive(Debug, Clone, Default)]
+pub struct TransactionFilter {
+    pub status: Option<TransactionStatus>,
+    /// Symbol or contract address of a token, or the native coin's symbol
+    pub token: Option<String>,
+    /// Only transactions recorded at or after this time
+    pub since: Option<DateTime<Utc>>,
+    /// Only transactions recorded before this time
+    pub until: Option<DateTime<Utc>>,
+}
+
+/// Extension to WalletService for the history of transfers
+impl WalletService {
+    /// Add a transaction history database service
+    pub fn with_transaction_db(
+        mut self,
+        transaction_db: Arc<DbService<'static, WalletTransaction>>,
+    ) -> Self {
+        self.transaction_db = Some(transaction_db);
+        self
+    }
+
+    pub(crate) fn transaction_db(&self) -> AppResult<&Arc<DbService<'static, WalletTransaction>>> {
+        self.transaction_db.as_ref().ok_or_else(|| {
+            error!("Transaction database not available");
+            AppError::ServerError(anyhow::anyhow!("Transaction database not available"))
+        })
+    }
+
+    /// Define the indexes the transactions table relies on. A unique index on idempotency
+    /// keys makes the database refuse a second record, and so a second broadcast, of a
+    /// transfer with the same key.
+    pub async fn define_transaction_indexes(&self) -> AppResult<()> {
+        self.transaction_db()?
+            .run_definition(
+                "DEFINE INDEX IF NOT EXISTS transactions_idempotency_key ON TABLE transactions FIELDS idempotency_key UNIQUE",
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to define the transaction indexes: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+        Ok(())
+    }
+
+    /// The outgoing record of the transfer sent with an idempotency key, if there is one
+    pub(crate) async fn find_transaction_by_idempotency_key(
+        &self,
+        idempotency_key: &str,
+    ) -> AppResult<Option<WalletTransaction>> {
+        let records = self
+            .transaction_db()?
+            .get_records_by_field("idempotency_key", idempotency_key.to_string())
+            .await
+            .map_err(|e| {
+                error!("Database error when looking up idempotency key: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+        Ok(records.into_iter().next())
+    }
+
+    /// Take the idempotency key off a transaction the node rejected, so the transfer can
+    /// be recorded again when it is signed once more
+    pub(crate) async fn release_idempotency_key(&self, hash: &str) -> AppResult<()> {
+        self.transaction_db()?
+            .run_custom_query(
+                "UPDATE transactions SET idempotency_key = NONE WHERE hash = $hash",
+                vec![("hash".to_string(), json!(hash))],
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to release the idempotency key of {}: {}", hash, e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+        Ok(())
+    }
+
+    /// The wallet and account index of a service wallet's address, if there is one
+    async fn find_account_by_address(&self, address: &str) -> AppResult<Option<(String, u32)>> {
+        let accounts = self
+            .wallet_account_db()?
+            .get_records_by_field("address", address.to_string())
+            .await
+            .map_err(|e| {
+                error!("Database error when looking up account address: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+        if let Some(account) = accounts.into_iter().next() {
+            return Ok(Some((account.wallet_id, account.account_index)));
+        }
+
+        // Wallets created before accounts were recorded only have their own address
+        let wallets = self
+            .wallet_db()?
+            .get_records_by_field("address", address.to_string())
+            .await
+            .map_err(|e| {
+                error!("Database error when looking up wallet address: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+        Ok(wallets
+            .into_iter()
+            .next()
+            .map(|wallet| (wallet.id.id.to_raw(), 0)))
+    }
+
+    /// Record a signed transfer of `amount` to `to` as pending, along with its incoming
+    /// side if the recipient is another of the service's wallets, and return the outgoing
+    /// record. `replaces` is the hash of the transaction it speeds up or cancels, and
+    /// `idempotency_key` the sender's key for the transfer, which only one record can hold.
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) async fn record_transfer(
+        &self,
+        wallet_id: &str,
+        account_index: u32,
+        asset: &Asset,
+        to: H160,
+        amount: Amount,
+        signed: &SignedTransaction,
+        replaces: Option<&str>,
+        idempotency_key: Option<&str>,
+    ) -> AppResult<WalletTransaction> {
+        // Recorded before broadcasting, so every transaction that may reach the chain has a
+        // record
+        let now = Utc::now();
+        let outgoing = WalletTransaction {
+            id: WalletTransaction::generate_id(),
+            wallet_id: wallet_id.to_string(),
+            account_index,
+            direction: TransactionDirection::Outgoing,
+            address: format_address(&signed.sender()?),
+            counterparty: format_address(&to),
+            token: asset.symbol.clone(),
+            token_address: asset.contract.as_ref().map(format_address),
+            amount,
+            fee: Some(Amount::new(
+                max_fee(signed),
+                self.chain_config.native_decimals,
+            )),
+            nonce: signed.transaction().nonce(),
+            hash: signed.hash_hex(),
+            // Kept so the transaction can be sped up or cancelled while it is stuck
+            raw_transaction: Some(signed.raw_hex()),
+            replaces: replaces.map(str::to_string),
+            idempotency_key: idempotency_key.map(str::to_string),
+            chain_id: self.chain_config.chain_id,
+            status: TransactionStatus::Pending,
+            error: None,
+            block_number: None,
+            block_hash: None,
+            confirmed_at: None,
+            created_at: now,
+            updated_at: now,
+        };
+
+        let mut records = vec![outgoing.clone()];
+        // A cancellation is sent to the sender itself, which receives nothing
+        let recipient = if outgoing.counterparty != outgoing.address {
+            self.find_account_by_address(&outgoing.counterparty).await?
+        } else {
+            None
+        };
+        if let Some((wallet_id, account_index)) = recipient {
+            records.push(outgoing.incoming(wallet_id, account_index));
+        }
+
+        self.transaction_db()?
+            .bulk_create_records(records)
+            .await
+            .map_err(|e| {
+                error!("Failed to record transaction {}: {}", outgoing.hash, e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+        Ok(outgoing)
+    }
+
+    /// Set the status of every record of a transaction, with the reason if it failed
+    pub(crate) async fn set_transaction_status(
+        &self,
+        hash: &str,
+        status: TransactionStatus,
+        error: Option<String>,
+    ) -> AppResult<()> {
+        let updated = self
+            .transaction_db()?
+            .run_custom_query(
+                "UPDATE transactions MERGE $changes WHERE hash = $hash",
+                vec![
+                    ("hash".to_string(), json!(hash)),
+                    (
+                        "changes".to_string(),
+                        json!({ "status": status, "error": error, "updated_at": Utc::now() }),
+                    ),
+                ],
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to update transaction {}: {}", hash, e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+
+        if updated.is_empty() {
+            return Err(AppError::NotFoundError(format!(
+                "Transaction '{}' not found",
+                hash
+            )));
+        }
+        info!("Transaction {} is now {:?}", hash, status);
+        Ok(())
+    }
+
+    /// Broadcast a recorded transaction and mark it `submitted`, or `failed` with the
+    /// node's reason if the node rejects it
+    pub(crate) async fn submit_transaction(&self, signed: &SignedTransaction) -> AppResult<()> {
+        let hash = signed.hash_hex();
+        if let Err(e) = self.broadcast_transaction(signed).await {
+            // A rejected transaction is never mined. Other errors leave it pending, as the
+            // node may have received it anyway.
+            if let AppError::ChainError(reason) = &e {
+                let marked = self
+                    .set_transaction_status(&hash, TransactionStatus::Failed, Some(reason.clone()))
+                    .await;
+                if let Err(update_error) = marked {
+                    error!(
+                        "Failed to mark transaction {} as failed: {}",
+                        hash, update_error
+                    );
+                }
+            }
+            return Err(e);
+        }
+
+        // The transaction is on its way, so a failed update must not make the caller retry it
+        if let Err(e) = self
+            .set_transaction_status(&hash, TransactionStatus::Submitted, None)
+            .await
+        {
+            error!("Failed to mark transaction {} as submitted: {}", hash, e);
+        }
+        Ok(())
+    }
+
+    /// A page of a wallet's transactions, newest first, starting after the transaction
+    /// with ID `after`
+    pub async fn list_wallet_transactions(
+        &self,
+        wallet_id: &str,
+        filter: &TransactionFilter,
+        first: Option<usize>,
+        after: Option<&str>,
+    ) -> AppResult<WalletTransactionPage> {
+        let limit = first.unwrap_or(DEFAULT_PAGE_SIZE);
+        if limit == 0 || limit > MAX_PAGE_SIZE {
+            return Err(AppError::validation(
+                "first",
+                &format!("must be between 1 and {}", MAX_PAGE_SIZE),
+            ));
+        }
+
+        let mut conditions = vec!["wallet_id = $wallet_id"];
+        let mut bindings: Vec<(String, Value)> = vec![("wallet_id".to_string(), json!(wallet_id))];
+
+        if let Some(status) = filter.status {
+            conditions.push("status = $status");
+            bindings.push(("status".to_string(), json!(status)));
+        }
+        if let Some(token) = filter.token.as_deref().map(str::trim) {
+            // Records keep the token's symbol and address, so tokens no longer listed
+            // can still be found
+            if token.starts_with("0x") {
+                conditions.push("token_address = $token_address");
+                bindings.push((
+                    "token_address".to_string(),
+                    json!(format_address(&parse_address(token)?)),
+                ));
+            } else {
+                conditions.push("string::lowercase(token) = $token_symbol");
+                bindings.push(("token_symbol".to_string(), json!(token.to_lowercase())));
+            }
+        }
+        if let Some(since) = filter.since {
+            conditions.push("type::datetime(created_at) >= type::datetime($since)");
+            bindings.push(("since".to_string(), json!(since)));
+        }
+        if let Some(until) = filter.until {
+            conditions.push("type::datetime(created_at) < type::datetime($until)");
+            bindings.push(("until".to_string(), json!(until)));
+        }
+        if let Some(after) = after {
+            conditions.push("id < type::thing('transactions', $after)");
+            bindings.push(("after".to_string(), json!(after)));
+        }
+
+        // One more than the page holds tells whether there is a next page
+        bindings.push(("limit".to_string(), json!(limit + 1)));
+        let sql = format!(
+            "SELECT * FROM transactions WHERE {} ORDER BY id DESC LIMIT $limit",
+            conditions.join(" AND ")
+        );
+
+        let mut transactions = self
+            .transaction_db()?
+            .run_custom_query(&sql, bindings)
+            .await
+            .map_err(|e| {
+                error!("Database error when listing wallet transactions: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+
+        let next_cursor = if transactions.len() > limit {
+            transactions.truncate(limit);
+            transactions
+                .last()
+                .map(|transaction| transaction.id.id.to_raw())
+        } else {
+            None
+        };
+
+        Ok(WalletTransactionPage {
+            transactions: transactions.into_iter().map(Into::into).collect(),
+            next_cursor,
+        })
+    }
+}
@@ -0,0 +1,273 @@
+use app_config::IdempotencyConfig;
+use app_error::{AppError, AppResult, ErrorRecord};
+use app_models::{Amount, FeeSpeed, TransactionStatus, WalletTransaction};
+use app_utils::idempotency::IdempotencyStore;
+use app_utils::secret::Pin;
+use app_utils::transaction::{format_address, parse_address};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{error, info};
+
+use crate::service::{QuoteChoice, TransferRequest, WalletService};
+
+/// Key prefix for the idempotency keys of transfers
+const IDEMPOTENCY_PREFIX: &str = "idempotency:transfer";
+
+/// Longest idempotency key a client can send
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 128;
+
+/// The parts of a transfer a replay must repeat, normalized so that different spellings
+/// of the same transfer match
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct TransferFingerprint {
+    wallet_id: String,
+    account_index: u32,
+    to: String,
+    /// Token contract, or the native coin's symbol
+    asset: String,
+    amount: Amount,
+    /// Quote the transfer is sent at, and the speed chosen from it
+    #[serde(default)]
+    quote_id: Option<String>,
+    #[serde(default)]
+    fee_speed: Option<FeeSpeed>,
+}
+
+/// What happened to the first transfer with a key
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TransferOutcome {
+    Submitted {
+        transaction_id: String,
+        hash: String,
+    },
+    Failed(ErrorRecord),
+}
+
+/// What is stored under an idempotency key
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+enum IdempotentTransfer {
+    InProgress {
+        request: TransferFingerprint,
+    },
+    Completed {
+        request: TransferFingerprint,
+        outcome: TransferOutcome,
+    },
+}
+
+fn encode(transfer: &IdempotentTransfer) -> AppResult<String> {
+    serde_json::to_string(transfer).map_err(|e| {
+        error!("Failed to encode idempotent transfer: {}", e);
+        AppError::ServerError(anyhow::anyhow!("Failed to encode idempotent transfer"))
+    })
+}
+
+/// Extension to WalletService for transfers that are safe to retry
+impl WalletService {
+    /// Set where the results of transfers with idempotency keys are kept
+    pub fn with_idempotency_store(mut self, idempotency_store: Arc<dyn IdempotencyStore>) -> Self {
+        self.idempotency_store = idempotency_store;
+        self
+    }
+
+    /// Set how long the results of transfers with idempotency keys are kept
+    pub fn with_idempotency_config(mut self, idempotency_config: IdempotencyConfig) -> Self {
+        self.idempotency_config = idempotency_config;
+        self
+    }
+
+    /// Transfer at most once per idempotency key of a user, returning the transaction
+    /// hash of the first transfer with the key (or failing as it did)
+    pub async fn transfer_with_idempotency_key(
+        &self,
+        user_id: &str,
+        idempotency_key: &str,
+        request: &TransferRequest,
+        quote: Option<&QuoteChoice>,
+        pin: &Pin,
+    ) -> AppResult<String> {
+        let key = Self::idempotency_store_key(user_id, idempotency_key)?;
+        let fingerprint = self.transfer_fingerprint(request, quote)?;
+        let ttl = Duration::from_secs(self.idempotency_config.retention_secs);
+
+        // The key is claimed before anything else happens. The claim only lasts about as
+        // long as a transfer takes, so a request that dies before storing its result does
+        // not hold the key for the whole retention window.
+        let claim = encode(&IdempotentTransfer::InProgress {
+            request: fingerprint.clone(),
+        })?;
+        let lease = Duration::from_secs(self.idempotency_config.lease_secs);
+        if let Some(stored) = self.idempotency_store.claim(&key, &claim, lease).await? {
+            return Self::replay_transfer(idempotency_key, &fingerprint, &stored);
+        }
+
+        // The claim can lapse while a transfer is still going, or its result may never be
+        // stored, so the transaction record is what keeps the transfer from being sent
+        // twice: it holds the key, and the database takes only one record per key
+        let result = match self.find_transaction_by_idempotency_key(&key).await? {
+            Some(outgoing) => Self::recorded_transfer(idempotency_key, &fingerprint, outgoing),
+            None => match self
+                .send_keyed_transfer(request, quote, pin, Some(&key))
+                .await
+            {
+                // Another request with the key recorded its transfer first
+                Err(AppError::DatabaseError(e)) => {
+                    match self.find_transaction_by_idempotency_key(&key).await? {
+                        Some(outgoing) => {
+                            Self::recorded_transfer(idempotency_key, &fingerprint, outgoing)
+                        }
+                        None => Err(AppError::DatabaseError(e)),
+                    }
+                }
+                result => result,
+            },
+        };
+
+        let outcome = match &result {
+            Ok(outgoing) => TransferOutcome::Submitted {
+                transaction_id: outgoing.id.id.to_raw(),
+                hash: outgoing.hash.clone(),
+            },
+            Err(e) => TransferOutcome::Failed(ErrorRecord::from(e)),
+        };
+        let completed = encode(&IdempotentTransfer::Completed {
+            request: fingerprint,
+            outcome,
+        })?;
+        // The transfer is done either way, so its result is returned even if it cannot be
+        // stored; retries then find the key in progress until it expires, and the
+        // transaction record after that
+        if let Err(e) = self.idempotency_store.store(&key, &completed, ttl).await {
+            error!(
+                "Failed to store the result of transfer with idempotency key {}: {}",
+                key, e
+            );
+        }
+
+        result.map(|outgoing| outgoing.hash)
+    }
+
+    fn idempotency_store_key(user_id: &str, idempotency_key: &str) -> AppResult<String> {
+        let is_valid = !idempotency_key.is_empty()
+            && idempotency_key.len() <= MAX_IDEMPOTENCY_KEY_LENGTH
+            && idempotency_key.chars().all(|c| c.is_ascii_graphic());
+        if !is_valid {
+            return Err(AppError::validation(
+                "idempotency_key",
+                &format!(
+                    "must be 1 to {} printable ASCII characters without spaces",
+                    MAX_IDEMPOTENCY_KEY_LENGTH
+                ),
+            ));
+        }
+
+        Ok(format!(
+            "{}:{}:{}",
+            IDEMPOTENCY_PREFIX, user_id, idempotency_key
+        ))
+    }
+
+    fn transfer_fingerprint(
+        &self,
+        request: &TransferRequest,
+        quote: Option<&QuoteChoice>,
+    ) -> AppResult<TransferFingerprint> {
+        let asset = self.resolve_asset(request.token.as_deref())?;
+        Ok(TransferFingerprint {
+            wallet_id: request.wallet_id.clone(),
+            account_index: request.account_index,
+            to: format_address(&parse_address(&request.to_address)?),
+            asset: asset
+                .contract
+                .as_ref()
+                .map(format_address)
+                .unwrap_or(asset.symbol),
+            amount: request.amount.to_decimals(asset.decimals)?,
+            quote_id: quote.map(|quote| quote.quote_id.trim().to_string()),
+            fee_speed: quote.map(|quote| quote.speed),
+        })
+    }
+
+    /// The recorded transfer with a key, for a retry whose claim on the key found nothing
+    /// stored. The record does not keep the quote, so only the transfer itself is compared.
+    fn recorded_transfer(
+        idempotency_key: &str,
+        fingerprint: &TransferFingerprint,
+        outgoing: WalletTransaction,
+    ) -> AppResult<WalletTransaction> {
+        let asset = outgoing
+            .token_address
+            .clone()
+            .unwrap_or_else(|| outgoing.token.clone());
+        let is_same_transfer = outgoing.wallet_id == fingerprint.wallet_id
+            && outgoing.account_index == fingerprint.account_index
+            && outgoing.counterparty == fingerprint.to
+            && asset == fingerprint.asset
+            && outgoing.amount == fingerprint.amount;
+        if !is_same_transfer {
+            return Err(AppError::ResourceExistsError(format!(
+                "Idempotency key '{}' was already used for a different transfer",
+                idempotency_key
+            )));
+        }
+
+        if outgoing.status == TransactionStatus::Failed {
+            return Err(AppError::ChainError(
+                outgoing
+                    .error
+                    .unwrap_or_else(|| "Transaction failed".to_string()),
+            ));
+        }
+        info!(
+            "Replaying recorded transfer {} for idempotency key {}",
+            outgoing.hash, idempotency_key
+        );
+        Ok(outgoing)
+    }
+
+    /// The stored result of the first transfer with a key, for a retry with the same key
+    fn replay_transfer(
+        idempotency_key: &str,
+        fingerprint: &TransferFingerprint,
+        stored: &str,
+    ) -> AppResult<String> {
+        let stored: IdempotentTransfer = serde_json::from_str(stored).map_err(|e| {
+            error!(
+                "Stored transfer for idempotency key {} is unreadable: {}",
+                idempotency_key, e
+            );
+            AppError::ServerError(anyhow::anyhow!("Stored idempotent transfer is unreadable"))
+        })?;
+        let (request, outcome) = match stored {
+            IdempotentTransfer::InProgress { request } => (request, None),
+            IdempotentTransfer::Completed { request, outcome } => (request, Some(outcome)),
+        };
+
+        if request != *fingerprint {
+            return Err(AppError::ResourceExistsError(format!(
+                "Idempotency key '{}' was already used for a different transfer",
+                idempotency_key
+            )));
+        }
+        match outcome {
+            None => Err(AppError::ResourceExistsError(format!(
+                "The transfer with idempotency key '{}' is still in progress",
+                idempotency_key
+            ))),
+            Some(TransferOutcome::Submitted {
+                transaction_id,
+                hash,
+            }) => {
+                info!(
+                    "Replaying transfer {} ({}) for idempotency key {}",
+                    transaction_id, hash, idempotency_key
+                );
+                Ok(hash)
+            }
+            Some(TransferOutcome::Failed(error)) => Err(error.into()),
+        }
+    }
+}
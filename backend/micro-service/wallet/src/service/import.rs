@@ -0,0 +1,101 @@
+use app_error::{AppError, AppResult};
+use app_utils::generate::{DEFAULT_DERIVATION_PATH, EthereumWallet};
+use app_utils::keystore::decrypt_keystore;
+use app_utils::secret::{MnemonicPhrase, PrivateKey, SecretString};
+use tracing::error;
+
+use crate::service::WalletService;
+
+/// Where the private key of an imported wallet comes from
+pub enum WalletImportSource {
+    /// A BIP-39 phrase, with an optional passphrase and derivation path
+    Mnemonic {
+        phrase: MnemonicPhrase,
+        passphrase: SecretString,
+        derivation_path: Option<String>,
+    },
+    /// A raw private key
+    PrivateKey(PrivateKey),
+    /// A Web3 Secret Storage (Keystore V3) JSON file and its password
+    Keystore {
+        json: String,
+        password: SecretString,
+    },
+}
+
+/// Extension to WalletService for importing existing wallets
+impl WalletService {
+    /// Recover the wallet described by an import source. Keystore KDFs and BIP-39 seed
+    /// derivation are deliberately slow, so keep them off the async workers.
+    pub(crate) async fn restore_imported_wallet(
+        source: WalletImportSource,
+    ) -> AppResult<EthereumWallet> {
+        tokio::task::spawn_blocking(move || Self::restore_wallet_blocking(source))
+            .await
+            .map_err(|e| AppError::ServerError(anyhow::anyhow!("Wallet restore failed: {}", e)))?
+    }
+
+    fn restore_wallet_blocking(source: WalletImportSource) -> AppResult<EthereumWallet> {
+        match source {
+            WalletImportSource::Mnemonic {
+                phrase,
+                passphrase,
+                derivation_path,
+            } => EthereumWallet::from_mnemonic_with_path(
+                &phrase,
+                &passphrase,
+                derivation_path
+                    .as_deref()
+                    .unwrap_or(DEFAULT_DERIVATION_PATH),
+            )
+            .map_err(|e| AppError::ValidationError(e.to_string())),
+            WalletImportSource::PrivateKey(private_key) => {
+                EthereumWallet::from_private_key(private_key)
+                    .map_err(|e| AppError::ValidationError(e.to_string()))
+            }
+            WalletImportSource::Keystore { json, password } => {
+                let decrypted = decrypt_keystore(&json, &password)?;
+                let wallet = EthereumWallet::from_private_key(decrypted.private_key)
+                    .map_err(|e| AppError::ValidationError(e.to_string()))?;
+
+                // The file's address is informational, but a mismatch means it was edited
+                if decrypted
+                    .address
+                    .is_some_and(|address| wallet.address().trim_start_matches("0x") != address)
+                {
+                    return Err(AppError::ValidationError(
+                        "Keystore address does not match its private key".to_string(),
+                    ));
+                }
+
+                Ok(wallet)
+            }
+        }
+    }
+
+    /// Fail if any wallet already uses this address, as its first or a derived account
+    pub(crate) async fn ensure_address_not_imported(&self, address: &str) -> AppResult<()> {
+        let wallets = self
+            .wallet_db()?
+            .get_records_by_field("address", address.to_string())
+            .await
+            .map_err(|e| {
+                error!("Database error when checking wallet address: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+        let accounts = self
+            .wallet_account_db()?
+            .get_records_by_field("address", address.to_string())
+            .await
+            .map_err(|e| {
+                error!("Database error when checking wallet account address: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+
+        if wallets.is_empty() && accounts.is_empty() {
+            Ok(())
+        } else {
+            Err(AppError::resource_exists("Wallet", address))
+        }
+    }
+}
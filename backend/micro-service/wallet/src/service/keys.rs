@@ -1,11 +1,27 @@
 use app_error::{AppError, AppResult};
+use app_models::DekCacheStatsInfo;
 use app_models::wallet::WalletKey;
-use app_utils::crypto::{WalletEncryptedData, WalletEncryptionService};
-use std::sync::Arc;
-use tracing::{error, info};
+use app_utils::crypto::WalletEncryptedData;
+use app_utils::secret::{Pin, WalletSecret};
+use serde_json::json;
+use tracing::{error, info, warn};
 
 use crate::service::WalletService;
 
+/// Times a wallet key update is tried before giving up on a record that keeps changing
+const MAX_WALLET_KEY_UPDATE_ATTEMPTS: usize = 3;
+
+/// Outcome of a legacy wallet key migration
+#[derive(Debug, Default)]
+pub struct LegacyKeyMigration {
+    /// Number of keys moved to the current format
+    pub migrated: usize,
+    /// Migrated wallets whose PIN layer is only re-encrypted the next time their PIN is used
+    pub awaiting_pin: Vec<String>,
+    /// Wallets whose key could not be migrated
+    pub failed: Vec<String>,
+}
+
 /// Extension to WalletService for managing wallet keys
 impl WalletService {
     /// Convert WalletEncryptedData to WalletKey
@@ -22,21 +38,26 @@ impl WalletService {
             data.dek_iv.clone(),
             data.master_iv.clone(),
         )
+        .with_integrity_mac(data.integrity_mac.clone())
+        .with_pin_kdf(data.pin_kdf.clone())
     }
 
     /// Convert WalletKey to WalletEncryptedData
     fn wallet_key_to_encrypted_data(key: &WalletKey) -> WalletEncryptedData {
         WalletEncryptedData {
             user_id: "".to_string(), // Not used for decryption
+            wallet_id: key.wallet_id.clone(),
             encrypted_private_key: key.encrypted_private_key.clone(),
             encrypted_dek: key.encrypted_dek.clone(),
             master_key_identifier: key.master_key_id.clone(),
             dek_id: key.dek_id.clone(),
             algorithm: key.algorithm.clone(),
+            pin_kdf: key.pin_kdf.clone().unwrap_or_default(),
             pin_salt: key.pin_salt.clone(),
             pin_iv: key.pin_iv.clone(),
             dek_iv: key.dek_iv.clone(),
             master_iv: key.master_iv.clone(),
+            integrity_mac: key.integrity_mac.clone().unwrap_or_default(),
         }
     }
 
@@ -48,7 +69,7 @@ impl WalletService {
     ) -> AppResult<String> {
         // Create a new wallet key record
         let wallet_key = Self::encrypted_data_to_wallet_key(wallet_id, encrypted_data);
-        let key_id = wallet_key.id.id.to_string();
+        let key_id = wallet_key.id.id.to_raw();
 
         // Store the key in the keys table
         if let Some(wallet_key_db) = &self.wallet_key_db {
@@ -174,103 +195,290 @@ impl WalletService {
         wallet_id: &str,
     ) -> AppResult<WalletEncryptedData> {
         let key = self.get_wallet_key_by_wallet_id(wallet_id).await?;
-        Ok(Self::wallet_key_to_encrypted_data(&key))
+
+        // The wallet's key reference must point at a key issued for this wallet
+        if key.wallet_id != wallet_id {
+            error!(
+                "Wallet {} references key {} which belongs to wallet {}",
+                wallet_id, key.id.id, key.wallet_id
+            );
+            return Err(AppError::IntegrityError(
+                "Wallet key does not belong to this wallet".to_string(),
+            ));
+        }
+
+        let encrypted_data = Self::wallet_key_to_encrypted_data(&key);
+        Ok(self.rewrap_if_retired(wallet_id, encrypted_data).await)
+    }
+
+    /// Lazily move a key wrapped with a retired master key onto the primary master key.
+    /// Failures are logged and the original data is returned, since it is still readable.
+    async fn rewrap_if_retired(
+        &self,
+        wallet_id: &str,
+        encrypted_data: WalletEncryptedData,
+    ) -> WalletEncryptedData {
+        // Legacy records are re-encrypted by the legacy key migration instead
+        if !self.encryption_service.needs_rewrap(&encrypted_data) || encrypted_data.is_legacy() {
+            return encrypted_data;
+        }
+
+        let rewrapped = self
+            .update_wallet_key(wallet_id, |current| async move {
+                if !self.encryption_service.needs_rewrap(&current) || current.is_legacy() {
+                    return Ok(None);
+                }
+                self.encryption_service.rewrap_dek(&current).await.map(Some)
+            })
+            .await;
+
+        match rewrapped {
+            Ok(Some(rewrapped)) => {
+                info!(
+                    "Re-wrapped key for wallet {} from master key {} to {}",
+                    wallet_id,
+                    encrypted_data.master_key_identifier,
+                    rewrapped.master_key_identifier
+                );
+                rewrapped
+            }
+            // Someone else changed the record first, so what is stored now is read again
+            Ok(None) => match self.get_wallet_key_by_wallet_id(wallet_id).await {
+                Ok(key) => Self::wallet_key_to_encrypted_data(&key),
+                Err(_) => encrypted_data,
+            },
+            Err(e) => {
+                warn!(
+                    "Failed to re-wrap key for wallet {} from master key {}: {}",
+                    wallet_id, encrypted_data.master_key_identifier, e
+                );
+                encrypted_data
+            }
+        }
     }
 
-    /// Update a wallet key with new encrypted data (for PIN changes or master key rotation)
-    pub async fn update_wallet_key(
+    /// Re-encrypt a wallet key. `reencrypt` gets the stored record and returns what to
+    /// replace it with, or `None` to leave it alone. The replacement is only written if the
+    /// record has not changed since it was read, so a re-encryption of an old record never
+    /// undoes a PIN change; a record that changed in between is read again and handed to
+    /// `reencrypt` anew. Returns the replacement if one was written.
+    pub async fn update_wallet_key<F, Fut>(
         &self,
         wallet_id: &str,
+        reencrypt: F,
+    ) -> AppResult<Option<WalletEncryptedData>>
+    where
+        F: Fn(WalletEncryptedData) -> Fut,
+        Fut: Future<Output = AppResult<Option<WalletEncryptedData>>>,
+    {
+        for _ in 0..MAX_WALLET_KEY_UPDATE_ATTEMPTS {
+            let current_key = self.get_wallet_key_by_wallet_id(wallet_id).await?;
+            let Some(replacement) =
+                reencrypt(Self::wallet_key_to_encrypted_data(&current_key)).await?
+            else {
+                return Ok(None);
+            };
+
+            if self.replace_wallet_key(&current_key, &replacement).await? {
+                return Ok(Some(replacement));
+            }
+            warn!(
+                "Key of wallet {} changed while it was being updated, reading it again",
+                wallet_id
+            );
+        }
+
+        Err(AppError::ResourceExistsError(format!(
+            "The key of wallet '{}' is being changed by another request. Please try again.",
+            wallet_id
+        )))
+    }
+
+    /// Write new encrypted data over a wallet key record unless the record changed since
+    /// `current_key` was read, returning whether it was written. Every re-encryption
+    /// replaces the DEK ciphertext, and a PIN change the PIN layer, so matching both
+    /// tells that nothing was written in between.
+    async fn replace_wallet_key(
+        &self,
+        current_key: &WalletKey,
         new_encrypted_data: &WalletEncryptedData,
-    ) -> AppResult<()> {
-        // Get the current key first
-        let current_key = self.get_wallet_key_by_wallet_id(wallet_id).await?;
-
-        // Update the key with new encrypted data
-        let mut updated_key = current_key.clone();
-        updated_key.encrypted_private_key = new_encrypted_data.encrypted_private_key.clone();
-        updated_key.encrypted_dek = new_encrypted_data.encrypted_dek.clone();
-        updated_key.master_key_id = new_encrypted_data.master_key_identifier.clone();
-        updated_key.dek_id = new_encrypted_data.dek_id.clone();
-        updated_key.algorithm = new_encrypted_data.algorithm.clone();
-        updated_key.pin_salt = new_encrypted_data.pin_salt.clone();
-        updated_key.pin_iv = new_encrypted_data.pin_iv.clone();
-        updated_key.dek_iv = new_encrypted_data.dek_iv.clone();
-        updated_key.master_iv = new_encrypted_data.master_iv.clone();
-        updated_key.updated_at = chrono::Utc::now();
-
-        // Save the updated key
+    ) -> AppResult<bool> {
+        let Some(wallet_key_db) = &self.wallet_key_db else {
+            error!("Wallet key database not available for key update");
+            return Err(AppError::ServerError(anyhow::anyhow!(
+                "Wallet key database not available"
+            )));
+        };
+
+        let changes = json!({
+            "encrypted_private_key": new_encrypted_data.encrypted_private_key,
+            "encrypted_dek": new_encrypted_data.encrypted_dek,
+            "master_key_id": new_encrypted_data.master_key_identifier,
+            "dek_id": new_encrypted_data.dek_id,
+            "algorithm": new_encrypted_data.algorithm,
+            "pin_kdf": new_encrypted_data.pin_kdf,
+            "pin_salt": new_encrypted_data.pin_salt,
+            "pin_iv": new_encrypted_data.pin_iv,
+            "dek_iv": new_encrypted_data.dek_iv,
+            "master_iv": new_encrypted_data.master_iv,
+            "integrity_mac": new_encrypted_data.integrity_mac,
+            "updated_at": chrono::Utc::now(),
+        });
+        let updated = wallet_key_db
+            .run_custom_query(
+                "UPDATE type::thing('wallet_keys', $id) MERGE $changes WHERE encrypted_dek = $previous_dek AND encrypted_private_key = $previous_private_key",
+                vec![
+                    ("id".to_string(), json!(current_key.id.id.to_raw())),
+                    ("changes".to_string(), changes),
+                    ("previous_dek".to_string(), json!(current_key.encrypted_dek)),
+                    (
+                        "previous_private_key".to_string(),
+                        json!(current_key.encrypted_private_key),
+                    ),
+                ],
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to update wallet key: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(format!(
+                    "Failed to update wallet key: {}",
+                    e
+                )))
+            })?;
+
+        Ok(!updated.is_empty())
+    }
+
+    /// Re-encrypt every wallet key still stored in the legacy (pre-envelope) format.
+    /// The PIN layer of pre-envelope keys needs the PIN, so those wallets are reported as
+    /// awaiting it.
+    pub async fn migrate_legacy_wallet_keys(&self) -> AppResult<LegacyKeyMigration> {
+        let mut report = LegacyKeyMigration::default();
+
         if let Some(wallet_key_db) = &self.wallet_key_db {
-            wallet_key_db
-                .update_record(&current_key.id.id.to_string(), updated_key)
+            let keys = wallet_key_db
+                .run_custom_query("SELECT * FROM wallet_keys", Vec::new())
                 .await
                 .map_err(|e| {
-                    error!("Failed to update wallet key: {}", e);
+                    error!("Database error when fetching keys for migration: {}", e);
                     AppError::DatabaseError(anyhow::anyhow!(format!(
-                        "Failed to update wallet key: {}",
+                        "Failed to fetch keys for migration: {}",
                         e
                     )))
                 })?;
 
-            Ok(())
+            let legacy_keys: Vec<WalletKey> = keys
+                .into_iter()
+                .filter(|key| Self::wallet_key_to_encrypted_data(key).is_legacy())
+                .collect();
+
+            info!("Found {} legacy wallet keys to migrate", legacy_keys.len());
+
+            for key in legacy_keys {
+                let wallet_id = &key.wallet_id;
+
+                let migrated = self
+                    .update_wallet_key(wallet_id, |current| async move {
+                        if !current.is_legacy() {
+                            return Ok(None);
+                        }
+                        self.encryption_service
+                            .migrate_legacy_record(&current)
+                            .await
+                            .map(Some)
+                    })
+                    .await;
+                match migrated {
+                    Ok(Some(new_encrypted_data)) => {
+                        report.migrated += 1;
+                        if new_encrypted_data.has_legacy_pin_layer() {
+                            report.awaiting_pin.push(wallet_id.clone());
+                        }
+                    }
+                    // Re-encrypted in the meantime, such as by a PIN change
+                    Ok(None) => {}
+                    Err(e) => {
+                        error!("Failed to migrate key for wallet {}: {}", wallet_id, e);
+                        report.failed.push(wallet_id.clone());
+                    }
+                }
+            }
         } else {
-            error!("Wallet key database not available for key update");
-            Err(AppError::ServerError(anyhow::anyhow!(
+            return Err(AppError::ServerError(anyhow::anyhow!(
                 "Wallet key database not available"
-            )))
+            )));
         }
+
+        info!(
+            "Legacy key migration completed: {} migrated ({} awaiting PIN), {} failed",
+            report.migrated,
+            report.awaiting_pin.len(),
+            report.failed.len()
+        );
+        Ok(report)
     }
 
-    /// Rotate master key for a specific wallet
-    pub async fn rotate_master_key(
+    /// Move a single wallet key onto the primary master key. Only the master key layer
+    /// (`encrypted_dek` and `master_iv`) is re-encrypted, so no PIN is needed; legacy
+    /// records are migrated to the current format along the way. Returns `None` if the
+    /// stored key no longer needs it.
+    pub(crate) async fn rewrap_wallet_key(
         &self,
-        wallet_id: &str,
-        pin: &str,
-        new_encryption_service: &Arc<WalletEncryptionService>,
-    ) -> AppResult<()> {
-        // 1. Get the current wallet key
-        let key = self.get_wallet_key_by_wallet_id(wallet_id).await?;
+        key: &WalletKey,
+    ) -> AppResult<Option<WalletEncryptedData>> {
+        self.update_wallet_key(&key.wallet_id, |current| async move {
+            if !self.encrypted_data_needs_rotation(&current) {
+                return Ok(None);
+            }
+            if current.is_legacy() {
+                self.encryption_service
+                    .migrate_legacy_record(&current)
+                    .await
+                    .map(Some)
+            } else {
+                self.encryption_service.rewrap_dek(&current).await.map(Some)
+            }
+        })
+        .await
+    }
 
-        // 2. Convert to encrypted data format for decryption
-        let encrypted_data = Self::wallet_key_to_encrypted_data(&key);
+    /// Whether a wallet key is wrapped with a retired master key or still in the legacy format
+    pub(crate) fn wallet_key_needs_rotation(&self, key: &WalletKey) -> bool {
+        self.encrypted_data_needs_rotation(&Self::wallet_key_to_encrypted_data(key))
+    }
 
-        // 3. Decrypt the private key using current encryption service
-        let private_key = self
-            .encryption_service
-            .decrypt_private_key(&encrypted_data, pin)
-            .await?;
+    fn encrypted_data_needs_rotation(&self, encrypted_data: &WalletEncryptedData) -> bool {
+        self.encryption_service.needs_rewrap(encrypted_data) || encrypted_data.is_legacy()
+    }
+
+    /// Rotate master key for a specific wallet
+    pub async fn rotate_master_key(&self, wallet_id: &str) -> AppResult<()> {
+        let key = self.get_wallet_key_by_wallet_id(wallet_id).await?;
 
-        // 4. Re-encrypt with the new encryption service
-        let new_encrypted_data = new_encryption_service
-            .encrypt_private_key(&private_key, pin)
-            .await?;
+        if !self.wallet_key_needs_rotation(&key) {
+            info!(
+                "Wallet {} is already on master key {}",
+                wallet_id,
+                self.encryption_service.master_key_id()
+            );
+            return Ok(());
+        }
 
-        // 5. Update the wallet key with the new encrypted data
-        self.update_wallet_key(wallet_id, &new_encrypted_data)
-            .await?;
+        self.rewrap_wallet_key(&key).await?;
 
         info!("Successfully rotated master key for wallet {}", wallet_id);
         Ok(())
     }
 
-    /// Rotate master key for all wallets (batch operation)
-    pub async fn rotate_all_master_keys(
-        &self,
-        new_encryption_service: &Arc<WalletEncryptionService>,
-        pin_provider: impl Fn(&str) -> AppResult<String>,
-    ) -> AppResult<(usize, Vec<String>)> {
+    /// Rotate every wallet key that is not yet on the primary master key (batch operation).
+    /// Returns the number of rotated keys and the wallet IDs that failed.
+    pub async fn rotate_all_master_keys(&self) -> AppResult<(usize, Vec<String>)> {
         let mut successful = 0;
         let mut failed_wallets = Vec::new();
 
-        // Get all wallet keys needing rotation (with old master key ID)
         if let Some(wallet_key_db) = &self.wallet_key_db {
-            // Find keys with the old master key ID
-            let old_master_key_id = &self.encryption_service.master_key_id;
-
-            // Query for keys with the old master key ID
-            // Note: In a real implementation, you'd use a more efficient query
-            let old_keys = wallet_key_db
-                .get_records_by_field("master_key_id", old_master_key_id.to_string())
+            let keys = wallet_key_db
+                .run_custom_query("SELECT * FROM wallet_keys", Vec::new())
                 .await
                 .map_err(|e| {
                     error!("Database error when fetching keys for rotation: {}", e);
@@ -280,32 +488,23 @@ impl WalletService {
                     )))
                 })?;
 
-            info!("Found {} wallet keys to rotate", old_keys.len());
+            let stale_keys: Vec<WalletKey> = keys
+                .into_iter()
+                .filter(|key| self.wallet_key_needs_rotation(key))
+                .collect();
 
-            // Process each key
-            for key in old_keys {
-                let wallet_id = &key.wallet_id;
+            info!(
+                "Found {} wallet keys to rotate to master key {}",
+                stale_keys.len(),
+                self.encryption_service.master_key_id()
+            );
 
-                // Get PIN for this wallet
-                match pin_provider(wallet_id) {
-                    Ok(pin) => {
-                        // Attempt to rotate this key
-                        match self
-                            .rotate_master_key(wallet_id, &pin, new_encryption_service)
-                            .await
-                        {
-                            Ok(_) => {
-                                successful += 1;
-                            }
-                            Err(e) => {
-                                error!("Failed to rotate key for wallet {}: {}", wallet_id, e);
-                                failed_wallets.push(wallet_id.clone());
-                            }
-                        }
-                    }
+            for key in stale_keys {
+                match self.rewrap_wallet_key(&key).await {
+                    Ok(_) => successful += 1,
                     Err(e) => {
-                        error!("Failed to get PIN for wallet {}: {}", wallet_id, e);
-                        failed_wallets.push(wallet_id.clone());
+                        error!("Failed to rotate key for wallet {}: {}", key.wallet_id, e);
+                        failed_wallets.push(key.wallet_id.clone());
                     }
                 }
             }
@@ -315,11 +514,74 @@ impl WalletService {
             )));
         }
 
+        let purged = self.encryption_service.purge_dek_cache().await;
+
         info!(
-            "Master key rotation completed: {} successful, {} failed",
+            "Master key rotation completed: {} successful, {} failed, {} cached DEKs purged",
             successful,
-            failed_wallets.len()
+            failed_wallets.len(),
+            purged
         );
         Ok((successful, failed_wallets))
     }
+
+    /// Re-encrypt a wallet key whose PIN layer was derived with weaker KDF parameters than
+    /// the current ones or is still a legacy blob. Called after the PIN has been verified;
+    /// failures are logged and leave the existing record in place.
+    pub(crate) async fn upgrade_pin_kdf_if_needed(
+        &self,
+        wallet_id: &str,
+        encrypted_data: &WalletEncryptedData,
+        secret: &WalletSecret,
+        pin: &Pin,
+    ) {
+        if !self.encryption_service.needs_pin_upgrade(encrypted_data) {
+            return;
+        }
+
+        let upgraded = self
+            .update_wallet_key(wallet_id, |current| async move {
+                // A PIN change since the PIN was verified replaced the layer it opened
+                if current.encrypted_private_key != encrypted_data.encrypted_private_key
+                    || !self.encryption_service.needs_pin_upgrade(&current)
+                {
+                    return Ok(None);
+                }
+                self.encryption_service
+                    .encrypt_wallet_secret(secret, pin, wallet_id)
+                    .await
+                    .map(|upgraded| Some(upgraded.with_user_id(&encrypted_data.user_id)))
+            })
+            .await;
+
+        match upgraded {
+            Ok(Some(upgraded)) => info!(
+                "Upgraded PIN KDF for wallet {} to {}",
+                wallet_id, upgraded.pin_kdf
+            ),
+            Ok(None) => {}
+            Err(e) => warn!("Failed to upgrade PIN KDF for wallet {}: {}", wallet_id, e),
+        }
+    }
+
+    /// Counters of the in-memory DEK cache
+    pub async fn get_dek_cache_stats(&self) -> DekCacheStatsInfo {
+        let stats = self.encryption_service.dek_cache_stats().await;
+
+        DekCacheStatsInfo {
+            hits: stats.hits,
+            misses: stats.misses,
+            evictions: stats.evictions,
+            expirations: stats.expirations,
+            size: stats.size,
+            capacity: stats.capacity,
+        }
+    }
+
+    /// Drop every plaintext DEK held in memory, returning how many were dropped
+    pub async fn purge_dek_cache(&self) -> usize {
+        let purged = self.encryption_service.purge_dek_cache().await;
+        info!("Purged {} cached DEKs", purged);
+        purged
+    }
 }
@@ -1,61 +1,210 @@
+mod accounts;
+mod chain;
+mod confirmations;
+mod history;
+mod idempotency;
+mod import;
 mod keys;
-
+mod nonces;
+mod ownership;
+mod pin_lockout;
+mod quotes;
+mod rotation;
+
+use app_config::{
+    ChainConfig, IdempotencyConfig, KeyRotationConfig, KeystoreExportConfig, PinLockoutConfig,
+    TokenConfig,
+};
 use app_database::service::DbService;
 use app_error::{AppError, AppResult};
-use app_models::WalletKey;
+use app_middleware::RedisPinRateLimiter;
 use app_models::user::User;
-use app_models::wallet::{Wallet, WalletInfo};
+use app_models::wallet::{Wallet, WalletAccount, WalletAccountInfo, WalletInfo};
+use app_models::{Amount, KeyRotationJob, TransferQuote, WalletKey, WalletTransaction};
+use app_utils::chain::{BlockTag, ChainClient, MemoryNonceStore, NonceManager, is_nonce_too_low};
 use app_utils::crypto::WalletEncryptionService;
 use app_utils::generate::EthereumWallet;
+use app_utils::idempotency::{IdempotencyStore, MemoryIdempotencyStore};
+use app_utils::keystore::encrypt_keystore;
+use app_utils::secret::{MnemonicPhrase, Pin, SecretString, WalletSecret};
+use app_utils::transaction::parse_address;
 use async_trait::async_trait;
+use serde_json::json;
 use std::sync::Arc;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
+
+pub use history::TransactionFilter;
+pub use import::WalletImportSource;
+pub use keys::LegacyKeyMigration;
+pub use quotes::QuoteChoice;
+
+/// Shortest password accepted for an exported keystore file
+const MIN_KEYSTORE_PASSWORD_LENGTH: usize = 8;
+
+/// Most wallets a single user may hold
+const MAX_WALLETS_PER_USER: usize = 20;
+
+/// Longest label a user can give a wallet or an account
+const MAX_LABEL_LENGTH: usize = 64;
+
+/// How recently the caller must have signed in to reset a PIN with the recovery phrase
+const PIN_RESET_MAX_AUTH_AGE_SECS: i64 = 300;
+
+/// A transfer as the caller asked for it
+#[derive(Debug, Clone)]
+pub struct TransferRequest {
+    pub wallet_id: String,
+    pub account_index: u32,
+    pub to_address: String,
+    /// Symbol or contract address of a token; the native coin if `None`
+    pub token: Option<String>,
+    pub amount: Amount,
+}
+
+/// Trim a user-chosen label, treating a blank one as no label
+pub(crate) fn normalize_label(label: Option<String>) -> AppResult<Option<String>> {
+    let label = label
+        .map(|label| label.trim().to_string())
+        .filter(|label| !label.is_empty());
+
+    if label
+        .as_ref()
+        .is_some_and(|label| label.chars().count() > MAX_LABEL_LENGTH)
+    {
+        return Err(AppError::validation(
+            "label",
+            &format!("must be at most {} characters", MAX_LABEL_LENGTH),
+        ));
+    }
+
+    Ok(label)
+}
 
 /// Trait defining the wallet service interface
 #[async_trait]
 pub trait WalletServiceTrait: Send + Sync {
-    /// Create a new wallet for a user with PIN
-    async fn create_wallet(&self, user_email: &str, pin: &str) -> AppResult<WalletInfo>;
+    /// Create a new wallet for a user with PIN, returning its recovery phrase once.
+    /// The user's first wallet becomes their default.
+    async fn create_wallet(
+        &self,
+        user_id: &str,
+        label: Option<String>,
+        pin: &Pin,
+    ) -> AppResult<(WalletInfo, MnemonicPhrase)>;
+
+    /// Import an existing wallet for a user and protect it with a PIN
+    async fn import_wallet(
+        &self,
+        user_id: &str,
+        label: Option<String>,
+        source: WalletImportSource,
+        pin: &Pin,
+    ) -> AppResult<WalletInfo>;
 
-    /// Get a wallet by user email
-    async fn get_wallet_by_user_email(&self, user_email: &str) -> AppResult<WalletInfo>;
+    /// Get all wallets of a user, oldest first
+    async fn get_user_wallets(&self, user_id: &str) -> AppResult<Vec<WalletInfo>>;
+
+    /// Get a user's wallet by ID, or their default wallet if no ID is given
+    async fn get_user_wallet(
+        &self,
+        user_id: &str,
+        wallet_id: Option<&str>,
+    ) -> AppResult<WalletInfo>;
 
     /// Get a wallet by ID
     async fn get_wallet_by_id(&self, wallet_id: &str) -> AppResult<WalletInfo>;
 
-    /// Transfer funds from a wallet (requires PIN)
+    /// Make one of a user's wallets their default
+    async fn set_default_wallet(&self, user_id: &str, wallet_id: &str) -> AppResult<WalletInfo>;
+
+    /// Change the label of a user's wallet
+    async fn rename_wallet(
+        &self,
+        user_id: &str,
+        wallet_id: &str,
+        label: Option<String>,
+    ) -> AppResult<WalletInfo>;
+
+    /// Transfer the native coin, or a token given by symbol or contract address, from one
+    /// account of a wallet (requires PIN)
     async fn transfer(
         &self,
         from_wallet_id: &str,
+        account_index: u32,
         to_address: &str,
-        amount: f64,
-        pin: &str,
+        token: Option<&str>,
+        amount: Amount,
+        pin: &Pin,
     ) -> AppResult<String>;
 
-    /// Get wallet balance
-    async fn get_balance(&self, wallet_id: &str) -> AppResult<f64>;
+    /// Get a wallet's balance of the native coin or of a token
+    async fn get_balance(&self, wallet_id: &str, token: Option<&str>) -> AppResult<Amount>;
 
-    /// Associate a wallet with a user
+    /// Record a wallet as the user's default on their user record
     async fn associate_wallet_with_user(&self, user_id: &str, wallet_id: &str) -> AppResult<()>;
 
     /// Change wallet PIN
     async fn change_wallet_pin(
         &self,
         wallet_id: &str,
-        old_pin: &str,
-        new_pin: &str,
+        old_pin: &Pin,
+        new_pin: &Pin,
     ) -> AppResult<()>;
 
+    /// Set a new wallet PIN using the recovery phrase, or the key material the wallet was
+    /// imported from, instead of the old PIN. HD wallets can only be recovered from a
+    /// phrase. `authenticated_at` is when the caller signed in (Unix timestamp)
+    async fn reset_wallet_pin(
+        &self,
+        wallet_id: &str,
+        recovery: WalletImportSource,
+        new_pin: &Pin,
+        authenticated_at: i64,
+    ) -> AppResult<()>;
+
+    /// Derive the next account of an HD wallet
+    async fn create_wallet_account(
+        &self,
+        wallet_id: &str,
+        pin: &Pin,
+        label: Option<String>,
+    ) -> AppResult<WalletAccountInfo>;
+
+    /// Export the private key of one account as a Keystore V3 JSON file encrypted with
+    /// `password`
+    async fn export_wallet_keystore(
+        &self,
+        wallet_id: &str,
+        account_index: u32,
+        pin: &Pin,
+        password: &SecretString,
+    ) -> AppResult<String>;
+
     /// Verify wallet PIN
-    async fn verify_pin(&self, wallet_id: &str, pin: &str) -> AppResult<bool>;
+    async fn verify_pin(&self, wallet_id: &str, pin: &Pin) -> AppResult<bool>;
 }
 
 /// Implementation of the wallet service
 pub struct WalletService {
     wallet_db: Option<Arc<DbService<'static, Wallet>>>,
     wallet_key_db: Option<Arc<DbService<'static, WalletKey>>>, // New field for wallet keys
+    wallet_account_db: Option<Arc<DbService<'static, WalletAccount>>>,
     pub user_db: Option<Arc<DbService<'static, User>>>,
+    key_rotation_job_db: Option<Arc<DbService<'static, KeyRotationJob>>>,
+    transaction_db: Option<Arc<DbService<'static, WalletTransaction>>>,
+    quote_db: Option<Arc<DbService<'static, TransferQuote>>>,
     encryption_service: Arc<WalletEncryptionService>,
+    key_rotation_config: KeyRotationConfig,
+    pin_rate_limiter: Option<Arc<RedisPinRateLimiter>>,
+    pin_lockout_config: PinLockoutConfig,
+    keystore_export_config: KeystoreExportConfig,
+    admin_user_ids: Vec<String>,
+    chain_client: Option<Arc<dyn ChainClient>>,
+    chain_config: ChainConfig,
+    tokens: Vec<TokenConfig>,
+    nonce_manager: Arc<NonceManager>,
+    idempotency_store: Arc<dyn IdempotencyStore>,
+    idempotency_config: IdempotencyConfig,
 }
 
 impl WalletService {
@@ -64,24 +213,29 @@ impl WalletService {
         Self {
             wallet_db: None,
             wallet_key_db: None, // Initialize as None
+            wallet_account_db: None,
             user_db: None,
+            key_rotation_job_db: None,
+            transaction_db: None,
+            quote_db: None,
             encryption_service,
+            key_rotation_config: KeyRotationConfig::default(),
+            pin_rate_limiter: None,
+            pin_lockout_config: PinLockoutConfig::default(),
+            keystore_export_config: KeystoreExportConfig::default(),
+            admin_user_ids: Vec::new(),
+            chain_client: None,
+            chain_config: ChainConfig::default(),
+            tokens: Vec::new(),
+            nonce_manager: Arc::new(NonceManager::new(
+                Arc::new(MemoryNonceStore::default()),
+                ChainConfig::default().chain_id,
+            )),
+            idempotency_store: Arc::new(MemoryIdempotencyStore::default()),
+            idempotency_config: IdempotencyConfig::default(),
         }
     }
 
-    async fn get_private_key(&self, wallet_id: &str, pin: &str) -> AppResult<String> {
-        // Validate PIN format
-        Self::validate_pin(pin)?;
-
-        // Get the encrypted data
-        let encrypted_data = self.get_wallet_encrypted_data(wallet_id).await?;
-
-        // Decrypt the private key
-        self.encryption_service
-            .decrypt_private_key(&encrypted_data, pin)
-            .await
-    }
-
     /// Add a wallet database service
     pub fn with_wallet_db(mut self, wallet_db: Arc<DbService<'static, Wallet>>) -> Self {
         self.wallet_db = Some(wallet_db);
@@ -100,104 +254,94 @@ impl WalletService {
         self
     }
 
-    /// Helper method to validate user exists
-    async fn validate_user_exists(&self, user_email: &str) -> AppResult<User> {
-        if let Some(user_db) = &self.user_db {
-            let users = user_db
-                .get_records_by_field("email", user_email.to_string())
-                .await
-                .map_err(|e| {
-                    error!("Database error when checking for user: {}", e);
-                    AppError::DatabaseError(anyhow::anyhow!(e))
-                })?;
-
-            if users.is_empty() {
-                return Err(AppError::NotFoundError(format!(
-                    "User with email '{}' not found",
-                    user_email
-                )));
-            }
-
-            Ok(users[0].clone())
-        } else {
-            Err(AppError::ServerError(anyhow::anyhow!(
-                "User database not available"
-            )))
-        }
+    /// Add a key rotation job database service
+    pub fn with_key_rotation_job_db(
+        mut self,
+        key_rotation_job_db: Arc<DbService<'static, KeyRotationJob>>,
+    ) -> Self {
+        self.key_rotation_job_db = Some(key_rotation_job_db);
+        self
     }
 
-    /// Helper method to check if wallet already exists for a user
-    async fn check_wallet_exists(&self, user_email: &str) -> AppResult<Option<Wallet>> {
-        if let Some(wallet_db) = &self.wallet_db {
-            let wallets = wallet_db
-                .get_records_by_field("user_email", user_email.to_string())
-                .await
-                .map_err(|e| {
-                    error!("Database error when checking for existing wallet: {}", e);
-                    AppError::DatabaseError(anyhow::anyhow!(e))
-                })?;
+    /// Set the batch size and concurrency used by master key rotation jobs
+    pub fn with_key_rotation_config(mut self, key_rotation_config: KeyRotationConfig) -> Self {
+        self.key_rotation_config = key_rotation_config;
+        self
+    }
 
-            if !wallets.is_empty() {
-                return Ok(Some(wallets[0].clone()));
-            }
-        } else {
-            return Err(AppError::ServerError(anyhow::anyhow!(
-                "Wallet database not available"
-            )));
-        }
+    /// Set whether and how private keys may be exported as keystore files
+    pub fn with_keystore_export_config(
+        mut self,
+        keystore_export_config: KeystoreExportConfig,
+    ) -> Self {
+        self.keystore_export_config = keystore_export_config;
+        self
+    }
 
-        Ok(None)
+    /// Set the user IDs allowed to run admin operations
+    pub fn with_admin_user_ids(mut self, admin_user_ids: Vec<String>) -> Self {
+        self.admin_user_ids = admin_user_ids;
+        self
     }
 
-    /// Helper method to validate PIN format
-    fn validate_pin(pin: &str) -> AppResult<()> {
-        if pin.len() != 6 || !pin.chars().all(|c| c.is_digit(10)) {
-            return Err(AppError::ValidationError(
-                "PIN must be a 6-digit number".to_string(),
-            ));
+    /// Check that a user (by the ID in their JWT claims) may run admin operations
+    pub fn require_admin(&self, user_id: &str) -> AppResult<()> {
+        if self.admin_user_ids.iter().any(|id| id == user_id) {
+            Ok(())
+        } else {
+            Err(AppError::AuthorizationError(
+                "Admin privileges are required for this operation".to_string(),
+            ))
         }
-        Ok(())
     }
-}
 
-#[async_trait]
-impl WalletServiceTrait for WalletService {
-    async fn create_wallet(&self, user_email: &str, pin: &str) -> AppResult<WalletInfo> {
-        // Validate PIN format
-        Self::validate_pin(pin)?;
+    /// Fail if the user already holds as many wallets as allowed, otherwise return
+    /// whether the next wallet will be their first
+    async fn check_wallet_limit(&self, user_id: &str) -> AppResult<bool> {
+        let wallets = self.find_user_wallets(user_id).await?;
 
-        // Validate user exists
-        let user = self.validate_user_exists(user_email).await?;
-
-        // Check if wallet already exists
-        if let Some(existing_wallet) = self.check_wallet_exists(user_email).await? {
-            return Ok(WalletInfo::from(existing_wallet));
+        if wallets.len() >= MAX_WALLETS_PER_USER {
+            return Err(AppError::ValidationError(format!(
+                "A user can hold at most {} wallets",
+                MAX_WALLETS_PER_USER
+            )));
         }
 
-        // Generate new Ethereum wallet
-        let eth_wallet = EthereumWallet::new();
-
-        // Extract wallet data
-        let address = eth_wallet.address().to_string();
-        let private_key = eth_wallet.private_key_hex();
+        Ok(wallets.is_empty())
+    }
 
-        // Encrypt private key with PIN and system encryption
+    /// Encrypt a wallet's seed or private key with the PIN and store the wallet and its
+    /// first account for a user
+    async fn store_new_wallet(
+        &self,
+        user: &User,
+        label: Option<String>,
+        eth_wallet: &EthereumWallet,
+        pin: &Pin,
+    ) -> AppResult<WalletInfo> {
+        let user_id = user.id.id.to_raw();
+        let is_first = self.check_wallet_limit(&user_id).await?;
+
+        // Create new wallet record (without private key); a user's first wallet is their default
+        let wallet = Wallet::new(user_id.clone(), eth_wallet.address().to_string())
+            .with_label(label)
+            .with_default(is_first)
+            .with_hd(eth_wallet.is_hd());
+
+        // Encrypt seed or key with PIN and system encryption, bound to the new wallet's ID
         let encrypted_data = self
             .encryption_service
-            .encrypt_private_key(&private_key, pin)
+            .encrypt_wallet_secret(&eth_wallet.secret(), pin, &wallet.id.id.to_raw())
             .await?;
 
-        // Create new wallet record (without private key)
-        let wallet = Wallet::new(user_email.to_string(), address.clone());
-
         // Store wallet if database is available
         if let Some(wallet_db) = &self.wallet_db {
-            info!("Creating new wallet for user: {}", user_email);
+            info!("Creating new wallet for user: {}", user_id);
 
             match wallet_db.create_record(wallet.clone()).await {
                 Ok(Some(stored)) => {
                     // Get the wallet ID
-                    let wallet_id = stored.id.id.to_string();
+                    let wallet_id = stored.id.id.to_raw();
 
                     // Store the encrypted key separately
                     match self.store_wallet_key(&wallet_id, &encrypted_data).await {
@@ -213,19 +357,23 @@ impl WalletServiceTrait for WalletService {
                                 .await
                             {
                                 Ok(_) => {
-                                    // Associate wallet with user
-                                    if let Some(user_db) = &self.user_db {
+                                    // Record the default wallet on the user
+                                    if let (true, Some(user_db)) = (is_first, &self.user_db) {
                                         let mut updated_user = user.clone();
                                         updated_user.wallet_id = Some(wallet_id.clone());
                                         updated_user.updated_at = chrono::Utc::now();
 
                                         // Update user record with wallet reference
                                         let _ = user_db
-                                            .update_record(&user.id.id.to_string(), updated_user)
+                                            .update_record(&user.id.id.to_raw(), updated_user)
                                             .await;
                                     }
 
-                                    Ok(WalletInfo::from(updated_wallet))
+                                    // Record the first account
+                                    self.store_wallet_account(&wallet_id, eth_wallet, None)
+                                        .await?;
+
+                                    self.wallet_info(updated_wallet).await
                                 }
                                 Err(e) => {
                                     error!("Failed to update wallet with key ID: {}", e);
@@ -254,130 +402,278 @@ impl WalletServiceTrait for WalletService {
         }
     }
 
-    async fn get_wallet_by_user_email(&self, user_email: &str) -> AppResult<WalletInfo> {
-        // Check if wallet exists
-        match self.check_wallet_exists(user_email).await? {
-            Some(wallet) => Ok(WalletInfo::from(wallet)),
-            None => Err(AppError::NotFoundError(format!(
-                "Wallet not found for user: {}",
-                user_email
-            ))),
-        }
-    }
-
-    async fn get_wallet_by_id(&self, wallet_id: &str) -> AppResult<WalletInfo> {
-        if let Some(wallet_db) = &self.wallet_db {
-            let wallet = wallet_db
-                .get_record_by_id(wallet_id)
-                .await
-                .map_err(|e| {
-                    error!("Database error when fetching wallet by ID: {}", e);
-                    AppError::DatabaseError(anyhow::anyhow!(e))
-                })?
-                .ok_or_else(|| {
-                    AppError::NotFoundError(format!("Wallet with ID '{}' not found", wallet_id))
-                })?;
-
-            Ok(WalletInfo::from(wallet))
-        } else {
-            Err(AppError::ServerError(anyhow::anyhow!(
-                "Wallet database not available"
-            )))
-        }
+    /// Sign, record and broadcast a transfer, returning its outgoing record. With a quote,
+    /// the transfer is sent with the quoted gas limit and the fees of the chosen speed.
+    pub async fn send_transfer(
+        &self,
+        request: &TransferRequest,
+        quote: Option<&QuoteChoice>,
+        pin: &Pin,
+    ) -> AppResult<WalletTransaction> {
+        self.send_keyed_transfer(request, quote, pin, None).await
     }
 
-    async fn transfer(
+    /// Send a transfer as `send_transfer` does, recording it under an idempotency key.
+    /// Recording fails if another transfer holds the key, before anything is broadcast.
+    pub(crate) async fn send_keyed_transfer(
         &self,
-        from_wallet_id: &str,
-        to_address: &str,
-        amount: f64,
-        pin: &str,
-    ) -> AppResult<String> {
+        request: &TransferRequest,
+        quote: Option<&QuoteChoice>,
+        pin: &Pin,
+        idempotency_key: Option<&str>,
+    ) -> AppResult<WalletTransaction> {
+        let from_wallet_id = request.wallet_id.as_str();
+        let account_index = request.account_index;
+        let to_address = request.to_address.as_str();
+
         // Validate PIN format
         Self::validate_pin(pin)?;
 
         // Validate amount
-        if amount <= 0.0 {
+        if request.amount.is_zero() {
             return Err(AppError::ValidationError(
                 "Amount must be greater than 0".to_string(),
             ));
         }
+        let to = parse_address(to_address)?;
+        let asset = self.resolve_asset(request.token.as_deref())?;
+        let amount = request.amount.to_decimals(asset.decimals)?;
 
         // Get source wallet
-        if let Some(wallet_db) = &self.wallet_db {
-            let wallet = wallet_db
-                .get_record_by_id(from_wallet_id)
-                .await
-                .map_err(|e| {
-                    error!("Database error when fetching wallet for transfer: {}", e);
-                    AppError::DatabaseError(anyhow::anyhow!(e))
-                })?
-                .ok_or_else(|| {
-                    AppError::NotFoundError(format!(
-                        "Wallet with ID '{}' not found",
-                        from_wallet_id
-                    ))
-                })?;
+        let wallet = self.fetch_wallet(from_wallet_id).await?;
+
+        // The quote must be for this very transfer and still valid
+        let plan = match quote {
+            Some(quote) => Some(
+                self.quoted_gas_plan(quote, request, &asset, to, amount)
+                    .await?,
+            ),
+            None => None,
+        };
+
+        // Verify the PIN is correct before proceeding with transfer
+        let Some(secret) = self.unlock_wallet_secret(from_wallet_id, pin).await? else {
+            return Err(AppError::AuthenticationError(
+                "Invalid PIN. Transfer canceled for security reasons.".to_string(),
+            ));
+        };
 
-            // Placeholder for balance check
-            // In production, you would check the actual blockchain balance
-            let balance = 10.0; // Placeholder balance
-            if amount > balance {
-                return Err(AppError::ValidationError("Insufficient funds".to_string()));
-            }
+        // Derive the sending account's key for transaction signing
+        let signer = self
+            .get_account_signer(from_wallet_id, account_index, secret)
+            .await?;
 
-            // Verify the PIN is correct before proceeding with transfer
-            let is_pin_valid = self.verify_pin(from_wallet_id, pin).await?;
-            if !is_pin_valid {
-                return Err(AppError::AuthenticationError(
-                    "Invalid PIN. Transfer canceled for security reasons.".to_string(),
-                ));
+        // Sign with the nonce and fees the node expects and record the transfer before it
+        // can reach the chain
+        let from = parse_address(signer.address())?;
+        let mut resynced = false;
+        let outgoing = loop {
+            let signed = self
+                .sign_transfer(&signer, &asset, to, amount.base_units(), plan)
+                .await?;
+            let nonce = signed.transaction().nonce();
+            let outgoing = match self
+                .record_transfer(
+                    from_wallet_id,
+                    account_index,
+                    &asset,
+                    to,
+                    amount,
+                    &signed,
+                    None,
+                    idempotency_key,
+                )
+                .await
+            {
+                Ok(outgoing) => outgoing,
+                Err(e) => {
+                    self.release_nonce(from, nonce).await;
+                    return Err(e);
+                }
+            };
+
+            match self.submit_transaction(&signed).await {
+                Ok(()) => break outgoing,
+                // The account sent transactions the nonce manager did not hand out, so
+                // count from the node again and sign once more
+                Err(e) if is_nonce_too_low(&e) && !resynced => {
+                    warn!(
+                        "Nonce {} of {} was already used: {}",
+                        nonce,
+                        signer.address(),
+                        e
+                    );
+                    self.nonce_manager.resync(from).await?;
+                    // The rejected attempt never reached the chain, so its key goes to
+                    // the next one
+                    if idempotency_key.is_some() {
+                        self.release_idempotency_key(&signed.hash_hex()).await?;
+                    }
+                    resynced = true;
+                }
+                Err(e) => {
+                    // A rejected transaction never used its nonce
+                    if matches!(e, AppError::ChainError(_)) {
+                        self.release_nonce(from, nonce).await;
+                    }
+                    return Err(e);
+                }
             }
+        };
+
+        info!(
+            "Transfer of {} {} from {} (account {} of wallet {}) to {} submitted as {}",
+            amount,
+            asset.symbol,
+            signer.address(),
+            account_index,
+            wallet.address,
+            to_address,
+            outgoing.hash
+        );
+
+        Ok(outgoing)
+    }
 
-            // Get the private key for transaction signing
-            let _private_key = self.get_private_key(from_wallet_id, pin).await?;
+    /// Helper method to validate PIN format
+    fn validate_pin(pin: &Pin) -> AppResult<()> {
+        let pin = pin.expose_secret();
+        if pin.len() != 6 || !pin.chars().all(|c| c.is_digit(10)) {
+            return Err(AppError::ValidationError(
+                "PIN must be a 6-digit number".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
 
-            // This is where you would use the private key to sign and broadcast the transaction
-            debug!("Successfully decrypted private key for transaction signing");
+#[async_trait]
+impl WalletServiceTrait for WalletService {
+    async fn create_wallet(
+        &self,
+        user_id: &str,
+        label: Option<String>,
+        pin: &Pin,
+    ) -> AppResult<(WalletInfo, MnemonicPhrase)> {
+        // Validate PIN format and label
+        Self::validate_pin(pin)?;
+        let label = normalize_label(label)?;
 
-            // For now, just return a placeholder transaction hash
-            let transaction_hash = format!("0x{}", hex::encode(uuid::Uuid::new_v4().as_bytes()));
+        // Validate user exists
+        let user = self.get_user_by_id(user_id).await?;
 
-            info!(
-                "Transfer of {} from {} to {} initiated",
-                amount, wallet.address, to_address
-            );
+        // Generate new Ethereum wallet
+        let eth_wallet = EthereumWallet::new();
+        let recovery_phrase = eth_wallet.mnemonic_phrase().ok_or_else(|| {
+            AppError::ServerError(anyhow::anyhow!("Generated wallet has no mnemonic"))
+        })?;
 
-            // In a real implementation, you would monitor the transaction status
-            // and update the database accordingly
+        // The phrase is handed to the user once and never stored
+        let wallet_info = self
+            .store_new_wallet(&user, label, &eth_wallet, pin)
+            .await?;
+        Ok((wallet_info, recovery_phrase))
+    }
 
-            Ok(transaction_hash)
-        } else {
-            Err(AppError::ServerError(anyhow::anyhow!(
-                "Wallet database not available"
-            )))
+    async fn import_wallet(
+        &self,
+        user_id: &str,
+        label: Option<String>,
+        source: WalletImportSource,
+        pin: &Pin,
+    ) -> AppResult<WalletInfo> {
+        // Validate PIN format and label
+        Self::validate_pin(pin)?;
+        let label = normalize_label(label)?;
+
+        // Validate user exists
+        let user = self.get_user_by_id(user_id).await?;
+
+        // Derive the key and address from the import source
+        let eth_wallet = Self::restore_imported_wallet(source).await?;
+
+        // The same address must not be held by two wallets
+        self.ensure_address_not_imported(eth_wallet.address())
+            .await?;
+
+        info!(
+            "Importing wallet {} for user: {}",
+            eth_wallet.address(),
+            user_id
+        );
+        self.store_new_wallet(&user, label, &eth_wallet, pin).await
+    }
+
+    async fn get_user_wallets(&self, user_id: &str) -> AppResult<Vec<WalletInfo>> {
+        let mut wallets = Vec::new();
+        for wallet in self.find_user_wallets(user_id).await? {
+            wallets.push(self.wallet_info(wallet).await?);
+        }
+        Ok(wallets)
+    }
+
+    async fn get_user_wallet(
+        &self,
+        user_id: &str,
+        wallet_id: Option<&str>,
+    ) -> AppResult<WalletInfo> {
+        let wallet = match wallet_id {
+            Some(wallet_id) => self.fetch_user_wallet(user_id, wallet_id).await?,
+            None => self.find_default_wallet(user_id).await?.ok_or_else(|| {
+                AppError::NotFoundError(format!("Wallet not found for user: {}", user_id))
+            })?,
+        };
+
+        self.wallet_info(wallet).await
+    }
+
+    async fn set_default_wallet(&self, user_id: &str, wallet_id: &str) -> AppResult<WalletInfo> {
+        let wallet = self.fetch_user_wallet(user_id, wallet_id).await?;
+
+        // Clear the flag on the previous default before setting it on the new one
+        for other in self.find_user_wallets(user_id).await? {
+            if other.is_default && other.id != wallet.id {
+                self.merge_wallet(&other.id.id.to_raw(), json!({ "is_default": false }))
+                    .await?;
+            }
         }
+        let wallet = self
+            .merge_wallet(wallet_id, json!({ "is_default": true }))
+            .await?;
+
+        self.associate_wallet_with_user(user_id, wallet_id).await?;
+        self.wallet_info(wallet).await
+    }
+
+    async fn rename_wallet(
+        &self,
+        user_id: &str,
+        wallet_id: &str,
+        label: Option<String>,
+    ) -> AppResult<WalletInfo> {
+        let label = normalize_label(label)?;
+        self.fetch_user_wallet(user_id, wallet_id).await?;
+
+        let wallet = self
+            .merge_wallet(wallet_id, json!({ "label": label }))
+            .await?;
+        self.wallet_info(wallet).await
     }
 
-    async fn get_balance(&self, wallet_id: &str) -> AppResult<f64> {
-        // Get wallet
+    async fn get_wallet_by_id(&self, wallet_id: &str) -> AppResult<WalletInfo> {
         if let Some(wallet_db) = &self.wallet_db {
             let wallet = wallet_db
                 .get_record_by_id(wallet_id)
                 .await
                 .map_err(|e| {
-                    error!("Database error when fetching wallet for balance: {}", e);
+                    error!("Database error when fetching wallet by ID: {}", e);
                     AppError::DatabaseError(anyhow::anyhow!(e))
                 })?
                 .ok_or_else(|| {
                     AppError::NotFoundError(format!("Wallet with ID '{}' not found", wallet_id))
                 })?;
 
-            debug!("Getting balance for wallet address: {}", wallet.address);
-
-            // In a real implementation, you would fetch the actual balance from the blockchain
-            // For now, return a placeholder value
-            Ok(10.0)
+            self.wallet_info(wallet).await
         } else {
             Err(AppError::ServerError(anyhow::anyhow!(
                 "Wallet database not available"
@@ -385,24 +681,58 @@ impl WalletServiceTrait for WalletService {
         }
     }
 
+    async fn transfer(
+        &self,
+        from_wallet_id: &str,
+        account_index: u32,
+        to_address: &str,
+        token: Option<&str>,
+        amount: Amount,
+        pin: &Pin,
+    ) -> AppResult<String> {
+        let outgoing = self
+            .send_transfer(
+                &TransferRequest {
+                    wallet_id: from_wallet_id.to_string(),
+                    account_index,
+                    to_address: to_address.to_string(),
+                    token: token.map(str::to_string),
+                    amount,
+                },
+                None,
+                pin,
+            )
+            .await?;
+        Ok(outgoing.hash)
+    }
+
+    async fn get_balance(&self, wallet_id: &str, token: Option<&str>) -> AppResult<Amount> {
+        let asset = self.resolve_asset(token)?;
+        let wallet = self.fetch_wallet(wallet_id).await?;
+        debug!(
+            "Getting {} balance for wallet address: {}",
+            asset.symbol, wallet.address
+        );
+
+        let address = parse_address(&wallet.address)?;
+        let balance = self
+            .asset_balance(address, &asset, BlockTag::Latest)
+            .await?;
+        Ok(Amount::new(balance, asset.decimals))
+    }
+
     async fn associate_wallet_with_user(&self, user_id: &str, wallet_id: &str) -> AppResult<()> {
         if let Some(user_db) = &self.user_db {
-            // Clean the user ID (remove surrounding angle brackets if present)
-            let clean_id = user_id
-                .trim_start_matches('⟨')
-                .trim_end_matches('⟩')
-                .to_string();
-
             // Get the user
             let mut user = user_db
-                .get_record_by_id(&clean_id)
+                .get_record_by_id(user_id)
                 .await
                 .map_err(|e| {
                     error!("Database error when fetching user: {}", e);
                     AppError::DatabaseError(anyhow::anyhow!(e))
                 })?
                 .ok_or_else(|| {
-                    AppError::NotFoundError(format!("User with ID '{}' not found", clean_id))
+                    AppError::NotFoundError(format!("User with ID '{}' not found", user_id))
                 })?;
 
             // Update the wallet_id field
@@ -410,12 +740,12 @@ impl WalletServiceTrait for WalletService {
             user.updated_at = chrono::Utc::now();
 
             // Save the updated user
-            user_db.update_record(&clean_id, user).await.map_err(|e| {
+            user_db.update_record(user_id, user).await.map_err(|e| {
                 error!("Failed to update user with wallet ID: {}", e);
                 AppError::DatabaseError(anyhow::anyhow!(e))
             })?;
 
-            info!("Associated wallet {} with user {}", wallet_id, clean_id);
+            info!("Associated wallet {} with user {}", wallet_id, user_id);
             Ok(())
         } else {
             Err(AppError::ServerError(anyhow::anyhow!(
@@ -424,54 +754,207 @@ impl WalletServiceTrait for WalletService {
         }
     }
 
-    async fn verify_pin(&self, wallet_id: &str, pin: &str) -> AppResult<bool> {
-        // Validate PIN format
-        Self::validate_pin(pin)?;
-
-        // Get the encrypted data
-        match self.get_wallet_encrypted_data(wallet_id).await {
-            Ok(encrypted_data) => {
-                // Try to decrypt with PIN - we don't need the result, just whether it succeeds
-                match self
-                    .encryption_service
-                    .decrypt_private_key(&encrypted_data, pin)
-                    .await
-                {
-                    Ok(_) => Ok(true),
-                    Err(_) => Ok(false),
-                }
-            }
-            Err(e) => {
-                // If it's just that the key doesn't exist, return false
-                if let AppError::NotFoundError(_) = e {
-                    return Ok(false);
-                }
-                // Otherwise propagate the error
-                Err(e)
-            }
-        }
+    async fn verify_pin(&self, wallet_id: &str, pin: &Pin) -> AppResult<bool> {
+        Ok(self.unlock_wallet_secret(wallet_id, pin).await?.is_some())
     }
 
     async fn change_wallet_pin(
         &self,
         wallet_id: &str,
-        old_pin: &str,
-        new_pin: &str,
+        old_pin: &Pin,
+        new_pin: &Pin,
     ) -> AppResult<()> {
         // Validate both PINs
         Self::validate_pin(old_pin)?;
         Self::validate_pin(new_pin)?;
 
-        // Get private key using old PIN
-        let private_key = self.get_private_key(wallet_id, old_pin).await?;
+        // Check the old PIN against the attempt limits
+        if self
+            .unlock_wallet_secret(wallet_id, old_pin)
+            .await?
+            .is_none()
+        {
+            return Err(AppError::AuthenticationError(
+                "Current PIN is incorrect. PIN change canceled for security reasons.".to_string(),
+            ));
+        }
 
-        // Re-encrypt with new PIN
-        let new_encrypted_data = self
-            .encryption_service
-            .encrypt_private_key(&private_key, new_pin)
-            .await?;
+        // Re-encrypt with the new PIN whatever is stored by then, which still has to open
+        // with the old PIN in case another request changed it in the meantime
+        self.update_wallet_key(wallet_id, |current| async move {
+            let secret = match self
+                .encryption_service
+                .decrypt_wallet_secret(&current, old_pin)
+                .await
+            {
+                Ok(secret) => secret,
+                Err(AppError::AuthenticationError(_)) => {
+                    return Err(AppError::AuthenticationError(
+                        "The wallet PIN was changed by another request. PIN change canceled for security reasons.".to_string(),
+                    ));
+                }
+                Err(e) => return Err(e),
+            };
+            self.encryption_service
+                .encrypt_wallet_secret(&secret, new_pin, wallet_id)
+                .await
+                .map(Some)
+        })
+        .await?;
+        Ok(())
+    }
+
+    async fn reset_wallet_pin(
+        &self,
+        wallet_id: &str,
+        recovery: WalletImportSource,
+        new_pin: &Pin,
+        authenticated_at: i64,
+    ) -> AppResult<()> {
+        // Validate the new PIN
+        Self::validate_pin(new_pin)?;
+
+        // Only a fresh sign-in may replace the PIN without knowing it
+        if chrono::Utc::now().timestamp() - authenticated_at > PIN_RESET_MAX_AUTH_AGE_SECS {
+            return Err(AppError::AuthenticationError(
+                "Please sign in again before resetting your wallet PIN".to_string(),
+            ));
+        }
+
+        // Re-derive the private key from the phrase or key material and check it belongs
+        // to this wallet
+        let wallet = self.fetch_wallet(wallet_id).await?;
+        let restored = Self::restore_imported_wallet(recovery).await?;
+        if restored.address() != wallet.address {
+            warn!("Recovery input for wallet {} did not match", wallet_id);
+            return Err(AppError::AuthenticationError(
+                "Recovery phrase or key does not belong to this wallet".to_string(),
+            ));
+        }
+
+        // The seed of an HD wallet derives its other accounts, so a single key cannot
+        // stand in for it
+        if wallet.hd && !restored.is_hd() {
+            return Err(AppError::ValidationError(
+                "An HD wallet can only be recovered with its recovery phrase on the default derivation path".to_string(),
+            ));
+        }
 
-        // Update the wallet key
-        self.update_wallet_key(wallet_id, &new_encrypted_data).await
+        // Re-encrypt with the new PIN. A phrase restores the seed, so a wallet that held
+        // only its first key becomes an HD wallet; a key or keystore stores that key again
+        let secret = &restored.secret();
+        self.update_wallet_key(wallet_id, |_| async move {
+            self.encryption_service
+                .encrypt_wallet_secret(secret, new_pin, wallet_id)
+                .await
+                .map(Some)
+        })
+        .await?;
+        if !wallet.hd && restored.is_hd() {
+            self.mark_wallet_hd(wallet_id).await?;
+        }
+
+        // Knowing the phrase proves ownership, so any PIN lockout no longer applies
+        self.unlock_wallet_pin(wallet_id).await?;
+
+        info!(
+            "Reset PIN of wallet {} using its recovery phrase or key",
+            wallet_id
+        );
+        Ok(())
+    }
+
+    async fn create_wallet_account(
+        &self,
+        wallet_id: &str,
+        pin: &Pin,
+        label: Option<String>,
+    ) -> AppResult<WalletAccountInfo> {
+        // Validate PIN format
+        Self::validate_pin(pin)?;
+
+        // Verify the PIN against the attempt limits and decrypt with it
+        let Some(secret) = self.unlock_wallet_secret(wallet_id, pin).await? else {
+            return Err(AppError::AuthenticationError(
+                "Invalid PIN. Account creation canceled for security reasons.".to_string(),
+            ));
+        };
+
+        let seed = match secret {
+            WalletSecret::Seed(seed) => seed,
+            WalletSecret::PrivateKey(_) => {
+                return Err(AppError::ValidationError(
+                    "This wallet holds a single key and cannot derive more accounts".to_string(),
+                ));
+            }
+        };
+
+        let wallet = self.fetch_wallet(wallet_id).await?;
+        self.derive_next_account(&wallet, seed, label).await
+    }
+
+    async fn export_wallet_keystore(
+        &self,
+        wallet_id: &str,
+        account_index: u32,
+        pin: &Pin,
+        password: &SecretString,
+    ) -> AppResult<String> {
+        if !self.keystore_export_config.enabled {
+            warn!(target: "audit", wallet_id, "Refused keystore export: export is disabled");
+            return Err(AppError::AuthorizationError(
+                "Keystore export is disabled".to_string(),
+            ));
+        }
+
+        // Validate PIN format and keystore password strength
+        Self::validate_pin(pin)?;
+        if password.expose_secret().chars().count() < MIN_KEYSTORE_PASSWORD_LENGTH {
+            return Err(AppError::validation(
+                "password",
+                &format!(
+                    "must be at least {} characters",
+                    MIN_KEYSTORE_PASSWORD_LENGTH
+                ),
+            ));
+        }
+
+        // Verify the PIN against the attempt limits and decrypt with it
+        let Some(secret) = self.unlock_wallet_secret(wallet_id, pin).await? else {
+            warn!(target: "audit", wallet_id, "Refused keystore export: invalid PIN");
+            return Err(AppError::AuthenticationError(
+                "Invalid PIN. Export canceled for security reasons.".to_string(),
+            ));
+        };
+
+        let signer = self
+            .get_account_signer(wallet_id, account_index, secret)
+            .await?;
+        let wallet = self.fetch_wallet(wallet_id).await?;
+
+        // scrypt is deliberately slow, so keep it off the async workers
+        let address = signer.address().to_string();
+        let password = password.clone();
+        let scrypt_log_n = self.keystore_export_config.scrypt_log_n;
+        let keystore = tokio::task::spawn_blocking(move || {
+            encrypt_keystore(
+                signer.private_key(),
+                signer.address(),
+                &password,
+                scrypt_log_n,
+            )
+        })
+        .await
+        .map_err(|e| AppError::ServerError(anyhow::anyhow!("Keystore export failed: {}", e)))??;
+
+        info!(
+            target: "audit",
+            wallet_id,
+            account_index,
+            address = %address,
+            user_id = %wallet.user_id,
+            "Exported wallet private key as a keystore file"
+        );
+        Ok(keystore)
     }
 }
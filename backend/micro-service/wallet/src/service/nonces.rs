@@ -0,0 +1,208 @@
+use app_error::{AppError, AppResult};
+use app_models::{Amount, TransactionDirection, TransactionStatus, WalletTransaction};
+use app_utils::chain::BlockTag;
+use app_utils::secret::Pin;
+use app_utils::transaction::{
+    DynamicFeeTransaction, SignedTransaction, Transaction, U256, parse_address,
+};
+use serde_json::json;
+use tracing::{error, info};
+
+use crate::service::WalletService;
+use crate::service::chain::Asset;
+
+/// Least increase of both fee caps a replacement offers, in percent. Nodes require 10%;
+/// the margin covers the rounding of fees some nodes apply.
+const REPLACEMENT_FEE_BUMP_PERCENT: u64 = 15;
+
+/// Gas of a plain transfer of the native coin, which is all a cancellation does
+const CANCEL_GAS_LIMIT: u64 = 21_000;
+
+/// Extension to WalletService for unsticking transfers
+impl WalletService {
+    /// Send a pending transfer of a wallet again with higher fees, returning the hash of
+    /// the replacement
+    pub async fn speed_up_transaction(
+        &self,
+        wallet_id: &str,
+        hash: &str,
+        pin: &Pin,
+    ) -> AppResult<String> {
+        self.replace_transaction(wallet_id, hash, pin, false).await
+    }
+
+    /// Replace a pending transfer of a wallet with a transaction that sends nothing,
+    /// returning the hash of the replacement
+    pub async fn cancel_transaction(
+        &self,
+        wallet_id: &str,
+        hash: &str,
+        pin: &Pin,
+    ) -> AppResult<String> {
+        self.replace_transaction(wallet_id, hash, pin, true).await
+    }
+
+    async fn replace_transaction(
+        &self,
+        wallet_id: &str,
+        hash: &str,
+        pin: &Pin,
+        cancel: bool,
+    ) -> AppResult<String> {
+        Self::validate_pin(pin)?;
+        let record = self.find_outgoing_transaction(wallet_id, hash).await?;
+        let original = Self::replaceable_transaction(&record)?;
+
+        let Some(secret) = self.unlock_wallet_secret(wallet_id, pin).await? else {
+            return Err(AppError::AuthenticationError(
+                "Invalid PIN. Replacement canceled for security reasons.".to_string(),
+            ));
+        };
+        let signer = self
+            .get_account_signer(wallet_id, record.account_index, secret)
+            .await?;
+        let from = parse_address(signer.address())?;
+        if original.sender()? != from {
+            return Err(AppError::IntegrityError(
+                "Transaction was not sent by the wallet account".to_string(),
+            ));
+        }
+
+        let Transaction::DynamicFee(mut transaction) = original.transaction().clone() else {
+            return Err(AppError::ValidationError(
+                "Only EIP-1559 transactions can be replaced".to_string(),
+            ));
+        };
+        let (suggested_tip, suggested_max_fee) = self.suggest_fees().await?;
+        transaction.max_priority_fee_per_gas =
+            bump_fee(transaction.max_priority_fee_per_gas).max(suggested_tip);
+        transaction.max_fee_per_gas = bump_fee(transaction.max_fee_per_gas).max(suggested_max_fee);
+        if cancel {
+            transaction = DynamicFeeTransaction {
+                gas_limit: CANCEL_GAS_LIMIT,
+                to: Some(from),
+                value: U256::zero(),
+                data: Vec::new(),
+                access_list: Vec::new(),
+                ..transaction
+            };
+        }
+
+        // Only one of the two is mined, so the original's cost does not count
+        self.check_native_balance(
+            from,
+            BlockTag::Latest,
+            transaction.gas_limit,
+            transaction.max_fee_per_gas,
+            transaction.value,
+        )
+        .await?;
+
+        // The nonce stays in use whatever happens to the replacement, so it is never
+        // released
+        let signed = self.sign_transaction(&signer, transaction)?;
+        let (asset, to, amount) = if cancel {
+            let asset = self.resolve_asset(None)?;
+            let amount = Amount::zero(asset.decimals);
+            (asset, from, amount)
+        } else {
+            let asset = Asset {
+                symbol: record.token.clone(),
+                decimals: record.amount.decimals(),
+                contract: record
+                    .token_address
+                    .as_deref()
+                    .map(parse_address)
+                    .transpose()?,
+            };
+            (asset, parse_address(&record.counterparty)?, record.amount)
+        };
+        // Whichever of the two is mined first uses the nonce, and the confirmation tracker
+        // drops the other
+        self.record_transfer(
+            wallet_id,
+            record.account_index,
+            &asset,
+            to,
+            amount,
+            &signed,
+            Some(record.hash.as_str()),
+            None,
+        )
+        .await?;
+        self.submit_transaction(&signed).await?;
+
+        let replacement = signed.hash_hex();
+        info!(
+            "Transaction {} with nonce {} {} by {}",
+            record.hash,
+            record.nonce,
+            if cancel { "cancelled" } else { "sped up" },
+            replacement
+        );
+        Ok(replacement)
+    }
+
+    async fn find_outgoing_transaction(
+        &self,
+        wallet_id: &str,
+        hash: &str,
+    ) -> AppResult<WalletTransaction> {
+        let records: Vec<WalletTransaction> = self
+            .transaction_db()?
+            .run_custom_query(
+                "SELECT * FROM transactions WHERE wallet_id = $wallet_id AND hash = $hash AND direction = $direction LIMIT 1",
+                vec![
+                    ("wallet_id".to_string(), json!(wallet_id)),
+                    ("hash".to_string(), json!(hash.trim().to_lowercase())),
+                    (
+                        "direction".to_string(),
+                        json!(TransactionDirection::Outgoing),
+                    ),
+                ],
+            )
+            .await
+            .map_err(|e| {
+                error!("Database error when fetching transaction {}: {}", hash, e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+
+        records.into_iter().next().ok_or_else(|| {
+            AppError::NotFoundError(format!("Transaction '{}' not found", hash.trim()))
+        })
+    }
+
+    /// The signed transaction of a record, if it can still be replaced
+    fn replaceable_transaction(record: &WalletTransaction) -> AppResult<SignedTransaction> {
+        let waiting = matches!(
+            record.status,
+            TransactionStatus::Pending | TransactionStatus::Submitted
+        );
+        if !waiting || record.block_hash.is_some() {
+            return Err(AppError::ValidationError(format!(
+                "Transaction {} is no longer waiting to be mined",
+                record.hash
+            )));
+        }
+
+        let raw = record
+            .raw_transaction
+            .as_deref()
+            .and_then(|raw| raw.strip_prefix("0x"))
+            .and_then(|raw| hex::decode(raw).ok())
+            .ok_or_else(|| {
+                AppError::ValidationError(format!(
+                    "Transaction {} was recorded without its signed form and cannot be replaced",
+                    record.hash
+                ))
+            })?;
+        SignedTransaction::decode(&raw)
+    }
+}
+
+/// `fee` raised by the replacement bump, rounded up
+fn bump_fee(fee: U256) -> U256 {
+    fee.saturating_mul(U256::from(100 + REPLACEMENT_FEE_BUMP_PERCENT))
+        .saturating_add(U256::from(99))
+        / U256::from(100)
+}
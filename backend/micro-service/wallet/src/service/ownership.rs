@@ -0,0 +1,165 @@
+use app_error::{AppError, AppResult};
+use app_models::wallet::Wallet;
+use chrono::Utc;
+use serde_json::{Value, json};
+use tracing::{error, info, warn};
+
+use crate::service::WalletService;
+
+/// Extension to WalletService for the wallets a user owns
+impl WalletService {
+    /// Merge fields into a wallet record and return the updated wallet
+    pub(crate) async fn merge_wallet(
+        &self,
+        wallet_id: &str,
+        mut changes: Value,
+    ) -> AppResult<Wallet> {
+        changes["updated_at"] = json!(Utc::now());
+
+        let updated = self
+            .wallet_db()?
+            .run_custom_query(
+                "UPDATE type::thing('wallets', $id) MERGE $changes",
+                vec![
+                    ("id".to_string(), json!(wallet_id)),
+                    ("changes".to_string(), changes),
+                ],
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to update wallet {}: {}", wallet_id, e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+
+        updated.into_iter().next().ok_or_else(|| {
+            AppError::NotFoundError(format!("Wallet with ID '{}' not found", wallet_id))
+        })
+    }
+
+    /// All wallets of a user, oldest first
+    pub(crate) async fn find_user_wallets(&self, user_id: &str) -> AppResult<Vec<Wallet>> {
+        let mut wallets = self
+            .wallet_db()?
+            .get_records_by_field("user_id", user_id.to_string())
+            .await
+            .map_err(|e| {
+                error!("Database error when fetching wallets of user: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+
+        wallets.sort_by_key(|wallet| wallet.created_at);
+        Ok(wallets)
+    }
+
+    /// The user's default wallet, or their oldest one if none is flagged
+    pub(crate) async fn find_default_wallet(&self, user_id: &str) -> AppResult<Option<Wallet>> {
+        let mut wallets = self.find_user_wallets(user_id).await?;
+
+        match wallets.iter().position(|wallet| wallet.is_default) {
+            Some(index) => Ok(Some(wallets.swap_remove(index))),
+            None => Ok(wallets.into_iter().next()),
+        }
+    }
+
+    /// A wallet, provided it belongs to the user
+    pub(crate) async fn fetch_user_wallet(
+        &self,
+        user_id: &str,
+        wallet_id: &str,
+    ) -> AppResult<Wallet> {
+        let wallet = self.fetch_wallet(wallet_id).await?;
+
+        // Ownership is by record ID rather than email, so changing an email keeps it
+        if wallet.user_id != user_id {
+            warn!(
+                "Access denied: user {} attempted to use wallet {}",
+                user_id, wallet_id
+            );
+            return Err(AppError::AuthorizationError(
+                "You do not have permission to access this wallet".to_string(),
+            ));
+        }
+
+        Ok(wallet)
+    }
+
+    /// Link wallets written before ownership moved to user IDs to their owner's record,
+    /// found by the email stored on the wallet. Each user's oldest wallet becomes their
+    /// default. Returns the number of migrated wallets and the wallet IDs that failed.
+    pub async fn migrate_wallet_owners(&self) -> AppResult<(usize, Vec<String>)> {
+        let mut migrated = 0;
+        let mut failed_wallets = Vec::new();
+
+        let mut wallets: Vec<Wallet> = self
+            .wallet_db()?
+            .run_custom_query("SELECT * FROM wallets", Vec::new())
+            .await
+            .map_err(|e| {
+                error!("Database error when fetching wallets for migration: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?
+            .into_iter()
+            .filter(|wallet| wallet.user_id.is_empty())
+            .collect();
+        wallets.sort_by_key(|wallet| wallet.created_at);
+
+        info!("Found {} wallets without an owner ID", wallets.len());
+
+        let user_db = self
+            .user_db
+            .as_ref()
+            .ok_or_else(|| AppError::ServerError(anyhow::anyhow!("User database not available")))?;
+
+        for wallet in wallets {
+            let wallet_id = wallet.id.id.to_raw();
+            let Some(email) = wallet.user_email.as_deref() else {
+                error!("Wallet {} has neither an owner ID nor an email", wallet_id);
+                failed_wallets.push(wallet_id);
+                continue;
+            };
+
+            let user = match user_db
+                .get_records_by_field("email", email.to_string())
+                .await
+            {
+                Ok(users) if !users.is_empty() => users[0].clone(),
+                Ok(_) => {
+                    error!("No user with the email of wallet {}", wallet_id);
+                    failed_wallets.push(wallet_id);
+                    continue;
+                }
+                Err(e) => {
+                    error!("Failed to look up owner of wallet {}: {}", wallet_id, e);
+                    failed_wallets.push(wallet_id);
+                    continue;
+                }
+            };
+
+            let user_id = user.id.id.to_raw();
+            let has_default = match self.find_user_wallets(&user_id).await {
+                Ok(owned) => owned.iter().any(|owned| owned.is_default),
+                Err(e) => {
+                    error!("Failed to fetch wallets of user {}: {}", user_id, e);
+                    failed_wallets.push(wallet_id);
+                    continue;
+                }
+            };
+
+            let changes = json!({ "user_id": user_id, "is_default": !has_default });
+            match self.merge_wallet(&wallet_id, changes).await {
+                Ok(_) => migrated += 1,
+                Err(e) => {
+                    error!("Failed to migrate owner of wallet {}: {}", wallet_id, e);
+                    failed_wallets.push(wallet_id);
+                }
+            }
+        }
+
+        info!(
+            "Wallet owner migration completed: {} successful, {} failed",
+            migrated,
+            failed_wallets.len()
+        );
+        Ok((migrated, failed_wallets))
+    }
+}
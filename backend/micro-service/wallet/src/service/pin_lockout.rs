@@ -0,0 +1,183 @@
+use app_config::PinLockoutConfig;
+use app_database::service::DbService;
+use app_error::{AppError, AppResult};
+use app_middleware::{AttemptReservation, RedisPinRateLimiter};
+use app_models::wallet::{Wallet, WalletInfo};
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+
+use crate::service::WalletService;
+
+/// Key prefix for failed PIN attempts in the rate limiter
+const PIN_ATTEMPT_PREFIX: &str = "wallet_pin";
+
+/// Extension to WalletService for limiting PIN guesses
+impl WalletService {
+    /// Add the limiter that counts failed PIN attempts
+    pub fn with_pin_rate_limiter(mut self, pin_rate_limiter: Arc<RedisPinRateLimiter>) -> Self {
+        self.pin_rate_limiter = Some(pin_rate_limiter);
+        self
+    }
+
+    /// Set the attempt limit and back-off used for failed PINs
+    pub fn with_pin_lockout_config(mut self, pin_lockout_config: PinLockoutConfig) -> Self {
+        self.pin_lockout_config = pin_lockout_config;
+        self
+    }
+
+    fn pin_attempt_key(wallet_id: &str) -> String {
+        format!("{}:{}", PIN_ATTEMPT_PREFIX, wallet_id)
+    }
+
+    pub(crate) fn wallet_db(&self) -> AppResult<&Arc<DbService<'static, Wallet>>> {
+        self.wallet_db.as_ref().ok_or_else(|| {
+            error!("Wallet database not available");
+            AppError::ServerError(anyhow::anyhow!("Wallet database not available"))
+        })
+    }
+
+    pub(crate) async fn fetch_wallet(&self, wallet_id: &str) -> AppResult<Wallet> {
+        self.wallet_db()?
+            .get_record_by_id(wallet_id)
+            .await
+            .map_err(|e| {
+                error!("Database error when fetching wallet: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?
+            .ok_or_else(|| {
+                AppError::NotFoundError(format!("Wallet with ID '{}' not found", wallet_id))
+            })
+    }
+
+    /// Persist (or clear) the PIN lock on the wallet record
+    async fn set_pin_lock(
+        &self,
+        wallet_id: &str,
+        pin_locked_at: Option<DateTime<Utc>>,
+    ) -> AppResult<Wallet> {
+        self.merge_wallet(wallet_id, json!({ "pin_locked_at": pin_locked_at }))
+            .await
+    }
+
+    /// Count a PIN attempt before the PIN is tried, failing if the wallet is locked or the
+    /// caller is still inside the back-off after the last incorrect PIN. Returns the
+    /// attempts counted so far, including this one.
+    pub(crate) async fn reserve_pin_attempt(&self, wallet_id: &str) -> AppResult<usize> {
+        let wallet = self.fetch_wallet(wallet_id).await?;
+        if wallet.is_pin_locked() {
+            return Err(AppError::wallet_locked());
+        }
+
+        let Some(limiter) = &self.pin_rate_limiter else {
+            return Ok(0);
+        };
+
+        // Reserving and checking happen in one Redis script, so concurrent guesses cannot
+        // all pass the check before any of them is counted. Without the attempt count a
+        // PIN could be guessed without limit, so errors fail closed. Each failure doubles
+        // the wait before the next attempt.
+        let retry_delays: Vec<u64> = (0..self.pin_lockout_config.max_attempts)
+            .map(|failures| self.pin_lockout_config.retry_delay(failures))
+            .collect();
+        match limiter
+            .reserve_attempt(&Self::pin_attempt_key(wallet_id), &retry_delays)
+            .await?
+        {
+            AttemptReservation::Reserved(attempts) => Ok(attempts),
+            // The lock may not have been written if the database failed when it was reached
+            AttemptReservation::Exhausted(_) => {
+                self.set_pin_lock(wallet_id, Some(Utc::now())).await?;
+                Err(AppError::wallet_locked())
+            }
+            AttemptReservation::RetryAfter(wait) => Err(AppError::pin_retry_delay(wait)),
+        }
+    }
+
+    /// Keep a reserved attempt counted after an incorrect PIN, locking the wallet once
+    /// `attempts` reaches the limit
+    pub(crate) async fn record_failed_pin_attempt(
+        &self,
+        wallet_id: &str,
+        attempts: usize,
+    ) -> AppResult<()> {
+        if self.pin_rate_limiter.is_none() || attempts < self.pin_lockout_config.max_attempts {
+            return Ok(());
+        }
+
+        warn!(
+            "Wallet {} locked after {} incorrect PIN attempts",
+            wallet_id, attempts
+        );
+        // The lock lives on the wallet record so a Redis flush cannot clear it; only
+        // signing in again or an admin unlock does
+        self.set_pin_lock(wallet_id, Some(Utc::now())).await?;
+        Err(AppError::wallet_locked())
+    }
+
+    /// Take back a reserved attempt when the PIN could not be checked at all
+    pub(crate) async fn release_pin_attempt(&self, wallet_id: &str) {
+        let Some(limiter) = &self.pin_rate_limiter else {
+            return;
+        };
+
+        if let Err(e) = limiter
+            .release_attempt(&Self::pin_attempt_key(wallet_id))
+            .await
+        {
+            warn!(
+                "Failed to release PIN attempt for wallet {}: {}",
+                wallet_id, e
+            );
+        }
+    }
+
+    /// Forget earlier failures after a correct PIN
+    pub(crate) async fn record_successful_pin_attempt(&self, wallet_id: &str) {
+        let Some(limiter) = &self.pin_rate_limiter else {
+            return;
+        };
+
+        if let Err(e) = limiter
+            .record_successful_attempt(&Self::pin_attempt_key(wallet_id), true)
+            .await
+        {
+            warn!(
+                "Failed to reset PIN attempts for wallet {}: {}",
+                wallet_id, e
+            );
+        }
+    }
+
+    /// Unlock a wallet and reset its failed PIN attempts
+    pub async fn unlock_wallet_pin(&self, wallet_id: &str) -> AppResult<WalletInfo> {
+        let wallet = self.set_pin_lock(wallet_id, None).await?;
+
+        if let Some(limiter) = &self.pin_rate_limiter {
+            limiter
+                .record_successful_attempt(&Self::pin_attempt_key(wallet_id), true)
+                .await?;
+        }
+
+        info!("Unlocked PIN attempts for wallet {}", wallet_id);
+        self.wallet_info(wallet).await
+    }
+
+    /// Unlock a wallet if its owner signed in (at `authenticated_at`, a Unix timestamp)
+    /// after it was locked
+    pub async fn unlock_wallet_pin_on_reauth(
+        &self,
+        wallet_id: &str,
+        authenticated_at: i64,
+    ) -> AppResult<()> {
+        let wallet = self.fetch_wallet(wallet_id).await?;
+
+        match wallet.pin_locked_at {
+            Some(locked_at) if authenticated_at > locked_at.timestamp() => {
+                self.unlock_wallet_pin(wallet_id).await.map(|_| ())
+            }
+            _ => Ok(()),
+        }
+    }
+}
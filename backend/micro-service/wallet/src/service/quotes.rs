@@ -0,0 +1,210 @@
+use app_database::service::DbService;
+use app_error::{AppError, AppResult};
+use app_models::{Amount, FeeSpeed, FeeTier, TransferQuote, TransferQuoteInfo};
+use app_utils::chain::BlockTag;
+use app_utils::transaction::{H160, U256, format_address, parse_address};
+use chrono::Utc;
+use std::sync::Arc;
+use tracing::{error, info};
+
+use crate::service::chain::{Asset, GasFees, GasPlan};
+use crate::service::{TransferRequest, WalletService};
+
+/// Decimal places of gwei, the unit quoted fees per gas are given in
+const GWEI_DECIMALS: u8 = 9;
+
+/// A quote to send a transfer with, and the speed whose fees the user chose
+#[derive(Debug, Clone)]
+pub struct QuoteChoice {
+    pub quote_id: String,
+    pub speed: FeeSpeed,
+}
+
+/// Extension to WalletService for quoting transfers before they are sent
+impl WalletService {
+    /// Add a transfer quote database service
+    pub fn with_quote_db(mut self, quote_db: Arc<DbService<'static, TransferQuote>>) -> Self {
+        self.quote_db = Some(quote_db);
+        self
+    }
+
+    fn quote_db(&self) -> AppResult<&Arc<DbService<'static, TransferQuote>>> {
+        self.quote_db.as_ref().ok_or_else(|| {
+            error!("Transfer quote database not available");
+            AppError::ServerError(anyhow::anyhow!("Transfer quote database not available"))
+        })
+    }
+
+    /// Estimate the gas of a transfer and its fees at each speed, and keep the quote so
+    /// the transfer can be sent with them until it expires
+    pub async fn quote_transfer(&self, request: &TransferRequest) -> AppResult<TransferQuoteInfo> {
+        if request.amount.is_zero() {
+            return Err(AppError::ValidationError(
+                "Amount must be greater than 0".to_string(),
+            ));
+        }
+        let to = parse_address(&request.to_address)?;
+        let asset = self.resolve_asset(request.token.as_deref())?;
+        let amount = request.amount.to_decimals(asset.decimals)?;
+
+        let wallet = self.fetch_wallet(&request.wallet_id).await?;
+        let account = self
+            .find_wallet_account(&wallet, request.account_index)
+            .await?;
+        let from = parse_address(&account.address)?;
+
+        let balance = self.asset_balance(from, &asset, BlockTag::Pending).await?;
+        if balance < amount.base_units() {
+            return Err(AppError::ValidationError(format!(
+                "Insufficient {} balance",
+                asset.symbol
+            )));
+        }
+
+        let chain_client = self.chain_client()?;
+        let call = Self::transfer_call(from, &asset, to, amount.base_units());
+        self.simulate_transfer(&call, &asset).await?;
+        let gas_limit = chain_client.estimate_gas(&call).await?;
+        let (base_fee, fees) = self.suggest_fee_tiers().await?;
+        let native_balance = chain_client.get_balance(from, BlockTag::Pending).await?;
+
+        let tiers = fees
+            .into_iter()
+            .map(|(speed, fees)| {
+                self.fee_tier(speed, fees, base_fee, gas_limit, call.value, native_balance)
+            })
+            .collect();
+
+        let created_at = Utc::now();
+        let quote = TransferQuote {
+            id: TransferQuote::generate_id(),
+            wallet_id: wallet.id.id.to_raw(),
+            account_index: request.account_index,
+            from: format_address(&from),
+            to: format_address(&to),
+            token: asset.symbol.clone(),
+            token_address: asset.contract.as_ref().map(format_address),
+            amount,
+            gas_limit,
+            base_fee_per_gas: Amount::new(base_fee, GWEI_DECIMALS),
+            native_balance: Amount::new(native_balance, self.chain_config.native_decimals),
+            tiers,
+            chain_id: self.chain_config.chain_id,
+            created_at,
+            expires_at: created_at
+                + chrono::Duration::seconds(self.chain_config.quotes.ttl_secs as i64),
+        };
+
+        let stored = self
+            .quote_db()?
+            .create_record(quote.clone())
+            .await
+            .map_err(|e| {
+                error!("Failed to store transfer quote: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?
+            .unwrap_or(quote);
+
+        info!(
+            "Quoted transfer of {} {} from {} to {}: {} gas",
+            stored.amount, stored.token, stored.from, stored.to, stored.gas_limit
+        );
+        Ok(stored.into())
+    }
+
+    /// What a transfer using `gas_limit` gas and sending `value` of the native coin costs
+    /// at `fees`
+    fn fee_tier(
+        &self,
+        speed: FeeSpeed,
+        fees: GasFees,
+        base_fee: U256,
+        gas_limit: u64,
+        value: U256,
+        native_balance: U256,
+    ) -> FeeTier {
+        let native_decimals = self.chain_config.native_decimals;
+        let gas_limit = U256::from(gas_limit);
+
+        // The base fee is burned and the tip paid on top, up to the fee cap
+        let likely_fee_per_gas = base_fee
+            .saturating_add(fees.max_priority_fee_per_gas)
+            .min(fees.max_fee_per_gas);
+        let estimated_fee = gas_limit.saturating_mul(likely_fee_per_gas);
+        let max_fee = gas_limit.saturating_mul(fees.max_fee_per_gas);
+        let total_cost = max_fee.saturating_add(value);
+
+        FeeTier {
+            speed,
+            max_priority_fee_per_gas: Amount::new(fees.max_priority_fee_per_gas, GWEI_DECIMALS),
+            max_fee_per_gas: Amount::new(fees.max_fee_per_gas, GWEI_DECIMALS),
+            estimated_fee: Amount::new(estimated_fee, native_decimals),
+            max_fee: Amount::new(max_fee, native_decimals),
+            total_cost: Amount::new(total_cost, native_decimals),
+            can_cover_gas: native_balance >= total_cost,
+        }
+    }
+
+    /// The gas limit and fees a quote offers at the chosen speed, if the quote is for
+    /// this transfer and has not expired
+    pub(crate) async fn quoted_gas_plan(
+        &self,
+        choice: &QuoteChoice,
+        request: &TransferRequest,
+        asset: &Asset,
+        to: H160,
+        amount: Amount,
+    ) -> AppResult<GasPlan> {
+        let quote_id = choice.quote_id.trim().to_string();
+        let quote = self
+            .quote_db()?
+            .get_record_by_id(&quote_id)
+            .await
+            .map_err(|e| {
+                error!("Database error when fetching transfer quote: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?
+            .ok_or_else(|| AppError::NotFoundError(format!("Quote '{}' not found", quote_id)))?;
+
+        let is_same_transfer = quote.wallet_id == request.wallet_id
+            && quote.account_index == request.account_index
+            && quote.chain_id == self.chain_config.chain_id
+            && quote.to == format_address(&to)
+            && quote.token == asset.symbol
+            && quote.token_address == asset.contract.as_ref().map(format_address)
+            && quote.amount == amount;
+        if !is_same_transfer {
+            return Err(AppError::ValidationError(format!(
+                "Quote '{}' was made for a different transfer",
+                quote_id
+            )));
+        }
+        if quote.is_expired() {
+            return Err(AppError::ValidationError(format!(
+                "Quote '{}' has expired; request a new one",
+                quote_id
+            )));
+        }
+
+        let tier = quote.tier(choice.speed).ok_or_else(|| {
+            AppError::ValidationError(format!(
+                "Quote '{}' has no {:?} fees",
+                quote_id, choice.speed
+            ))
+        })?;
+        // Used instead of fresh estimates, so the user pays the fee they accepted
+        Ok(GasPlan {
+            gas_limit: quote.gas_limit,
+            fees: GasFees {
+                max_priority_fee_per_gas: tier
+                    .max_priority_fee_per_gas
+                    .to_decimals(GWEI_DECIMALS)?
+                    .base_units(),
+                max_fee_per_gas: tier
+                    .max_fee_per_gas
+                    .to_decimals(GWEI_DECIMALS)?
+                    .base_units(),
+            },
+        })
+    }
+}
@@ -0,0 +1,524 @@
+use app_database::service::DbService;
+use app_error::{AppError, AppResult};
+use app_models::{KeyRotationJob, KeyRotationJobInfo, KeyRotationStatus, WalletKey};
+use futures::{StreamExt, stream};
+use serde_json::json;
+use std::sync::Arc;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::service::WalletService;
+
+/// Extension to WalletService for running master key rotation as a resumable background job.
+///
+/// Progress is stored in the `key_rotation_jobs` table after every batch, so a job that was
+/// running when the service stopped continues from its cursor on the next start. A run holds
+/// its job under a lease that it renews with every batch, so however many instances or
+/// resumes start a run, only one works on a job at a time; the others wait to take over
+/// once the lease runs out.
+impl WalletService {
+    fn key_rotation_job_db(&self) -> AppResult<&Arc<DbService<'static, KeyRotationJob>>> {
+        self.key_rotation_job_db.as_ref().ok_or_else(|| {
+            error!("Key rotation job database not available");
+            AppError::ServerError(anyhow::anyhow!("Key rotation job database not available"))
+        })
+    }
+
+    /// Define the fields and indexes the key rotation jobs table relies on. The database
+    /// sets `active` on a running or paused job and a unique index allows it on one job
+    /// only, so two jobs started at the same time cannot both be stored.
+    pub async fn define_key_rotation_indexes(&self) -> AppResult<()> {
+        let job_db = self.key_rotation_job_db()?;
+        for definition in [
+            "DEFINE FIELD IF NOT EXISTS active ON TABLE key_rotation_jobs VALUE IF status IN ['running', 'paused'] THEN true ELSE NONE END",
+            "DEFINE INDEX IF NOT EXISTS key_rotation_jobs_active ON TABLE key_rotation_jobs FIELDS active UNIQUE",
+        ] {
+            job_db.run_definition(definition).await.map_err(|e| {
+                error!("Failed to define the key rotation job indexes: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Get a key rotation job by ID
+    pub async fn get_key_rotation_job(&self, job_id: &str) -> AppResult<KeyRotationJobInfo> {
+        self.fetch_key_rotation_job(job_id).await.map(Into::into)
+    }
+
+    /// List all key rotation jobs, newest first
+    pub async fn list_key_rotation_jobs(&self) -> AppResult<Vec<KeyRotationJobInfo>> {
+        let jobs = self
+            .key_rotation_job_db()?
+            .run_custom_query(
+                "SELECT * FROM key_rotation_jobs ORDER BY created_at DESC",
+                Vec::new(),
+            )
+            .await
+            .map_err(|e| {
+                error!("Database error when listing key rotation jobs: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+
+        Ok(jobs.into_iter().map(Into::into).collect())
+    }
+
+    /// Create a rotation job onto the current primary master key and start it in the background.
+    /// Only one job may be running or paused at a time.
+    pub async fn start_key_rotation_job(self: &Arc<Self>) -> AppResult<KeyRotationJobInfo> {
+        let job_db = self.key_rotation_job_db()?;
+        self.ensure_no_active_key_rotation_job().await?;
+
+        let job = KeyRotationJob::new(
+            self.encryption_service.master_key_id().to_string(),
+            self.key_rotation_config.batch_size,
+            self.key_rotation_config.concurrency,
+        );
+
+        // The unique index on `active` refuses the job if another one was stored since the
+        // check above, in which case that job is reported
+        let job = match job_db.create_record(job).await {
+            Ok(job) => job,
+            Err(e) => {
+                self.ensure_no_active_key_rotation_job().await?;
+                error!("Failed to store key rotation job: {}", e);
+                return Err(AppError::DatabaseError(anyhow::anyhow!(e)));
+            }
+        };
+        let job = job.ok_or_else(|| {
+            error!("Database did not return stored key rotation job");
+            AppError::DatabaseError(anyhow::anyhow!("Failed to store key rotation job"))
+        })?;
+
+        let job_id = job.id.id.to_raw();
+        info!(
+            "Started key rotation job {} onto master key {}",
+            job_id, job.target_master_key_id
+        );
+        self.spawn_key_rotation_job(job_id);
+
+        Ok(job.into())
+    }
+
+    /// Fail if a job is running or paused
+    async fn ensure_no_active_key_rotation_job(&self) -> AppResult<()> {
+        let active_jobs = self
+            .key_rotation_job_db()?
+            .run_custom_query(
+                "SELECT * FROM key_rotation_jobs WHERE status IN ['running', 'paused']",
+                Vec::new(),
+            )
+            .await
+            .map_err(|e| {
+                error!(
+                    "Database error when checking for active rotation jobs: {}",
+                    e
+                );
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+
+        match active_jobs.first() {
+            Some(active) => Err(AppError::ResourceExistsError(format!(
+                "Key rotation job '{}' is already in progress",
+                active.id.id.to_raw()
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Stop a running job after its current batch. It can be resumed later.
+    pub async fn pause_key_rotation_job(&self, job_id: &str) -> AppResult<KeyRotationJobInfo> {
+        self.transition_key_rotation_job(
+            job_id,
+            &[KeyRotationStatus::Running],
+            json!({ "status": KeyRotationStatus::Paused }),
+        )
+        .await
+    }
+
+    /// Continue a paused job from its stored cursor
+    pub async fn resume_key_rotation_job(
+        self: &Arc<Self>,
+        job_id: &str,
+    ) -> AppResult<KeyRotationJobInfo> {
+        let job = self.fetch_key_rotation_job(job_id).await?;
+
+        // Keys before the cursor were only checked against the job's target key
+        if job.target_master_key_id != self.encryption_service.master_key_id() {
+            return Err(AppError::ValidationError(format!(
+                "Job targets master key '{}' but the primary master key is now '{}'. Cancel it and start a new job.",
+                job.target_master_key_id,
+                self.encryption_service.master_key_id()
+            )));
+        }
+
+        let job = self
+            .transition_key_rotation_job(
+                job_id,
+                &[KeyRotationStatus::Paused],
+                json!({ "status": KeyRotationStatus::Running }),
+            )
+            .await?;
+
+        info!("Resumed key rotation job {}", job_id);
+        self.spawn_key_rotation_job(job_id.to_string());
+
+        Ok(job)
+    }
+
+    /// Stop a running or paused job for good
+    pub async fn cancel_key_rotation_job(&self, job_id: &str) -> AppResult<KeyRotationJobInfo> {
+        self.transition_key_rotation_job(
+            job_id,
+            &[KeyRotationStatus::Running, KeyRotationStatus::Paused],
+            json!({
+                "status": KeyRotationStatus::Cancelled,
+                "finished_at": chrono::Utc::now(),
+            }),
+        )
+        .await
+    }
+
+    /// Restart jobs that were still running when the service last stopped, or that are
+    /// running on another instance, which keeps them unless it stops renewing their lease.
+    /// Returns the number of jobs resumed.
+    pub async fn resume_interrupted_key_rotation_jobs(self: &Arc<Self>) -> AppResult<usize> {
+        let jobs = self
+            .key_rotation_job_db()?
+            .get_records_by_field("status", KeyRotationStatus::Running)
+            .await
+            .map_err(|e| {
+                error!(
+                    "Database error when fetching interrupted rotation jobs: {}",
+                    e
+                );
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+
+        for job in &jobs {
+            let job_id = job.id.id.to_raw();
+            info!(
+                "Resuming key rotation job {} from cursor {:?}",
+                job_id, job.cursor
+            );
+            self.spawn_key_rotation_job(job_id);
+        }
+
+        Ok(jobs.len())
+    }
+
+    /// Run a job in a background task, logging (rather than returning) any error
+    pub fn spawn_key_rotation_job(self: &Arc<Self>, job_id: String) {
+        let service = Arc::clone(self);
+        tokio::spawn(async move {
+            if let Err(e) = service.run_key_rotation_job(&job_id).await {
+                error!("Key rotation job {} stopped with an error: {}", job_id, e);
+            }
+        });
+    }
+
+    /// Process a job batch by batch until it completes or is paused or cancelled.
+    /// The job's lease is renewed before each batch, which fails once the job is no longer
+    /// running, so pausing or cancelling takes effect once the batch in flight has been
+    /// stored. While another run holds the lease, this one waits for it to run out.
+    pub async fn run_key_rotation_job(&self, job_id: &str) -> AppResult<KeyRotationJobInfo> {
+        let owner = Uuid::new_v4().to_string();
+        let mut held = false;
+
+        loop {
+            let Some(job) = self.lease_key_rotation_job(job_id, &owner).await? else {
+                let job = self.fetch_key_rotation_job(job_id).await?;
+
+                if job.status != KeyRotationStatus::Running {
+                    if held {
+                        self.release_key_rotation_job(job_id, &owner).await?;
+                    }
+                    info!("Key rotation job {} is {:?}, stopping", job_id, job.status);
+                    return Ok(job.into());
+                }
+
+                if held {
+                    warn!(
+                        "Key rotation job {} was taken over by another run, stopping",
+                        job_id
+                    );
+                    return Ok(job.into());
+                }
+
+                let wait = job
+                    .lease_expires_at
+                    .and_then(|expires_at| (expires_at - chrono::Utc::now()).to_std().ok())
+                    .unwrap_or_default();
+                tokio::time::sleep(wait.max(std::time::Duration::from_secs(1))).await;
+                continue;
+            };
+            held = true;
+
+            if job.target_master_key_id != self.encryption_service.master_key_id() {
+                self.release_key_rotation_job(job_id, &owner).await?;
+                return Err(AppError::ValidationError(format!(
+                    "Job targets master key '{}' but the primary master key is '{}'",
+                    job.target_master_key_id,
+                    self.encryption_service.master_key_id()
+                )));
+            }
+
+            let batch = self
+                .fetch_wallet_key_batch(job.cursor.as_deref(), job.batch_size)
+                .await?;
+
+            // Nothing left after the cursor: the job is done
+            let Some(last_key) = batch.last() else {
+                let job = self
+                    .transition_key_rotation_job(
+                        job_id,
+                        &[KeyRotationStatus::Running],
+                        json!({
+                            "status": KeyRotationStatus::Completed,
+                            "finished_at": chrono::Utc::now(),
+                            "lease_expires_at": chrono::Utc::now(),
+                        }),
+                    )
+                    .await?;
+
+                // Every DEK is now reachable through the primary key; drop the plaintext
+                // copies unwrapped during the job
+                let purged = self.encryption_service.purge_dek_cache().await;
+
+                info!(
+                    "Key rotation job {} completed: {} scanned, {} rotated, {} failed, {} cached DEKs purged",
+                    job_id, job.scanned, job.rotated, job.failed, purged
+                );
+                return Ok(job);
+            };
+            let next_cursor = last_key.id.id.to_raw();
+            let scanned = batch.len() as u64;
+
+            let stale_keys: Vec<WalletKey> = batch
+                .into_iter()
+                .filter(|key| self.wallet_key_needs_rotation(key))
+                .collect();
+
+            let results: Vec<(String, AppResult<()>)> = stream::iter(stale_keys)
+                .map(|key| async move {
+                    let result = self.rewrap_wallet_key(&key).await.map(|_| ());
+                    (key.wallet_id, result)
+                })
+                .buffer_unordered(job.concurrency.max(1))
+                .collect()
+                .await;
+
+            let mut rotated = job.rotated;
+            let mut failed = job.failed;
+            let mut failed_wallets = job.failed_wallets;
+            for (wallet_id, result) in results {
+                match result {
+                    Ok(_) => rotated += 1,
+                    Err(e) => {
+                        error!("Failed to rotate key for wallet {}: {}", wallet_id, e);
+                        failed += 1;
+                        if failed_wallets.len() < KeyRotationJob::MAX_FAILED_WALLETS {
+                            failed_wallets.push(wallet_id);
+                        }
+                    }
+                }
+            }
+
+            // Only progress fields are written, so a concurrent pause or cancel is kept
+            self.record_key_rotation_progress(
+                job_id,
+                &owner,
+                json!({
+                    "cursor": next_cursor,
+                    "scanned": job.scanned + scanned,
+                    "rotated": rotated,
+                    "failed": failed,
+                    "failed_wallets": failed_wallets,
+                    "updated_at": chrono::Utc::now(),
+                }),
+            )
+            .await?;
+        }
+    }
+
+    /// The next `limit` wallet keys after `cursor`, in record ID order
+    async fn fetch_wallet_key_batch(
+        &self,
+        cursor: Option<&str>,
+        limit: usize,
+    ) -> AppResult<Vec<WalletKey>> {
+        let wallet_key_db = self.wallet_key_db.as_ref().ok_or_else(|| {
+            AppError::ServerError(anyhow::anyhow!("Wallet key database not available"))
+        })?;
+
+        let result = match cursor {
+            Some(cursor) => {
+                wallet_key_db
+                    .run_custom_query(
+                        "SELECT * FROM wallet_keys WHERE id > type::thing('wallet_keys', $cursor) ORDER BY id LIMIT $limit",
+                        vec![
+                            ("cursor".to_string(), json!(cursor)),
+                            ("limit".to_string(), json!(limit)),
+                        ],
+                    )
+                    .await
+            }
+            None => {
+                wallet_key_db
+                    .run_custom_query(
+                        "SELECT * FROM wallet_keys ORDER BY id LIMIT $limit",
+                        vec![("limit".to_string(), json!(limit))],
+                    )
+                    .await
+            }
+        };
+
+        result.map_err(|e| {
+            error!(
+                "Database error when fetching wallet keys for rotation: {}",
+                e
+            );
+            AppError::DatabaseError(anyhow::anyhow!(e))
+        })
+    }
+
+    async fn fetch_key_rotation_job(&self, job_id: &str) -> AppResult<KeyRotationJob> {
+        self.key_rotation_job_db()?
+            .get_record_by_id(job_id)
+            .await
+            .map_err(|e| {
+                error!("Database error when fetching key rotation job: {}", e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?
+            .ok_or_else(|| {
+                AppError::NotFoundError(format!("Key rotation job '{}' not found", job_id))
+            })
+    }
+
+    /// Move a job to a new status, but only if it is currently in one of `from`
+    async fn transition_key_rotation_job(
+        &self,
+        job_id: &str,
+        from: &[KeyRotationStatus],
+        mut changes: serde_json::Value,
+    ) -> AppResult<KeyRotationJobInfo> {
+        changes["updated_at"] = json!(chrono::Utc::now());
+
+        // The status check and the update happen in one statement, so two
+        // transitions racing on the same job cannot both succeed
+        let updated = self
+            .key_rotation_job_db()?
+            .run_custom_query(
+                "UPDATE type::thing('key_rotation_jobs', $id) MERGE $changes WHERE status IN $from",
+                vec![
+                    ("id".to_string(), json!(job_id)),
+                    ("changes".to_string(), changes),
+                    ("from".to_string(), json!(from)),
+                ],
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to update key rotation job {}: {}", job_id, e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+
+        match updated.into_iter().next() {
+            Some(job) => Ok(job.into()),
+            None => {
+                let job = self.fetch_key_rotation_job(job_id).await?;
+                Err(AppError::ValidationError(format!(
+                    "Key rotation job '{}' is {:?} and cannot be changed",
+                    job_id, job.status
+                )))
+            }
+        }
+    }
+
+    /// Take or renew the lease on a running job for `owner`, unless another run holds it.
+    /// Returns the job if the lease is now `owner`'s.
+    async fn lease_key_rotation_job(
+        &self,
+        job_id: &str,
+        owner: &str,
+    ) -> AppResult<Option<KeyRotationJob>> {
+        let now = chrono::Utc::now();
+        let lease_expires_at =
+            now + chrono::Duration::seconds(self.key_rotation_config.lease_secs as i64);
+
+        // Checking the holder and taking the lease happen in one statement, so two runs
+        // racing for a free job cannot both get it
+        let leased = self
+            .key_rotation_job_db()?
+            .run_custom_query(
+                "UPDATE type::thing('key_rotation_jobs', $id) MERGE $lease WHERE status = 'running' AND (owner = $owner OR !lease_expires_at OR type::datetime(lease_expires_at) < type::datetime($now))",
+                vec![
+                    ("id".to_string(), json!(job_id)),
+                    (
+                        "lease".to_string(),
+                        json!({ "owner": owner, "lease_expires_at": lease_expires_at }),
+                    ),
+                    ("owner".to_string(), json!(owner)),
+                    ("now".to_string(), json!(now)),
+                ],
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to lease key rotation job {}: {}", job_id, e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+
+        Ok(leased.into_iter().next())
+    }
+
+    /// Let go of a job's lease so that a later run can take it at once
+    async fn release_key_rotation_job(&self, job_id: &str, owner: &str) -> AppResult<()> {
+        self.key_rotation_job_db()?
+            .run_custom_query(
+                "UPDATE type::thing('key_rotation_jobs', $id) MERGE $release WHERE owner = $owner",
+                vec![
+                    ("id".to_string(), json!(job_id)),
+                    (
+                        "release".to_string(),
+                        json!({ "lease_expires_at": chrono::Utc::now() }),
+                    ),
+                    ("owner".to_string(), json!(owner)),
+                ],
+            )
+            .await
+            .map_err(|e| {
+                error!("Failed to release key rotation job {}: {}", job_id, e);
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+
+        Ok(())
+    }
+
+    /// Store the progress of a job without touching its status, as long as `owner` still
+    /// holds it
+    async fn record_key_rotation_progress(
+        &self,
+        job_id: &str,
+        owner: &str,
+        progress: serde_json::Value,
+    ) -> AppResult<()> {
+        self.key_rotation_job_db()?
+            .run_custom_query(
+                "UPDATE type::thing('key_rotation_jobs', $id) MERGE $progress WHERE owner = $owner",
+                vec![
+                    ("id".to_string(), json!(job_id)),
+                    ("progress".to_string(), progress),
+                    ("owner".to_string(), json!(owner)),
+                ],
+            )
+            .await
+            .map_err(|e| {
+                error!(
+                    "Failed to store progress of key rotation job {}: {}",
+                    job_id, e
+                );
+                AppError::DatabaseError(anyhow::anyhow!(e))
+            })?;
+
+        Ok(())
+    }
+}
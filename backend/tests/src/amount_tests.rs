@@ -0,0 +1,103 @@
+use app_models::Amount;
+use async_graphql::{ScalarType, Value};
+use primitive_types::U256;
+
+fn amount(s: &str) -> Amount {
+    s.parse().unwrap()
+}
+
+#[test]
+fn test_parse_and_format() {
+    let parsed = amount("12.5");
+    assert_eq!(parsed.base_units(), U256::from(125));
+    assert_eq!(parsed.decimals(), 1);
+    assert_eq!(parsed.to_string(), "12.5");
+
+    // Every decimal place is kept when formatting
+    assert_eq!(
+        Amount::new(U256::from(12_500_000), 6).to_string(),
+        "12.500000"
+    );
+    assert_eq!(
+        Amount::new(U256::from(5), 18).to_string(),
+        "0.000000000000000005"
+    );
+    assert_eq!(Amount::new(U256::from(42), 0).to_string(), "42");
+    assert_eq!(Amount::zero(2).to_string(), "0.00");
+    assert_eq!(amount(" 0.10 ").to_string(), "0.10");
+
+    // The largest 256-bit value still parses
+    assert_eq!(amount(&U256::MAX.to_string()).base_units(), U256::MAX);
+
+    for invalid in [
+        "", ".5", "1.", "-1", "+1", "1e18", "1,5", "1.2.3", "0x10", "NaN",
+    ] {
+        assert!(invalid.parse::<Amount>().is_err(), "{:?} parsed", invalid);
+    }
+    let too_large = format!("{}0", U256::MAX);
+    assert!(too_large.parse::<Amount>().is_err());
+}
+
+#[test]
+fn test_exact_decimal_conversion() {
+    // 0.1 is exact, unlike as a float
+    assert_eq!(
+        amount("0.1").to_decimals(18).unwrap().base_units(),
+        U256::from(100_000_000_000_000_000u64)
+    );
+    assert_eq!(
+        amount("1250.000001").to_decimals(6).unwrap().base_units(),
+        U256::from(1_250_000_001u64)
+    );
+
+    // Dropping zero decimal places is fine, dropping digits is not
+    assert_eq!(amount("2.500").to_decimals(1).unwrap(), amount("2.5"));
+    assert!(amount("0.0000001").to_decimals(6).is_err());
+
+    // Shifting must not overflow
+    assert!(Amount::new(U256::MAX, 0).to_decimals(1).is_err());
+}
+
+#[test]
+fn test_checked_arithmetic() {
+    let sum = amount("1.5").checked_add(&amount("0.25")).unwrap();
+    assert_eq!(sum.to_string(), "1.75");
+    assert_eq!(
+        amount("1")
+            .checked_sub(&amount("0.000001"))
+            .unwrap()
+            .to_string(),
+        "0.999999"
+    );
+
+    assert!(amount("1").checked_sub(&amount("1.01")).is_err());
+    assert!(Amount::new(U256::MAX, 0).checked_add(&amount("1")).is_err());
+}
+
+#[test]
+fn test_comparison_ignores_trailing_zeros() {
+    assert_eq!(amount("2.50"), amount("2.5"));
+    assert_eq!(amount("0.000"), Amount::zero(0));
+    assert!(amount("0.5") > amount("0.49"));
+    assert!(amount("10") > amount("9.999999999"));
+    assert!(Amount::new(U256::MAX, 0) > amount("1.5"));
+}
+
+#[test]
+fn test_serialization() {
+    let json = serde_json::to_string(&amount("87.500000")).unwrap();
+    assert_eq!(json, "\"87.500000\"");
+    let parsed: Amount = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed.decimals(), 6);
+    assert!(serde_json::from_str::<Amount>("87.5").is_err());
+
+    // GraphQL takes decimal strings, or whole numbers as integers
+    assert_eq!(
+        Amount::parse(Value::String("0.5".to_string())).unwrap(),
+        amount("0.5")
+    );
+    assert_eq!(Amount::parse(Value::from(3)).unwrap(), amount("3"));
+    assert!(Amount::parse(Value::from(0.5)).is_err());
+    assert!(Amount::parse(Value::Boolean(true)).is_err());
+    assert_eq!(amount("1.25").to_value(), Value::String("1.25".to_string()));
+}
@@ -3,3 +3,9 @@ mod system_tests;
 
 #[cfg(test)]
 mod intergrate_test;
+
+#[cfg(test)]
+mod wallet_chain_tests;
+
+#[cfg(test)]
+mod amount_tests;
@@ -0,0 +1,1683 @@
+use app_config::{ChainConfig, ConfirmationConfig, QuoteConfig, TokenConfig};
+use app_database::{Database, db_connect::initialize_memory_db, service::DbService};
+use app_error::{AppError, AppResult};
+use app_models::{
+    Amount, FeeSpeed, KeyRotationJob, KeyRotationStatus, TransactionDirection, TransactionStatus,
+    TransferQuote, WalletAccount, WalletKey, WalletTransaction, WalletTransactionInfo, user::User,
+    wallet::Wallet,
+};
+use app_utils::abi::erc20::TransferEvent;
+use app_utils::abi::revert::{AMOUNT_EXCEEDS_CAPACITY, ENFORCED_PAUSE};
+use app_utils::abi::{Token, encode_call};
+use app_utils::chain::{
+    BlockTag, CallOutcome, CallRequest, ChainClient, FakeChain, FeeHistory, MemoryNonceStore,
+    NonceManager, TransactionReceipt,
+};
+use app_utils::crypto::WalletEncryptionService;
+use app_utils::generate::EthereumWallet;
+use app_utils::idempotency::IdempotencyStore;
+use app_utils::secret::{Pin, PrivateKey, SecretString};
+use app_utils::transaction::{H160, H256, U256, parse_address, parse_hash};
+use async_trait::async_trait;
+use chrono::{Duration, Utc};
+use micro_wallet::service::{
+    QuoteChoice, TransactionFilter, TransferRequest, WalletImportSource, WalletService,
+    WalletServiceTrait,
+};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const CHAIN_ID: u64 = 1961;
+const PIN: &str = "135790";
+const RECIPIENT: &str = "0x3535353535353535353535353535353535353535";
+const COIN: u64 = 1_000_000_000_000_000_000;
+const GWEI: u64 = 1_000_000_000;
+const USDT: &str = "0xdac17f958d2ee523a2206206994597c13d831ec7";
+const USDT_UNIT: u64 = 1_000_000;
+
+fn amount(s: &str) -> Amount {
+    s.parse().unwrap()
+}
+
+fn token_config(symbol: &str, address: &str, chain_id: u64) -> TokenConfig {
+    TokenConfig {
+        symbol: symbol.to_string(),
+        address: address.to_string(),
+        decimals: 6,
+        chain_id,
+    }
+}
+
+// Chain settings that confirm transactions `required` blocks deep
+fn confirming_after(required: u64) -> ChainConfig {
+    ChainConfig {
+        confirmations: ConfirmationConfig {
+            required,
+            ..Default::default()
+        },
+        ..Default::default()
+    }
+}
+
+async fn find_transaction(
+    wallet_service: &WalletService,
+    wallet_id: &str,
+    hash: &str,
+) -> WalletTransactionInfo {
+    wallet_service
+        .list_wallet_transactions(wallet_id, &TransactionFilter::default(), None, None)
+        .await
+        .unwrap()
+        .transactions
+        .into_iter()
+        .find(|transaction| transaction.hash == hash)
+        .expect("transaction is recorded")
+}
+
+// Nonces that start over from the node's count as soon as they are ahead of it, as if
+// every reservation was older than the gap timeout
+fn nonces_without_gap_timeout() -> Arc<NonceManager> {
+    Arc::new(
+        NonceManager::new(Arc::new(MemoryNonceStore::default()), CHAIN_ID)
+            .with_gap_timeout(std::time::Duration::ZERO),
+    )
+}
+
+// An idempotency store that keeps nothing, as if every claim lapsed before its transfer
+// stored a result
+struct ForgetfulIdempotencyStore;
+
+#[async_trait]
+impl IdempotencyStore for ForgetfulIdempotencyStore {
+    async fn claim(
+        &self,
+        _key: &str,
+        _value: &str,
+        _ttl: std::time::Duration,
+    ) -> AppResult<Option<String>> {
+        Ok(None)
+    }
+
+    async fn store(&self, _key: &str, _value: &str, _ttl: std::time::Duration) -> AppResult<()> {
+        Ok(())
+    }
+}
+
+// A node behind a load balancer that can answer one nonce query from before any of the
+// account's transactions were mined
+struct LaggingNode {
+    chain: Arc<FakeChain>,
+    lagging: AtomicBool,
+}
+
+#[async_trait]
+impl ChainClient for LaggingNode {
+    async fn get_balance(&self, address: H160, block: BlockTag) -> AppResult<U256> {
+        self.chain.get_balance(address, block).await
+    }
+
+    async fn call(&self, request: &CallRequest, block: BlockTag) -> AppResult<Vec<u8>> {
+        self.chain.call(request, block).await
+    }
+
+    async fn simulate_call(
+        &self,
+        request: &CallRequest,
+        block: BlockTag,
+    ) -> AppResult<CallOutcome> {
+        self.chain.simulate_call(request, block).await
+    }
+
+    async fn get_transaction_count(&self, address: H160, block: BlockTag) -> AppResult<u64> {
+        if self.lagging.swap(false, Ordering::SeqCst) {
+            return Ok(0);
+        }
+        self.chain.get_transaction_count(address, block).await
+    }
+
+    async fn estimate_gas(&self, request: &CallRequest) -> AppResult<u64> {
+        self.chain.estimate_gas(request).await
+    }
+
+    async fn fee_history(
+        &self,
+        block_count: u64,
+        newest_block: BlockTag,
+        reward_percentiles: &[f64],
+    ) -> AppResult<FeeHistory> {
+        self.chain
+            .fee_history(block_count, newest_block, reward_percentiles)
+            .await
+    }
+
+    async fn send_raw_transaction(&self, raw: &[u8]) -> AppResult<H256> {
+        self.chain.send_raw_transaction(raw).await
+    }
+
+    async fn get_transaction_receipt(&self, hash: H256) -> AppResult<Option<TransactionReceipt>> {
+        self.chain.get_transaction_receipt(hash).await
+    }
+
+    async fn block_number(&self) -> AppResult<u64> {
+        self.chain.block_number().await
+    }
+
+    async fn get_block_hash(&self, number: u64) -> AppResult<Option<H256>> {
+        self.chain.get_block_hash(number).await
+    }
+
+    async fn has_transaction(&self, hash: H256) -> AppResult<bool> {
+        self.chain.has_transaction(hash).await
+    }
+}
+
+// Wallet service backed by a fresh in-memory database and an in-process chain
+async fn setup_wallet_service() -> (WalletService, Arc<FakeChain>, String) {
+    let db: &'static Arc<Database> = Box::leak(Box::new(
+        initialize_memory_db()
+            .await
+            .expect("Database initialization failed"),
+    ));
+
+    let user_db = Arc::new(DbService::<User>::new(db, "users"));
+    let user = User::new(
+        "Chain Tester".to_string(),
+        "chaintester".to_string(),
+        "chain@example.com".to_string(),
+        "hashed-password".to_string(),
+    );
+    let user = user_db.create_record(user).await.unwrap().unwrap();
+
+    let chain = Arc::new(FakeChain::new(CHAIN_ID));
+    chain.deploy_token(parse_address(USDT).unwrap(), 6);
+    let encryption_service = Arc::new(WalletEncryptionService::new("test", &[7u8; 32]));
+    let wallet_service = WalletService::new(encryption_service)
+        .with_wallet_db(Arc::new(DbService::<Wallet>::new(db, "wallets")))
+        .with_wallet_key_db(Arc::new(DbService::<WalletKey>::new(db, "wallet_keys")))
+        .with_wallet_account_db(Arc::new(DbService::<WalletAccount>::new(
+            db,
+            "wallet_accounts",
+        )))
+        .with_user_db(user_db)
+        .with_transaction_db(Arc::new(DbService::<WalletTransaction>::new(
+            db,
+            "transactions",
+        )))
+        .with_quote_db(Arc::new(DbService::<TransferQuote>::new(
+            db,
+            "transfer_quotes",
+        )))
+        .with_chain_client(chain.clone())
+        .with_tokens(vec![
+            token_config("USDT", USDT, CHAIN_ID),
+            // Listed for another network, so not offered here
+            token_config(
+                "USDC",
+                "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+                CHAIN_ID + 1,
+            ),
+        ]);
+
+    wallet_service.define_transaction_indexes().await.unwrap();
+
+    (wallet_service, chain, user.id.id.to_raw())
+}
+
+#[tokio::test]
+async fn test_balance_is_read_from_the_chain() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        wallet_service.get_balance(&wallet.id, None).await.unwrap(),
+        Amount::zero(18)
+    );
+
+    chain.set_balance(
+        parse_address(&wallet.address).unwrap(),
+        U256::from(COIN) * U256::from(5) / U256::from(2),
+    );
+    assert_eq!(
+        wallet_service.get_balance(&wallet.id, None).await.unwrap(),
+        amount("2.5")
+    );
+}
+
+#[tokio::test]
+async fn test_transfer_is_broadcast_and_mined() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    let sender = parse_address(&wallet.address).unwrap();
+    let recipient = parse_address(RECIPIENT).unwrap();
+    chain.set_balance(sender, U256::from(10 * COIN));
+
+    let hash = wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, None, amount("1.5"), &pin)
+        .await
+        .unwrap();
+    let hash = H256::from_slice(&hex::decode(hash.trim_start_matches("0x")).unwrap());
+
+    let receipt = chain.get_transaction_receipt(hash).await.unwrap().unwrap();
+    assert!(receipt.status);
+    assert_eq!(receipt.from, sender);
+    assert_eq!(receipt.to, Some(recipient));
+
+    // The sender pays the amount plus the gas actually used
+    let fee = U256::from(receipt.gas_used) * receipt.effective_gas_price;
+    assert_eq!(chain.balance(recipient), U256::from(3 * COIN / 2));
+    assert_eq!(
+        chain.balance(sender),
+        U256::from(10 * COIN) - U256::from(3 * COIN / 2) - fee
+    );
+
+    // The next transfer picks up the following nonce
+    wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, None, amount("1"), &pin)
+        .await
+        .unwrap();
+    assert_eq!(chain.balance(recipient), U256::from(5 * COIN / 2));
+}
+
+#[tokio::test]
+async fn test_transfer_needs_funds_for_amount_and_fees() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    let sender = parse_address(&wallet.address).unwrap();
+
+    // Enough for the amount but not for gas on top of it
+    chain.set_balance(sender, U256::from(COIN));
+    let result = wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, None, amount("1"), &pin)
+        .await;
+    assert!(matches!(result, Err(AppError::ValidationError(_))));
+
+    let result = wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, None, amount("2"), &pin)
+        .await;
+    assert!(matches!(result, Err(AppError::ValidationError(_))));
+
+    assert_eq!(chain.pending_count(), 0);
+    assert_eq!(chain.balance(sender), U256::from(COIN));
+}
+
+#[tokio::test]
+async fn test_token_balance_and_transfer() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    let sender = parse_address(&wallet.address).unwrap();
+    let recipient = parse_address(RECIPIENT).unwrap();
+    let token = parse_address(USDT).unwrap();
+    chain.set_balance(sender, U256::from(COIN));
+    chain.set_token_balance(token, sender, U256::from(100 * USDT_UNIT));
+
+    // Tokens are found by symbol, in any case, or by contract address
+    for token_id in ["USDT", "usdt", USDT] {
+        assert_eq!(
+            wallet_service
+                .get_balance(&wallet.id, Some(token_id))
+                .await
+                .unwrap(),
+            amount("100")
+        );
+    }
+    assert_eq!(
+        wallet_service.get_balance(&wallet.id, None).await.unwrap(),
+        amount("1")
+    );
+
+    let hash = wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, Some("USDT"), amount("12.5"), &pin)
+        .await
+        .unwrap();
+    let hash = H256::from_slice(&hex::decode(hash.trim_start_matches("0x")).unwrap());
+
+    let receipt = chain.get_transaction_receipt(hash).await.unwrap().unwrap();
+    assert!(receipt.status);
+    assert_eq!(receipt.to, Some(token));
+    let events: Vec<_> = receipt
+        .logs
+        .iter()
+        .filter_map(|log| TransferEvent::from_log(log).unwrap())
+        .collect();
+    assert_eq!(
+        events,
+        vec![TransferEvent {
+            token,
+            from: sender,
+            to: recipient,
+            value: U256::from(12 * USDT_UNIT + USDT_UNIT / 2),
+        }]
+    );
+
+    assert_eq!(
+        chain.token_balance(token, recipient),
+        U256::from(12 * USDT_UNIT + USDT_UNIT / 2)
+    );
+    assert_eq!(
+        wallet_service
+            .get_balance(&wallet.id, Some("USDT"))
+            .await
+            .unwrap(),
+        amount("87.5")
+    );
+
+    // Only gas was paid in the native coin
+    let fee = U256::from(receipt.gas_used) * receipt.effective_gas_price;
+    assert_eq!(chain.balance(sender), U256::from(COIN) - fee);
+    assert_eq!(chain.balance(recipient), U256::zero());
+}
+
+#[tokio::test]
+async fn test_token_transfer_checks() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    let sender = parse_address(&wallet.address).unwrap();
+    let token = parse_address(USDT).unwrap();
+    chain.set_token_balance(token, sender, U256::from(10 * USDT_UNIT));
+
+    // Tokens that are not listed for this chain are unknown
+    for token_id in ["DAI", "USDC", "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48"] {
+        let result = wallet_service.get_balance(&wallet.id, Some(token_id)).await;
+        assert!(matches!(result, Err(AppError::ValidationError(_))));
+    }
+
+    // More tokens than the wallet holds
+    chain.set_balance(sender, U256::from(COIN));
+    let result = wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, Some("USDT"), amount("11"), &pin)
+        .await;
+    assert!(matches!(result, Err(AppError::ValidationError(_))));
+
+    // More decimal places than the token has
+    let result = wallet_service
+        .transfer(
+            &wallet.id,
+            0,
+            RECIPIENT,
+            Some("USDT"),
+            amount("0.0000001"),
+            &pin,
+        )
+        .await;
+    assert!(matches!(result, Err(AppError::ValidationError(_))));
+
+    // Enough tokens but nothing to pay gas with
+    chain.set_balance(sender, U256::zero());
+    let result = wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, Some("USDT"), amount("1"), &pin)
+        .await;
+    assert!(matches!(result, Err(AppError::ValidationError(_))));
+
+    assert_eq!(chain.pending_count(), 0);
+    assert_eq!(
+        chain.token_balance(token, sender),
+        U256::from(10 * USDT_UNIT)
+    );
+}
+
+#[tokio::test]
+async fn test_transfers_that_would_revert_are_not_sent() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    let sender = parse_address(&wallet.address).unwrap();
+    let token = parse_address(USDT).unwrap();
+    chain.set_balance(sender, U256::from(COIN));
+    chain.set_token_balance(token, sender, U256::from(100 * USDT_UNIT));
+
+    // The token's cap is lower than the amount, as `Role.sol` reports it
+    chain.set_transfer_revert(
+        token,
+        Some(encode_call(
+            AMOUNT_EXCEEDS_CAPACITY,
+            &[
+                Token::Uint(U256::from(50 * USDT_UNIT)),
+                Token::Uint(U256::from(10 * USDT_UNIT)),
+                Token::String("Requested amount exceeds available capacity".to_string()),
+            ],
+        )),
+    );
+    let result = wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, Some("USDT"), amount("50"), &pin)
+        .await;
+    match result {
+        Err(AppError::RevertError(revert)) => {
+            assert_eq!(revert.action, "USDT transfer");
+            assert_eq!(revert.error_name, "AmountExceedsCapacity");
+            assert_eq!(
+                revert.arguments[..2],
+                [
+                    ("amount".to_string(), "50000000".to_string()),
+                    ("currentCapacity".to_string(), "10000000".to_string()),
+                ]
+            );
+            assert_eq!(
+                revert.reason,
+                "AmountExceedsCapacity: Requested amount exceeds available capacity \
+                 (amount 50000000, capacity 10000000)"
+            );
+        }
+        other => panic!("expected a revert error, got {:?}", other),
+    }
+
+    // Quotes are refused the same way
+    chain.set_transfer_revert(token, Some(encode_call(ENFORCED_PAUSE, &[])));
+    let request = TransferRequest {
+        wallet_id: wallet.id.clone(),
+        account_index: 0,
+        to_address: RECIPIENT.to_string(),
+        token: Some("USDT".to_string()),
+        amount: amount("5"),
+    };
+    match wallet_service.quote_transfer(&request).await {
+        Err(error @ AppError::RevertError(_)) => assert_eq!(
+            error.to_string(),
+            "Execution reverted in USDT transfer: EnforcedPause: the contract is paused"
+        ),
+        other => panic!("expected a revert error, got {:?}", other),
+    }
+
+    // Nothing was signed, recorded or sent
+    assert_eq!(chain.pending_count(), 0);
+    assert!(
+        wallet_service
+            .list_wallet_transactions(&wallet.id, &TransactionFilter::default(), None, None)
+            .await
+            .unwrap()
+            .transactions
+            .is_empty()
+    );
+    assert_eq!(
+        chain.token_balance(token, sender),
+        U256::from(100 * USDT_UNIT)
+    );
+
+    chain.set_transfer_revert(token, None);
+    wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, Some("USDT"), amount("5"), &pin)
+        .await
+        .unwrap();
+    assert_eq!(
+        chain.token_balance(token, parse_address(RECIPIENT).unwrap()),
+        U256::from(5 * USDT_UNIT)
+    );
+}
+
+#[tokio::test]
+async fn test_transfers_are_recorded() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    let (receiving_wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    let sender = parse_address(&wallet.address).unwrap();
+    chain.set_balance(sender, U256::from(10 * COIN));
+    chain.set_token_balance(
+        parse_address(USDT).unwrap(),
+        sender,
+        U256::from(100 * USDT_UNIT),
+    );
+
+    let native_hash = wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, None, amount("1.5"), &pin)
+        .await
+        .unwrap();
+    let token_hash = wallet_service
+        .transfer(
+            &wallet.id,
+            0,
+            &receiving_wallet.address,
+            Some("USDT"),
+            amount("12.5"),
+            &pin,
+        )
+        .await
+        .unwrap();
+
+    // Newest first
+    let page = wallet_service
+        .list_wallet_transactions(&wallet.id, &TransactionFilter::default(), None, None)
+        .await
+        .unwrap();
+    assert_eq!(page.next_cursor, None);
+    let hashes: Vec<_> = page.transactions.iter().map(|tx| tx.hash.clone()).collect();
+    assert_eq!(hashes, vec![token_hash.clone(), native_hash.clone()]);
+
+    let native = &page.transactions[1];
+    assert_eq!(native.direction, TransactionDirection::Outgoing);
+    assert_eq!(native.status, TransactionStatus::Submitted);
+    assert_eq!(native.address, wallet.address);
+    assert_eq!(native.counterparty, RECIPIENT);
+    assert_eq!(native.token, "SEL");
+    assert_eq!(native.token_address, None);
+    assert_eq!(native.amount.to_string(), "1.500000000000000000");
+    assert_eq!(native.nonce, 0);
+    assert_eq!(native.chain_id, CHAIN_ID);
+    assert!(native.fee.is_some_and(|fee| !fee.is_zero()));
+
+    let token = &page.transactions[0];
+    assert_eq!(token.token, "USDT");
+    assert_eq!(token.token_address.as_deref(), Some(USDT));
+    assert_eq!(token.amount, amount("12.5"));
+    assert_eq!(token.nonce, 1);
+
+    // The receiving wallet belongs to the service, so it sees the transfer too
+    let received = wallet_service
+        .list_wallet_transactions(
+            &receiving_wallet.id,
+            &TransactionFilter::default(),
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+    assert_eq!(received.transactions.len(), 1);
+    let incoming = &received.transactions[0];
+    assert_eq!(incoming.direction, TransactionDirection::Incoming);
+    assert_eq!(incoming.hash, token_hash);
+    assert_eq!(incoming.counterparty, wallet.address);
+    assert_eq!(incoming.status, TransactionStatus::Submitted);
+    assert_eq!(incoming.fee, None);
+}
+
+#[tokio::test]
+async fn test_transaction_filters_and_pages() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    let sender = parse_address(&wallet.address).unwrap();
+    chain.set_balance(sender, U256::from(10 * COIN));
+    chain.set_token_balance(
+        parse_address(USDT).unwrap(),
+        sender,
+        U256::from(100 * USDT_UNIT),
+    );
+
+    let started = Utc::now();
+    let mut hashes = Vec::new();
+    for (token, value) in [(None, "1"), (Some("USDT"), "2"), (None, "3")] {
+        let hash = wallet_service
+            .transfer(&wallet.id, 0, RECIPIENT, token, amount(value), &pin)
+            .await
+            .unwrap();
+        hashes.push(hash);
+    }
+    hashes.reverse();
+
+    let service = &wallet_service;
+    let list = move |filter: TransactionFilter, first: Option<usize>, after: Option<String>| {
+        let wallet_id = wallet.id.clone();
+        async move {
+            service
+                .list_wallet_transactions(&wallet_id, &filter, first, after.as_deref())
+                .await
+        }
+    };
+
+    // Pages of one, following the cursor
+    let mut seen = Vec::new();
+    let mut after = None;
+    loop {
+        let page = list(TransactionFilter::default(), Some(1), after)
+            .await
+            .unwrap();
+        seen.extend(page.transactions.into_iter().map(|tx| tx.hash));
+        match page.next_cursor {
+            Some(cursor) => after = Some(cursor),
+            None => break,
+        }
+    }
+    assert_eq!(seen, hashes);
+
+    // By token symbol, in any case, or contract address
+    for token in ["usdt", USDT] {
+        let filter = TransactionFilter {
+            token: Some(token.to_string()),
+            ..Default::default()
+        };
+        let page = list(filter, None, None).await.unwrap();
+        assert_eq!(page.transactions.len(), 1);
+        assert_eq!(page.transactions[0].hash, hashes[1]);
+    }
+
+    // By status
+    let filter = TransactionFilter {
+        status: Some(TransactionStatus::Submitted),
+        token: Some("sel".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(
+        list(filter, None, None).await.unwrap().transactions.len(),
+        2
+    );
+    let filter = TransactionFilter {
+        status: Some(TransactionStatus::Confirmed),
+        ..Default::default()
+    };
+    assert!(
+        list(filter, None, None)
+            .await
+            .unwrap()
+            .transactions
+            .is_empty()
+    );
+
+    // By date
+    let filter = TransactionFilter {
+        since: Some(started),
+        until: Some(Utc::now() + Duration::minutes(1)),
+        ..Default::default()
+    };
+    assert_eq!(
+        list(filter, None, None).await.unwrap().transactions.len(),
+        3
+    );
+    let filter = TransactionFilter {
+        until: Some(started),
+        ..Default::default()
+    };
+    assert!(
+        list(filter, None, None)
+            .await
+            .unwrap()
+            .transactions
+            .is_empty()
+    );
+
+    // Page sizes are bounded
+    for first in [0, 101] {
+        let result = list(TransactionFilter::default(), Some(first), None).await;
+        assert!(matches!(result, Err(AppError::ValidationError(_))));
+    }
+}
+
+#[tokio::test]
+async fn test_transfers_are_confirmed_after_enough_blocks() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let wallet_service = wallet_service.with_chain_config(confirming_after(3));
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    chain.set_balance(parse_address(&wallet.address).unwrap(), U256::from(COIN));
+
+    let hash = wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, None, amount("0.5"), &pin)
+        .await
+        .unwrap();
+    let most_fee = find_transaction(&wallet_service, &wallet.id, &hash)
+        .await
+        .fee
+        .unwrap();
+
+    // Mined, but only one block deep
+    assert_eq!(wallet_service.track_confirmations().await.unwrap(), 1);
+    let transaction = find_transaction(&wallet_service, &wallet.id, &hash).await;
+    assert_eq!(transaction.status, TransactionStatus::Submitted);
+    assert_eq!(transaction.block_number, Some(1));
+    let block_hash = chain.get_block_hash(1).await.unwrap().unwrap();
+    assert_eq!(transaction.block_hash, Some(format!("{:#x}", block_hash)));
+    assert_eq!(transaction.confirmed_at, None);
+
+    // The fee is now what the transfer paid, which is at most what it could have paid
+    let receipt = chain
+        .get_transaction_receipt(parse_hash(&hash).unwrap())
+        .await
+        .unwrap()
+        .unwrap();
+    let paid = U256::from(receipt.gas_used) * receipt.effective_gas_price;
+    assert_eq!(transaction.fee, Some(Amount::new(paid, 18)));
+    assert!(transaction.fee.unwrap() <= most_fee);
+
+    // Nothing changes until the block is deep enough
+    chain.mine_block();
+    assert_eq!(wallet_service.track_confirmations().await.unwrap(), 0);
+    chain.mine_block();
+    assert_eq!(wallet_service.track_confirmations().await.unwrap(), 1);
+    let transaction = find_transaction(&wallet_service, &wallet.id, &hash).await;
+    assert_eq!(transaction.status, TransactionStatus::Confirmed);
+    assert!(transaction.confirmed_at.is_some());
+
+    // Confirmed transactions are no longer checked
+    chain.reorg(3);
+    assert_eq!(wallet_service.track_confirmations().await.unwrap(), 0);
+}
+
+#[tokio::test]
+async fn test_reorged_transfers_go_back_to_pending() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let wallet_service = wallet_service.with_chain_config(confirming_after(2));
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    chain.set_balance(parse_address(&wallet.address).unwrap(), U256::from(COIN));
+
+    let hash = wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, None, amount("0.5"), &pin)
+        .await
+        .unwrap();
+    wallet_service.track_confirmations().await.unwrap();
+    let mined = find_transaction(&wallet_service, &wallet.id, &hash).await;
+    assert_eq!(mined.block_number, Some(1));
+
+    // The block is replaced and the transfer is back in the node's pool
+    chain.reorg(1);
+    assert_eq!(wallet_service.track_confirmations().await.unwrap(), 1);
+    let transaction = find_transaction(&wallet_service, &wallet.id, &hash).await;
+    assert_eq!(transaction.status, TransactionStatus::Pending);
+    assert_eq!(transaction.block_number, None);
+    assert_eq!(transaction.block_hash, None);
+
+    // Mined again on the new chain, and confirmed there
+    assert_eq!(chain.mine_block(), 2);
+    wallet_service.track_confirmations().await.unwrap();
+    let transaction = find_transaction(&wallet_service, &wallet.id, &hash).await;
+    assert_eq!(transaction.status, TransactionStatus::Submitted);
+    assert_eq!(transaction.block_number, Some(2));
+    assert_ne!(transaction.block_hash, mined.block_hash);
+
+    chain.mine_block();
+    wallet_service.track_confirmations().await.unwrap();
+    let transaction = find_transaction(&wallet_service, &wallet.id, &hash).await;
+    assert_eq!(transaction.status, TransactionStatus::Confirmed);
+    assert_eq!(transaction.block_number, Some(2));
+}
+
+#[tokio::test]
+async fn test_transfers_the_node_forgets_are_dropped() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let wallet_service = wallet_service.with_chain_config(confirming_after(2));
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    chain.set_balance(parse_address(&wallet.address).unwrap(), U256::from(COIN));
+    chain.set_automine(false);
+    // The nonce of a transaction the node forgot is handed out again
+    let wallet_service = wallet_service.with_nonce_manager(nonces_without_gap_timeout());
+
+    let evicted = wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, None, amount("0.5"), &pin)
+        .await
+        .unwrap();
+    assert_eq!(wallet_service.track_confirmations().await.unwrap(), 0);
+
+    // Evicted from the pool, but it may still come back until the drop timeout
+    assert!(chain.drop_transaction(parse_hash(&evicted).unwrap()));
+    assert_eq!(wallet_service.track_confirmations().await.unwrap(), 0);
+    let transaction = find_transaction(&wallet_service, &wallet.id, &evicted).await;
+    assert_eq!(transaction.status, TransactionStatus::Submitted);
+
+    // Until another transaction uses its nonce
+    let replacement = wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, None, amount("0.25"), &pin)
+        .await
+        .unwrap();
+    chain.mine_block();
+    assert_eq!(wallet_service.track_confirmations().await.unwrap(), 2);
+    let transaction = find_transaction(&wallet_service, &wallet.id, &evicted).await;
+    assert_eq!(transaction.status, TransactionStatus::Dropped);
+    assert_eq!(
+        transaction.error.as_deref(),
+        Some("Replaced by another transaction with the same nonce")
+    );
+    let transaction = find_transaction(&wallet_service, &wallet.id, &replacement).await;
+    assert_eq!(transaction.nonce, 0);
+    assert_eq!(transaction.status, TransactionStatus::Submitted);
+
+    // A reorg takes the replacement's block away and the node forgets it
+    chain.reorg(1);
+    chain.drop_transaction(parse_hash(&replacement).unwrap());
+    assert_eq!(wallet_service.track_confirmations().await.unwrap(), 1);
+    let transaction = find_transaction(&wallet_service, &wallet.id, &replacement).await;
+    assert_eq!(transaction.status, TransactionStatus::Dropped);
+    assert_eq!(
+        transaction.error.as_deref(),
+        Some("Removed from the chain by a reorg")
+    );
+    assert_eq!(transaction.block_number, None);
+}
+
+#[tokio::test]
+async fn test_concurrent_transfers_get_distinct_nonces() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    chain.set_balance(
+        parse_address(&wallet.address).unwrap(),
+        U256::from(10 * COIN),
+    );
+
+    let (first, second) = tokio::join!(
+        wallet_service.transfer(&wallet.id, 0, RECIPIENT, None, amount("1"), &pin),
+        wallet_service.transfer(&wallet.id, 0, RECIPIENT, None, amount("0.5"), &pin),
+    );
+    let (first, second) = (first.unwrap(), second.unwrap());
+
+    let mut nonces = Vec::new();
+    for hash in [&first, &second] {
+        nonces.push(
+            find_transaction(&wallet_service, &wallet.id, hash)
+                .await
+                .nonce,
+        );
+        let receipt = chain
+            .get_transaction_receipt(parse_hash(hash).unwrap())
+            .await
+            .unwrap();
+        assert!(receipt.is_some_and(|receipt| receipt.status));
+    }
+    nonces.sort();
+    assert_eq!(nonces, vec![0, 1]);
+    assert_eq!(
+        chain.balance(parse_address(RECIPIENT).unwrap()),
+        U256::from(3 * COIN / 2)
+    );
+}
+
+#[tokio::test]
+async fn test_used_nonces_are_resynced_from_the_node() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let node = Arc::new(LaggingNode {
+        chain: chain.clone(),
+        lagging: AtomicBool::new(false),
+    });
+    let wallet_service = wallet_service
+        .with_chain_client(node.clone())
+        .with_nonce_manager(nonces_without_gap_timeout());
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    chain.set_balance(
+        parse_address(&wallet.address).unwrap(),
+        U256::from(10 * COIN),
+    );
+
+    wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, None, amount("1"), &pin)
+        .await
+        .unwrap();
+
+    // Nonce 0 is handed out again and rejected, so the transfer is signed once more with
+    // the node's count. A different amount keeps it from being the same transaction as
+    // the first, which the node would report as already known
+    node.lagging.store(true, Ordering::SeqCst);
+    let hash = wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, None, amount("2"), &pin)
+        .await
+        .unwrap();
+    let transaction = find_transaction(&wallet_service, &wallet.id, &hash).await;
+    assert_eq!(transaction.nonce, 1);
+    assert_eq!(transaction.status, TransactionStatus::Submitted);
+    assert_eq!(
+        chain.balance(parse_address(RECIPIENT).unwrap()),
+        U256::from(3 * COIN)
+    );
+
+    // The rejected attempt stays in the history
+    let failed = wallet_service
+        .list_wallet_transactions(
+            &wallet.id,
+            &TransactionFilter {
+                status: Some(TransactionStatus::Failed),
+                ..Default::default()
+            },
+            None,
+            None,
+        )
+        .await
+        .unwrap()
+        .transactions;
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].nonce, 0);
+    assert!(
+        failed[0]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("nonce too low")
+    );
+}
+
+#[tokio::test]
+async fn test_stuck_transfers_can_be_sped_up() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    chain.set_balance(
+        parse_address(&wallet.address).unwrap(),
+        U256::from(10 * COIN),
+    );
+    chain.set_automine(false);
+
+    let stuck = wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, None, amount("1"), &pin)
+        .await
+        .unwrap();
+    let replacement = wallet_service
+        .speed_up_transaction(&wallet.id, &stuck, &pin)
+        .await
+        .unwrap();
+    assert_ne!(replacement, stuck);
+    assert_eq!(chain.pending_count(), 1);
+
+    let original = find_transaction(&wallet_service, &wallet.id, &stuck).await;
+    let transaction = find_transaction(&wallet_service, &wallet.id, &replacement).await;
+    assert_eq!(transaction.replaces.as_deref(), Some(stuck.as_str()));
+    assert_eq!(transaction.nonce, original.nonce);
+    assert_eq!(transaction.amount, original.amount);
+    assert_eq!(transaction.counterparty, original.counterparty);
+    assert!(transaction.fee.unwrap().base_units() > original.fee.unwrap().base_units());
+
+    // Only the replacement is mined, and the original is dropped
+    chain.mine_block();
+    assert_eq!(wallet_service.track_confirmations().await.unwrap(), 2);
+    let original = find_transaction(&wallet_service, &wallet.id, &stuck).await;
+    assert_eq!(original.status, TransactionStatus::Dropped);
+    assert_eq!(
+        original.error.as_deref(),
+        Some("Replaced by another transaction with the same nonce")
+    );
+    assert_eq!(
+        chain.balance(parse_address(RECIPIENT).unwrap()),
+        U256::from(COIN)
+    );
+
+    // A dropped transaction can no longer be replaced
+    let result = wallet_service
+        .speed_up_transaction(&wallet.id, &stuck, &pin)
+        .await;
+    assert!(matches!(result, Err(AppError::ValidationError(_))));
+}
+
+#[tokio::test]
+async fn test_stuck_transfers_can_be_cancelled() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    let sender = parse_address(&wallet.address).unwrap();
+    chain.set_balance(sender, U256::from(10 * COIN));
+    chain.set_automine(false);
+
+    let stuck = wallet_service
+        .transfer(&wallet.id, 0, RECIPIENT, None, amount("1"), &pin)
+        .await
+        .unwrap();
+    let cancellation = wallet_service
+        .cancel_transaction(&wallet.id, &stuck, &pin)
+        .await
+        .unwrap();
+
+    // Sent to the sender itself, so it has no incoming side
+    let transaction = find_transaction(&wallet_service, &wallet.id, &cancellation).await;
+    assert_eq!(transaction.replaces.as_deref(), Some(stuck.as_str()));
+    assert_eq!(transaction.counterparty, transaction.address);
+    assert_eq!(transaction.amount, Amount::zero(18));
+    assert_eq!(transaction.nonce, 0);
+    let records = wallet_service
+        .list_wallet_transactions(&wallet.id, &TransactionFilter::default(), None, None)
+        .await
+        .unwrap()
+        .transactions;
+    assert_eq!(records.len(), 2);
+
+    // Nothing reaches the recipient; the sender only pays for gas
+    chain.mine_block();
+    wallet_service.track_confirmations().await.unwrap();
+    let original = find_transaction(&wallet_service, &wallet.id, &stuck).await;
+    assert_eq!(original.status, TransactionStatus::Dropped);
+    assert_eq!(
+        chain.balance(parse_address(RECIPIENT).unwrap()),
+        U256::zero()
+    );
+    let receipt = chain
+        .get_transaction_receipt(parse_hash(&cancellation).unwrap())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(
+        chain.balance(sender),
+        U256::from(10 * COIN) - U256::from(receipt.gas_used) * receipt.effective_gas_price
+    );
+
+    // A mined transaction can no longer be replaced
+    let result = wallet_service
+        .cancel_transaction(&wallet.id, &cancellation, &pin)
+        .await;
+    assert!(matches!(result, Err(AppError::ValidationError(_))));
+
+    // Only the wallet's own transactions can be replaced
+    let result = wallet_service
+        .cancel_transaction(&wallet.id, &format!("0x{}", "11".repeat(32)), &pin)
+        .await;
+    assert!(matches!(result, Err(AppError::NotFoundError(_))));
+}
+
+#[tokio::test]
+async fn test_transfers_with_an_idempotency_key_are_sent_once() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    chain.set_balance(
+        parse_address(&wallet.address).unwrap(),
+        U256::from(10 * COIN),
+    );
+    let request = TransferRequest {
+        wallet_id: wallet.id.clone(),
+        account_index: 0,
+        to_address: RECIPIENT.to_string(),
+        token: None,
+        amount: amount("1"),
+    };
+
+    let hash = wallet_service
+        .transfer_with_idempotency_key(&user_id, "retry-1", &request, None, &pin)
+        .await
+        .unwrap();
+
+    // The same transfer, spelled differently, gets the first result back
+    let retry = TransferRequest {
+        token: Some("sel".to_string()),
+        amount: amount("1.000"),
+        ..request.clone()
+    };
+    let replayed = wallet_service
+        .transfer_with_idempotency_key(&user_id, "retry-1", &retry, None, &pin)
+        .await
+        .unwrap();
+    assert_eq!(replayed, hash);
+    assert_eq!(
+        chain.balance(parse_address(RECIPIENT).unwrap()),
+        U256::from(COIN)
+    );
+    let records = wallet_service
+        .list_wallet_transactions(&wallet.id, &TransactionFilter::default(), None, None)
+        .await
+        .unwrap()
+        .transactions;
+    assert_eq!(records.len(), 1);
+
+    // A different transfer cannot reuse the key
+    let different = TransferRequest {
+        amount: amount("2"),
+        ..request.clone()
+    };
+    let result = wallet_service
+        .transfer_with_idempotency_key(&user_id, "retry-1", &different, None, &pin)
+        .await;
+    assert!(matches!(result, Err(AppError::ResourceExistsError(_))));
+
+    // Nor can the same transfer sent at a quote's fees
+    let quoted = QuoteChoice {
+        quote_id: "transfer_quotes:any".to_string(),
+        speed: FeeSpeed::Fast,
+    };
+    let result = wallet_service
+        .transfer_with_idempotency_key(&user_id, "retry-1", &request, Some(&quoted), &pin)
+        .await;
+    assert!(matches!(result, Err(AppError::ResourceExistsError(_))));
+
+    // Another key sends another transfer
+    let second = wallet_service
+        .transfer_with_idempotency_key(&user_id, "retry-2", &request, None, &pin)
+        .await
+        .unwrap();
+    assert_ne!(second, hash);
+    assert_eq!(
+        chain.balance(parse_address(RECIPIENT).unwrap()),
+        U256::from(2 * COIN)
+    );
+
+    let result = wallet_service
+        .transfer_with_idempotency_key(&user_id, "has spaces", &request, None, &pin)
+        .await;
+    assert!(matches!(result, Err(AppError::ValidationError(_))));
+}
+
+#[tokio::test]
+async fn test_failed_transfers_with_an_idempotency_key_replay_the_error() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    let request = TransferRequest {
+        wallet_id: wallet.id.clone(),
+        account_index: 0,
+        to_address: RECIPIENT.to_string(),
+        token: None,
+        amount: amount("1"),
+    };
+
+    let first = wallet_service
+        .transfer_with_idempotency_key(&user_id, "unfunded", &request, None, &pin)
+        .await;
+    let Err(AppError::ValidationError(message)) = first else {
+        panic!("expected a validation error, got {:?}", first);
+    };
+
+    // Funding the wallet afterwards does not change the stored result
+    chain.set_balance(
+        parse_address(&wallet.address).unwrap(),
+        U256::from(10 * COIN),
+    );
+    let replayed = wallet_service
+        .transfer_with_idempotency_key(&user_id, "unfunded", &request, None, &pin)
+        .await;
+    assert!(
+        matches!(replayed, Err(AppError::ValidationError(ref replayed)) if *replayed == message)
+    );
+    assert_eq!(chain.pending_count(), 0);
+
+    wallet_service
+        .transfer_with_idempotency_key(&user_id, "funded", &request, None, &pin)
+        .await
+        .unwrap();
+    assert_eq!(
+        chain.balance(parse_address(RECIPIENT).unwrap()),
+        U256::from(COIN)
+    );
+}
+
+#[tokio::test]
+async fn test_transfers_with_a_lapsed_idempotency_claim_are_sent_once() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let wallet_service = wallet_service.with_idempotency_store(Arc::new(ForgetfulIdempotencyStore));
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    chain.set_balance(
+        parse_address(&wallet.address).unwrap(),
+        U256::from(10 * COIN),
+    );
+    let request = TransferRequest {
+        wallet_id: wallet.id.clone(),
+        account_index: 0,
+        to_address: RECIPIENT.to_string(),
+        token: None,
+        amount: amount("1"),
+    };
+
+    // The retry finds the recorded transfer rather than sending another
+    let hash = wallet_service
+        .transfer_with_idempotency_key(&user_id, "lapsed", &request, None, &pin)
+        .await
+        .unwrap();
+    let replayed = wallet_service
+        .transfer_with_idempotency_key(&user_id, "lapsed", &request, None, &pin)
+        .await
+        .unwrap();
+    assert_eq!(replayed, hash);
+
+    let different = TransferRequest {
+        amount: amount("2"),
+        ..request.clone()
+    };
+    let result = wallet_service
+        .transfer_with_idempotency_key(&user_id, "lapsed", &different, None, &pin)
+        .await;
+    assert!(matches!(result, Err(AppError::ResourceExistsError(_))));
+    assert_eq!(
+        chain.balance(parse_address(RECIPIENT).unwrap()),
+        U256::from(COIN)
+    );
+
+    // Of two requests racing with one key, only one transfer is recorded and sent
+    let (first, second) = tokio::join!(
+        wallet_service.transfer_with_idempotency_key(&user_id, "racing", &request, None, &pin),
+        wallet_service.transfer_with_idempotency_key(&user_id, "racing", &request, None, &pin),
+    );
+    assert_eq!(first.unwrap(), second.unwrap());
+    let records = wallet_service
+        .list_wallet_transactions(&wallet.id, &TransactionFilter::default(), None, None)
+        .await
+        .unwrap()
+        .transactions;
+    assert_eq!(records.len(), 2);
+}
+
+#[tokio::test]
+async fn test_transfer_quotes_offer_fee_tiers() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    let sender = parse_address(&wallet.address).unwrap();
+    chain.set_balance(sender, U256::from(10 * COIN));
+    chain.set_base_fee_per_gas(U256::from(10 * GWEI));
+    chain.set_priority_fee_per_gas(U256::from(2 * GWEI));
+
+    let quote = wallet_service
+        .quote_transfer(&TransferRequest {
+            wallet_id: wallet.id.clone(),
+            account_index: 0,
+            to_address: RECIPIENT.to_string(),
+            token: None,
+            amount: amount("1"),
+        })
+        .await
+        .unwrap();
+    assert_eq!(quote.gas_limit, 21_000);
+    assert_eq!(quote.token, "SEL");
+    assert_eq!(quote.base_fee_per_gas, amount("10"));
+    assert_eq!(quote.native_balance, amount("10"));
+    assert!(quote.expires_at > quote.created_at);
+
+    let speeds: Vec<FeeSpeed> = quote.tiers.iter().map(|tier| tier.speed).collect();
+    assert_eq!(speeds, [FeeSpeed::Slow, FeeSpeed::Normal, FeeSpeed::Fast]);
+    let tips: Vec<Amount> = quote
+        .tiers
+        .iter()
+        .map(|tier| tier.max_priority_fee_per_gas)
+        .collect();
+    assert_eq!(tips, [amount("0.4"), amount("2"), amount("3.6")]);
+
+    // Fees per gas are in gwei, costs in the native coin
+    let normal = &quote.tiers[1];
+    assert_eq!(normal.max_fee_per_gas, amount("22"));
+    assert_eq!(normal.estimated_fee, amount("0.000252"));
+    assert_eq!(normal.max_fee, amount("0.000462"));
+    assert_eq!(normal.total_cost, amount("1.000462"));
+    assert!(normal.can_cover_gas);
+
+    // A token transfer still pays for gas in the native coin
+    let token = parse_address(USDT).unwrap();
+    chain.set_balance(sender, U256::zero());
+    chain.set_token_balance(token, sender, U256::from(10 * USDT_UNIT));
+    let quote = wallet_service
+        .quote_transfer(&TransferRequest {
+            wallet_id: wallet.id.clone(),
+            account_index: 0,
+            to_address: RECIPIENT.to_string(),
+            token: Some("USDT".to_string()),
+            amount: amount("5"),
+        })
+        .await
+        .unwrap();
+    assert_eq!(quote.token_address.as_deref(), Some(USDT));
+    assert!(quote.gas_limit > 21_000);
+    assert!(
+        quote
+            .tiers
+            .iter()
+            .all(|tier| tier.total_cost == tier.max_fee)
+    );
+    assert!(quote.tiers.iter().all(|tier| !tier.can_cover_gas));
+
+    let result = wallet_service
+        .quote_transfer(&TransferRequest {
+            wallet_id: wallet.id.clone(),
+            account_index: 0,
+            to_address: RECIPIENT.to_string(),
+            token: Some("USDT".to_string()),
+            amount: amount("50"),
+        })
+        .await;
+    assert!(matches!(result, Err(AppError::ValidationError(_))));
+}
+
+#[tokio::test]
+async fn test_transfers_with_a_quote_pay_the_quoted_fees() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    chain.set_balance(
+        parse_address(&wallet.address).unwrap(),
+        U256::from(10 * COIN),
+    );
+    let request = TransferRequest {
+        wallet_id: wallet.id.clone(),
+        account_index: 0,
+        to_address: RECIPIENT.to_string(),
+        token: None,
+        amount: amount("1"),
+    };
+    let quote = wallet_service.quote_transfer(&request).await.unwrap();
+    let fast = quote.tiers[2].clone();
+
+    // Fees move after the quote, but the transfer pays what was accepted
+    chain.set_base_fee_per_gas(U256::from(3 * GWEI));
+    let choice = QuoteChoice {
+        quote_id: quote.id.clone(),
+        speed: FeeSpeed::Fast,
+    };
+    let outgoing = wallet_service
+        .send_transfer(&request, Some(&choice), &pin)
+        .await
+        .unwrap();
+    assert_eq!(outgoing.fee, Some(fast.max_fee));
+    assert_eq!(
+        chain.balance(parse_address(RECIPIENT).unwrap()),
+        U256::from(COIN)
+    );
+
+    // A quote only covers the transfer it was made for
+    let different = TransferRequest {
+        amount: amount("2"),
+        ..request.clone()
+    };
+    let result = wallet_service
+        .send_transfer(&different, Some(&choice), &pin)
+        .await;
+    assert!(matches!(result, Err(AppError::ValidationError(_))));
+
+    let unknown = QuoteChoice {
+        quote_id: "missing".to_string(),
+        speed: FeeSpeed::Normal,
+    };
+    let result = wallet_service
+        .send_transfer(&request, Some(&unknown), &pin)
+        .await;
+    assert!(matches!(result, Err(AppError::NotFoundError(_))));
+    assert_eq!(
+        chain.balance(parse_address(RECIPIENT).unwrap()),
+        U256::from(COIN)
+    );
+}
+
+#[tokio::test]
+async fn test_expired_quotes_are_refused() {
+    let (wallet_service, chain, user_id) = setup_wallet_service().await;
+    let wallet_service = wallet_service.with_chain_config(ChainConfig {
+        quotes: QuoteConfig { ttl_secs: 0 },
+        ..Default::default()
+    });
+    let pin = Pin::from(PIN);
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    chain.set_balance(
+        parse_address(&wallet.address).unwrap(),
+        U256::from(10 * COIN),
+    );
+    let request = TransferRequest {
+        wallet_id: wallet.id.clone(),
+        account_index: 0,
+        to_address: RECIPIENT.to_string(),
+        token: None,
+        amount: amount("1"),
+    };
+    let quote = wallet_service.quote_transfer(&request).await.unwrap();
+
+    let choice = QuoteChoice {
+        quote_id: quote.id,
+        speed: FeeSpeed::Normal,
+    };
+    let result = wallet_service
+        .send_transfer(&request, Some(&choice), &pin)
+        .await;
+    assert!(matches!(result, Err(AppError::ValidationError(_))));
+    assert_eq!(chain.pending_count(), 0);
+    assert_eq!(
+        chain.balance(parse_address(RECIPIENT).unwrap()),
+        U256::zero()
+    );
+}
+
+#[tokio::test]
+async fn test_key_updates_do_not_undo_a_concurrent_pin_change() {
+    let (wallet_service, _chain, user_id) = setup_wallet_service().await;
+    let pin = Pin::from(PIN);
+    let new_pin = Pin::from("246801");
+    let (wallet, _) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+
+    // The PIN changes after the update read the record, and the update then tries to
+    // write back what it read, as a re-wrap of the old record would
+    let pin_changed = AtomicBool::new(false);
+    let updated = wallet_service
+        .update_wallet_key(&wallet.id, |current| {
+            let (wallet_service, wallet_id) = (&wallet_service, &wallet.id);
+            let (pin, new_pin, pin_changed) = (&pin, &new_pin, &pin_changed);
+            async move {
+                if pin_changed.swap(true, Ordering::SeqCst) {
+                    return Ok(None);
+                }
+                wallet_service
+                    .change_wallet_pin(wallet_id, pin, new_pin)
+                    .await?;
+                Ok(Some(current))
+            }
+        })
+        .await
+        .unwrap();
+
+    // The stale write was refused and the update read the record again
+    assert!(updated.is_none());
+    assert!(
+        wallet_service
+            .verify_pin(&wallet.id, &new_pin)
+            .await
+            .unwrap()
+    );
+    assert!(!wallet_service.verify_pin(&wallet.id, &pin).await.unwrap());
+}
+
+#[tokio::test]
+async fn test_key_rotation_jobs_have_one_run_at_a_time() {
+    let db: &'static Arc<Database> = Box::leak(Box::new(
+        initialize_memory_db()
+            .await
+            .expect("Database initialization failed"),
+    ));
+    let job_db = Arc::new(DbService::<KeyRotationJob>::new(db, "key_rotation_jobs"));
+    let encryption_service = Arc::new(WalletEncryptionService::new("test", &[7u8; 32]));
+    let wallet_service = WalletService::new(encryption_service)
+        .with_wallet_key_db(Arc::new(DbService::<WalletKey>::new(db, "wallet_keys")))
+        .with_key_rotation_job_db(job_db.clone());
+    wallet_service.define_key_rotation_indexes().await.unwrap();
+
+    // A job nobody works on is taken at once
+    let job = KeyRotationJob::new("test".to_string(), 10, 1);
+    let job = job_db.create_record(job).await.unwrap().unwrap();
+    let finished = wallet_service
+        .run_key_rotation_job(&job.id.id.to_raw())
+        .await
+        .unwrap();
+    assert_eq!(finished.status, KeyRotationStatus::Completed);
+
+    // A job another instance is working on
+    let mut job = KeyRotationJob::new("test".to_string(), 10, 1);
+    job.owner = Some("other-instance".to_string());
+    job.lease_expires_at = Some(Utc::now() + Duration::hours(1));
+    let job = job_db.create_record(job).await.unwrap().unwrap();
+    let job_id = job.id.id.to_raw();
+
+    // While its lease lasts, another run waits
+    let waiting = tokio::time::timeout(
+        std::time::Duration::from_secs(2),
+        wallet_service.run_key_rotation_job(&job_id),
+    )
+    .await;
+    assert!(waiting.is_err());
+
+    // Once the lease runs out the job is taken over and finished
+    let mut job = job_db.get_record_by_id(&job_id).await.unwrap().unwrap();
+    job.lease_expires_at = Some(Utc::now() - Duration::seconds(1));
+    job_db.update_record(&job_id, job).await.unwrap();
+    let finished = wallet_service.run_key_rotation_job(&job_id).await.unwrap();
+    assert_eq!(finished.status, KeyRotationStatus::Completed);
+}
+
+#[tokio::test]
+async fn test_pins_are_reset_with_the_phrase_or_the_imported_key() {
+    let (wallet_service, _chain, user_id) = setup_wallet_service().await;
+    let pin = Pin::from(PIN);
+    let new_pin = Pin::from("246801");
+    let signed_in_at = Utc::now().timestamp();
+    let private_key = || {
+        WalletImportSource::PrivateKey(
+            PrivateKey::from_hex(
+                "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318",
+            )
+            .unwrap(),
+        )
+    };
+
+    // A wallet imported from a single key is recovered with that key
+    let imported = wallet_service
+        .import_wallet(&user_id, None, private_key(), &pin)
+        .await
+        .unwrap();
+    wallet_service
+        .reset_wallet_pin(&imported.id, private_key(), &new_pin, signed_in_at)
+        .await
+        .unwrap();
+    assert!(
+        wallet_service
+            .verify_pin(&imported.id, &new_pin)
+            .await
+            .unwrap()
+    );
+
+    // It still holds a single key, so the key keeps working for later resets
+    wallet_service
+        .reset_wallet_pin(&imported.id, private_key(), &pin, signed_in_at)
+        .await
+        .unwrap();
+    let imported = wallet_service
+        .get_user_wallet(&user_id, Some(&imported.id))
+        .await
+        .unwrap();
+    assert!(!imported.hd);
+    assert!(wallet_service.verify_pin(&imported.id, &pin).await.unwrap());
+
+    // An HD wallet needs its phrase; the key of its first account is refused
+    let (wallet, phrase) = wallet_service
+        .create_wallet(&user_id, None, &pin)
+        .await
+        .unwrap();
+    let first_key = EthereumWallet::from_mnemonic(&phrase)
+        .unwrap()
+        .private_key_hex();
+    let result = wallet_service
+        .reset_wallet_pin(
+            &wallet.id,
+            WalletImportSource::PrivateKey(
+                PrivateKey::from_hex(first_key.expose_secret()).unwrap(),
+            ),
+            &new_pin,
+            signed_in_at,
+        )
+        .await;
+    assert!(matches!(result, Err(AppError::ValidationError(_))));
+
+    wallet_service
+        .reset_wallet_pin(
+            &wallet.id,
+            WalletImportSource::Mnemonic {
+                phrase,
+                passphrase: SecretString::from(""),
+                derivation_path: None,
+            },
+            &new_pin,
+            signed_in_at,
+        )
+        .await
+        .unwrap();
+    assert!(
+        wallet_service
+            .verify_pin(&wallet.id, &new_pin)
+            .await
+            .unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_only_one_key_rotation_job_can_be_started() {
+    let db: &'static Arc<Database> = Box::leak(Box::new(
+        initialize_memory_db()
+            .await
+            .expect("Database initialization failed"),
+    ));
+    let job_db = Arc::new(DbService::<KeyRotationJob>::new(db, "key_rotation_jobs"));
+    let encryption_service = Arc::new(WalletEncryptionService::new("test", &[7u8; 32]));
+    // Without a wallet key database the jobs stop at their first batch and stay running
+    let wallet_service =
+        Arc::new(WalletService::new(encryption_service).with_key_rotation_job_db(job_db.clone()));
+    wallet_service.define_key_rotation_indexes().await.unwrap();
+
+    let (first, second) = tokio::join!(
+        wallet_service.start_key_rotation_job(),
+        wallet_service.start_key_rotation_job()
+    );
+    let (started, refused) = match (first, second) {
+        (Ok(started), Err(refused)) | (Err(refused), Ok(started)) => (started, refused),
+        _ => panic!("exactly one of two concurrent starts must succeed"),
+    };
+    assert!(matches!(refused, AppError::ResourceExistsError(_)));
+    assert_eq!(started.status, KeyRotationStatus::Running);
+
+    // The database itself refuses a second active job
+    let result = job_db
+        .create_record(KeyRotationJob::new("test".to_string(), 10, 1))
+        .await;
+    assert!(result.is_err());
+
+    // Once the job is cancelled another one can start
+    wallet_service
+        .cancel_key_rotation_job(&started.id)
+        .await
+        .unwrap();
+    wallet_service.start_key_rotation_job().await.unwrap();
+}